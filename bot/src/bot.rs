@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use proto::project::{Clip, ClipProp, Note, ObjectId, Operation, Track, TrackProp};
+use proto::{Command, Response, UserActivity};
+use rand::Rng;
+use sha3::{Digest, Sha3_256};
+
+use stats::{Request, Stats};
+
+pub const PASSWORD: &str = "bot";
+
+pub fn user_name(n: usize) -> String {
+    format!("bot_{}", n)
+}
+
+pub fn email(n: usize) -> String {
+    format!("bot_{}@bot.local", n)
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub server: SocketAddr,
+    /// Edits per second and bot
+    pub edit_rate: f64,
+    /// Lock acquisitions or releases per second and bot
+    pub lock_rate: f64,
+    /// Chat messages per second and bot
+    pub chat_rate: f64,
+    /// Changes between away and back per second and bot
+    pub presence_rate: f64,
+}
+
+/// What the bot's reader thread learns and the bot acts on.
+#[derive(Default)]
+struct Shared {
+    seq: u64,
+    /// Tracks in the project, to pick locks from
+    tracks: Vec<ObjectId>,
+    edits: HashMap<u32, Instant>,
+    locks: HashMap<ObjectId, Instant>,
+    /// Sent chat messages by their text
+    chats: HashMap<String, Instant>,
+    /// The presence the bot asked for and when, until the user list shows it
+    presence: Option<(bool, Instant)>,
+    away: bool,
+    held: Option<ObjectId>,
+    /// The bot's own clips and notes, once the server applied their insertion
+    clips: Vec<ObjectId>,
+    notes: Vec<ObjectId>,
+}
+
+/// A simulated user: logs in, joins a project and keeps editing it until `stop` is set.
+pub struct Bot {
+    n: usize,
+    project_id: u32,
+    config: Config,
+    stream: TcpStream,
+    site: u32,
+    counter: u32,
+    local_seq: u32,
+    chats_sent: u32,
+    track: ObjectId,
+    shared: Arc<Mutex<Shared>>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Bot {
+    /// Connects, logs in and joins the project.
+    pub fn connect(n: usize, project_id: u32, config: Config, stats: Arc<Mutex<Stats>>) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&config.server, Duration::from_secs(5))?;
+        stream.set_nodelay(true)?;
+        let mut bot = Bot {
+            n,
+            project_id,
+            config,
+            stream,
+            site: 0,
+            counter: 0,
+            local_seq: 0,
+            chats_sent: 0,
+            track: ObjectId { site: 0, counter: 0 },
+            shared: Arc::new(Mutex::new(Shared::default())),
+            stats,
+        };
+
+        let sent = Instant::now();
+        bot.send(&Command::Login {
+            email: email(n),
+            password: Sha3_256::digest(PASSWORD.as_bytes()).to_vec(),
+        })?;
+        loop {
+            match bot.recv()? {
+                Response::LoginOk => break,
+                Response::LoginInvalid => return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Invalid login")),
+                Response::LoginThrottled { retry_after_secs } => {
+                    let msg = format!("Login throttled, retry after {}s", retry_after_secs);
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+                }
+                _ => {}
+            }
+        }
+        bot.stats.lock().unwrap().record(Request::Login, sent.elapsed());
+
+        let sent = Instant::now();
+        bot.send(&Command::JoinProject { project_id })?;
+        loop {
+            match bot.recv()? {
+                Response::ProjectJoined { site, seq, state, .. } => {
+                    bot.site = site;
+                    let mut shared = bot.shared.lock().unwrap();
+                    shared.seq = seq;
+                    shared.tracks = state.tracks.iter().map(|t| t.id).collect();
+                    break;
+                }
+                Response::NoSuchProject => return Err(io::Error::new(io::ErrorKind::NotFound, "No such project")),
+                _ => {}
+            }
+        }
+        bot.stats.lock().unwrap().record(Request::Join, sent.elapsed());
+        Ok(bot)
+    }
+
+    /// Sends edits, lock requests, chat messages and presence changes at random intervals
+    /// until `stop` is set.
+    pub fn run(mut self, stop: Arc<AtomicBool>) {
+        let reader = {
+            let stream = match self.stream.try_clone() {
+                Ok(stream) => stream,
+                Err(e) => {
+                    self.stats.lock().unwrap().error(format!("Socket clone failed: {}", e));
+                    return;
+                }
+            };
+            let (shared, stats, stop) = (self.shared.clone(), self.stats.clone(), stop.clone());
+            let (site, user_name) = (self.site, user_name(self.n));
+            thread::spawn(move || read_responses(stream, site, &user_name, &shared, &stats, &stop))
+        };
+
+        let mut rng = rand::thread_rng();
+        // A track of its own to edit, so the bot's edits don't depend on anybody else's
+        self.track = self.next_id();
+        let track = Track {
+            id: self.track,
+            name: format!("Track of {}", user_name(self.n)),
+            volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            clips: Vec::new(),
+            devices: Vec::new(),
+        };
+        let mut ok = self.submit(Operation::InsertTrack { after: None, track }).is_ok();
+
+        let mut next_edit = Instant::now() + interval(&mut rng, self.config.edit_rate);
+        let mut next_lock = Instant::now() + interval(&mut rng, self.config.lock_rate);
+        let mut next_chat = Instant::now() + interval(&mut rng, self.config.chat_rate);
+        let mut next_presence = Instant::now() + interval(&mut rng, self.config.presence_rate);
+        while ok && !stop.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            if now >= next_edit {
+                let op = self.random_op(&mut rng);
+                ok = self.submit(op).is_ok();
+                next_edit = now + interval(&mut rng, self.config.edit_rate);
+            } else if now >= next_lock {
+                ok = self.toggle_lock(&mut rng).is_ok();
+                next_lock = now + interval(&mut rng, self.config.lock_rate);
+            } else if now >= next_chat {
+                ok = self.chat().is_ok();
+                next_chat = now + interval(&mut rng, self.config.chat_rate);
+            } else if now >= next_presence {
+                ok = self.toggle_presence().is_ok();
+                next_presence = now + interval(&mut rng, self.config.presence_rate);
+            } else {
+                // Short naps, so stopping doesn't wait for slow bots
+                let next = next_edit.min(next_lock).min(next_chat).min(next_presence);
+                thread::sleep((next - now).min(Duration::from_millis(100)));
+            }
+        }
+        if !ok && !stop.load(Ordering::Relaxed) {
+            self.stats.lock().unwrap().error("Send failed");
+        }
+
+        let _ = self.send(&Command::Disconnect);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = reader.join();
+        let shared = self.shared.lock().unwrap();
+        let unanswered = shared.edits.len() + shared.locks.len() + shared.chats.len() + shared.presence.iter().count();
+        if unanswered > 0 {
+            let mut stats = self.stats.lock().unwrap();
+            for _ in 0..unanswered {
+                stats.error("Unanswered at the end");
+            }
+        }
+    }
+
+    fn next_id(&mut self) -> ObjectId {
+        self.counter += 1;
+        ObjectId { site: self.site, counter: self.counter }
+    }
+
+    fn random_op(&mut self, rng: &mut impl Rng) -> Operation {
+        let mut shared = self.shared.lock().unwrap();
+        match rng.gen_range(0, 10) {
+            0..=3 => {
+                let prop = match rng.gen_range(0, 4) {
+                    0 => TrackProp::Volume(rng.gen()),
+                    1 => TrackProp::Pan(rng.gen_range(-1.0, 1.0)),
+                    2 => TrackProp::Muted(rng.gen()),
+                    _ => TrackProp::Name(format!("Track of {} #{}", user_name(self.n), rng.gen_range(0, 100))),
+                };
+                Operation::SetTrack { id: self.track, prop }
+            }
+            4 | 5 if !shared.clips.is_empty() => {
+                let id = shared.clips[rng.gen_range(0, shared.clips.len())];
+                let prop = if rng.gen() { ClipProp::Start(rng.gen_range(0, 10_000)) } else { ClipProp::Length(rng.gen_range(1, 2_000)) };
+                Operation::SetClip { id, prop }
+            }
+            6 | 7 if !shared.clips.is_empty() => {
+                let clip = shared.clips[rng.gen_range(0, shared.clips.len())];
+                self.counter += 1;
+                let note = Note {
+                    id: ObjectId { site: self.site, counter: self.counter },
+                    start: rng.gen_range(0, 2_000),
+                    length: rng.gen_range(1, 500),
+                    pitch: rng.gen_range(0, 128),
+                    velocity: rng.gen_range(1, 128),
+                };
+                Operation::InsertNote { clip, note }
+            }
+            8 if !shared.notes.is_empty() => {
+                let idx = rng.gen_range(0, shared.notes.len());
+                Operation::RemoveNote { id: shared.notes.swap_remove(idx) }
+            }
+            _ => {
+                self.counter += 1;
+                let clip = Clip {
+                    id: ObjectId { site: self.site, counter: self.counter },
+                    start: rng.gen_range(0, 10_000),
+                    length: rng.gen_range(1, 2_000),
+                    notes: Vec::new(),
+                };
+                Operation::InsertClip { track: self.track, clip }
+            }
+        }
+    }
+
+    fn submit(&mut self, op: Operation) -> io::Result<()> {
+        self.local_seq += 1;
+        let base_seq = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.edits.insert(self.local_seq, Instant::now());
+            shared.seq
+        };
+        let cmd = Command::SubmitOp {
+            project_id: self.project_id,
+            local_seq: self.local_seq,
+            base_seq,
+            op,
+        };
+        self.send(&cmd)
+    }
+
+    /// Releases the held lock, or tries to lock a random track.
+    fn toggle_lock(&mut self, rng: &mut impl Rng) -> io::Result<()> {
+        let cmd = {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some(object) = shared.held.take() {
+                Command::ReleaseLock { project_id: self.project_id, object }
+            } else if shared.tracks.is_empty() || !shared.locks.is_empty() {
+                return Ok(());
+            } else {
+                let object = shared.tracks[rng.gen_range(0, shared.tracks.len())];
+                shared.locks.insert(object, Instant::now());
+                Command::AcquireLock { project_id: self.project_id, object }
+            }
+        };
+        self.send(&cmd)
+    }
+
+    fn chat(&mut self) -> io::Result<()> {
+        self.chats_sent += 1;
+        // Numbered, to tell the bot's messages apart when they come back
+        let text = format!("Message #{} from {}", self.chats_sent, user_name(self.n));
+        self.shared.lock().unwrap().chats.insert(text.clone(), Instant::now());
+        self.send(&Command::SendChat { project_id: self.project_id, text })
+    }
+
+    /// Steps away or comes back, unless the last change isn't visible yet.
+    fn toggle_presence(&mut self) -> io::Result<()> {
+        let away = {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.presence.is_some() {
+                return Ok(());
+            }
+            let away = !shared.away;
+            shared.presence = Some((away, Instant::now()));
+            away
+        };
+        self.send(&Command::SetPresence { away })
+    }
+
+    fn send(&mut self, cmd: &Command) -> io::Result<()> {
+        // In one piece, bincode writes field by field
+        let buf = bincode::serialize(cmd).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.stream.write_all(&buf)
+    }
+
+    fn recv(&mut self) -> io::Result<Response> {
+        bincode::deserialize_from(&mut self.stream).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A random wait, so that on average `rate` things happen per second.
+fn interval(rng: &mut impl Rng, rate: f64) -> Duration {
+    if rate <= 0.0 {
+        // Never
+        return Duration::from_secs(60 * 60 * 24 * 365);
+    }
+    // Exponentially distributed, like independent users would act
+    let u: f64 = rng.gen_range(f64::EPSILON, 1.0);
+    Duration::from_secs_f64(-u.ln() / rate)
+}
+
+fn read_responses(mut stream: TcpStream, site: u32, user_name: &str, shared: &Mutex<Shared>, stats: &Mutex<Stats>, stop: &AtomicBool) {
+    loop {
+        let resp = match bincode::deserialize_from(&mut stream) {
+            Ok(resp) => resp,
+            Err(e) => {
+                if !stop.load(Ordering::Relaxed) {
+                    stats.lock().unwrap().error(format!("Connection lost: {}", e));
+                }
+                return;
+            }
+        };
+        let now = Instant::now();
+        let mut shared = shared.lock().unwrap();
+        match resp {
+            Response::OpApplied { seq, site: op_site, local_seq, op, .. } => {
+                shared.seq = seq;
+                match op {
+                    Operation::InsertTrack { track, .. } => shared.tracks.push(track.id),
+                    Operation::RemoveTrack { id } => shared.tracks.retain(|&t| t != id),
+                    Operation::Restore { state } => shared.tracks = state.tracks.iter().map(|t| t.id).collect(),
+                    Operation::InsertClip { clip, .. } if op_site == site => shared.clips.push(clip.id),
+                    Operation::InsertNote { note, .. } if op_site == site => shared.notes.push(note.id),
+                    _ => {}
+                }
+                if op_site == site {
+                    if let Some(sent) = local_seq.and_then(|l| shared.edits.remove(&l)) {
+                        stats.lock().unwrap().record(Request::Edit, now - sent);
+                    }
+                }
+            }
+            Response::OpRejected { local_seq, error, .. } => {
+                shared.edits.remove(&local_seq);
+                match error {
+                    // Another bot locked the track
+                    proto::project::OpError::Locked(_) => stats.lock().unwrap().edit_locked(),
+                    other => stats.lock().unwrap().error(format!("Operation rejected: {:?}", other)),
+                }
+            }
+            Response::LockChanged { object, holder, .. } if holder.as_deref() == Some(user_name) => {
+                if let Some(sent) = shared.locks.remove(&object) {
+                    shared.held = Some(object);
+                    stats.lock().unwrap().record(Request::Lock, now - sent);
+                }
+            }
+            Response::LockDenied { object, reason, .. } => {
+                shared.locks.remove(&object);
+                match reason {
+                    proto::LockError::HeldBy(_) => stats.lock().unwrap().lock_denied(),
+                    other => stats.lock().unwrap().error(format!("Lock denied: {:?}", other)),
+                }
+            }
+            Response::ChatMessage { user_name: ref sender, text, .. } if sender == user_name => {
+                if let Some(sent) = shared.chats.remove(&text) {
+                    stats.lock().unwrap().record(Request::Chat, now - sent);
+                }
+            }
+            Response::UserList(users) => {
+                let shown_away = users.iter()
+                    .find(|u| u.user_name == user_name)
+                    .map(|u| matches!(u.activity, UserActivity::Away));
+                if let Some((away, sent)) = shared.presence {
+                    if shown_away == Some(away) {
+                        shared.presence = None;
+                        shared.away = away;
+                        stats.lock().unwrap().record(Request::Presence, now - sent);
+                    }
+                }
+            }
+            Response::InternalError => stats.lock().unwrap().error("Internal server error"),
+            Response::NotInProject => stats.lock().unwrap().error("Not in project"),
+            Response::NotLoggedIn => stats.lock().unwrap().error("Not logged in"),
+            Response::ServerShuttingDown { reason, .. } => stats.lock().unwrap().error(format!("Server shutting down: {}", reason)),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Load tester for the Chorus Studio server.
+//!
+//! Opens many connections, each logging in as a generated user, joining a project and
+//! editing it, chatting and stepping away at random. Reports round trip percentiles and protocol errors at the end.
+//! The accounts are created through the server's control socket, so only a server
+//! running on the same machine can be tested.
+
+extern crate bincode;
+extern crate proto;
+extern crate rand;
+extern crate sha3;
+
+mod bot;
+mod stats;
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stats::{Request, Stats};
+
+const USAGE: &str = "\
+Usage: chorus_bot [options]
+  --server <address>            Server to test, must be local (default 127.0.0.1:4450)
+  --control-socket <path>       The server's control socket, to create the bot accounts
+                                (default chorus_studio.sock)
+  --bots <n>                    Number of simulated users (default 10)
+  --projects <id,id,...>        Projects the bots are spread over (default 1)
+  --edit-rate <per second>      Edits per bot and second (default 2)
+  --lock-rate <per second>      Lock acquisitions and releases per bot and second (default 0.5)
+  --chat-rate <per second>      Chat messages per bot and second (default 0.2)
+  --presence-rate <per second>  Changes between away and back per bot and second (default 0.05)
+  --duration <seconds>          How long to keep editing (default 30)
+  --ramp-up <seconds>           Time over which the bots connect (default 5)";
+
+/// How often progress is printed.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+fn main() {
+    if std::env::args().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let server = flag_value("--server").unwrap_or_else(|| "127.0.0.1:4450".to_owned());
+    let server: SocketAddr = match server.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+        Some(addr) => addr,
+        None => exit_with(&format!("Invalid server address {:?}", server)),
+    };
+    if !server.ip().is_loopback() {
+        exit_with("chorus_bot only tests servers on this machine");
+    }
+    let control_socket = flag_value("--control-socket").unwrap_or_else(|| "chorus_studio.sock".to_owned());
+    let bots: usize = parse_flag("--bots", 10);
+    let projects: Vec<u32> = match flag_value("--projects") {
+        Some(list) => list.split(',')
+            .map(|id| id.trim().parse().unwrap_or_else(|_| exit_with(&format!("Invalid project id {:?}", id))))
+            .collect(),
+        None => vec![1],
+    };
+    let config = bot::Config {
+        server,
+        edit_rate: parse_flag("--edit-rate", 2.0),
+        lock_rate: parse_flag("--lock-rate", 0.5),
+        chat_rate: parse_flag("--chat-rate", 0.2),
+        presence_rate: parse_flag("--presence-rate", 0.05),
+    };
+    let duration = Duration::from_secs_f64(parse_flag("--duration", 30.0));
+    let ramp_up = Duration::from_secs_f64(parse_flag("--ramp-up", 5.0));
+    if bots == 0 || projects.is_empty() {
+        exit_with("Nothing to do without bots or projects");
+    }
+
+    if let Err(e) = create_accounts(&control_socket, bots, &projects) {
+        exit_with(&format!("Couldn't create the bot accounts through {}: {}", control_socket, e));
+    }
+
+    println!(
+        "{} bots on {} project(s), {} edits, {} lock changes, {} chat messages and {} presence changes per bot and second, for {}s",
+        bots, projects.len(), config.edit_rate, config.lock_rate, config.chat_rate, config.presence_rate, duration.as_secs(),
+    );
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let start = Instant::now();
+    let threads: Vec<_> = (0..bots)
+        .map(|n| {
+            let project_id = projects[n % projects.len()];
+            let (config, stats, stop) = (config.clone(), stats.clone(), stop.clone());
+            let delay = ramp_up.mul_f64(n as f64 / bots as f64);
+            thread::spawn(move || {
+                thread::sleep(delay);
+                match bot::Bot::connect(n, project_id, config, stats.clone()) {
+                    Ok(bot) => bot.run(stop),
+                    Err(e) => stats.lock().unwrap().error(format!("Joining failed: {}", e)),
+                }
+            })
+        })
+        .collect();
+
+    let end = start + ramp_up + duration;
+    let mut next_progress = start + PROGRESS_INTERVAL;
+    while Instant::now() < end {
+        thread::sleep(next_progress.min(end).saturating_duration_since(Instant::now()));
+        if Instant::now() >= next_progress {
+            let stats = stats.lock().unwrap();
+            println!(
+                "{:>4}s: {} joined, {} edits, {} locks, {} chats, {} errors",
+                start.elapsed().as_secs(), stats.count(Request::Join), stats.count(Request::Edit),
+                stats.count(Request::Lock), stats.count(Request::Chat), stats.error_count(),
+            );
+            next_progress += PROGRESS_INTERVAL;
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    println!("\n{}", stats.lock().unwrap().report());
+}
+
+/// Creates the accounts of the bots, unless they exist already, and makes each bot a
+/// member of the project it's going to join.
+#[cfg(unix)]
+fn create_accounts(control_socket: &str, bots: usize, projects: &[u32]) -> std::io::Result<()> {
+    use std::io::{BufRead, BufReader, Error, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(control_socket)?;
+    let mut replies = BufReader::new(stream.try_clone()?);
+    let mut reply = String::new();
+    for n in 0..bots {
+        writeln!(stream, "create-user {} {} {}", bot::email(n), bot::user_name(n), bot::PASSWORD)?;
+        reply.clear();
+        replies.read_line(&mut reply)?;
+        // Left over from a previous run
+        let exists = reply.contains("UNIQUE constraint failed");
+        if !reply.starts_with("Created user") && !exists {
+            return Err(Error::other(reply.trim().to_owned()));
+        }
+        writeln!(stream, "add-member {} {}", bot::user_name(n), projects[n % projects.len()])?;
+        reply.clear();
+        replies.read_line(&mut reply)?;
+        if !reply.starts_with("Added") && !reply.contains("is a member of project") {
+            return Err(Error::other(reply.trim().to_owned()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_accounts(_control_socket: &str, _bots: usize, _projects: &[u32]) -> std::io::Result<()> {
+    Err(std::io::Error::other("Control sockets are only supported on Unix"))
+}
+
+/// The argument after the command line flag `name`.
+fn flag_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|a| a != name).nth(1)
+}
+
+fn parse_flag<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match flag_value(name) {
+        Some(value) => value.parse().unwrap_or_else(|_| exit_with(&format!("Invalid value {:?} for {}", value, name))),
+        None => default,
+    }
+}
+
+fn exit_with(msg: &str) -> ! {
+    println!("{}\n\n{}", msg, USAGE);
+    std::process::exit(1);
+}
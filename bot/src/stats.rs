@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Requests whose round trip is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Request {
+    Login,
+    Join,
+    /// `SubmitOp` until the server echoes the operation
+    Edit,
+    /// `AcquireLock` until the lock is ours
+    Lock,
+    /// `SendChat` until the message comes back
+    Chat,
+    /// `SetPresence` until the user list shows it
+    Presence,
+}
+
+/// Measurements of all bots.
+#[derive(Default)]
+pub struct Stats {
+    latencies: BTreeMap<Request, Vec<Duration>>,
+    /// Protocol errors by what went wrong
+    errors: BTreeMap<String, usize>,
+    /// Locks held by someone else. Expected with many bots in one project, so not an error.
+    locks_denied: usize,
+    /// Edits rejected because of someone else's lock, just as expected
+    edits_locked: usize,
+}
+
+impl Stats {
+    pub fn record(&mut self, req: Request, latency: Duration) {
+        self.latencies.entry(req).or_default().push(latency);
+    }
+
+    pub fn error(&mut self, what: impl Into<String>) {
+        *self.errors.entry(what.into()).or_insert(0) += 1;
+    }
+
+    pub fn lock_denied(&mut self) {
+        self.locks_denied += 1;
+    }
+
+    pub fn edit_locked(&mut self) {
+        self.edits_locked += 1;
+    }
+
+    pub fn count(&self, req: Request) -> usize {
+        self.latencies.get(&req).map_or(0, |l| l.len())
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = format!("{:<8} {:>8} {:>10} {:>10} {:>10} {:>10}", "request", "count", "p50 ms", "p90 ms", "p99 ms", "max ms");
+        for (req, latencies) in &self.latencies {
+            let mut sorted = latencies.clone();
+            sorted.sort();
+            let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+            let _ = write!(
+                &mut out,
+                "\n{:<8} {:>8} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+                format!("{:?}", req), sorted.len(), percentile(50), percentile(90), percentile(99), percentile(100),
+            );
+        }
+        let _ = write!(&mut out, "\n\nLocks denied: {}\nEdits rejected by locks: {}", self.locks_denied, self.edits_locked);
+        if self.errors.is_empty() {
+            out.push_str("\nProtocol errors: none");
+        } else {
+            out.push_str("\nProtocol errors:");
+            for (what, count) in &self.errors {
+                let _ = write!(&mut out, "\n  {:>6}  {}", count, what);
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,317 @@
+//! `chorus-cli`, a command-line client for scripting account and project chores.
+//!
+//! Output is meant to be read by humans, `--json` prints JSON instead. The password is
+//! taken from the `CHORUS_PASSWORD` environment variable, or else read from stdin.
+
+extern crate client;
+extern crate proto;
+#[macro_use]
+extern crate serde_json;
+extern crate sha3;
+
+mod session;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use client::assets::{AssetCache, AssetSync};
+use client::settings::Settings;
+use proto::asset::{hash_hex, CHUNK_SIZE};
+use proto::{Command, Response, UserActivity};
+use session::Session;
+use sha3::{Digest, Sha3_256};
+
+const USAGE: &str = "\
+Usage: chorus-cli [options] <command> [arguments]
+
+Commands:
+  login                               Checks the credentials and remembers them for next time
+  list-users                          Lists the users who are online
+  list-projects                       Lists the projects you're a member of
+  upload-asset <project id> <file>    Uploads a file as an asset of a project
+  export-project <project id> <dir>   Writes a project and its assets to a directory
+
+Options:
+  --server <host:port>   The server to connect to, the last one used by default
+  --email <address>      The account to log in with, the last one used by default
+  --config <path>        The settings file, shared with the desktop client
+  --json                 Prints JSON instead of text";
+
+/// The command line, split into options and the command with its arguments.
+struct Args {
+    server: Option<String>,
+    email: Option<String>,
+    config: Option<PathBuf>,
+    json: bool,
+    command: Vec<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            server: None,
+            email: None,
+            config: None,
+            json: false,
+            command: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} expects a value", arg));
+            match arg.as_str() {
+                "--server" => parsed.server = Some(value()?),
+                "--email" => parsed.email = Some(value()?),
+                "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--json" => parsed.json = true,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => parsed.command.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(ref args) if args.command.is_empty() => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let settings_path = args.config.clone().or_else(Settings::default_path);
+    let mut settings = match settings_path {
+        Some(ref path) => Settings::load(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+        None => Settings::default(),
+    };
+    // Only for this run, `login` shouldn't make it the default
+    settings.server_override = args.server.clone();
+    let email = match args.email.clone().or_else(|| settings.last_email.clone()) {
+        Some(email) => email,
+        None => return Err("No account to log in with, use --email".to_owned()),
+    };
+
+    let (command, params) = (args.command[0].as_str(), &args.command[1..]);
+    let expect_params = |n: usize| {
+        if params.len() == n {
+            Ok(())
+        } else {
+            Err(format!("{} expects {} arguments\n\n{}", command, n, USAGE))
+        }
+    };
+    match command {
+        "login" | "list-users" | "list-projects" => expect_params(0)?,
+        "upload-asset" | "export-project" => expect_params(2)?,
+        _ => return Err(format!("Unknown command {}\n\n{}", command, USAGE)),
+    }
+
+    let password = read_password()?;
+    let mut session = Session::connect(settings.current_server())?;
+    session.login(&email, Sha3_256::digest(password.as_bytes()).as_slice())?;
+
+    match command {
+        "login" => {
+            settings.last_email = Some(email.clone());
+            if let Some(ref path) = settings_path {
+                settings.save(path).map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+            }
+            if args.json {
+                print_json(&json!({ "server": settings.current_server(), "email": email }));
+            } else {
+                println!("Logged in to {} as {}", settings.current_server(), email);
+            }
+        }
+        "list-users" => list_users(&mut session, args.json)?,
+        "list-projects" => list_projects(&mut session, args.json)?,
+        "upload-asset" => upload_asset(&mut session, project_id(&params[0])?, Path::new(&params[1]), args.json)?,
+        "export-project" => export_project(&mut session, project_id(&params[0])?, Path::new(&params[1]), args.json)?,
+        _ => unreachable!(),
+    }
+    session.close();
+    Ok(())
+}
+
+fn read_password() -> Result<String, String> {
+    if let Ok(password) = env::var("CHORUS_PASSWORD") {
+        return Ok(password);
+    }
+    eprint!("Password: ");
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| format!("Failed to read the password: {}", e))?;
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+fn project_id(arg: &str) -> Result<u32, String> {
+    arg.parse().map_err(|_| format!("Invalid project id {}", arg))
+}
+
+fn print_json(value: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(value).expect("JSON values always serialize"));
+}
+
+fn list_users(session: &mut Session, json: bool) -> Result<(), String> {
+    let users = session.request(Command::ListUsers, |resp| match resp {
+        Response::UserList(users) => Some(Ok(users)),
+        _ => None,
+    })?;
+    if json {
+        print_json(&json!(users));
+        return Ok(());
+    }
+    for user in users {
+        let activity = match user.activity {
+            UserActivity::Offline => "offline".to_owned(),
+            UserActivity::Away => "away".to_owned(),
+            UserActivity::Active => "active".to_owned(),
+            UserActivity::InProject(project) => format!("in project {}", project),
+        };
+        println!("{}  ({})", user.user_name, activity);
+    }
+    Ok(())
+}
+
+fn list_projects(session: &mut Session, json: bool) -> Result<(), String> {
+    let projects = session.request(Command::ListProjects, |resp| match resp {
+        Response::Projects(projects) => Some(Ok(projects)),
+        _ => None,
+    })?;
+    if json {
+        print_json(&json!(projects));
+        return Ok(());
+    }
+    if projects.is_empty() {
+        println!("You're not a member of any project");
+    }
+    for project in projects {
+        println!("{}  {}  (created {})", project.id, project.title, project.creation_date);
+        if !project.description.is_empty() {
+            println!("    {}", project.description);
+        }
+    }
+    Ok(())
+}
+
+/// Joins the project, returning its sequence number and state.
+fn join(session: &mut Session, project_id: u32) -> Result<(u64, proto::project::Project), String> {
+    session.request(Command::JoinProject { project_id }, |resp| match resp {
+        Response::ProjectJoined { seq, state, .. } => Some(Ok((seq, state))),
+        _ => None,
+    })
+}
+
+fn upload_asset(session: &mut Session, project_id: u32, path: &Path, json: bool) -> Result<(), String> {
+    let read_err = |e: io::Error| format!("Failed to read {}: {}", path.display(), e);
+    let mut file = File::open(path).map_err(read_err)?;
+    let size = file.metadata().map_err(read_err)?.len();
+    let mut hasher = Sha3_256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).map_err(read_err)?;
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(hasher.result().as_slice());
+
+    join(session, project_id)?;
+    let failed = |error| Some(Err(format!("Upload failed: {:?}", error)));
+    // `None` once the server has the whole asset
+    let mut offset = session.request(Command::BeginUpload { project_id, hash, size }, |resp| match resp {
+        Response::UploadReady { hash: h, offset } if h == hash => Some(Ok(Some(offset))),
+        Response::UploadComplete { hash: h, .. } if h == hash => Some(Ok(None)),
+        Response::AssetFailed { hash: h, error } if h == hash => failed(error),
+        _ => None,
+    })?;
+
+    // Uploads resume where the server's copy ends, so start reading there
+    while let Some(start) = offset {
+        let mut data = vec![0u8; CHUNK_SIZE];
+        file.seek(SeekFrom::Start(start)).map_err(read_err)?;
+        let n = file.read(&mut data).map_err(read_err)?;
+        data.truncate(n);
+        offset = session.request(Command::UploadChunk { hash, offset: start, data }, |resp| match resp {
+            Response::UploadProgress { hash: h, received } if h == hash => Some(Ok(Some(received))),
+            Response::UploadComplete { hash: h, .. } if h == hash => Some(Ok(None)),
+            Response::AssetFailed { hash: h, error } if h == hash => failed(error),
+            _ => None,
+        })?;
+        if !json {
+            if let Some(received) = offset {
+                eprint!("\r{}%", received * 100 / size.max(1));
+                let _ = io::stderr().flush();
+            }
+        }
+    }
+
+    if json {
+        print_json(&json!({ "project_id": project_id, "hash": hash_hex(&hash), "size": size }));
+    } else {
+        eprint!("\r");
+        println!("Uploaded {} to project {} as {} ({} bytes)", path.display(), project_id, hash_hex(&hash), size);
+    }
+    Ok(())
+}
+
+/// Writes the project's state to `project.json` in `dir` and its assets to `assets`,
+/// each named after its hash. Assets already in the directory aren't downloaded again.
+fn export_project(session: &mut Session, project_id: u32, dir: &Path, json: bool) -> Result<(), String> {
+    let write_err = |e: io::Error| format!("Failed to write to {}: {}", dir.display(), e);
+    let (seq, state) = join(session, project_id)?;
+    fs::create_dir_all(dir).map_err(write_err)?;
+    let state_json = serde_json::to_vec_pretty(&state).map_err(|e| format!("Failed to encode the project: {}", e))?;
+    fs::write(dir.join("project.json"), state_json).map_err(write_err)?;
+
+    let assets = session.request(Command::ListAssets { project_id }, |resp| match resp {
+        Response::Assets { project_id: p, assets } if p == project_id => Some(Ok(assets)),
+        _ => None,
+    })?;
+    let asset_count = assets.len();
+    let mut sync = AssetSync::new(AssetCache::open(dir.join("assets")).map_err(write_err)?);
+    sync.start(project_id, assets);
+    while let Some(cmd) = sync.next_request().map_err(write_err)? {
+        session.request(cmd, |resp| match resp {
+            Response::AssetChunk { hash, offset, data, .. } => Some(sync.on_chunk(hash, offset, &data).map_err(write_err)),
+            Response::AssetFailed { hash, error } => {
+                sync.on_failed(hash, error);
+                Some(Ok(()))
+            }
+            _ => None,
+        })?;
+    }
+
+    if json {
+        print_json(&json!({
+            "project_id": project_id,
+            "seq": seq,
+            "tracks": state.tracks.len(),
+            "assets": asset_count,
+            "path": dir.display().to_string(),
+        }));
+    } else {
+        println!(
+            "Exported project {} with {} tracks and {} assets to {}",
+            project_id,
+            state.tracks.len(),
+            asset_count,
+            dir.display()
+        );
+    }
+    Ok(())
+}
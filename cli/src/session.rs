@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use client::net::{NetClient, NetState};
+use proto::{Command, Response};
+
+/// How long to wait for an answer before giving up.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to the server that sends one command at a time and waits for its
+/// answer. Unlike the desktop client, it gives up instead of reconnecting.
+pub struct Session {
+    client: NetClient<'static>,
+    /// Why the connection was lost, if it was
+    lost: Rc<RefCell<Option<String>>>,
+}
+
+impl Session {
+    pub fn connect(server: &str) -> Result<Self, String> {
+        let lost = Rc::new(RefCell::new(None));
+        let on_lost = lost.clone();
+        let mut client = NetClient::new(
+            server.to_owned(),
+            Box::new(move |state| {
+                if let NetState::Disconnected(ref reason) = *state {
+                    *on_lost.borrow_mut() = Some(reason.clone());
+                }
+            }),
+        );
+        client.connect();
+        if client.retry_in().is_some() {
+            return Err(format!("Couldn't connect to {}", server));
+        }
+        Ok(Self { client, lost })
+    }
+
+    pub fn login(&mut self, email: &str, password: &[u8]) -> Result<(), String> {
+        let login = Command::Login {
+            email: email.to_owned(),
+            password: password.to_vec(),
+        };
+        self.request(login, |resp| match resp {
+            Response::LoginOk => Some(Ok(())),
+            Response::LoginInvalid => Some(Err("Invalid email address or password".to_owned())),
+            Response::LoginThrottled { retry_after_secs } => Some(Err(format!(
+                "Too many failed logins, try again in {} seconds",
+                retry_after_secs
+            ))),
+            _ => None,
+        })
+    }
+
+    /// Sends `cmd` and waits until `answer` picks a response. Responses it doesn't
+    /// care about, e.g. broadcasts, are skipped, but refusals end the wait.
+    pub fn request<T, F>(&mut self, cmd: Command, answer: F) -> Result<T, String>
+    where
+        F: FnMut(Response) -> Option<Result<T, String>>,
+    {
+        self.client.send(cmd);
+        self.wait(answer)
+    }
+
+    /// Waits until `answer` picks a response, like [Session::request].
+    pub fn wait<T, F>(&mut self, mut answer: F) -> Result<T, String>
+    where
+        F: FnMut(Response) -> Option<Result<T, String>>,
+    {
+        let mut deadline = Instant::now() + ANSWER_TIMEOUT;
+        loop {
+            if let Some(reason) = self.lost.borrow_mut().take() {
+                return Err(reason);
+            }
+            let resp = match self.client.receive() {
+                Some(resp) => resp,
+                None if Instant::now() > deadline => return Err("The server didn't answer in time".to_owned()),
+                None => continue,
+            };
+            deadline = Instant::now() + ANSWER_TIMEOUT;
+            let refusal = refusal(&resp);
+            if let Some(res) = answer(resp) {
+                return res;
+            }
+            if let Some(refusal) = refusal {
+                return Err(refusal);
+            }
+        }
+    }
+
+    /// Says goodbye to the server.
+    pub fn close(mut self) {
+        self.client.send_now(&Command::Disconnect);
+    }
+}
+
+/// Describes a response that refuses whatever was asked for.
+fn refusal(resp: &Response) -> Option<String> {
+    let reason = match *resp {
+        Response::NoSuchProject => "There's no such project".to_owned(),
+        Response::NotLoggedIn => "Not logged in".to_owned(),
+        Response::NotInProject => "You're not a member of the project".to_owned(),
+        Response::InternalError => "The server failed to handle the request".to_owned(),
+        Response::ServerShuttingDown { ref reason, .. } => format!("The server shut down: {}", reason),
+        _ => return None,
+    };
+    Some(reason)
+}
@@ -0,0 +1,83 @@
+//! Runs `chorus-cli` against the mock server, serving the example fixtures.
+
+extern crate mock_server;
+extern crate proto;
+extern crate serde_json;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use mock_server::{Fixtures, MockServer};
+use proto::UserActivity;
+use serde_json::Value;
+
+fn start() -> MockServer {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../mock/fixtures/example.json");
+    let fixtures: Fixtures = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    MockServer::start(fixtures).unwrap()
+}
+
+/// A settings file of its own for every test, removed up front in case a run before left it.
+fn settings_path(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chorus-cli-{}-{}.conf", test, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Runs the CLI as alice, returns whether it succeeded and what it printed.
+fn run(server: &MockServer, settings: &Path, password: &str, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_chorus-cli"))
+        .arg("--server")
+        .arg(server.addr().to_string())
+        .arg("--config")
+        .arg(settings)
+        .args(["--email", "a@example.com"])
+        .args(args)
+        .env("CHORUS_PASSWORD", password)
+        .output()
+        .unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn lists_users_and_projects() {
+    let server = start();
+    let settings = settings_path("lists");
+    assert!(server.set_presence("bob", Some(UserActivity::Active)));
+    assert!(server.set_presence("carol", None));
+
+    let (ok, out) = run(&server, &settings, "a", &["--json", "list-users"]);
+    assert!(ok, "{}", out);
+    let users: Value = serde_json::from_str(&out).unwrap();
+    let activity = |name: &str| {
+        let users = users.as_array().unwrap();
+        users.iter().find(|u| u["user_name"] == name).map(|u| u["activity"].clone())
+    };
+    assert_eq!(activity("bob"), Some(Value::from("Active")));
+    assert_eq!(activity("carol"), None);
+
+    let (ok, out) = run(&server, &settings, "a", &["--json", "list-projects"]);
+    assert!(ok, "{}", out);
+    let projects: Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(projects[0]["title"], "Demo Song");
+    let _ = fs::remove_file(&settings);
+}
+
+#[test]
+fn login_remembers_the_account_but_not_the_server() {
+    let server = start();
+    let settings = settings_path("login");
+
+    let (ok, _) = run(&server, &settings, "wrong", &["login"]);
+    assert!(!ok);
+    assert!(!settings.exists());
+
+    let (ok, out) = run(&server, &settings, "a", &["login"]);
+    assert!(ok, "{}", out);
+    assert_eq!(out.trim(), format!("Logged in to {} as a@example.com", server.addr()));
+    let saved = fs::read_to_string(&settings).unwrap();
+    assert!(saved.contains("last_email = a@example.com"));
+    assert!(!saved.contains(&server.addr().to_string()));
+    let _ = fs::remove_file(&settings);
+}
@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use proto::asset::{hash_hex, AssetError, AssetHash, AssetInfo, CHUNK_SIZE};
+use proto::{Command, Response};
+use sha3::{Digest, Sha3_256};
+
+/// Local content addressed copy of the assets of the projects the user worked on.
+///
+/// Laid out like the server's asset store: every asset is a file named after its hash,
+/// partially downloaded assets live in the `partial` subdirectory until they're complete
+/// and verified.
+pub struct AssetCache {
+    dir: PathBuf,
+}
+
+impl AssetCache {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("partial"))?;
+        Ok(Self { dir })
+    }
+
+    /// Opens the cache in the user's data directory.
+    pub fn open_default() -> io::Result<Self> {
+        let dir = ::dirs::data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No user data directory"))?;
+        Self::open(dir.join("assets"))
+    }
+
+    pub fn path(&self, hash: &AssetHash) -> PathBuf {
+        self.dir.join(hash_hex(hash))
+    }
+
+    fn partial_path(&self, hash: &AssetHash) -> PathBuf {
+        self.dir.join("partial").join(hash_hex(hash))
+    }
+
+    pub fn contains(&self, hash: &AssetHash) -> bool {
+        self.path(hash).is_file()
+    }
+}
+
+struct Download {
+    info: AssetInfo,
+    file: File,
+    received: u64,
+    /// Hash of the first `received` bytes
+    hasher: Sha3_256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncProgress {
+    pub project_id: u32,
+    /// Assets still missing, including the one currently downloading
+    pub remaining: usize,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Brings the asset cache up to date with a project's asset list.
+///
+/// Lives on the network thread. Only one chunk is requested at a time and only when
+/// nothing else is waiting to be sent, so downloads never delay realtime edits.
+/// Downloads resume from what's already in the cache's `partial` directory, so
+/// reconnecting or restarting the client doesn't start large assets over.
+pub struct AssetSync {
+    cache: AssetCache,
+    project_id: Option<u32>,
+    queue: VecDeque<AssetInfo>,
+    current: Option<Download>,
+    chunk_in_flight: bool,
+    done_bytes: u64,
+    total_bytes: u64,
+}
+
+impl AssetSync {
+    pub fn new(cache: AssetCache) -> Self {
+        Self {
+            cache,
+            project_id: None,
+            queue: VecDeque::new(),
+            current: None,
+            chunk_in_flight: false,
+            done_bytes: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Starts syncing `project_id` against its asset list, dropping any sync in progress.
+    pub fn start(&mut self, project_id: u32, assets: Vec<AssetInfo>) {
+        let cache = &self.cache;
+        self.queue = assets.into_iter().filter(|a| !cache.contains(&a.hash)).collect();
+        self.project_id = Some(project_id);
+        self.current = None;
+        self.chunk_in_flight = false;
+        self.done_bytes = 0;
+        self.total_bytes = self.queue.iter().map(|a| a.size).sum();
+    }
+
+    /// Forgets about the current project, e.g. after the connection was lost.
+    pub fn stop(&mut self) {
+        self.project_id = None;
+        self.queue.clear();
+        self.current = None;
+        self.chunk_in_flight = false;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current.is_none() && self.queue.is_empty()
+    }
+
+    pub fn progress(&self) -> Option<SyncProgress> {
+        let project_id = self.project_id?;
+        Some(SyncProgress {
+            project_id,
+            remaining: self.queue.len() + self.current.iter().count(),
+            done_bytes: self.done_bytes,
+            total_bytes: self.total_bytes,
+        })
+    }
+
+    /// The next chunk request to send, if one is due.
+    pub fn next_request(&mut self) -> io::Result<Option<Command>> {
+        let project_id = match self.project_id {
+            Some(id) if !self.chunk_in_flight => id,
+            _ => return Ok(None),
+        };
+        if self.current.is_none() {
+            let info = match self.queue.pop_front() {
+                Some(info) => info,
+                None => return Ok(None),
+            };
+            let download = self.resume(info)?;
+            self.done_bytes += download.received;
+            self.current = Some(download);
+        }
+        let download = self.current.as_ref().unwrap();
+        self.chunk_in_flight = true;
+        Ok(Some(Command::FetchAssetChunk {
+            project_id,
+            hash: download.info.hash,
+            offset: download.received,
+        }))
+    }
+
+    fn resume(&self, info: AssetInfo) -> io::Result<Download> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(self.cache.partial_path(&info.hash))?;
+        let mut received = file.metadata()?.len();
+        let mut hasher = Sha3_256::new();
+        if received > info.size {
+            file.set_len(0)?;
+            received = 0;
+        } else {
+            let mut buf = vec![0u8; CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.input(&buf[..n]);
+            }
+        }
+        Ok(Download {
+            info,
+            file,
+            received,
+            hasher,
+        })
+    }
+
+    /// Handles a chunk sent by the server. Chunks of assets we're not waiting for are ignored.
+    pub fn on_chunk(&mut self, hash: AssetHash, offset: u64, data: &[u8]) -> io::Result<()> {
+        let complete = match self.current {
+            Some(ref mut download) if download.info.hash == hash && download.received == offset => {
+                self.chunk_in_flight = false;
+                if data.is_empty() || download.received + data.len() as u64 > download.info.size {
+                    // The server's copy doesn't match the asset list, give up on this one
+                    true
+                } else {
+                    download.file.write_all(data)?;
+                    download.hasher.input(data);
+                    download.received += data.len() as u64;
+                    self.done_bytes += data.len() as u64;
+                    download.received == download.info.size
+                }
+            }
+            _ => return Ok(()),
+        };
+        if complete {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Handles the server failing to send an asset. The asset is skipped.
+    pub fn on_failed(&mut self, hash: AssetHash, error: AssetError) {
+        let failed = self.current.as_ref().is_some_and(|d| d.info.hash == hash);
+        if failed {
+            println!("Couldn't download asset {}: {:?}", hash_hex(&hash), error);
+            let download = self.current.take().unwrap();
+            self.done_bytes += download.info.size - download.received;
+            self.chunk_in_flight = false;
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let download = self.current.take().unwrap();
+        let hash = download.info.hash;
+        let partial = self.cache.partial_path(&hash);
+        if download.received != download.info.size || download.hasher.result().as_slice() != &hash[..] {
+            println!("Downloaded asset {} is corrupt, discarding it", hash_hex(&hash));
+            self.done_bytes += download.info.size - download.received;
+            drop(download.file);
+            return fs::remove_file(&partial);
+        }
+        drop(download.file);
+        fs::rename(&partial, self.cache.path(&hash))
+    }
+}
+
+/// A local file being uploaded as an asset of a project.
+///
+/// Driven by the network thread like [AssetSync], one chunk at a time. The server keeps
+/// what it received, so an upload that was interrupted resumes where its copy ends.
+pub struct AssetUpload {
+    project_id: u32,
+    file: File,
+    hash: AssetHash,
+    size: u64,
+    /// Where the next chunk starts, `None` until the server said where to resume
+    offset: Option<u64>,
+    in_flight: bool,
+}
+
+impl AssetUpload {
+    /// Prepares uploading the file at `path`. Reads the whole file to hash it, so this
+    /// better not run on the network thread.
+    pub fn open(project_id: u32, path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = Sha3_256::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.input(&buf[..n]);
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(hasher.result().as_slice());
+        Ok(Self {
+            project_id,
+            file,
+            hash,
+            size,
+            offset: None,
+            in_flight: false,
+        })
+    }
+
+    pub fn project_id(&self) -> u32 {
+        self.project_id
+    }
+
+    pub fn hash(&self) -> AssetHash {
+        self.hash
+    }
+
+    /// The next request to send, if one is due.
+    pub fn next_request(&mut self) -> io::Result<Option<Command>> {
+        if self.in_flight {
+            return Ok(None);
+        }
+        self.in_flight = true;
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => return Ok(Some(Command::BeginUpload {
+                project_id: self.project_id,
+                hash: self.hash,
+                size: self.size,
+            })),
+        };
+        let mut data = vec![0u8; CHUNK_SIZE];
+        self.file.seek(SeekFrom::Start(offset))?;
+        let n = self.file.read(&mut data)?;
+        data.truncate(n);
+        Ok(Some(Command::UploadChunk { hash: self.hash, offset, data }))
+    }
+
+    /// Handles a response about this upload. Returns whether the upload is over, because
+    /// the server has the whole asset or refused it.
+    pub fn on_response(&mut self, resp: &Response) -> bool {
+        match *resp {
+            Response::UploadReady { hash, offset } | Response::UploadProgress { hash, received: offset } if hash == self.hash => {
+                self.offset = Some(offset);
+                self.in_flight = false;
+                false
+            }
+            Response::UploadComplete { hash, .. } | Response::AssetFailed { hash, .. } => hash == self.hash,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "chorus_studio";
+
+/// Per-user directory for data the client keeps between runs, like the asset cache.
+///
+/// Follows the platform's convention: `$XDG_DATA_HOME` (or `~/.local/share`) on Linux,
+/// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows.
+pub fn data_dir() -> Option<PathBuf> {
+    platform_data_dir().map(|d| d.join(APP_DIR))
+}
+
+/// Per-user directory for the client's settings.
+///
+/// `$XDG_CONFIG_HOME` (or `~/.config`) on Linux, the same as [data_dir] elsewhere.
+pub fn config_dir() -> Option<PathBuf> {
+    platform_config_dir().map(|d| d.join(APP_DIR))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|h| PathBuf::from(h).join("Library/Application Support"))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn platform_config_dir() -> Option<PathBuf> {
+    platform_data_dir()
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|d| d.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+}
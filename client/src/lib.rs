@@ -0,0 +1,11 @@
+//! The parts of the client that don't need a window: the connection to the server,
+//! the asset cache and the settings. Shared by the desktop client and `chorus-cli`.
+
+extern crate bincode;
+extern crate proto;
+extern crate sha3;
+
+pub mod assets;
+pub mod dirs;
+pub mod net;
+pub mod settings;
@@ -0,0 +1,297 @@
+//! The client's connection to the server. Survives the server going away: lost
+//! connections are reestablished with exponential backoff, the user is logged in again
+//! and rejoins their project, and commands issued while offline are sent afterwards.
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use bincode;
+use proto::{Command, Response};
+
+/// How long to wait for the server to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(4);
+/// How long [NetClient::receive] waits for a response.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait before reconnecting, doubled after every failed attempt up to [MAX_BACKOFF].
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetState {
+    /// Connecting for the first time
+    Connecting,
+    /// Connected, but not logged in
+    Connected,
+    /// Waiting for the server to accept the login
+    Authenticating,
+    /// Logged in
+    Online,
+    /// The connection is gone, reconnecting is scheduled
+    Disconnected(String),
+    /// Attempting to reconnect, the attempt counts from 1
+    Reconnecting(u32),
+}
+
+pub struct NetClient<'a> {
+    /// "host:port", resolved whenever connecting
+    server: String,
+    stream: Option<TcpStream>,
+    /// Received bytes that don't make up a whole response yet
+    in_buf: Vec<u8>,
+    state: NetState,
+    /// Called whenever the state changes
+    on_state: Box<dyn FnMut(&NetState) + 'a>,
+    /// Whether there ever was a connection, only reconnecting counts attempts
+    was_connected: bool,
+    /// Failed connection attempts since the last login
+    attempts: u32,
+    /// When to try connecting again, while disconnected
+    retry_at: Instant,
+    /// The last login command, sent again after reconnecting
+    login: Option<Command>,
+    /// The joined project, joined again after reconnecting
+    project: Option<u32>,
+    /// Commands issued while offline, sent once logged in again
+    queue: VecDeque<Command>,
+}
+
+impl<'a> NetClient<'a> {
+    pub fn new(server: String, on_state: Box<dyn FnMut(&NetState) + 'a>) -> Self {
+        Self {
+            server,
+            stream: None,
+            in_buf: Vec::new(),
+            state: NetState::Connecting,
+            on_state,
+            was_connected: false,
+            attempts: 0,
+            retry_at: Instant::now(),
+            login: None,
+            project: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// How long until the next attempt to connect, `None` while connected.
+    pub fn retry_in(&self) -> Option<Duration> {
+        match self.stream {
+            Some(_) => None,
+            None => Some(self.retry_at.saturating_duration_since(Instant::now())),
+        }
+    }
+
+    /// Tries to connect, blocking for up to [CONNECT_TIMEOUT]. Logs in again with the
+    /// previous credentials, if there are any.
+    pub fn connect(&mut self) {
+        if self.stream.is_some() {
+            return;
+        }
+        if self.was_connected {
+            let attempt = self.attempts + 1;
+            self.set_state(NetState::Reconnecting(attempt));
+        }
+
+        let addr = self.server.to_socket_addrs().and_then(|mut addrs| {
+            addrs.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown server"))
+        });
+        let stream = addr.and_then(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)).and_then(|s| {
+            s.set_read_timeout(Some(READ_TIMEOUT))?;
+            Ok(s)
+        });
+        match stream {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.in_buf.clear();
+                self.was_connected = true;
+                match self.login.clone() {
+                    Some(login) => {
+                        self.set_state(NetState::Authenticating);
+                        self.write(&login);
+                    }
+                    None => self.set_state(NetState::Connected),
+                }
+            }
+            Err(e) => {
+                self.attempts += 1;
+                self.retry_at = Instant::now() + backoff(self.attempts);
+                // Before the first connection, there's nothing to lose
+                if self.was_connected {
+                    self.set_state(NetState::Disconnected(e.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Switches to another server. Starts over as if the client just started, except
+    /// that a pending login is sent to the new server.
+    pub fn set_server(&mut self, server: String) {
+        if server == self.server {
+            return;
+        }
+        self.server = server;
+        self.stream = None;
+        self.in_buf.clear();
+        self.was_connected = false;
+        self.attempts = 0;
+        self.retry_at = Instant::now();
+        self.project = None;
+        self.queue.clear();
+        self.set_state(NetState::Connecting);
+    }
+
+    /// Sends a command. While the connection is down, the command is queued and sent
+    /// after logging in again, except for edits.
+    pub fn send(&mut self, cmd: Command) {
+        match cmd {
+            Command::Login { .. } => {
+                self.login = Some(cmd.clone());
+                if self.stream.is_some() {
+                    self.set_state(NetState::Authenticating);
+                    self.write(&cmd);
+                }
+                return;
+            }
+            Command::LeaveProject { project_id } if self.project == Some(project_id) => self.project = None,
+            _ => {}
+        }
+        let sent = match self.state {
+            NetState::Connected | NetState::Online => self.write(&cmd),
+            _ => false,
+        };
+        // The project submits its pending edits again after rejoining
+        if !sent && !matches!(cmd, Command::SubmitOp { .. }) {
+            self.queue.push_back(cmd);
+        }
+    }
+
+    /// Sends a command if logged in, otherwise drops it. For requests that are made
+    /// again anyway, like asset downloads. Returns whether the command was sent.
+    pub fn send_now(&mut self, cmd: &Command) -> bool {
+        self.state == NetState::Online && self.write(cmd)
+    }
+
+    /// Waits up to [READ_TIMEOUT] for data from the server. `None` if that didn't
+    /// complete a response or the connection is down.
+    pub fn receive(&mut self) -> Option<Response> {
+        // Responses that arrived together with an earlier one come first
+        if let Some(resp) = self.take_response() {
+            return Some(resp);
+        }
+        let mut chunk = [0u8; 4096];
+        let read = match self.stream {
+            Some(ref mut stream) => stream.read(&mut chunk),
+            None => return None,
+        };
+        match read {
+            Ok(0) => {
+                self.lost("Connection closed by the server".to_owned());
+                None
+            }
+            Ok(n) => {
+                self.in_buf.extend_from_slice(&chunk[..n]);
+                self.take_response()
+            }
+            // Nothing received within the read timeout
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => None,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => None,
+            Err(_) => {
+                self.lost("Connection lost".to_owned());
+                None
+            }
+        }
+    }
+
+    /// Decodes the first response in the receive buffer, if it's complete.
+    fn take_response(&mut self) -> Option<Response> {
+        let (resp, used) = {
+            let mut bytes = self.in_buf.as_slice();
+            let resp = bincode::deserialize_from::<_, Response>(&mut bytes);
+            (resp, self.in_buf.len() - bytes.len())
+        };
+        match resp {
+            Ok(resp) => {
+                self.in_buf.drain(..used);
+                self.handle(&resp);
+                Some(resp)
+            }
+            Err(e) => {
+                match *e {
+                    // Not all of it is here yet
+                    bincode::ErrorKind::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+                    // Can't tell where the next response starts
+                    _ => self.lost("Invalid response from the server".to_owned()),
+                }
+                None
+            }
+        }
+    }
+
+    fn handle(&mut self, resp: &Response) {
+        match *resp {
+            Response::LoginOk => {
+                self.attempts = 0;
+                self.set_state(NetState::Online);
+                if let Some(project_id) = self.project {
+                    if !self.write(&Command::JoinProject { project_id }) {
+                        return;
+                    }
+                }
+                // Whatever can't be sent stays queued for the next connection
+                while let Some(cmd) = self.queue.front().cloned() {
+                    if !self.write(&cmd) {
+                        return;
+                    }
+                    self.queue.pop_front();
+                }
+            }
+            Response::LoginInvalid | Response::LoginThrottled { .. } => {
+                self.login = None;
+                self.set_state(NetState::Connected);
+            }
+            Response::ProjectJoined { project_id, .. } => self.project = Some(project_id),
+            Response::ServerShuttingDown { ref reason, reconnect_after } => {
+                self.stream = None;
+                self.in_buf.clear();
+                // Give the server a head start before reconnecting
+                self.attempts = 0;
+                self.retry_at = Instant::now() + Duration::from_secs(u64::from(reconnect_after.unwrap_or(1)));
+                self.set_state(NetState::Disconnected(format!("Server shut down: {}", reason)));
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes a command to the server. Returns false if the connection is gone.
+    fn write(&mut self, cmd: &Command) -> bool {
+        let res = match self.stream {
+            Some(ref mut stream) => bincode::serialize_into(stream, cmd),
+            None => return false,
+        };
+        if res.is_err() {
+            self.lost("Connection lost".to_owned());
+            return false;
+        }
+        true
+    }
+
+    fn lost(&mut self, reason: String) {
+        self.stream = None;
+        self.in_buf.clear();
+        self.retry_at = Instant::now() + backoff(self.attempts);
+        self.set_state(NetState::Disconnected(reason));
+    }
+
+    fn set_state(&mut self, state: NetState) {
+        if self.state != state {
+            self.state = state;
+            (self.on_state)(&self.state);
+        }
+    }
+}
+
+/// Wait after `attempts` failed attempts to connect.
+fn backoff(attempts: u32) -> Duration {
+    MIN_BACKOFF.checked_mul(1 << attempts.min(16)).map_or(MAX_BACKOFF, |d| d.min(MAX_BACKOFF))
+}
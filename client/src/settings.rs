@@ -0,0 +1,238 @@
+//! Client settings, kept between runs in `settings.conf` in the user's config directory.
+//!
+//! The file has one `key = value` pair per line, `#` starts a comment:
+//!
+//! ```text
+//! server = 127.0.0.1:4450
+//! server = chorus.example.com:4450
+//! last_server = chorus.example.com:4450
+//! last_email = someone@example.com
+//! window = 100 80 1280 720
+//! audio_device = Built-in Output
+//! theme = dark
+//! ```
+//!
+//! `server` can be given any number of times, these are the servers to choose from on
+//! the login screen. Unknown keys and invalid values are ignored with a warning, so an
+//! older client can read the file of a newer one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The server used if the settings don't name any.
+pub const DEFAULT_SERVER: &str = "127.0.0.1:4450";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Classic,
+    Dark,
+}
+
+impl Theme {
+    /// Color the window is cleared with, as RGB from 0 to 1.
+    pub fn background(self) -> (f32, f32, f32) {
+        match self {
+            Theme::Classic => (0.2, 0.4, 0.8),
+            Theme::Dark => (0.12, 0.12, 0.14),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Theme::Classic => "classic",
+            Theme::Dark => "dark",
+        }
+    }
+}
+
+/// Position and size of the main window, in screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Servers to choose from on the login screen, as "host:port"
+    pub servers: Vec<String>,
+    /// The server to connect to, one of `servers`
+    pub server: String,
+    /// Given on the command line, connected to instead of `server` but never saved
+    pub server_override: Option<String>,
+    /// Email address of the last login, filled in on the login screen
+    pub last_email: Option<String>,
+    pub window: Option<WindowGeometry>,
+    /// Name of the audio output device
+    pub audio_device: Option<String>,
+    pub theme: Theme,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            servers: vec![DEFAULT_SERVER.to_owned()],
+            server: DEFAULT_SERVER.to_owned(),
+            server_override: None,
+            last_email: None,
+            window: None,
+            audio_device: None,
+            theme: Theme::Classic,
+        }
+    }
+}
+
+impl Settings {
+    /// Where the settings are kept unless `--config` says otherwise.
+    pub fn default_path() -> Option<PathBuf> {
+        ::dirs::config_dir().map(|d| d.join("settings.conf"))
+    }
+
+    /// Reads the settings at `path`. A missing file gives the defaults.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Self::default();
+        let mut servers = Vec::new();
+        let mut last_server = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(idx) => (line[..idx].trim(), line[idx + 1..].trim()),
+                None => {
+                    println!("Settings line {}: expected `key = value`", n + 1);
+                    continue;
+                }
+            };
+            let valid = match key {
+                "server" => {
+                    servers.push(value.to_owned());
+                    true
+                }
+                "last_server" => {
+                    last_server = Some(value.to_owned());
+                    true
+                }
+                "last_email" => {
+                    settings.last_email = Some(value.to_owned());
+                    true
+                }
+                "window" => {
+                    let numbers: Vec<i32> = value.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                    match numbers[..] {
+                        [x, y, width, height] if width > 0 && height > 0 => {
+                            settings.window = Some(WindowGeometry { x, y, width, height });
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+                "audio_device" => {
+                    settings.audio_device = Some(value.to_owned());
+                    true
+                }
+                "theme" => match value {
+                    "classic" => {
+                        settings.theme = Theme::Classic;
+                        true
+                    }
+                    "dark" => {
+                        settings.theme = Theme::Dark;
+                        true
+                    }
+                    _ => false,
+                },
+                _ => {
+                    println!("Settings line {}: unknown setting `{}`", n + 1, key);
+                    continue;
+                }
+            };
+            if !valid {
+                println!("Settings line {}: invalid value for `{}`", n + 1, key);
+            }
+        }
+
+        if !servers.is_empty() {
+            settings.servers = servers;
+            settings.server = settings.servers[0].clone();
+        }
+        if let Some(server) = last_server {
+            settings.select_server(&server);
+        }
+        settings
+    }
+
+    /// Writes the settings to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written next to the old file first, so a crash can't leave half a file behind
+        let tmp = path.with_extension("conf.tmp");
+        fs::write(&tmp, self.to_text())?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# Chorus Studio client settings\n");
+        for server in self.servers.iter() {
+            text.push_str(&format!("server = {}\n", server));
+        }
+        text.push_str(&format!("last_server = {}\n", self.server));
+        if let Some(ref email) = self.last_email {
+            text.push_str(&format!("last_email = {}\n", email));
+        }
+        if let Some(w) = self.window {
+            text.push_str(&format!("window = {} {} {} {}\n", w.x, w.y, w.width, w.height));
+        }
+        if let Some(ref device) = self.audio_device {
+            text.push_str(&format!("audio_device = {}\n", device));
+        }
+        text.push_str(&format!("theme = {}\n", self.theme.name()));
+        text
+    }
+
+    /// Makes `server` the one to connect to, adding it to the list if it's new. Ends an
+    /// override, the user chose something else.
+    pub fn select_server(&mut self, server: &str) {
+        if !self.servers.iter().any(|s| s == server) {
+            self.servers.push(server.to_owned());
+        }
+        self.server = server.to_owned();
+        self.server_override = None;
+    }
+
+    /// The server to connect to, the override if there is one.
+    pub fn current_server(&self) -> &str {
+        self.server_override.as_ref().unwrap_or(&self.server)
+    }
+
+    /// The servers to choose from on the login screen, the override among them.
+    pub fn server_choices(&self) -> Vec<String> {
+        let mut servers = self.servers.clone();
+        if let Some(ref server) = self.server_override {
+            if !servers.contains(server) {
+                servers.push(server.clone());
+            }
+        }
+        servers
+    }
+
+    /// Index of the current server in [Settings::server_choices].
+    pub fn server_index(&self) -> usize {
+        let current = self.current_server();
+        self.server_choices().iter().position(|s| s == current).unwrap_or(0)
+    }
+}
@@ -0,0 +1,61 @@
+//! Responses split across reads or packed into one must come out whole, like they were
+//! sent.
+
+extern crate bincode;
+extern crate client;
+extern crate proto;
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::net::TcpListener;
+use std::rc::Rc;
+
+use client::net::{NetClient, NetState};
+use proto::Response;
+
+fn message(text: &str) -> Vec<u8> {
+    bincode::serialize(&Response::SystemMessage(text.to_owned())).unwrap()
+}
+
+fn text(resp: Option<Response>) -> String {
+    match resp {
+        Some(Response::SystemMessage(text)) => text,
+        other => panic!("Expected a system message, got {:?}", other),
+    }
+}
+
+#[test]
+fn response_split_across_reads() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let states = Rc::new(RefCell::new(Vec::new()));
+    let seen = states.clone();
+    let mut net = NetClient::new(
+        listener.local_addr().unwrap().to_string(),
+        Box::new(move |state: &NetState| seen.borrow_mut().push(state.clone())),
+    );
+    net.connect();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let bytes = message("hello");
+    server.write_all(&bytes[..3]).unwrap();
+    // Nothing more arrives within the read timeout
+    assert!(net.receive().is_none());
+    server.write_all(&bytes[3..]).unwrap();
+    assert_eq!(text(net.receive()), "hello");
+
+    assert_eq!(*states.borrow(), vec![NetState::Connected]);
+}
+
+#[test]
+fn responses_in_one_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut net = NetClient::new(listener.local_addr().unwrap().to_string(), Box::new(|_: &NetState| {}));
+    net.connect();
+    let (mut server, _) = listener.accept().unwrap();
+
+    let mut bytes = message("first");
+    bytes.extend(message("second"));
+    server.write_all(&bytes).unwrap();
+    assert_eq!(text(net.receive()), "first");
+    assert_eq!(text(net.receive()), "second");
+}
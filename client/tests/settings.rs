@@ -0,0 +1,30 @@
+//! A server given with `--server` is used for one run and never saved.
+
+extern crate client;
+
+use client::settings::Settings;
+
+const SAVED: &str = "server = a:4450\nserver = b:4450\nlast_server = b:4450\n";
+
+#[test]
+fn override_is_not_saved() {
+    let mut settings = Settings::parse(SAVED);
+    settings.server_override = Some("c:4450".to_owned());
+    assert_eq!(settings.current_server(), "c:4450");
+    assert_eq!(settings.server_choices(), vec!["a:4450", "b:4450", "c:4450"]);
+    assert_eq!(settings.server_index(), 2);
+
+    let saved = Settings::parse(&settings.to_text());
+    assert_eq!(saved.servers, vec!["a:4450", "b:4450"]);
+    assert_eq!(saved.current_server(), "b:4450");
+}
+
+#[test]
+fn choosing_a_server_ends_the_override() {
+    let mut settings = Settings::parse(SAVED);
+    settings.server_override = Some("c:4450".to_owned());
+    settings.select_server("a:4450");
+    assert_eq!(settings.current_server(), "a:4450");
+    assert_eq!(settings.server_choices(), vec!["a:4450", "b:4450"]);
+    assert_eq!(Settings::parse(&settings.to_text()).server, "a:4450");
+}
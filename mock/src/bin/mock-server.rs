@@ -0,0 +1,95 @@
+//! Serves fixtures for the desktop client, changed by a script on stdin.
+//!
+//! `mock-server <fixtures.json> [--listen 127.0.0.1:4450]`, then one command per line:
+//!
+//! ```text
+//! presence <user name> active|away|offline|gone|<project name>
+//! message <text>
+//! latency <milliseconds>
+//! disconnect
+//! refuse
+//! accept
+//! shutdown <seconds until back> <reason>
+//! received
+//! ```
+
+extern crate mock_server;
+extern crate proto;
+extern crate serde_json;
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+use mock_server::{Fixtures, MockServer};
+use proto::UserActivity;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let path = match args.get(1) {
+        Some(path) if !path.starts_with("--") => path,
+        _ => {
+            println!("Usage: mock-server <fixtures.json> [--listen 127.0.0.1:4450]");
+            std::process::exit(2);
+        }
+    };
+    let listen = args
+        .iter()
+        .skip_while(|a| *a != "--listen")
+        .nth(1)
+        .map_or("127.0.0.1:4450", |a| a.as_str());
+
+    let text = fs::read_to_string(path).expect("Fixture file");
+    let fixtures: Fixtures = serde_json::from_str(&text).expect("Fixtures");
+    let server = MockServer::bind(listen, fixtures).expect("Listen");
+    println!("Mock server listening on {}", server.addr());
+
+    for line in io::stdin().lock().lines() {
+        let line = line.expect("Script");
+        let line = line.trim();
+        let (cmd, rest) = match line.find(' ') {
+            Some(idx) => (&line[..idx], line[idx + 1..].trim()),
+            None => (line, ""),
+        };
+        match cmd {
+            "" => {}
+            "presence" => {
+                let (user_name, activity) = match rest.find(' ') {
+                    Some(idx) => (&rest[..idx], rest[idx + 1..].trim()),
+                    None => (rest, ""),
+                };
+                let activity = match activity {
+                    "active" => Some(UserActivity::Active),
+                    "away" => Some(UserActivity::Away),
+                    "offline" => Some(UserActivity::Offline),
+                    "gone" => None,
+                    project => Some(UserActivity::InProject(project.to_owned())),
+                };
+                if !server.set_presence(user_name, activity) {
+                    println!("There's no user {}", user_name);
+                }
+            }
+            "message" => server.message(rest),
+            "latency" => match rest.parse() {
+                Ok(ms) => server.set_latency(Duration::from_millis(ms)),
+                Err(_) => println!("latency expects milliseconds"),
+            },
+            "disconnect" => server.disconnect_all(),
+            "refuse" => server.set_accepting(false),
+            "accept" => server.set_accepting(true),
+            "shutdown" => {
+                let (secs, reason) = match rest.find(' ') {
+                    Some(idx) => (&rest[..idx], rest[idx + 1..].trim()),
+                    None => (rest, "Maintenance"),
+                };
+                server.shut_down(reason, secs.parse().ok());
+            }
+            "received" => {
+                for cmd in server.received() {
+                    println!("{:?}", cmd);
+                }
+            }
+            _ => println!("Unknown command {}", cmd),
+        }
+    }
+}
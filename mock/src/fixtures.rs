@@ -0,0 +1,36 @@
+use proto::project::Project;
+use proto::{Activity, Profile, ProjectInfo, UserActivity};
+
+/// Everything the mock server knows about. The `mock-server` binary reads it from JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Fixtures {
+    pub users: Vec<MockUser>,
+    pub projects: Vec<MockProject>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MockUser {
+    pub email: String,
+    /// In plain text, hashed like the client does before comparing
+    pub password: String,
+    pub user_name: String,
+    /// Shown in the user list even if nobody is logged in as this user, for users that
+    /// are supposed to be online already
+    #[serde(default)]
+    pub presence: Option<UserActivity>,
+    /// A profile with nothing but the user name if there's none
+    #[serde(default)]
+    pub profile: Option<Profile>,
+}
+
+/// Every user is a member of every project.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MockProject {
+    pub info: ProjectInfo,
+    #[serde(default)]
+    pub state: Project,
+    /// Newest first, like the server sends it
+    #[serde(default)]
+    pub activity: Vec<Activity>,
+}
@@ -0,0 +1,208 @@
+//! A stand-in for the real server, for working on the client without a database.
+//!
+//! [MockServer] runs in-process on its own threads and answers commands from
+//! [Fixtures]: a few accounts, their profiles and some projects. Whoever drives it can
+//! change the world from the outside, e.g. make users come and go, slow the server
+//! down or drop every connection, and check what the client sent. The `mock-server`
+//! binary does the same from a fixture file and a script on stdin, for pointing the
+//! desktop client at with `--server`.
+
+extern crate bincode;
+extern crate proto;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate sha3;
+
+mod fixtures;
+mod world;
+
+pub use fixtures::{Fixtures, MockProject, MockUser};
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use proto::{Command, Response, UserActivity};
+use world::{Replies, World};
+
+struct Shared {
+    world: Mutex<World>,
+    /// Client id -> its connection
+    clients: Mutex<HashMap<usize, TcpStream>>,
+    /// Every command received so far, in order
+    received: Mutex<Vec<Command>>,
+    /// How long every command waits before it's handled
+    latency: Mutex<Duration>,
+    /// Whether new connections are accepted or closed right away
+    accepting: AtomicBool,
+    running: AtomicBool,
+}
+
+impl Shared {
+    fn deliver(&self, replies: Replies) {
+        let clients = self.clients.lock().unwrap();
+        for (to, resp) in replies {
+            for client in to.iter().filter_map(|id| clients.get(id)) {
+                // A failed write means the client is gone, its thread notices that
+                let _ = bincode::serialize_into(client, &resp);
+            }
+        }
+    }
+
+    fn serve(&self, client_id: usize, stream: TcpStream) {
+        while let Ok(cmd) = bincode::deserialize_from::<_, Command>(&stream) {
+            self.received.lock().unwrap().push(cmd.clone());
+            if let Command::Disconnect = cmd {
+                break;
+            }
+            let latency = *self.latency.lock().unwrap();
+            if latency > Duration::from_secs(0) {
+                thread::sleep(latency);
+            }
+            let replies = self.world.lock().unwrap().handle(client_id, cmd);
+            self.deliver(replies);
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+        self.clients.lock().unwrap().remove(&client_id);
+        let replies = self.world.lock().unwrap().disconnect(client_id);
+        self.deliver(replies);
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    acceptor: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts serving `fixtures` on a free port of the loopback interface.
+    pub fn start(fixtures: Fixtures) -> io::Result<Self> {
+        Self::bind("127.0.0.1:0", fixtures)
+    }
+
+    pub fn bind(addr: &str, fixtures: Fixtures) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            world: Mutex::new(World::new(fixtures)),
+            clients: Mutex::new(HashMap::new()),
+            received: Mutex::new(Vec::new()),
+            latency: Mutex::new(Duration::from_secs(0)),
+            accepting: AtomicBool::new(true),
+            running: AtomicBool::new(true),
+        });
+        let acceptor = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let mut next_id = 0;
+                for stream in listener.incoming() {
+                    if !shared.running.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    // Dropped right away, so the client sees the connection fail
+                    if !shared.accepting.load(Ordering::SeqCst) {
+                        continue;
+                    }
+                    let client_id = next_id;
+                    next_id += 1;
+                    let writer = match stream.try_clone() {
+                        Ok(writer) => writer,
+                        Err(_) => continue,
+                    };
+                    shared.clients.lock().unwrap().insert(client_id, writer);
+                    shared.world.lock().unwrap().connect(client_id);
+                    let shared = shared.clone();
+                    thread::spawn(move || shared.serve(client_id, stream));
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Where the server listens, to connect the client to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Every command received so far, in order.
+    pub fn received(&self) -> Vec<Command> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Changes how a user shows up in everyone's user list, `None` removes them from it.
+    /// Returns `false` if there's no such user in the fixtures.
+    pub fn set_presence(&self, user_name: &str, activity: Option<UserActivity>) -> bool {
+        let replies = self.shared.world.lock().unwrap().set_presence(user_name, activity);
+        match replies {
+            Some(replies) => {
+                self.shared.deliver(replies);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sends every connected client a message. There's no chat yet, so this is what
+    /// the server's operator would send.
+    pub fn message(&self, text: &str) {
+        self.broadcast(Response::SystemMessage(text.to_owned()));
+    }
+
+    /// Sends every connected client `resp`, whether it makes sense or not.
+    pub fn broadcast(&self, resp: Response) {
+        let everyone = self.shared.world.lock().unwrap().everyone();
+        self.shared.deliver(vec![(everyone, resp)]);
+    }
+
+    /// Delays every command by `latency` before it's answered.
+    pub fn set_latency(&self, latency: Duration) {
+        *self.shared.latency.lock().unwrap() = latency;
+    }
+
+    /// Whether new connections are accepted. Refused ones are closed right away.
+    pub fn set_accepting(&self, accepting: bool) {
+        self.shared.accepting.store(accepting, Ordering::SeqCst);
+    }
+
+    /// Drops every connection without a word, like a crashed server or a dead network.
+    pub fn disconnect_all(&self) {
+        for client in self.shared.clients.lock().unwrap().values() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Announces a shutdown like the real server, then drops every connection.
+    pub fn shut_down(&self, reason: &str, reconnect_after: Option<u32>) {
+        self.broadcast(Response::ServerShuttingDown {
+            reason: reason.to_owned(),
+            reconnect_after,
+        });
+        self.disconnect_all();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        self.disconnect_all();
+        // Wakes up the acceptor, which then sees it should stop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
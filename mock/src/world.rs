@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use proto::asset::StorageUsage;
+use proto::project::{LoggedOp, OpError, Operation};
+use proto::{Command, Profile, Response, User, UserActivity, ACTIVITY_PAGE_SIZE};
+use sha3::{Digest, Sha3_256};
+
+use fixtures::{Fixtures, MockUser};
+
+/// Responses to send and who gets them.
+pub type Replies = Vec<(Vec<usize>, Response)>;
+
+/// A project while it's being served.
+struct Session {
+    seq: u64,
+    ops: Vec<LoggedOp>,
+    next_site: u32,
+    /// Client -> site
+    sites: HashMap<usize, u32>,
+}
+
+/// The mock server's state. Answers commands the same way every time, so a client
+/// driven through the same inputs always sees the same responses.
+pub struct World {
+    fixtures: Fixtures,
+    /// Every connected client, logged in or not
+    clients: Vec<usize>,
+    /// Client -> index of its user in the fixtures
+    logins: HashMap<usize, usize>,
+    /// Presence of every user, `None` for users who aren't in the user list
+    presence: Vec<Option<UserActivity>>,
+    /// Index of a project in the fixtures -> its session
+    sessions: HashMap<usize, Session>,
+}
+
+impl World {
+    pub fn new(fixtures: Fixtures) -> Self {
+        Self {
+            presence: fixtures.users.iter().map(|u| u.presence.clone()).collect(),
+            fixtures,
+            clients: Vec::new(),
+            logins: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn connect(&mut self, client_id: usize) {
+        self.clients.push(client_id);
+    }
+
+    pub fn disconnect(&mut self, client_id: usize) -> Replies {
+        self.clients.retain(|&c| c != client_id);
+        for session in self.sessions.values_mut() {
+            session.sites.remove(&client_id);
+        }
+        match self.logins.remove(&client_id) {
+            Some(user) => {
+                if !self.logins.values().any(|&u| u == user) {
+                    self.presence[user] = self.fixtures.users[user].presence.clone();
+                }
+                self.user_list_changed()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Every connected client.
+    pub fn everyone(&self) -> Vec<usize> {
+        self.clients.clone()
+    }
+
+    /// Changes where a user shows up in the user list, `None` removes them from it.
+    /// `None` if there's no such user.
+    pub fn set_presence(&mut self, user_name: &str, activity: Option<UserActivity>) -> Option<Replies> {
+        let user = self.fixtures.users.iter().position(|u| u.user_name == user_name)?;
+        self.presence[user] = activity;
+        Some(self.user_list_changed())
+    }
+
+    fn user_list(&self) -> Vec<User> {
+        self.fixtures
+            .users
+            .iter()
+            .zip(self.presence.iter())
+            .filter_map(|(user, presence)| {
+                presence.clone().map(|activity| User {
+                    user_name: user.user_name.clone(),
+                    activity,
+                })
+            })
+            .collect()
+    }
+
+    fn user_list_changed(&self) -> Replies {
+        vec![(self.everyone(), Response::UserList(self.user_list()))]
+    }
+
+    fn profile(user: &MockUser) -> Profile {
+        user.profile.clone().unwrap_or_else(|| Profile {
+            user_name: user.user_name.clone(),
+            real_name: None,
+            birth_date: None,
+            register_date: None,
+            bio: None,
+            avatar: None,
+            visibility: Some(Default::default()),
+        })
+    }
+
+    /// Index of the project `client_id` joined, if it joined `project_id`.
+    fn joined(&self, client_id: usize, project_id: u32) -> Option<usize> {
+        let project = self.fixtures.projects.iter().position(|p| p.info.id == project_id)?;
+        match self.sessions.get(&project) {
+            Some(session) if session.sites.contains_key(&client_id) => Some(project),
+            _ => None,
+        }
+    }
+
+    fn login(&mut self, client_id: usize, email: &str, password: &[u8]) -> Replies {
+        let found = self.fixtures.users.iter().position(|u| {
+            u.email == email && Sha3_256::digest(u.password.as_bytes()).as_slice() == password
+        });
+        let user = match found {
+            Some(user) => user,
+            None => return vec![(vec![client_id], Response::LoginInvalid)],
+        };
+        self.logins.insert(client_id, user);
+        self.presence[user] = Some(UserActivity::Active);
+        let mut replies = vec![(vec![client_id], Response::LoginOk)];
+        replies.extend(self.user_list_changed());
+        replies
+    }
+
+    pub fn handle(&mut self, client_id: usize, cmd: Command) -> Replies {
+        use proto::Command::*;
+        let caller = vec![client_id];
+        let cmd = match cmd {
+            Login { email, password } => return self.login(client_id, &email, &password),
+            cmd => cmd,
+        };
+        let user = match self.logins.get(&client_id) {
+            Some(&user) => user,
+            None => return vec![(caller, Response::NotLoggedIn)],
+        };
+        let resp = match cmd {
+            Login { .. } => unreachable!("Handled above"),
+            ListUsers => Response::UserList(self.user_list()),
+            ListProjects => Response::Projects(self.fixtures.projects.iter().map(|p| p.info.clone()).collect()),
+            GetUsage => Response::Usage(StorageUsage {
+                used: 0,
+                limit: None,
+                projects: Vec::new(),
+            }),
+            GetProfile { user_name } => match self.fixtures.users.iter().position(|u| u.user_name == user_name) {
+                Some(other) if other == user => Response::Profile(Self::profile(&self.fixtures.users[other])),
+                Some(other) => Response::Profile(Self::profile(&self.fixtures.users[other]).public()),
+                None => Response::NoSuchUser { user_name },
+            },
+            // There are no assets to have an avatar from
+            FetchAvatarChunk { user_name, .. } => if self.fixtures.users.iter().any(|u| u.user_name == user_name) {
+                Response::NoAvatar { user_name }
+            } else {
+                Response::NoSuchUser { user_name }
+            },
+            UpdateProfile(update) => {
+                if let Err(e) = update.check() {
+                    return vec![(caller, Response::ProfileRejected(e))];
+                }
+                // There are no assets to pick an avatar from
+                if update.avatar.is_some() {
+                    return vec![(caller, Response::ProfileRejected(proto::ProfileError::NoSuchAsset))];
+                }
+                let mut profile = Self::profile(&self.fixtures.users[user]);
+                profile.real_name = update.real_name;
+                profile.birth_date = update.birth_date;
+                profile.bio = update.bio;
+                profile.visibility = Some(update.visibility);
+                self.fixtures.users[user].profile = Some(profile.clone());
+                Response::Profile(profile)
+            }
+            JoinProject { project_id } => {
+                let project = match self.fixtures.projects.iter().position(|p| p.info.id == project_id) {
+                    Some(project) => project,
+                    None => return vec![(caller, Response::NoSuchProject)],
+                };
+                let session = self.sessions.entry(project).or_insert_with(|| Session {
+                    seq: 0,
+                    ops: Vec::new(),
+                    next_site: 1,
+                    sites: HashMap::new(),
+                });
+                let site = session.next_site;
+                session.next_site += 1;
+                session.sites.insert(client_id, site);
+                Response::ProjectJoined {
+                    project_id,
+                    site,
+                    seq: session.seq,
+                    state: self.fixtures.projects[project].state.clone(),
+                    locks: Vec::new(),
+                }
+            }
+            LeaveProject { project_id } => {
+                if let Some(project) = self.joined(client_id, project_id) {
+                    self.sessions.get_mut(&project).unwrap().sites.remove(&client_id);
+                }
+                return Vec::new();
+            }
+            SubmitOp { project_id, local_seq, base_seq, op } => {
+                let project = match self.joined(client_id, project_id) {
+                    Some(project) => project,
+                    None => return vec![(caller, Response::NotInProject)],
+                };
+                let session = self.sessions.get_mut(&project).unwrap();
+                let res = if base_seq > session.seq {
+                    Err(OpError::InvalidBase)
+                } else if let Operation::Restore { .. } = op {
+                    Err(OpError::NotAllowed)
+                } else {
+                    self.fixtures.projects[project].state.apply(&op)
+                };
+                if let Err(error) = res {
+                    return vec![(caller, Response::OpRejected { project_id, local_seq, error })];
+                }
+                session.seq += 1;
+                let logged = LoggedOp {
+                    seq: session.seq,
+                    site: session.sites[&client_id],
+                    local_seq: Some(local_seq),
+                    op,
+                };
+                session.ops.push(logged.clone());
+                let members = session.sites.keys().cloned().collect();
+                return vec![(members, Response::OpApplied {
+                    project_id,
+                    seq: logged.seq,
+                    site: logged.site,
+                    local_seq: logged.local_seq,
+                    op: logged.op,
+                })];
+            }
+            FetchOps { project_id, since_seq } => match self.joined(client_id, project_id) {
+                Some(project) => {
+                    let ops = self.sessions[&project].ops.iter().filter(|o| o.seq > since_seq).cloned().collect();
+                    Response::Ops { project_id, ops }
+                }
+                None => Response::NotInProject,
+            },
+            ListAssets { project_id } => match self.joined(client_id, project_id) {
+                Some(_) => Response::Assets { project_id, assets: Vec::new() },
+                None => Response::NotInProject,
+            },
+            GetProjectActivity { project_id, before } => match self.joined(client_id, project_id) {
+                Some(project) => {
+                    let mut entries: Vec<_> = self.fixtures.projects[project]
+                        .activity
+                        .iter()
+                        .filter(|a| before.is_none_or(|before| a.id < before))
+                        .cloned()
+                        .collect();
+                    let more = entries.len() > ACTIVITY_PAGE_SIZE;
+                    entries.truncate(ACTIVITY_PAGE_SIZE);
+                    Response::ProjectActivity { project_id, entries, more }
+                }
+                None => Response::NotInProject,
+            },
+            SendChat { project_id, text } => match self.joined(client_id, project_id) {
+                Some(project) => {
+                    let members = self.sessions[&project].sites.keys().cloned().collect();
+                    let user_name = self.fixtures.users[user].user_name.clone();
+                    let text = text.chars().take(proto::MAX_CHAT_LEN).collect();
+                    return vec![(members, Response::ChatMessage { project_id, user_name, text })];
+                }
+                None => Response::NotInProject,
+            },
+            SetPresence { away } => {
+                let activity = if away { UserActivity::Away } else { UserActivity::Active };
+                self.presence[user] = Some(activity);
+                return self.user_list_changed();
+            }
+            Disconnect | ReleaseLock { .. } => return Vec::new(),
+            AcquireLock { .. } | Undo { .. } | Redo { .. } | CreateSnapshot { .. } | ListSnapshots { .. }
+            | DiffSnapshot { .. } | PreviewSnapshot { .. } | RestoreSnapshot { .. } | BeginUpload { .. }
+            | UploadChunk { .. } | FetchAssetChunk { .. } | ReleaseAsset { .. } => {
+                println!("The mock server doesn't handle {:?}", cmd);
+                Response::InternalError
+            }
+        };
+        vec![(caller, resp)]
+    }
+}
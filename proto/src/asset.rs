@@ -0,0 +1,68 @@
+use std::fmt::Write;
+
+/// SHA3-256 hash of an asset's content, which is also its id.
+pub type AssetHash = [u8; 32];
+
+/// Maximum number of bytes in a single upload or download chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Lower case hex representation of a hash, as used for file names.
+pub fn hash_hex(hash: &AssetHash) -> String {
+    let mut buf = String::with_capacity(hash.len() * 2);
+    for b in hash.iter() {
+        let _ = write!(&mut buf, "{:02x}", b);
+    }
+    buf
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetInfo {
+    pub hash: AssetHash,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetError {
+    NoSuchAsset,
+    /// A chunk was sent for an upload that wasn't started with [::Command::BeginUpload].
+    NoUpload,
+    /// A chunk doesn't continue where the previous one ended.
+    UnexpectedOffset { expected: u64 },
+    /// More data was sent than announced.
+    TooLarge,
+    /// The uploaded data doesn't hash to the announced hash. The upload was discarded.
+    HashMismatch,
+    /// Another user, or the same user for another project, is uploading the same asset.
+    UploadBusy,
+    /// The upload in progress was started with a different size.
+    SizeMismatch { size: u64 },
+    /// The server failed to read or write the asset.
+    Storage,
+    /// Storing the asset would exceed a storage quota. `used` doesn't include the asset.
+    QuotaExceeded { scope: QuotaScope, used: u64, limit: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaScope {
+    /// The bytes stored by the uploading user, over all projects
+    User,
+    /// The bytes stored in the project, by all users
+    Project,
+}
+
+/// Storage used by a user, as answered to [::Command::GetUsage].
+/// A limit of `None` means unlimited.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub used: u64,
+    pub limit: Option<u64>,
+    /// The projects the user is a member of
+    pub projects: Vec<ProjectUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProjectUsage {
+    pub project_id: u32,
+    pub used: u64,
+    pub limit: Option<u64>,
+}
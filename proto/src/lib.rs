@@ -2,18 +2,355 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
-#[derive(Serialize, Deserialize)]
+pub mod asset;
+pub mod project;
+pub mod sync;
+
+use asset::{AssetError, AssetHash, AssetInfo, StorageUsage};
+use project::{DiffSummary, LoggedOp, ObjectId, OpError, Operation, Project};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Command {
     ListUsers,
     Login { email: String, password: Vec<u8> },
     Disconnect,
+    JoinProject { project_id: u32 },
+    LeaveProject { project_id: u32 },
+    /// Submits an edit. `local_seq` is chosen by the client and echoed back in the
+    /// server's answer, `base_seq` is the last server sequence number the client had
+    /// applied when making the edit.
+    SubmitOp { project_id: u32, local_seq: u32, base_seq: u64, op: Operation },
+    /// Requests all logged operations after `since_seq`.
+    FetchOps { project_id: u32, since_seq: u64 },
+    /// Locks a track, clip, note or device for exclusive editing. Acquiring a lock one
+    /// already holds renews it. Locks expire if they aren't renewed in time.
+    AcquireLock { project_id: u32, object: ObjectId },
+    ReleaseLock { project_id: u32, object: ObjectId },
+    /// Reverts the caller's most recent edit that hasn't been undone yet. Edits of other
+    /// users are never reverted.
+    Undo { project_id: u32 },
+    Redo { project_id: u32 },
+    CreateSnapshot { project_id: u32, name: String },
+    ListSnapshots { project_id: u32 },
+    /// Asks what changed between a snapshot and the current state of the project.
+    DiffSnapshot { project_id: u32, snapshot_id: i64 },
+    PreviewSnapshot { project_id: u32, snapshot_id: i64 },
+    /// Makes the snapshot's state the new current state. This is an edit like any
+    /// other, so it can be undone and doesn't remove any history.
+    RestoreSnapshot { project_id: u32, snapshot_id: i64 },
+    /// Starts or resumes uploading an asset to a project. If the server already has the
+    /// asset, it's added to the project right away.
+    BeginUpload { project_id: u32, hash: AssetHash, size: u64 },
+    UploadChunk { hash: AssetHash, offset: u64, data: Vec<u8> },
+    /// Asks for at most [asset::CHUNK_SIZE] bytes of an asset, starting at `offset`.
+    /// Assets are downloaded chunk by chunk, so a download can be resumed at any offset.
+    FetchAssetChunk { project_id: u32, hash: AssetHash, offset: u64 },
+    /// Lists the assets referenced by a project.
+    ListAssets { project_id: u32 },
+    /// Drops a project's reference to an asset.
+    ReleaseAsset { project_id: u32, hash: AssetHash },
+    /// Asks how much storage the user and their projects use.
+    GetUsage,
+    /// Asks for a page of the project's activity log, newest first. `before` is the id
+    /// of the oldest entry of the previous page, `None` for the first page. At most
+    /// [ACTIVITY_PAGE_SIZE] entries are returned.
+    GetProjectActivity { project_id: u32, before: Option<i64> },
+    /// Asks for a user's profile. Fields the user doesn't share are left out, unless
+    /// it's the caller's own profile.
+    GetProfile { user_name: String },
+    /// Replaces the caller's profile. Answered with the updated [Profile].
+    UpdateProfile(ProfileUpdate),
+    /// Asks for a chunk of a user's avatar, like [Command::FetchAssetChunk]. Other
+    /// users only get it if the profile shares the avatar.
+    FetchAvatarChunk { user_name: String, offset: u64 },
+    /// Asks for the projects the caller is a member of.
+    ListProjects,
+    /// Sends a chat message to everyone in the project. Messages longer than
+    /// [MAX_CHAT_LEN] characters are cut off.
+    SendChat { project_id: u32, text: String },
+    /// Tells the other users whether the caller is at the keyboard. The user list shows
+    /// a user as [UserActivity::Away] while all of their clients are away.
+    SetPresence { away: bool },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Entries per page of [Command::GetProjectActivity].
+pub const ACTIVITY_PAGE_SIZE: usize = 50;
+
+/// The longest chat message, in characters.
+pub const MAX_CHAT_LEN: usize = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Response {
     UserList(Vec<User>),
     LoginOk,
     LoginInvalid,
+    /// Too many failed logins from the client's address, try again later.
+    LoginThrottled { retry_after_secs: u64 },
+    /// A message from the server's operator, meant to be shown to the user.
+    SystemMessage(String),
+    /// Sent right before the server closes all connections. `reconnect_after` is the
+    /// number of seconds after which the server is expected back, if it's restarting.
+    ServerShuttingDown { reason: String, reconnect_after: Option<u32> },
+    /// The full project state at sequence number `seq`. Objects created by the joining
+    /// client must use `site` in their [project::ObjectId]s.
+    ProjectJoined { project_id: u32, site: u32, seq: u64, state: Project, locks: Vec<Lock> },
+    NoSuchProject,
+    NotLoggedIn,
+    NotInProject,
+    /// The server failed to handle the request, e.g. because of a database error.
+    InternalError,
+    /// Broadcast to every member of the project whenever the server accepted an edit.
+    /// `local_seq` is `None` for edits the server made on behalf of `site`, like undo.
+    OpApplied { project_id: u32, seq: u64, site: u32, local_seq: Option<u32>, op: Operation },
+    /// Sent only to the author of a rejected edit.
+    OpRejected { project_id: u32, local_seq: u32, error: OpError },
+    Ops { project_id: u32, ops: Vec<LoggedOp> },
+    /// Broadcast to every member of the project whenever a lock is acquired, released
+    /// or expires. `holder` is `None` if the object isn't locked anymore.
+    LockChanged { project_id: u32, object: ObjectId, holder: Option<String> },
+    LockDenied { project_id: u32, object: ObjectId, reason: LockError },
+    NothingToUndo { project_id: u32 },
+    NothingToRedo { project_id: u32 },
+    /// The next undo or redo step can't be applied right now, e.g. because of a lock.
+    UndoRejected { project_id: u32, error: OpError },
+    /// Steps of the caller's history that were dropped while undoing or redoing, sent
+    /// before the outcome of the step that was applied, if any.
+    HistorySkipped { project_id: u32, steps: Vec<SkippedStep> },
+    SnapshotCreated { project_id: u32, snapshot: SnapshotInfo },
+    Snapshots { project_id: u32, snapshots: Vec<SnapshotInfo> },
+    SnapshotDiff { project_id: u32, snapshot_id: i64, summary: DiffSummary },
+    SnapshotPreview { project_id: u32, snapshot_id: i64, state: Project },
+    NoSuchSnapshot { project_id: u32, snapshot_id: i64 },
+    /// Restoring was refused, e.g. because another user holds a lock.
+    RestoreRejected { project_id: u32, error: OpError },
+    /// The server expects the upload's data from `offset` on.
+    UploadReady { hash: AssetHash, offset: u64 },
+    UploadProgress { hash: AssetHash, received: u64 },
+    /// The asset is stored, verified and referenced by the project.
+    UploadComplete { project_id: u32, hash: AssetHash },
+    AssetChunk { hash: AssetHash, offset: u64, size: u64, data: Vec<u8> },
+    Assets { project_id: u32, assets: Vec<AssetInfo> },
+    AssetFailed { hash: AssetHash, error: AssetError },
+    Usage(StorageUsage),
+    /// A page of the activity log, newest first. `more` tells whether there are older entries.
+    ProjectActivity { project_id: u32, entries: Vec<Activity>, more: bool },
+    Profile(Profile),
+    NoSuchUser { user_name: String },
+    /// The user has no avatar, or doesn't share it.
+    NoAvatar { user_name: String },
+    ProfileRejected(ProfileError),
+    Projects(Vec<ProjectInfo>),
+    /// Broadcast to every member of the project, including the sender.
+    ChatMessage { project_id: u32, user_name: String, text: String },
+}
+
+/// An undo or redo step that was dropped instead of applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SkippedStep {
+    /// What the step would have done, e.g. `Removed track "Bass"`
+    pub summary: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// It would revert what this user did since
+    Clobbered { user_name: String },
+    /// It doesn't apply to the current state anymore
+    Conflict(OpError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Lock {
+    pub object: ObjectId,
+    /// User name of the lock holder
+    pub holder: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LockError {
+    NoSuchObject,
+    HeldBy(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProjectInfo {
+    pub id: u32,
+    pub title: String,
+    pub description: String,
+    /// UTC, formatted like "2019-03-01 18:30:00"
+    pub creation_date: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub id: i64,
+    /// Sequence number of the last operation contained in the snapshot
+    pub seq: u64,
+    /// `None` for snapshots taken automatically
+    pub name: Option<String>,
+    pub creation_date: String,
+}
+
+/// An entry of a project's activity log, which keeps every accepted edit, who joined
+/// or left the project and changes of its membership.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Activity {
+    pub id: i64,
+    pub user_name: String,
+    /// UTC, formatted like "2019-03-01 18:30:00"
+    pub date: String,
+    pub kind: ActivityKind,
+    /// E.g. `Removed track "Bass"`
+    pub summary: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityKind {
+    /// An edit, with the sequence number it was applied at
+    Edit { seq: u64 },
+    /// An undo or redo, which the server applied as a new edit
+    Undo { seq: u64 },
+    Redo { seq: u64 },
+    Joined,
+    Left,
+    /// The user became a member of the project. The summary says with which role.
+    MemberAdded,
+    MemberRemoved,
+    RoleChanged,
+}
+
+/// What a member is to a project.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectRole {
+    Owner,
+    Member,
+}
+
+impl ProjectRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProjectRole::Owner => "owner",
+            ProjectRole::Member => "member",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(ProjectRole::Owner),
+            "member" => Some(ProjectRole::Member),
+            _ => None,
+        }
+    }
+}
+
+/// Maximum number of characters of a user's real name.
+pub const MAX_REAL_NAME_LEN: usize = 100;
+/// Maximum number of characters of a user's bio.
+pub const MAX_BIO_LEN: usize = 2000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub user_name: String,
+    pub real_name: Option<String>,
+    /// Formatted like "1997-02-06"
+    pub birth_date: Option<String>,
+    /// UTC, formatted like "2019-03-01 18:30:00"
+    pub register_date: Option<String>,
+    pub bio: Option<String>,
+    /// An image asset
+    pub avatar: Option<AssetHash>,
+    /// Which fields other users can see. Only sent with the caller's own profile.
+    pub visibility: Option<ProfileVisibility>,
+}
+
+impl Profile {
+    /// The profile as other users get to see it.
+    pub fn public(self) -> Self {
+        let visibility = self.visibility.unwrap_or_default();
+        Self {
+            user_name: self.user_name,
+            real_name: self.real_name.filter(|_| visibility.real_name),
+            birth_date: self.birth_date.filter(|_| visibility.birth_date),
+            register_date: self.register_date.filter(|_| visibility.register_date),
+            bio: self.bio.filter(|_| visibility.bio),
+            avatar: self.avatar.filter(|_| visibility.avatar),
+            visibility: None,
+        }
+    }
+}
+
+/// Which fields of a profile are shown to other users.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileVisibility {
+    pub real_name: bool,
+    pub birth_date: bool,
+    pub register_date: bool,
+    pub bio: bool,
+    pub avatar: bool,
+}
+
+impl Default for ProfileVisibility {
+    /// Personal data stays private until the user decides otherwise.
+    fn default() -> Self {
+        Self {
+            real_name: false,
+            birth_date: false,
+            register_date: true,
+            bio: true,
+            avatar: true,
+        }
+    }
+}
+
+/// The fields of a profile the user can change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProfileUpdate {
+    pub real_name: Option<String>,
+    /// Formatted like "1997-02-06"
+    pub birth_date: Option<String>,
+    pub bio: Option<String>,
+    /// An asset of one of the user's projects, or the avatar they have already
+    pub avatar: Option<AssetHash>,
+    pub visibility: ProfileVisibility,
+}
+
+impl ProfileUpdate {
+    /// Checks everything but the avatar, which only the server can.
+    pub fn check(&self) -> Result<(), ProfileError> {
+        let too_long = |text: &Option<String>, max| text.as_ref().is_some_and(|t| t.chars().count() > max);
+        if too_long(&self.real_name, MAX_REAL_NAME_LEN) || too_long(&self.bio, MAX_BIO_LEN) {
+            return Err(ProfileError::TooLong);
+        }
+        if let Some(ref date) = self.birth_date {
+            if !is_date(date) {
+                return Err(ProfileError::InvalidBirthDate);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `date` is formatted like "1997-02-06".
+fn is_date(date: &str) -> bool {
+    let parts: Vec<_> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return false;
+    }
+    match (parts[0].parse::<u32>(), parts[1].parse::<u32>(), parts[2].parse::<u32>()) {
+        (Ok(_), Ok(month), Ok(day)) => (1..=12).contains(&month) && (1..=31).contains(&day),
+        _ => false,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileError {
+    /// The real name is longer than [MAX_REAL_NAME_LEN] or the bio longer than
+    /// [MAX_BIO_LEN] characters.
+    TooLong,
+    InvalidBirthDate,
+    /// The avatar isn't an asset of one of the user's projects.
+    NoSuchAsset,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
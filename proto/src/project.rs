@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, HashSet};
+
+/// Globally unique id of an object inside a project.
+///
+/// `site` is handed out by the server when a client joins a project, `counter`
+/// is incremented locally by that client. This way clients can create objects
+/// without asking the server for an id first.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId {
+    pub site: u32,
+    pub counter: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Project {
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Track {
+    pub id: ObjectId,
+    pub name: String,
+    pub volume: f32,
+    pub pan: f32,
+    pub muted: bool,
+    pub clips: Vec<Clip>,
+    pub devices: Vec<Device>,
+}
+
+/// A region on a track. Positions and lengths are in ticks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Clip {
+    pub id: ObjectId,
+    pub start: u64,
+    pub length: u64,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Note {
+    pub id: ObjectId,
+    pub start: u64,
+    pub length: u64,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+/// An effect or instrument in a track's device chain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Device {
+    pub id: ObjectId,
+    pub name: String,
+    pub params: BTreeMap<u32, f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TrackProp {
+    Name(String),
+    Volume(f32),
+    Pan(f32),
+    Muted(bool),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ClipProp {
+    Start(u64),
+    Length(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum NoteProp {
+    Start(u64),
+    Length(u64),
+    Pitch(u8),
+    Velocity(u8),
+}
+
+/// A single edit of a [Project].
+///
+/// Operations address objects by [ObjectId] only, never by index, so an
+/// operation stays meaningful no matter which other operations were applied
+/// before it. The server decides the final order of all operations.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Inserts `track` after the track `after`, or at the top if `after` is `None`.
+    /// If `after` does not exist (anymore), the track is appended instead.
+    InsertTrack { after: Option<ObjectId>, track: Track },
+    RemoveTrack { id: ObjectId },
+    SetTrack { id: ObjectId, prop: TrackProp },
+    InsertClip { track: ObjectId, clip: Clip },
+    RemoveClip { id: ObjectId },
+    SetClip { id: ObjectId, prop: ClipProp },
+    InsertNote { clip: ObjectId, note: Note },
+    RemoveNote { id: ObjectId },
+    SetNote { id: ObjectId, prop: NoteProp },
+    /// Same placement rules as [Operation::InsertTrack], within `track`'s device chain.
+    InsertDevice { track: ObjectId, after: Option<ObjectId>, device: Device },
+    RemoveDevice { id: ObjectId },
+    /// Sets a device parameter, or resets it to its default if `value` is `None`.
+    SetDeviceParam { id: ObjectId, param: u32, value: Option<f32> },
+    /// Replaces the whole project, as done when restoring a snapshot.
+    Restore { state: Project },
+}
+
+/// How many objects of each kind were added, removed or changed between two project states.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DiffSummary {
+    pub tracks: DiffCount,
+    pub clips: DiffCount,
+    pub notes: DiffCount,
+    pub devices: DiffCount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiffCount {
+    pub added: u32,
+    pub removed: u32,
+    pub changed: u32,
+}
+
+impl DiffCount {
+    fn count<'a, T: PartialEq + 'a>(
+        from: impl Iterator<Item = &'a T> + Clone,
+        to: impl Iterator<Item = &'a T> + Clone,
+        id_of: impl Fn(&T) -> ObjectId,
+        same: impl Fn(&T, &T) -> bool,
+    ) -> Self {
+        let mut count = DiffCount::default();
+        for a in from.clone() {
+            match to.clone().find(|b| id_of(b) == id_of(a)) {
+                Some(b) => {
+                    if !same(a, b) {
+                        count.changed += 1;
+                    }
+                }
+                None => count.removed += 1,
+            }
+        }
+        // Counted on their own rather than from what's kept, ids may not be unique
+        count.added = to.filter(|b| !from.clone().any(|a| id_of(a) == id_of(b))).count() as u32;
+        count
+    }
+}
+
+/// An operation accepted by the server, as stored in the project's operation log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggedOp {
+    pub seq: u64,
+    pub site: u32,
+    pub local_seq: Option<u32>,
+    pub op: Operation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpError {
+    NoSuchObject(ObjectId),
+    DuplicateId(ObjectId),
+    /// The operation claims to be based on a sequence number the server hasn't reached yet.
+    InvalidBase,
+    /// The object, one of its parents or one of its children is locked by another user.
+    Locked(ObjectId),
+    /// Only the server makes this operation, e.g. [Operation::Restore] when restoring a
+    /// snapshot.
+    NotAllowed,
+}
+
+fn insert_after<T>(list: &mut Vec<T>, after: Option<ObjectId>, item: T, id_of: impl Fn(&T) -> ObjectId) {
+    let idx = match after {
+        None => 0,
+        Some(after) => list
+            .iter()
+            .position(|i| id_of(i) == after)
+            .map(|i| i + 1)
+            .unwrap_or_else(|| list.len()),
+    };
+    list.insert(idx, item);
+}
+
+/// The id of the item in front of `idx`, as needed for re-inserting an item at `idx`.
+fn previous_id<T>(list: &[T], idx: usize, id_of: impl Fn(&T) -> ObjectId) -> Option<ObjectId> {
+    if idx == 0 {
+        None
+    } else {
+        list.get(idx - 1).map(id_of)
+    }
+}
+
+impl Operation {
+    /// The existing object this operation modifies. For insertions that's the object the
+    /// new one is inserted into, so inserting a top level track has no target.
+    pub fn target(&self) -> Option<ObjectId> {
+        use self::Operation::*;
+        match *self {
+            InsertTrack { .. } | Restore { .. } => None,
+            InsertClip { track, .. } | InsertDevice { track, .. } => Some(track),
+            InsertNote { clip, .. } => Some(clip),
+            RemoveTrack { id }
+            | SetTrack { id, .. }
+            | RemoveClip { id }
+            | SetClip { id, .. }
+            | RemoveNote { id }
+            | SetNote { id, .. }
+            | RemoveDevice { id }
+            | SetDeviceParam { id, .. } => Some(id),
+        }
+    }
+
+    /// Whether applying `self` now would revert (part of) the effect of `other`, which
+    /// was applied earlier. Undo uses this to keep a user from reverting somebody else's
+    /// work: changing the same property again, or removing an object someone has
+    /// edited since.
+    pub fn clobbers(&self, other: &Operation, state: &Project) -> bool {
+        use self::Operation::*;
+        use std::mem::discriminant;
+        match (self, other) {
+            (&SetTrack { id: a, prop: ref pa }, &SetTrack { id: b, prop: ref pb }) => {
+                a == b && discriminant(pa) == discriminant(pb)
+            }
+            (&SetClip { id: a, prop: ref pa }, &SetClip { id: b, prop: ref pb }) => {
+                a == b && discriminant(pa) == discriminant(pb)
+            }
+            (&SetNote { id: a, prop: ref pa }, &SetNote { id: b, prop: ref pb }) => {
+                a == b && discriminant(pa) == discriminant(pb)
+            }
+            (&SetDeviceParam { id: a, param: pa, .. }, &SetDeviceParam { id: b, param: pb, .. }) => {
+                a == b && pa == pb
+            }
+            (&RemoveTrack { id }, _)
+            | (&RemoveClip { id }, _)
+            | (&RemoveNote { id }, _)
+            | (&RemoveDevice { id }, _) => other
+                .target()
+                .map(|t| state.lineage(t).contains(&id))
+                .unwrap_or(false),
+            // Replacing everything reverts whatever anyone did
+            (&Restore { .. }, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Project {
+    /// Applies `op` and returns the operation that reverts it.
+    pub fn apply(&mut self, op: &Operation) -> Result<Operation, OpError> {
+        use self::Operation::*;
+        use std::mem::replace;
+        let inverse = match *op {
+            InsertTrack { after, ref track } => {
+                self.check_new_ids(track.ids())?;
+                insert_after(&mut self.tracks, after, track.clone(), |t| t.id);
+                RemoveTrack { id: track.id }
+            }
+            RemoveTrack { id } => {
+                let idx = self.locate_track(id).ok_or(OpError::NoSuchObject(id))?;
+                let track = self.tracks.remove(idx);
+                InsertTrack {
+                    after: previous_id(&self.tracks, idx, |t| t.id),
+                    track,
+                }
+            }
+            SetTrack { id, ref prop } => {
+                let idx = self.locate_track(id).ok_or(OpError::NoSuchObject(id))?;
+                let track = &mut self.tracks[idx];
+                let old = match *prop {
+                    TrackProp::Name(ref name) => TrackProp::Name(replace(&mut track.name, name.clone())),
+                    TrackProp::Volume(v) => TrackProp::Volume(replace(&mut track.volume, v)),
+                    TrackProp::Pan(p) => TrackProp::Pan(replace(&mut track.pan, p)),
+                    TrackProp::Muted(m) => TrackProp::Muted(replace(&mut track.muted, m)),
+                };
+                SetTrack { id, prop: old }
+            }
+            InsertClip { track, ref clip } => {
+                self.check_new_ids(clip.ids())?;
+                let idx = self.locate_track(track).ok_or(OpError::NoSuchObject(track))?;
+                self.tracks[idx].clips.push(clip.clone());
+                RemoveClip { id: clip.id }
+            }
+            RemoveClip { id } => {
+                let (t, c) = self.locate_clip(id).ok_or(OpError::NoSuchObject(id))?;
+                InsertClip {
+                    track: self.tracks[t].id,
+                    clip: self.tracks[t].clips.remove(c),
+                }
+            }
+            SetClip { id, ref prop } => {
+                let (t, c) = self.locate_clip(id).ok_or(OpError::NoSuchObject(id))?;
+                let clip = &mut self.tracks[t].clips[c];
+                let old = match *prop {
+                    ClipProp::Start(s) => ClipProp::Start(replace(&mut clip.start, s)),
+                    ClipProp::Length(l) => ClipProp::Length(replace(&mut clip.length, l)),
+                };
+                SetClip { id, prop: old }
+            }
+            InsertNote { clip, ref note } => {
+                self.check_new_ids(Some(note.id))?;
+                let (t, c) = self.locate_clip(clip).ok_or(OpError::NoSuchObject(clip))?;
+                self.tracks[t].clips[c].notes.push(note.clone());
+                RemoveNote { id: note.id }
+            }
+            RemoveNote { id } => {
+                let (t, c, n) = self.locate_note(id).ok_or(OpError::NoSuchObject(id))?;
+                let clip = &mut self.tracks[t].clips[c];
+                InsertNote {
+                    clip: clip.id,
+                    note: clip.notes.remove(n),
+                }
+            }
+            SetNote { id, ref prop } => {
+                let (t, c, n) = self.locate_note(id).ok_or(OpError::NoSuchObject(id))?;
+                let note = &mut self.tracks[t].clips[c].notes[n];
+                let old = match *prop {
+                    NoteProp::Start(s) => NoteProp::Start(replace(&mut note.start, s)),
+                    NoteProp::Length(l) => NoteProp::Length(replace(&mut note.length, l)),
+                    NoteProp::Pitch(p) => NoteProp::Pitch(replace(&mut note.pitch, p)),
+                    NoteProp::Velocity(v) => NoteProp::Velocity(replace(&mut note.velocity, v)),
+                };
+                SetNote { id, prop: old }
+            }
+            InsertDevice { track, after, ref device } => {
+                self.check_new_ids(Some(device.id))?;
+                let idx = self.locate_track(track).ok_or(OpError::NoSuchObject(track))?;
+                insert_after(&mut self.tracks[idx].devices, after, device.clone(), |d| d.id);
+                RemoveDevice { id: device.id }
+            }
+            RemoveDevice { id } => {
+                let (t, d) = self.locate_device(id).ok_or(OpError::NoSuchObject(id))?;
+                let track = &mut self.tracks[t];
+                let device = track.devices.remove(d);
+                InsertDevice {
+                    track: track.id,
+                    after: previous_id(&track.devices, d, |d| d.id),
+                    device,
+                }
+            }
+            SetDeviceParam { id, param, value } => {
+                let (t, d) = self.locate_device(id).ok_or(OpError::NoSuchObject(id))?;
+                let params = &mut self.tracks[t].devices[d].params;
+                let old = match value {
+                    Some(v) => params.insert(param, v),
+                    None => params.remove(&param),
+                };
+                SetDeviceParam { id, param, value: old }
+            }
+            Restore { ref state } => {
+                state.check_unique_ids()?;
+                Restore {
+                    state: replace(self, state.clone()),
+                }
+            }
+        };
+        Ok(inverse)
+    }
+
+    /// Summarizes what changed going from `self` to `to`.
+    pub fn diff_summary(&self, to: &Project) -> DiffSummary {
+        fn clips(p: &Project) -> impl Iterator<Item = &Clip> + Clone {
+            p.tracks.iter().flat_map(|t| t.clips.iter())
+        }
+        fn notes(p: &Project) -> impl Iterator<Item = &Note> + Clone {
+            clips(p).flat_map(|c| c.notes.iter())
+        }
+        fn devices(p: &Project) -> impl Iterator<Item = &Device> + Clone {
+            p.tracks.iter().flat_map(|t| t.devices.iter())
+        }
+
+        DiffSummary {
+            // Only a track's own properties count as a change, not its contents
+            tracks: DiffCount::count(self.tracks.iter(), to.tracks.iter(), |t| t.id, |a, b| {
+                a.name == b.name && a.volume == b.volume && a.pan == b.pan && a.muted == b.muted
+            }),
+            clips: DiffCount::count(clips(self), clips(to), |c| c.id, |a, b| {
+                a.start == b.start && a.length == b.length
+            }),
+            notes: DiffCount::count(notes(self), notes(to), |n| n.id, |a, b| a == b),
+            devices: DiffCount::count(devices(self), devices(to), |d| d.id, |a, b| a == b),
+        }
+    }
+
+    /// What `op` does to this state in a few words, e.g. `Removed track "Bass"`.
+    pub fn summarize(&self, op: &Operation) -> String {
+        use self::Operation::*;
+        let state = self;
+        let track = |t: usize| format!("track \"{}\"", state.tracks[t].name);
+        let track_of = |t: Option<usize>| t.map(track).unwrap_or_else(|| "a track".to_owned());
+        let clip_track = |id| track_of(state.locate_clip(id).map(|(t, _)| t));
+        match *op {
+            InsertTrack { ref track, .. } => format!("Added track \"{}\"", track.name),
+            RemoveTrack { id } => format!("Removed {}", track_of(state.locate_track(id))),
+            SetTrack { id, ref prop } => {
+                let target = track_of(state.locate_track(id));
+                match *prop {
+                    TrackProp::Name(ref name) => format!("Renamed {} to \"{}\"", target, name),
+                    TrackProp::Volume(volume) => format!("Set the volume of {} to {:.0}%", target, volume * 100.0),
+                    TrackProp::Pan(pan) => format!("Set the pan of {} to {:.2}", target, pan),
+                    TrackProp::Muted(true) => format!("Muted {}", target),
+                    TrackProp::Muted(false) => format!("Unmuted {}", target),
+                }
+            }
+            InsertClip { track, .. } => format!("Added a clip to {}", track_of(state.locate_track(track))),
+            RemoveClip { id } => format!("Removed a clip from {}", clip_track(id)),
+            SetClip { id, prop: ClipProp::Start(_) } => format!("Moved a clip on {}", clip_track(id)),
+            SetClip { id, prop: ClipProp::Length(_) } => format!("Resized a clip on {}", clip_track(id)),
+            InsertNote { clip, .. } => format!("Added a note on {}", clip_track(clip)),
+            RemoveNote { id } => format!("Removed a note from {}", track_of(state.locate_note(id).map(|(t, _, _)| t))),
+            SetNote { id, .. } => format!("Changed a note on {}", track_of(state.locate_note(id).map(|(t, _, _)| t))),
+            InsertDevice { track, ref device, .. } => format!("Added device \"{}\" to {}", device.name, track_of(state.locate_track(track))),
+            RemoveDevice { id } => match state.locate_device(id) {
+                Some((t, d)) => format!("Removed device \"{}\" from {}", state.tracks[t].devices[d].name, track(t)),
+                None => "Removed a device".to_owned(),
+            },
+            SetDeviceParam { id, param, value } => {
+                let device = match state.locate_device(id) {
+                    Some((t, d)) => format!("device \"{}\" on {}", state.tracks[t].devices[d].name, track(t)),
+                    None => "a device".to_owned(),
+                };
+                match value {
+                    Some(value) => format!("Set parameter {} of {} to {}", param, device, value),
+                    None => format!("Reset parameter {} of {}", param, device),
+                }
+            }
+            Restore { .. } => "Restored a snapshot".to_owned(),
+        }
+    }
+
+    /// Returns whether any object in the project has the given id.
+    pub fn contains(&self, id: ObjectId) -> bool {
+        self.tracks.iter().any(|t| t.ids().any(|i| i == id))
+    }
+
+    /// The highest site that created any object in the project, or 0 for an empty project.
+    pub fn max_site(&self) -> u32 {
+        self.tracks
+            .iter()
+            .flat_map(|t| t.ids())
+            .map(|id| id.site)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// `id` followed by the ids of the objects containing it, innermost first.
+    /// Empty if there's no such object.
+    pub fn lineage(&self, id: ObjectId) -> Vec<ObjectId> {
+        if self.locate_track(id).is_some() {
+            vec![id]
+        } else if let Some((t, _)) = self.locate_clip(id) {
+            vec![id, self.tracks[t].id]
+        } else if let Some((t, _)) = self.locate_device(id) {
+            vec![id, self.tracks[t].id]
+        } else if let Some((t, c, _)) = self.locate_note(id) {
+            vec![id, self.tracks[t].clips[c].id, self.tracks[t].id]
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn locate_track(&self, id: ObjectId) -> Option<usize> {
+        self.tracks.iter().position(|t| t.id == id)
+    }
+
+    pub fn locate_clip(&self, id: ObjectId) -> Option<(usize, usize)> {
+        self.tracks.iter().enumerate().filter_map(|(t, track)| {
+            track.clips.iter().position(|c| c.id == id).map(|c| (t, c))
+        }).next()
+    }
+
+    pub fn locate_note(&self, id: ObjectId) -> Option<(usize, usize, usize)> {
+        self.tracks.iter().enumerate().flat_map(|(t, track)| {
+            track.clips.iter().enumerate().map(move |(c, clip)| (t, c, clip))
+        }).filter_map(|(t, c, clip)| {
+            clip.notes.iter().position(|n| n.id == id).map(|n| (t, c, n))
+        }).next()
+    }
+
+    pub fn locate_device(&self, id: ObjectId) -> Option<(usize, usize)> {
+        self.tracks.iter().enumerate().filter_map(|(t, track)| {
+            track.devices.iter().position(|d| d.id == id).map(|d| (t, d))
+        }).next()
+    }
+
+    /// Fails with the first id that's used by more than one object.
+    pub fn check_unique_ids(&self) -> Result<(), OpError> {
+        let mut seen = HashSet::new();
+        match self.tracks.iter().flat_map(|t| t.ids()).find(|&id| !seen.insert(id)) {
+            Some(id) => Err(OpError::DuplicateId(id)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_new_ids(&self, ids: impl IntoIterator<Item = ObjectId>) -> Result<(), OpError> {
+        for id in ids {
+            if self.contains(id) {
+                return Err(OpError::DuplicateId(id));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Track {
+    /// Iterates over the id of this track and the ids of everything on it.
+    pub fn ids<'a>(&'a self) -> impl Iterator<Item = ObjectId> + 'a {
+        Some(self.id).into_iter()
+            .chain(self.clips.iter().flat_map(|c| c.ids()))
+            .chain(self.devices.iter().map(|d| d.id))
+    }
+}
+
+impl Clip {
+    /// Iterates over the id of this clip and the ids of its notes.
+    pub fn ids<'a>(&'a self) -> impl Iterator<Item = ObjectId> + 'a {
+        Some(self.id).into_iter().chain(self.notes.iter().map(|n| n.id))
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, VecDeque};
+
+use project::{LoggedOp, ObjectId, OpError, Operation, Project};
+use {Command, Lock};
+
+/// An operation that was applied locally but not yet confirmed by the server.
+#[derive(Debug, Clone)]
+pub struct PendingOp {
+    pub local_seq: u32,
+    /// The confirmed sequence number the operation was last submitted on top of
+    pub base_seq: u64,
+    pub op: Operation,
+    /// What the operation did when it was made, e.g. `Removed track "Bass"`
+    pub summary: String,
+}
+
+/// A local edit that was thrown away, because it conflicted with someone else's edit
+/// or the server rejected it.
+#[derive(Debug, Clone)]
+pub struct Discarded {
+    pub summary: String,
+    pub error: OpError,
+}
+
+/// How many discarded edits are kept to be shown to the user.
+const MAX_DISCARDED: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Online,
+    /// Edits are only applied locally and kept pending
+    Offline,
+    /// Rejoined after being offline, waiting for the operations since the old confirmed
+    /// state. Some of the pending edits may have reached the server before the
+    /// connection dropped, those show up there as coming from `old_site`.
+    CatchingUp { old_site: u32, joined_seq: u64 },
+    /// An operation from the server didn't apply to the confirmed state, which means it
+    /// diverged from the server's. Waiting for the state of a fresh join, the operations
+    /// until then are ignored.
+    Resyncing,
+    /// Fetching the missed operations failed, so it can't be told which pending edits
+    /// reached the server. Waiting for the state of a fresh join to submit all of them
+    /// on top of; one that was applied already is rejected or sets the same again.
+    Rejoining,
+}
+
+/// Client side copy of a project.
+///
+/// Local edits are applied optimistically on top of the last state confirmed
+/// by the server. Whenever the server reports an operation (ours or someone
+/// else's), it is applied to the confirmed state and all still pending local
+/// operations are replayed on top of it. Pending operations that no longer
+/// apply are dropped, the server will reject them as well. Since every client
+/// applies the server's operations in the server's order, all clients end up
+/// with the same confirmed state.
+///
+/// While the connection is down, edits keep being applied locally and queued. After
+/// rejoining, the operations missed in the meantime are fetched, and the pending edits
+/// the server hasn't seen yet are submitted again on top of them.
+pub struct ClientProject {
+    project_id: u32,
+    site: u32,
+    next_counter: u32,
+    next_local_seq: u32,
+    confirmed: Project,
+    confirmed_seq: u64,
+    pending: VecDeque<PendingOp>,
+    local: Project,
+    /// Locked object -> user name of the lock holder
+    locks: HashMap<ObjectId, String>,
+    link: Link,
+    /// Most recent last
+    discarded: VecDeque<Discarded>,
+}
+
+impl ClientProject {
+    pub fn new(project_id: u32, site: u32, seq: u64, state: Project, locks: Vec<Lock>) -> Self {
+        Self {
+            project_id,
+            site,
+            next_counter: 0,
+            next_local_seq: 0,
+            local: state.clone(),
+            confirmed: state,
+            confirmed_seq: seq,
+            pending: VecDeque::new(),
+            locks: locks.into_iter().map(|l| (l.object, l.holder)).collect(),
+            link: Link::Online,
+            discarded: VecDeque::new(),
+        }
+    }
+
+    pub fn project_id(&self) -> u32 {
+        self.project_id
+    }
+
+    /// The project as seen by the user, including pending local edits.
+    pub fn state(&self) -> &Project {
+        &self.local
+    }
+
+    /// The last state confirmed by the server.
+    pub fn confirmed_state(&self) -> &Project {
+        &self.confirmed
+    }
+
+    /// Sequence number of the last operation confirmed by the server.
+    pub fn seq(&self) -> u64 {
+        self.confirmed_seq
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &PendingOp> {
+        self.pending.iter()
+    }
+
+    /// Whether edits are submitted right away. False while offline and until the
+    /// edits made offline have been submitted after rejoining.
+    pub fn is_online(&self) -> bool {
+        self.link == Link::Online
+    }
+
+    /// Local edits that were thrown away, most recent last.
+    pub fn discarded(&self) -> impl Iterator<Item = &Discarded> {
+        self.discarded.iter()
+    }
+
+    /// The user name of whoever locked `object`, if it's locked.
+    pub fn lock_holder(&self, object: ObjectId) -> Option<&str> {
+        self.locks.get(&object).map(|h| h.as_str())
+    }
+
+    /// Handles the lock state of an object changing.
+    pub fn set_lock(&mut self, object: ObjectId, holder: Option<String>) {
+        match holder {
+            Some(holder) => self.locks.insert(object, holder),
+            None => self.locks.remove(&object),
+        };
+    }
+
+    /// Generates an id for a new object created by this client.
+    pub fn new_id(&mut self) -> ObjectId {
+        let id = ObjectId {
+            site: self.site,
+            counter: self.next_counter,
+        };
+        self.next_counter += 1;
+        id
+    }
+
+    /// Applies `op` locally and returns the command that submits it to the server.
+    /// While offline there's no command, the edit is submitted after rejoining.
+    pub fn submit(&mut self, op: Operation) -> Result<Option<Command>, OpError> {
+        let summary = self.local.summarize(&op);
+        self.local.apply(&op)?;
+        let local_seq = self.next_local_seq;
+        self.next_local_seq += 1;
+        let base_seq = self.confirmed_seq;
+        self.pending.push_back(PendingOp {
+            local_seq,
+            base_seq,
+            op: op.clone(),
+            summary,
+        });
+        if self.link != Link::Online {
+            return Ok(None);
+        }
+        Ok(Some(Command::SubmitOp {
+            project_id: self.project_id,
+            local_seq,
+            base_seq,
+            op,
+        }))
+    }
+
+    /// Handles the connection to the server dropping. Edits from now on are queued.
+    pub fn disconnected(&mut self) {
+        self.link = Link::Offline;
+    }
+
+    /// Handles having joined the project again after the connection dropped. Returns
+    /// the commands to send: either fetching the operations missed in the meantime, or
+    /// if there can't be any of ours among them, submitting the pending edits again.
+    pub fn rejoin(&mut self, site: u32, seq: u64, state: Project, locks: Vec<Lock>) -> Vec<Command> {
+        // Our edits can only show up after the state the oldest pending one was based on
+        let since_seq = self.pending.front().map_or(self.confirmed_seq, |p| p.base_seq);
+        let old_site = self.site;
+        let fetch = self.link != Link::Rejoining;
+        self.site = site;
+        self.next_counter = 0;
+        self.confirmed = state;
+        self.confirmed_seq = seq;
+        self.locks = locks.into_iter().map(|l| (l.object, l.holder)).collect();
+
+        if !fetch || self.pending.is_empty() || seq == since_seq {
+            return self.caught_up();
+        }
+        // The local state stays as it is until it's known which edits are left
+        self.link = Link::CatchingUp { old_site, joined_seq: seq };
+        vec![Command::FetchOps {
+            project_id: self.project_id,
+            since_seq,
+        }]
+    }
+
+    /// Handles the server refusing to send the operations needed to catch up. Returns
+    /// the command joining the project again, the pending edits are submitted on top of
+    /// whatever state that brings.
+    pub fn fetch_failed(&mut self) -> Option<Command> {
+        match self.link {
+            Link::CatchingUp { .. } => {
+                self.link = Link::Rejoining;
+                Some(Command::JoinProject { project_id: self.project_id })
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles an operation the server accepted, no matter who authored it. Returns the
+    /// command joining the project again if the operation doesn't apply, the answer to
+    /// it is handled like a rejoin after the connection dropped.
+    pub fn server_op(&mut self, seq: u64, site: u32, local_seq: Option<u32>, op: &Operation) -> Option<Command> {
+        if seq <= self.confirmed_seq || matches!(self.link, Link::Resyncing | Link::Rejoining) {
+            // Already seen, e.g. because it was also part of a fetched log
+            return None;
+        }
+        // The server has already applied the operation, so it must apply here too.
+        // If it doesn't, the states have diverged, start over from the server's.
+        if self.confirmed.apply(op).is_err() {
+            self.link = Link::Resyncing;
+            return Some(Command::JoinProject { project_id: self.project_id });
+        }
+        self.confirmed_seq = seq;
+        if self.link != Link::Online {
+            // Pending edits are rebased once caught up
+            return None;
+        }
+
+        let own = match local_seq {
+            Some(local_seq) if site == self.site => {
+                self.pending.iter().position(|p| p.local_seq == local_seq)
+            }
+            _ => None,
+        };
+        match own {
+            // Our oldest pending operation came back. It has been applied on top of
+            // exactly the same state as locally, so nothing else changes.
+            Some(0) => {
+                self.pending.pop_front();
+            }
+            Some(idx) => {
+                self.pending.remove(idx);
+                self.rebase();
+            }
+            None => self.rebase(),
+        }
+        None
+    }
+
+    /// Handles a batch of logged operations, as answered to [Command::FetchOps]. Returns
+    /// the commands submitting the pending edits again, if this was the answer needed to
+    /// catch up after rejoining.
+    pub fn server_ops(&mut self, ops: &[LoggedOp]) -> Vec<Command> {
+        let (old_site, joined_seq) = match self.link {
+            Link::CatchingUp { old_site, joined_seq } => (old_site, joined_seq),
+            _ => {
+                for op in ops {
+                    if let Some(resync) = self.server_op(op.seq, op.site, op.local_seq, &op.op) {
+                        return vec![resync];
+                    }
+                }
+                return Vec::new();
+            }
+        };
+        for op in ops {
+            if op.seq > joined_seq {
+                if let Some(resync) = self.server_op(op.seq, op.site, op.local_seq, &op.op) {
+                    return vec![resync];
+                }
+            } else if op.site == old_site {
+                // Already part of the joined state: it reached the server before the
+                // connection dropped, only the answer got lost
+                if let Some(local_seq) = op.local_seq {
+                    self.pending.retain(|p| p.local_seq != local_seq);
+                }
+            }
+        }
+        self.caught_up()
+    }
+
+    /// Handles the server rejecting one of our operations.
+    pub fn reject(&mut self, local_seq: u32, error: OpError) -> Option<PendingOp> {
+        let idx = self.pending.iter().position(|p| p.local_seq == local_seq)?;
+        let rejected = self.pending.remove(idx)?;
+        self.discard(rejected.summary.clone(), error);
+        self.rebase();
+        Some(rejected)
+    }
+
+    /// Goes online again, returning the commands that submit all pending edits.
+    fn caught_up(&mut self) -> Vec<Command> {
+        self.link = Link::Online;
+        self.rebase();
+        let (project_id, base_seq) = (self.project_id, self.confirmed_seq);
+        self.pending
+            .iter_mut()
+            .map(|p| {
+                p.base_seq = base_seq;
+                Command::SubmitOp {
+                    project_id,
+                    local_seq: p.local_seq,
+                    base_seq,
+                    op: p.op.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn rebase(&mut self) {
+        self.local = self.confirmed.clone();
+        let mut dropped = Vec::new();
+        let local = &mut self.local;
+        self.pending.retain(|p| match local.apply(&p.op) {
+            Ok(_) => true,
+            Err(error) => {
+                dropped.push((p.summary.clone(), error));
+                false
+            }
+        });
+        for (summary, error) in dropped {
+            self.discard(summary, error);
+        }
+    }
+
+    fn discard(&mut self, summary: String, error: OpError) {
+        if self.discarded.len() == MAX_DISCARDED {
+            self.discarded.pop_front();
+        }
+        self.discarded.push_back(Discarded { summary, error });
+    }
+}
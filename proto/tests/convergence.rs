@@ -0,0 +1,313 @@
+//! Random concurrent edits of several clients, ordered by a simulated server, must leave
+//! every client with the server's state once all messages have been delivered.
+
+extern crate proptest;
+extern crate proto;
+
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+
+use proto::project::{Clip, ClipProp, LoggedOp, ObjectId, Operation, Project, Track, TrackProp};
+use proto::sync::ClientProject;
+use proto::{Command, Response};
+
+const PROJECT_ID: u32 = 1;
+const CLIENTS: usize = 3;
+
+/// The server's side of a project: applies the submitted operations in the order they
+/// arrive and tells everyone about it, like the server's project session.
+struct Server {
+    state: Project,
+    seq: u64,
+    log: Vec<LoggedOp>,
+    next_site: u32,
+}
+
+impl Server {
+    fn handle(&mut self, site: u32, cmd: Command, clients: &mut [Client]) {
+        match cmd {
+            Command::SubmitOp { local_seq, base_seq, op, .. } => {
+                assert!(base_seq <= self.seq, "Edit based on a future state");
+                match self.state.apply(&op) {
+                    Ok(_) => {
+                        self.seq += 1;
+                        self.log.push(LoggedOp { seq: self.seq, site, local_seq: Some(local_seq), op: op.clone() });
+                        for client in clients.iter_mut().filter(|c| c.online) {
+                            client.inbox.push_back(Response::OpApplied {
+                                project_id: PROJECT_ID,
+                                seq: self.seq,
+                                site,
+                                local_seq: Some(local_seq),
+                                op: op.clone(),
+                            });
+                        }
+                    }
+                    Err(error) => {
+                        let author = clients.iter_mut().find(|c| c.site == site).unwrap();
+                        author.inbox.push_back(Response::OpRejected { project_id: PROJECT_ID, local_seq, error });
+                    }
+                }
+            }
+            Command::FetchOps { since_seq, .. } => {
+                let ops = self.log.iter().filter(|op| op.seq > since_seq).cloned().collect();
+                let client = clients.iter_mut().find(|c| c.site == site).unwrap();
+                client.inbox.push_back(Response::Ops { project_id: PROJECT_ID, ops });
+            }
+            Command::JoinProject { .. } => {
+                let client = clients.iter_mut().find(|c| c.site == site).unwrap();
+                client.inbox.push_back(self.joined(site));
+            }
+            cmd => panic!("Unexpected command {:?}", cmd),
+        }
+    }
+
+    fn joined(&self, site: u32) -> Response {
+        Response::ProjectJoined { project_id: PROJECT_ID, site, seq: self.seq, state: self.state.clone(), locks: Vec::new() }
+    }
+}
+
+struct Client {
+    project: ClientProject,
+    site: u32,
+    online: bool,
+    /// Sent, but not received by the server yet
+    outbox: VecDeque<Command>,
+    /// Sent by the server, but not handled by the client yet
+    inbox: VecDeque<Response>,
+}
+
+impl Client {
+    fn receive(&mut self) {
+        let res = match self.inbox.pop_front() {
+            Some(res) => res,
+            None => return,
+        };
+        let cmds = match res {
+            Response::OpApplied { seq, site, local_seq, op, .. } => {
+                self.project.server_op(seq, site, local_seq, &op).into_iter().collect()
+            }
+            Response::OpRejected { local_seq, error, .. } => {
+                self.project.reject(local_seq, error);
+                Vec::new()
+            }
+            Response::Ops { ops, .. } => self.project.server_ops(&ops),
+            Response::ProjectJoined { site, seq, state, locks, .. } => self.project.rejoin(site, seq, state, locks),
+            res => panic!("Unexpected response {:?}", res),
+        };
+        self.outbox.extend(cmds);
+    }
+
+    /// Makes an edit of the kind `kind` on the `pick`th object that fits, as seen locally.
+    fn edit(&mut self, kind: u8, pick: usize, value: u64) {
+        let state = self.project.state().clone();
+        let tracks: Vec<ObjectId> = state.tracks.iter().map(|t| t.id).collect();
+        let clips: Vec<ObjectId> = state.tracks.iter().flat_map(|t| t.clips.iter().map(|c| c.id)).collect();
+        let nth = |ids: &[ObjectId]| if ids.is_empty() { None } else { Some(ids[pick % ids.len()]) };
+        let op = match kind % 6 {
+            0 => Operation::InsertTrack {
+                after: nth(&tracks),
+                track: Track {
+                    id: self.project.new_id(),
+                    name: format!("Track {}", value),
+                    volume: 1.0,
+                    pan: 0.0,
+                    muted: false,
+                    clips: Vec::new(),
+                    devices: Vec::new(),
+                },
+            },
+            1 => match nth(&tracks) {
+                Some(id) => Operation::RemoveTrack { id },
+                None => return,
+            },
+            2 => match nth(&tracks) {
+                Some(id) => Operation::SetTrack { id, prop: TrackProp::Volume(value as f32 / 100.0) },
+                None => return,
+            },
+            3 => match nth(&tracks) {
+                Some(track) => Operation::InsertClip {
+                    track,
+                    clip: Clip { id: self.project.new_id(), start: value, length: 96, notes: Vec::new() },
+                },
+                None => return,
+            },
+            4 => match nth(&clips) {
+                Some(id) => Operation::RemoveClip { id },
+                None => return,
+            },
+            _ => match nth(&clips) {
+                Some(id) => Operation::SetClip { id, prop: ClipProp::Start(value) },
+                None => return,
+            },
+        };
+        if let Some(cmd) = self.project.submit(op).expect("Edit of the local state") {
+            self.outbox.push_back(cmd);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Edit { client: usize, kind: u8, pick: usize, value: u64 },
+    /// The server handles the client's oldest command
+    Deliver { client: usize },
+    /// The client handles the server's oldest message
+    Receive { client: usize },
+    /// Everything in flight is lost, the client keeps editing offline
+    Disconnect { client: usize },
+    /// The client logs in again and rejoins the project
+    Reconnect { client: usize },
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let client = 0..CLIENTS;
+    prop_oneof![
+        4 => (client.clone(), any::<u8>(), any::<usize>(), 0..1000u64)
+            .prop_map(|(client, kind, pick, value)| Step::Edit { client, kind, pick, value }),
+        4 => client.clone().prop_map(|client| Step::Deliver { client }),
+        4 => client.clone().prop_map(|client| Step::Receive { client }),
+        1 => client.clone().prop_map(|client| Step::Disconnect { client }),
+        1 => client.prop_map(|client| Step::Reconnect { client }),
+    ]
+}
+
+fn run(steps: Vec<Step>) -> (Server, Vec<Client>) {
+    let mut server = Server { state: Project::default(), seq: 0, log: Vec::new(), next_site: 1 };
+    let mut clients: Vec<Client> = (0..CLIENTS)
+        .map(|_| {
+            let site = server.next_site;
+            server.next_site += 1;
+            Client {
+                project: ClientProject::new(PROJECT_ID, site, 0, Project::default(), Vec::new()),
+                site,
+                online: true,
+                outbox: VecDeque::new(),
+                inbox: VecDeque::new(),
+            }
+        })
+        .collect();
+
+    let deliver = |server: &mut Server, clients: &mut Vec<Client>, c: usize| {
+        if let Some(cmd) = clients[c].outbox.pop_front() {
+            let site = clients[c].site;
+            server.handle(site, cmd, clients);
+        }
+    };
+    let reconnect = |server: &mut Server, client: &mut Client| {
+        // Leaving the project on disconnecting, the server hands out a new site
+        client.site = server.next_site;
+        server.next_site += 1;
+        client.online = true;
+        client.inbox.push_back(server.joined(client.site));
+    };
+
+    for step in steps {
+        match step {
+            Step::Edit { client, kind, pick, value } => clients[client].edit(kind, pick, value),
+            Step::Deliver { client } if clients[client].online => deliver(&mut server, &mut clients, client),
+            Step::Receive { client } => clients[client].receive(),
+            Step::Disconnect { client } if clients[client].online => {
+                let client = &mut clients[client];
+                client.online = false;
+                client.outbox.clear();
+                client.inbox.clear();
+                client.project.disconnected();
+            }
+            Step::Reconnect { client } if !clients[client].online => reconnect(&mut server, &mut clients[client]),
+            _ => {}
+        }
+    }
+
+    // Let everything settle
+    for client in clients.iter_mut().filter(|c| !c.online) {
+        reconnect(&mut server, client);
+    }
+    while clients.iter().any(|c| !c.outbox.is_empty() || !c.inbox.is_empty()) {
+        for c in 0..CLIENTS {
+            deliver(&mut server, &mut clients, c);
+            clients[c].receive();
+        }
+    }
+    (server, clients)
+}
+
+proptest! {
+    #[test]
+    fn replicas_converge(steps in prop::collection::vec(step(), 0..200)) {
+        let (server, clients) = run(steps);
+        for client in &clients {
+            prop_assert!(client.project.is_online());
+            prop_assert_eq!(client.project.pending().count(), 0);
+            prop_assert_eq!(client.project.seq(), server.seq);
+            prop_assert_eq!(client.project.confirmed_state(), &server.state);
+            prop_assert_eq!(client.project.state(), &server.state);
+        }
+    }
+}
+
+#[test]
+fn diverged_replica_resyncs() {
+    let track = Track {
+        id: ObjectId { site: 1, counter: 0 },
+        name: "Drums".to_owned(),
+        volume: 1.0,
+        pan: 0.0,
+        muted: false,
+        clips: Vec::new(),
+        devices: Vec::new(),
+    };
+    let state = Project { tracks: vec![track.clone()] };
+    // Missing the track the server has
+    let mut project = ClientProject::new(PROJECT_ID, 2, 1, Project::default(), Vec::new());
+
+    let set = Operation::SetTrack { id: track.id, prop: TrackProp::Muted(true) };
+    let resync = project.server_op(2, 1, Some(0), &set);
+    assert!(matches!(resync, Some(Command::JoinProject { project_id: PROJECT_ID })));
+    assert!(!project.is_online());
+    // Anything until the fresh state arrives is ignored
+    assert!(project.server_op(3, 1, Some(1), &set).is_none());
+
+    let mut fresh = state;
+    fresh.apply(&set).unwrap();
+    fresh.apply(&set).unwrap();
+    assert!(project.rejoin(2, 3, fresh.clone(), Vec::new()).is_empty());
+    assert!(project.is_online());
+    assert_eq!(project.state(), &fresh);
+}
+
+#[test]
+fn failed_fetch_falls_back_to_rejoin() {
+    let track = Track {
+        id: ObjectId { site: 1, counter: 0 },
+        name: "Drums".to_owned(),
+        volume: 1.0,
+        pan: 0.0,
+        muted: false,
+        clips: Vec::new(),
+        devices: Vec::new(),
+    };
+    let state = Project { tracks: vec![track.clone()] };
+    let mut project = ClientProject::new(PROJECT_ID, 2, 1, state.clone(), Vec::new());
+    let mute = Operation::SetTrack { id: track.id, prop: TrackProp::Muted(true) };
+    assert!(project.submit(mute).unwrap().is_some());
+    project.disconnected();
+    let volume = Operation::SetTrack { id: track.id, prop: TrackProp::Volume(0.5) };
+    assert!(project.submit(volume).unwrap().is_none());
+    assert!(project.pending().all(|p| p.base_seq == 1));
+
+    // Someone else's edit came in meanwhile, so ours may be among the missed ones
+    let fetch = project.rejoin(3, 2, state.clone(), Vec::new());
+    assert!(matches!(fetch[..], [Command::FetchOps { since_seq: 1, .. }]));
+    let rejoin = project.fetch_failed();
+    assert!(matches!(rejoin, Some(Command::JoinProject { project_id: PROJECT_ID })));
+    assert!(project.fetch_failed().is_none());
+    assert!(!project.is_online());
+
+    // Without the log, everything pending goes to the server on top of the fresh state
+    let submits = project.rejoin(4, 2, state, Vec::new());
+    assert_eq!(submits.len(), 2);
+    assert!(submits.iter().all(|cmd| matches!(cmd, Command::SubmitOp { base_seq: 2, .. })));
+    assert!(project.pending().all(|p| p.base_seq == 2));
+    assert!(project.is_online());
+}
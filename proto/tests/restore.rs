@@ -0,0 +1,46 @@
+//! A restored state must keep every id unique, and comparing states that don't must not
+//! break the diff.
+
+extern crate proto;
+
+use proto::project::{DiffCount, ObjectId, OpError, Operation, Project, Track};
+
+fn track(counter: u32, name: &str) -> Track {
+    Track {
+        id: ObjectId { site: 1, counter },
+        name: name.to_owned(),
+        volume: 1.0,
+        pan: 0.0,
+        muted: false,
+        clips: Vec::new(),
+        devices: Vec::new(),
+    }
+}
+
+#[test]
+fn restore_with_duplicate_ids_is_rejected() {
+    let mut project = Project { tracks: vec![track(0, "Drums")] };
+    let state = Project { tracks: vec![track(1, "Bass"), track(1, "Keys")] };
+    let res = project.apply(&Operation::Restore { state });
+    assert_eq!(res, Err(OpError::DuplicateId(ObjectId { site: 1, counter: 1 })));
+    assert_eq!(project.tracks, vec![track(0, "Drums")]);
+}
+
+#[test]
+fn restore_returns_the_replaced_state() {
+    let old = Project { tracks: vec![track(0, "Drums")] };
+    let new = Project { tracks: vec![track(0, "Drums"), track(1, "Bass")] };
+    let mut project = old.clone();
+    let inverse = project.apply(&Operation::Restore { state: new.clone() }).unwrap();
+    assert_eq!(project, new);
+    assert_eq!(inverse, Operation::Restore { state: old });
+}
+
+#[test]
+fn diff_with_duplicate_ids() {
+    let from = Project { tracks: vec![track(0, "Drums"), track(0, "Drums")] };
+    let to = Project { tracks: vec![track(0, "Drums"), track(1, "Bass")] };
+    let expected = DiffCount { added: 1, removed: 0, changed: 0 };
+    assert_eq!(from.diff_summary(&to).tracks, expected);
+    assert_eq!(to.diff_summary(&from).tracks, DiffCount { added: 0, removed: 1, changed: 0 });
+}
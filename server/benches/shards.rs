@@ -0,0 +1,260 @@
+//! How much do busy projects slow down edits in a quiet one? Run with `cargo bench`.
+//!
+//! Starts the server on a scratch copy of the database, once with a single shard and
+//! once with several. A few projects are crowded with simulated clients, each editing
+//! as fast as the server echoes its operations back. Meanwhile a single client edits
+//! its own project at a steady pace and times every round trip.
+
+extern crate bincode;
+extern crate proto;
+extern crate rusqlite;
+extern crate sha3;
+
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use proto::project::{ObjectId, Operation, Track, TrackProp};
+use rusqlite as sql;
+use sha3::{Digest, Sha3_256};
+
+const ADDR: &str = "127.0.0.1:4451";
+const BUSY_PROJECTS: usize = 3;
+const CLIENTS_PER_PROJECT: usize = 16;
+const PROBE_INTERVAL: Duration = Duration::from_millis(20);
+const WARM_UP: Duration = Duration::from_secs(1);
+const RUN_TIME: Duration = Duration::from_secs(5);
+const PROJECTS: usize = 16;
+const PASSWORD: &str = "bench";
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("chorus_bench_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("Scratch directory");
+    let db_path = dir.join("chorus_studio.db");
+    fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/chorus_studio.db"), &db_path).expect("Database copy");
+    let projects = prepare_db(&db_path);
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    for &shards in &[1, cores.max(2)] {
+        run(&dir, shards, &projects);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Adds the simulated users and some projects. Returns the ids of the projects.
+fn prepare_db(path: &Path) -> Vec<u32> {
+    let db = sql::Connection::open(path).expect("Database");
+    let password = Sha3_256::digest(PASSWORD.as_bytes());
+    for n in 0..BUSY_PROJECTS * CLIENTS_PER_PROJECT + 1 {
+        db.execute_named(r#"
+            INSERT INTO user (email, password, user_name, register_date)
+            VALUES (:email, :password, :user_name, datetime('now'))
+        "#, &[(":email", &email(n)), (":password", &password.as_slice()), (":user_name", &format!("bench_{}", n))]).expect("Add user");
+    }
+    let projects = (0..PROJECTS)
+        .map(|n| {
+            db.execute(r#"
+                INSERT INTO project (title, description, creation_date)
+                VALUES (?, 'Benchmark', datetime('now'))
+            "#, &[&format!("Bench #{}", n)]).expect("Add project");
+            db.last_insert_rowid() as u32
+        })
+        .collect();
+    // Only members may join, let every simulated user into every project
+    db.execute(r#"
+        INSERT INTO user_project (user_email, project_id)
+        SELECT user.email, project.id FROM user, project
+        WHERE user.email LIKE 'bench\_%' ESCAPE '\' AND project.description = 'Benchmark'
+    "#, sql::NO_PARAMS).expect("Add members");
+    projects
+}
+
+fn email(n: usize) -> String {
+    format!("bench_{}@bench.local", n)
+}
+
+fn run(dir: &Path, shards: usize, projects: &[u32]) {
+    let mut server = start_server(dir, shards);
+
+    // The server puts a project on shard `id % shards`, keep the busy ones away from the quiet one
+    let quiet = projects[0];
+    let busy: Vec<u32> = projects[1..].iter()
+        .cloned()
+        .filter(|&id| shards == 1 || id as usize % shards != quiet as usize % shards)
+        .take(BUSY_PROJECTS)
+        .collect();
+    assert_eq!(busy.len(), BUSY_PROJECTS, "Not enough projects for {} shards", shards);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let busy_ops = Arc::new(AtomicUsize::new(0));
+    let mut threads = Vec::new();
+    for (p, &project_id) in busy.iter().enumerate() {
+        for c in 0..CLIENTS_PER_PROJECT {
+            let (stop, busy_ops) = (stop.clone(), busy_ops.clone());
+            let user = p * CLIENTS_PER_PROJECT + c;
+            threads.push(thread::spawn(move || {
+                let mut client = Client::join(user, project_id);
+                let mut value = 0.0;
+                while !stop.load(Ordering::Relaxed) {
+                    value = 1.0 - value;
+                    if client.edit(TrackProp::Volume(value)).is_none() {
+                        break;
+                    }
+                    busy_ops.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+    }
+
+    let mut probe = Client::join(BUSY_PROJECTS * CLIENTS_PER_PROJECT, quiet);
+    thread::sleep(WARM_UP);
+    let ops_before = busy_ops.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut round_trips = Vec::new();
+    while start.elapsed() < RUN_TIME {
+        let sent = Instant::now();
+        if probe.edit(TrackProp::Pan(0.0)).is_none() {
+            break;
+        }
+        round_trips.push(sent.elapsed());
+        thread::sleep(PROBE_INTERVAL.checked_sub(sent.elapsed()).unwrap_or_default());
+    }
+    let busy_rate = (busy_ops.load(Ordering::Relaxed) - ops_before) as f64 / start.elapsed().as_secs_f64();
+
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        let _ = thread.join();
+    }
+    stop_server(&mut server);
+
+    round_trips.sort();
+    let percentile = |p: usize| round_trips[(round_trips.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+    println!(
+        "{} shard(s): quiet project round trip p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, max {:.2} ms ({} samples); busy projects {:.0} ops/s",
+        shards, percentile(50), percentile(90), percentile(99), percentile(100), round_trips.len(), busy_rate,
+    );
+}
+
+fn start_server(dir: &Path, shards: usize) -> Child {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--listen", ADDR, "--workers", &shards.to_string(), "--control-socket", "bench.sock", "--asset-dir", "assets"])
+        .current_dir(dir)
+        // Stays open, so the console keeps waiting for input
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Server start");
+    for _ in 0..50 {
+        if TcpStream::connect(ADDR).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let _ = server.kill();
+    let _ = server.wait();
+    panic!("Server doesn't accept connections");
+}
+
+fn stop_server(server: &mut Child) {
+    let _ = server.stdin.as_mut().unwrap().write_all(b"quit\n");
+    let _ = server.wait();
+}
+
+/// A simulated user, editing a track of its own.
+struct Client {
+    stream: TcpStream,
+    project_id: u32,
+    site: u32,
+    seq: u64,
+    local_seq: u32,
+    track: ObjectId,
+}
+
+impl Client {
+    fn join(user: usize, project_id: u32) -> Self {
+        let stream = TcpStream::connect(ADDR).expect("Connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut client = Client {
+            stream,
+            project_id,
+            site: 0,
+            seq: 0,
+            local_seq: 0,
+            track: ObjectId { site: 0, counter: 0 },
+        };
+        client.send(&proto::Command::Login {
+            email: email(user),
+            password: Sha3_256::digest(PASSWORD.as_bytes()).to_vec(),
+        });
+        client.send(&proto::Command::JoinProject { project_id });
+        loop {
+            match client.recv().expect("Join") {
+                proto::Response::ProjectJoined { site, seq, .. } => {
+                    client.site = site;
+                    client.seq = seq;
+                    break;
+                }
+                proto::Response::LoginInvalid => panic!("Login of bench user {} failed", user),
+                _ => {}
+            }
+        }
+        client.track = ObjectId { site: client.site, counter: 1 };
+        let track = Track {
+            id: client.track,
+            name: format!("Track of {}", user),
+            volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            clips: Vec::new(),
+            devices: Vec::new(),
+        };
+        client.submit(Operation::InsertTrack { after: None, track }).expect("Insert track");
+        client
+    }
+
+    fn edit(&mut self, prop: TrackProp) -> Option<()> {
+        let id = self.track;
+        self.submit(Operation::SetTrack { id, prop })
+    }
+
+    /// Submits `op` and waits until the server applied it. Other clients' operations are skipped.
+    fn submit(&mut self, op: Operation) -> Option<()> {
+        self.local_seq += 1;
+        let cmd = proto::Command::SubmitOp {
+            project_id: self.project_id,
+            local_seq: self.local_seq,
+            base_seq: self.seq,
+            op,
+        };
+        self.send(&cmd);
+        loop {
+            match self.recv()? {
+                proto::Response::OpApplied { seq, site, local_seq, .. } => {
+                    self.seq = seq;
+                    if site == self.site && local_seq == Some(self.local_seq) {
+                        return Some(());
+                    }
+                }
+                proto::Response::OpRejected { error, .. } => panic!("Operation rejected: {:?}", error),
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, cmd: &proto::Command) {
+        // In one piece, bincode writes field by field
+        let buf = bincode::serialize(cmd).expect("Serialize");
+        self.stream.write_all(&buf).expect("Send");
+    }
+
+    fn recv(&mut self) -> Option<proto::Response> {
+        bincode::deserialize_from(&mut self.stream).ok()
+    }
+}
@@ -0,0 +1,347 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use proto::ProjectRole;
+use quota::Quotas;
+
+pub const HELP: &str = "\
+Commands:
+  clients                              List connected clients
+  kick <client id>                     Disconnect a client
+  say <message>                        Send a system message to every client
+  limits                               List login rate limits
+  reset-limits [address]               Reset the login rate limit of one or all addresses
+  create-user <email> <user name> <password>
+  quota <user name> <MiB|unlimited|default>
+  add-member <user name> <project id> [owner|member]
+                                       Let a user join a project, as a member by default
+  remove-member <user name> <project id>
+  set-role <user name> <project id> <owner|member>
+  shutdown [seconds] [reason]          Shut down after a countdown (default 60)
+  restart [seconds] [reason]           Like shutdown, but tells clients to reconnect soon
+  cancel-shutdown
+  quit                                 Shut down right away";
+
+/// Seconds after which clients are told to reconnect when the server restarts.
+const RESTART_RECONNECT_AFTER: u32 = 10;
+
+pub enum AdminCommand {
+    Help,
+    Clients,
+    Kick(usize),
+    Say(String),
+    Limits,
+    ResetLimits(Option<IpAddr>),
+    CreateUser { email: String, user_name: String, password: String },
+    /// `None` restores the default quota, `Some(None)` removes the limit.
+    Quota { user_name: String, limit: Option<Option<u64>> },
+    AddMember { user_name: String, project_id: u32, role: ProjectRole },
+    RemoveMember { user_name: String, project_id: u32 },
+    SetRole { user_name: String, project_id: u32, role: ProjectRole },
+    Shutdown { delay: Duration, reason: String, reconnect_after: Option<u32> },
+    CancelShutdown,
+}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, ""),
+        };
+        let args: Vec<_> = rest.split_whitespace().collect();
+        match (name, args.as_slice()) {
+            ("help", []) => Ok(AdminCommand::Help),
+            ("clients", []) => Ok(AdminCommand::Clients),
+            ("kick", [id]) => id.parse()
+                .map(AdminCommand::Kick)
+                .map_err(|_| format!("Invalid client id {:?}", id)),
+            ("say", _) if !rest.is_empty() => Ok(AdminCommand::Say(rest.to_owned())),
+            ("limits", []) => Ok(AdminCommand::Limits),
+            ("reset-limits", []) => Ok(AdminCommand::ResetLimits(None)),
+            ("reset-limits", [addr]) => addr.parse()
+                .map(|addr| AdminCommand::ResetLimits(Some(addr)))
+                .map_err(|_| format!("Invalid address {:?}", addr)),
+            ("create-user", [email, user_name, password]) => Ok(AdminCommand::CreateUser {
+                email: (*email).to_owned(),
+                user_name: (*user_name).to_owned(),
+                password: (*password).to_owned(),
+            }),
+            ("quota", [user_name, "default"]) => Ok(AdminCommand::Quota { user_name: (*user_name).to_owned(), limit: None }),
+            ("quota", [user_name, limit]) => match Quotas::parse_limit(limit) {
+                Some(limit) => Ok(AdminCommand::Quota { user_name: (*user_name).to_owned(), limit: Some(limit) }),
+                None => Err(format!("Invalid quota {:?}", limit)),
+            },
+            ("add-member", [user_name, project_id]) => Ok(AdminCommand::AddMember {
+                user_name: (*user_name).to_owned(),
+                project_id: parse_project_id(project_id)?,
+                role: ProjectRole::Member,
+            }),
+            ("add-member", [user_name, project_id, role]) => Ok(AdminCommand::AddMember {
+                user_name: (*user_name).to_owned(),
+                project_id: parse_project_id(project_id)?,
+                role: parse_role(role)?,
+            }),
+            ("remove-member", [user_name, project_id]) => Ok(AdminCommand::RemoveMember {
+                user_name: (*user_name).to_owned(),
+                project_id: parse_project_id(project_id)?,
+            }),
+            ("set-role", [user_name, project_id, role]) => Ok(AdminCommand::SetRole {
+                user_name: (*user_name).to_owned(),
+                project_id: parse_project_id(project_id)?,
+                role: parse_role(role)?,
+            }),
+            ("shutdown", _) => Ok(Self::parse_shutdown(rest, None)),
+            ("restart", _) => Ok(Self::parse_shutdown(rest, Some(RESTART_RECONNECT_AFTER))),
+            ("cancel-shutdown", []) => Ok(AdminCommand::CancelShutdown),
+            ("quit", []) => Ok(Self::parse_shutdown("0", None)),
+            _ => Err(format!("Unknown command or wrong arguments: {:?}. Enter \"help\" for a list of commands.", line)),
+        }
+    }
+
+    /// Parses "[seconds] [reason]".
+    fn parse_shutdown(args: &str, reconnect_after: Option<u32>) -> Self {
+        let (secs, reason) = match args.find(char::is_whitespace) {
+            Some(idx) => (&args[..idx], args[idx..].trim()),
+            None => (args, ""),
+        };
+        let (delay, reason) = match secs.parse() {
+            Ok(secs) => (Duration::from_secs(secs), reason),
+            // No countdown given, it's all reason
+            Err(_) => (Duration::from_secs(60), args),
+        };
+        let reason = if reason.is_empty() {
+            "Shut down by the server operator".to_owned()
+        } else {
+            reason.to_owned()
+        };
+        AdminCommand::Shutdown { delay, reason, reconnect_after }
+    }
+}
+
+fn parse_project_id(arg: &str) -> Result<u32, String> {
+    arg.parse().map_err(|_| format!("Invalid project id {:?}", arg))
+}
+
+fn parse_role(arg: &str) -> Result<ProjectRole, String> {
+    ProjectRole::from_name(arg).ok_or_else(|| format!("Invalid role {:?}, expected owner or member", arg))
+}
+
+/// An admin command together with where to send its output.
+pub struct AdminRequest {
+    pub cmd: AdminCommand,
+    pub reply: mpsc::Sender<String>,
+}
+
+/// Remaining seconds at which a pending shutdown is announced to the clients.
+const ANNOUNCE_AT: &[u64] = &[600, 300, 120, 60, 30, 10, 5, 4, 3, 2, 1];
+
+/// A shutdown counting down.
+pub struct Shutdown {
+    at: Instant,
+    /// Remaining seconds of the last announcement
+    announced: Option<u64>,
+    pub reason: String,
+    pub reconnect_after: Option<u32>,
+}
+
+impl Shutdown {
+    pub fn new(delay: Duration, reason: String, reconnect_after: Option<u32>, now: Instant) -> Self {
+        Self {
+            at: now + delay,
+            announced: None,
+            reason,
+            reconnect_after,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        now >= self.at
+    }
+
+    /// The remaining seconds, if they should be announced now.
+    pub fn announcement(&mut self, now: Instant) -> Option<u64> {
+        if self.is_due(now) {
+            return None;
+        }
+        // Round up, so a countdown started with 60 seconds announces 60 and not 59
+        let left = self.at - now;
+        let secs = left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 };
+        let due = match self.announced {
+            None => true,
+            Some(last) => ANNOUNCE_AT.iter().any(|&a| a >= secs && a < last),
+        };
+        if due {
+            self.announced = Some(secs);
+            Some(secs)
+        } else {
+            None
+        }
+    }
+}
+
+/// Handles one line of admin input: parses it, forwards it to the event loop and
+/// writes the output to `out`.
+fn handle_line(line: &str, requests: &mpsc::Sender<AdminRequest>, out: &mut impl Write) -> io::Result<bool> {
+    if line.trim().is_empty() {
+        return Ok(true);
+    }
+    let output = match AdminCommand::parse(line) {
+        Ok(AdminCommand::Help) => HELP.to_owned(),
+        Ok(cmd) => {
+            let (reply, reply_rx) = mpsc::channel();
+            if requests.send(AdminRequest { cmd, reply }).is_err() {
+                // The server is gone
+                return Ok(false);
+            }
+            match reply_rx.recv() {
+                Ok(output) => output,
+                Err(_) => return Ok(false),
+            }
+        }
+        Err(e) => e,
+    };
+    writeln!(out, "{}", output)?;
+    out.flush()?;
+    Ok(true)
+}
+
+/// Reads admin commands from stdin.
+pub fn spawn_console(requests: mpsc::Sender<AdminRequest>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            match handle_line(&line, &requests, &mut io::stdout()) {
+                Ok(true) => {}
+                _ => return,
+            }
+        }
+    })
+}
+
+/// Accepts admin connections on a local Unix socket, so a server running in the
+/// background can be administrated with e.g. `socat - UNIX-CONNECT:<path>`.
+/// Every line sent is one command, answered with its output.
+#[cfg(unix)]
+pub fn spawn_control_socket(path: PathBuf, requests: mpsc::Sender<AdminRequest>) -> io::Result<thread::JoinHandle<()>> {
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    // Left over from a previous run that didn't exit cleanly
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("Control socket accept ERR: {}", e);
+                    continue;
+                }
+            };
+            let requests = requests.clone();
+            thread::spawn(move || {
+                let reader = match stream.try_clone() {
+                    Ok(s) => BufReader::new(s),
+                    Err(_) => return,
+                };
+                for line in reader.lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => return,
+                    };
+                    match handle_line(&line, &requests, &mut stream) {
+                        Ok(true) => {}
+                        _ => return,
+                    }
+                }
+            });
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn spawn_control_socket(_path: PathBuf, _requests: mpsc::Sender<AdminRequest>) -> io::Result<thread::JoinHandle<()>> {
+    Err(io::Error::new(io::ErrorKind::Other, "Control sockets are only supported on Unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert!(matches!(AdminCommand::parse(" kick 3 "), Ok(AdminCommand::Kick(3))));
+        assert!(matches!(AdminCommand::parse("say Back in  5"), Ok(AdminCommand::Say(ref text)) if text == "Back in  5"));
+        assert!(matches!(AdminCommand::parse("reset-limits"), Ok(AdminCommand::ResetLimits(None))));
+        assert!(matches!(
+            AdminCommand::parse("reset-limits 10.0.0.1"),
+            Ok(AdminCommand::ResetLimits(Some(addr))) if addr == IpAddr::from([10, 0, 0, 1])
+        ));
+        assert!(matches!(AdminCommand::parse("quota bob default"), Ok(AdminCommand::Quota { limit: None, .. })));
+        assert!(matches!(AdminCommand::parse("quota bob unlimited"), Ok(AdminCommand::Quota { limit: Some(None), .. })));
+        assert!(matches!(
+            AdminCommand::parse("quota bob 2"),
+            Ok(AdminCommand::Quota { ref user_name, limit: Some(Some(2097152)) }) if user_name == "bob"
+        ));
+        assert!(matches!(
+            AdminCommand::parse("add-member bob 4"),
+            Ok(AdminCommand::AddMember { project_id: 4, role: ProjectRole::Member, .. })
+        ));
+        assert!(matches!(
+            AdminCommand::parse("add-member bob 4 owner"),
+            Ok(AdminCommand::AddMember { project_id: 4, role: ProjectRole::Owner, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_rejects_bad_arguments() {
+        for line in &["kick", "kick bob", "say", "reset-limits nowhere", "quota bob lots", "add-member bob four",
+                      "set-role bob 4 admin", "remove-member bob", "quit now", "frobnicate"] {
+            assert!(AdminCommand::parse(line).is_err(), "{:?} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn parse_shutdowns() {
+        let secs = Duration::from_secs;
+        let parsed = |line| match AdminCommand::parse(line) {
+            Ok(AdminCommand::Shutdown { delay, reason, reconnect_after }) => (delay, reason, reconnect_after),
+            _ => panic!("{:?} isn't a shutdown", line),
+        };
+        assert_eq!(parsed("shutdown"), (secs(60), "Shut down by the server operator".to_owned(), None));
+        assert_eq!(parsed("shutdown 30 Disk swap"), (secs(30), "Disk swap".to_owned(), None));
+        assert_eq!(parsed("shutdown Disk swap"), (secs(60), "Disk swap".to_owned(), None));
+        assert_eq!(parsed("restart 5"), (secs(5), "Shut down by the server operator".to_owned(), Some(RESTART_RECONNECT_AFTER)));
+        assert_eq!(parsed("quit").0, secs(0));
+    }
+
+    #[test]
+    fn shutdown_announcements() {
+        let start = Instant::now();
+        let mut shutdown = Shutdown::new(Duration::from_secs(65), String::new(), None, start);
+        let at = |secs: u64, millis: u64| start + Duration::from_secs(secs) + Duration::from_millis(millis);
+        // The start is always announced, rounded up
+        assert_eq!(shutdown.announcement(at(0, 500)), Some(65));
+        assert_eq!(shutdown.announcement(at(1, 0)), None);
+        assert_eq!(shutdown.announcement(at(5, 0)), Some(60));
+        assert_eq!(shutdown.announcement(at(5, 500)), None);
+        // Ticks that were missed are announced late, once
+        assert_eq!(shutdown.announcement(at(40, 0)), Some(25));
+        assert_eq!(shutdown.announcement(at(41, 0)), None);
+        assert_eq!(shutdown.announcement(at(64, 0)), Some(1));
+        assert!(!shutdown.is_due(at(64, 999)));
+        assert!(shutdown.is_due(at(65, 0)));
+        assert_eq!(shutdown.announcement(at(65, 0)), None);
+    }
+}
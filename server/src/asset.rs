@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use proto::asset::{hash_hex, AssetError, AssetHash, CHUNK_SIZE};
+use quota::Quotas;
+use sha3::{Digest, Sha3_256};
+
+/// How long an upload has to be idle before someone else may take it over.
+pub const UPLOAD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// An upload in progress, owned by whoever started it for a project.
+struct Upload {
+    user_name: String,
+    project_id: u32,
+    size: u64,
+    last_activity: Instant,
+    partial: Arc<Mutex<Partial>>,
+}
+
+/// The data of an upload in progress. Only locked by whoever reads or writes the file,
+/// so a slow disk never blocks the lookup of other uploads.
+struct Partial {
+    /// Opened by the first [AssetStore::begin_upload]
+    file: Option<File>,
+    received: u64,
+    /// Hash of the first `received` bytes
+    hasher: Sha3_256,
+    /// Verified and moved to its final place, or discarded
+    done: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChunkResult {
+    Progress(u64),
+    /// The upload is complete and verified.
+    Complete { project_id: u32, size: u64 },
+}
+
+/// Content addressed storage of asset files.
+///
+/// Every asset is stored in a file named after the hex representation of its hash.
+/// Uploads in progress live in the `partial` subdirectory, so an interrupted upload
+/// can be resumed where it stopped, even after a server restart.
+/// Uploads and downloads happen in chunks of at most [CHUNK_SIZE] bytes. Everything that
+/// touches files is meant to run on the database thread, not on a shard's event loop.
+pub struct AssetStore {
+    dir: PathBuf,
+    /// Locked only to look up or add uploads, never during file I/O
+    uploads: Mutex<HashMap<AssetHash, Upload>>,
+    quotas: Quotas,
+}
+
+impl AssetStore {
+    pub fn new(dir: impl Into<PathBuf>, quotas: Quotas) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("partial"))?;
+        Ok(Self {
+            dir,
+            uploads: Mutex::new(HashMap::new()),
+            quotas,
+        })
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    fn path(&self, hash: &AssetHash) -> PathBuf {
+        self.dir.join(hash_hex(hash))
+    }
+
+    fn partial_path(&self, hash: &AssetHash) -> PathBuf {
+        self.dir.join("partial").join(hash_hex(hash))
+    }
+
+    pub fn contains(&self, hash: &AssetHash) -> bool {
+        self.path(hash).is_file()
+    }
+
+    /// Starts or resumes an upload. Returns the offset the upload continues at, or the
+    /// completed upload if the server has all of it already. An upload in progress is
+    /// only resumed by whoever started it for the same project, unless it has been idle
+    /// for [UPLOAD_TIMEOUT].
+    ///
+    /// `known` tells whether a project of the user references the asset already. Only
+    /// then is a stored asset taken as it is, anyone else has to upload all of it to
+    /// prove they have the content, rather than get someone else's file by its hash.
+    pub fn begin_upload(&self, user_name: &str, project_id: u32, hash: AssetHash, size: u64, known: bool) -> Result<ChunkResult, AssetError> {
+        if known && self.contains(&hash) {
+            // Nothing new for the user, no need to transfer anything
+            return Ok(ChunkResult::Complete { project_id, size });
+        }
+        let received = {
+            let partial = self.claim_upload(user_name, project_id, hash, size)?;
+            let mut partial = partial.lock().unwrap();
+            if partial.done {
+                return Err(AssetError::NoUpload);
+            }
+            if partial.file.is_none() {
+                partial.open(&self.partial_path(&hash), size).map_err(|e| {
+                    println!("Failed to start upload of {}: {}", hash_hex(&hash), e);
+                    AssetError::Storage
+                })?;
+            }
+            partial.received
+        };
+        if received == size {
+            // Everything's there already, e.g. an empty file
+            self.write_chunk(user_name, hash, received, &[])
+        } else {
+            Ok(ChunkResult::Progress(received))
+        }
+    }
+
+    /// Finds or adds the upload of `hash` for the user and project.
+    fn claim_upload(&self, user_name: &str, project_id: u32, hash: AssetHash, size: u64) -> Result<Arc<Mutex<Partial>>, AssetError> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let now = Instant::now();
+        let upload = uploads.entry(hash).or_insert_with(|| Upload {
+            user_name: user_name.to_owned(),
+            project_id,
+            size,
+            last_activity: now,
+            partial: Arc::new(Mutex::new(Partial {
+                file: None,
+                received: 0,
+                hasher: Sha3_256::new(),
+                done: false,
+            })),
+        });
+        if upload.user_name != user_name || upload.project_id != project_id {
+            if now < upload.last_activity + UPLOAD_TIMEOUT {
+                return Err(AssetError::UploadBusy);
+            }
+            // Abandoned, what's there is as good as anyone's since it's verified in the end
+            upload.user_name = user_name.to_owned();
+            upload.project_id = project_id;
+        }
+        if upload.size != size {
+            return Err(AssetError::SizeMismatch { size: upload.size });
+        }
+        upload.last_activity = now;
+        Ok(upload.partial.clone())
+    }
+
+    /// The project an upload of `user_name` is for.
+    pub fn upload_project(&self, user_name: &str, hash: &AssetHash) -> Result<u32, AssetError> {
+        match self.uploads.lock().unwrap().get(hash) {
+            Some(upload) if upload.user_name == user_name => Ok(upload.project_id),
+            Some(_) => Err(AssetError::UploadBusy),
+            None => Err(AssetError::NoUpload),
+        }
+    }
+
+    pub fn write_chunk(&self, user_name: &str, hash: AssetHash, offset: u64, data: &[u8]) -> Result<ChunkResult, AssetError> {
+        let (project_id, size, partial) = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let upload = uploads.get_mut(&hash).ok_or(AssetError::NoUpload)?;
+            if upload.user_name != user_name {
+                return Err(AssetError::UploadBusy);
+            }
+            upload.last_activity = Instant::now();
+            (upload.project_id, upload.size, upload.partial.clone())
+        };
+        let mut upload = partial.lock().unwrap();
+        if upload.done {
+            return Err(AssetError::NoUpload);
+        }
+        let received = upload.received;
+        if offset != received {
+            return Err(AssetError::UnexpectedOffset { expected: received });
+        }
+        if received + data.len() as u64 > size {
+            return Err(AssetError::TooLarge);
+        }
+        {
+            let file = upload.file.as_mut().ok_or(AssetError::NoUpload)?;
+            file.seek(SeekFrom::Start(offset)).map_err(|_| AssetError::Storage)?;
+            file.write_all(data).map_err(|_| AssetError::Storage)?;
+        }
+        upload.hasher.input(data);
+        upload.received += data.len() as u64;
+        if upload.received < size {
+            return Ok(ChunkResult::Progress(upload.received));
+        }
+
+        upload.done = true;
+        upload.file = None;
+        {
+            let mut uploads = self.uploads.lock().unwrap();
+            if uploads.get(&hash).is_some_and(|u| Arc::ptr_eq(&u.partial, &partial)) {
+                uploads.remove(&hash);
+            }
+        }
+        let partial_path = self.partial_path(&hash);
+        let hasher = mem::replace(&mut upload.hasher, Sha3_256::new());
+        if hasher.result().as_slice() != &hash[..] {
+            let _ = fs::remove_file(&partial_path);
+            return Err(AssetError::HashMismatch);
+        }
+        fs::rename(&partial_path, self.path(&hash)).map_err(|_| AssetError::Storage)?;
+        Ok(ChunkResult::Complete { project_id, size })
+    }
+
+    /// Reads the chunk starting at `offset`. Returns the data and the asset's total size.
+    pub fn read_chunk(&self, hash: &AssetHash, offset: u64) -> Result<(Vec<u8>, u64), AssetError> {
+        let mut file = File::open(self.path(hash)).map_err(|_| AssetError::NoSuchAsset)?;
+        let size = file.metadata().map_err(|_| AssetError::Storage)?.len();
+        let mut data = Vec::with_capacity(CHUNK_SIZE);
+        file.seek(SeekFrom::Start(offset)).map_err(|_| AssetError::Storage)?;
+        file.take(CHUNK_SIZE as u64).read_to_end(&mut data).map_err(|_| AssetError::Storage)?;
+        Ok((data, size))
+    }
+
+    pub fn remove(&self, hash: &AssetHash) -> io::Result<()> {
+        fs::remove_file(self.path(hash))
+    }
+}
+
+impl Partial {
+    /// Opens the partial file, picking up what an earlier upload left there.
+    fn open(&mut self, path: &Path, size: u64) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)?;
+        let mut received = file.metadata()?.len();
+        let mut hasher = Sha3_256::new();
+        if received > size {
+            // Left over from an upload of different content, start from scratch
+            file.set_len(0)?;
+            received = 0;
+        } else {
+            hash_into(&mut hasher, &mut file)?;
+        }
+        self.file = Some(file);
+        self.received = received;
+        self.hasher = hasher;
+        Ok(())
+    }
+}
+
+fn hash_into(hasher: &mut Sha3_256, reader: &mut impl Read) -> io::Result<()> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        hasher.input(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in a directory of its own, emptied in case a run before left it.
+    fn store(test: &str) -> AssetStore {
+        let dir = std::env::temp_dir().join(format!("chorus_assets_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        AssetStore::new(dir, Quotas::default()).unwrap()
+    }
+
+    fn hash(data: &[u8]) -> AssetHash {
+        let mut hash = AssetHash::default();
+        hash.copy_from_slice(Sha3_256::digest(data).as_slice());
+        hash
+    }
+
+    fn read_all(store: &AssetStore, hash: &AssetHash) -> Vec<u8> {
+        let (data, size) = store.read_chunk(hash, 0).unwrap();
+        assert_eq!(data.len() as u64, size);
+        data
+    }
+
+    #[test]
+    fn upload_in_chunks() {
+        let store = store("chunks");
+        let data = b"kick snare kick snare";
+        let hash = hash(data);
+        assert_eq!(store.begin_upload("alice", 1, hash, data.len() as u64, false), Ok(ChunkResult::Progress(0)));
+        assert_eq!(store.write_chunk("alice", hash, 0, &data[..10]), Ok(ChunkResult::Progress(10)));
+        assert_eq!(store.write_chunk("alice", hash, 5, &data[10..]), Err(AssetError::UnexpectedOffset { expected: 10 }));
+        assert_eq!(store.write_chunk("bob", hash, 10, &data[10..]), Err(AssetError::UploadBusy));
+        assert!(!store.contains(&hash));
+
+        assert_eq!(
+            store.write_chunk("alice", hash, 10, &data[10..]),
+            Ok(ChunkResult::Complete { project_id: 1, size: data.len() as u64 })
+        );
+        assert!(store.contains(&hash));
+        assert_eq!(read_all(&store, &hash), data);
+        assert_eq!(store.write_chunk("alice", hash, 0, data), Err(AssetError::NoUpload));
+    }
+
+    #[test]
+    fn hash_mismatch_on_the_last_chunk() {
+        let store = store("mismatch");
+        let hash = hash(b"the real thing");
+        let fake = b"something else";
+        assert_eq!(store.begin_upload("alice", 1, hash, fake.len() as u64, false), Ok(ChunkResult::Progress(0)));
+        assert_eq!(store.write_chunk("alice", hash, 0, &fake[..4]), Ok(ChunkResult::Progress(4)));
+        assert_eq!(store.write_chunk("alice", hash, 4, &fake[4..]), Err(AssetError::HashMismatch));
+        assert!(!store.contains(&hash));
+        // Discarded, starting over begins at nothing
+        assert_eq!(store.begin_upload("alice", 1, hash, fake.len() as u64, false), Ok(ChunkResult::Progress(0)));
+    }
+
+    #[test]
+    fn too_much_data() {
+        let store = store("too_much");
+        let data = b"four";
+        let hash = hash(data);
+        assert_eq!(store.begin_upload("alice", 1, hash, 3, false), Ok(ChunkResult::Progress(0)));
+        assert_eq!(store.write_chunk("alice", hash, 0, data), Err(AssetError::TooLarge));
+        assert_eq!(store.begin_upload("alice", 1, hash, 4, false), Err(AssetError::SizeMismatch { size: 3 }));
+    }
+
+    #[test]
+    fn stored_assets_are_only_skipped_when_known() {
+        let store = store("known");
+        let data = b"a loop everyone likes";
+        let hash = hash(data);
+        let size = data.len() as u64;
+        store.begin_upload("alice", 1, hash, size, false).unwrap();
+        store.write_chunk("alice", hash, 0, data).unwrap();
+
+        assert_eq!(store.begin_upload("alice", 2, hash, size, true), Ok(ChunkResult::Complete { project_id: 2, size }));
+        // Knowing the hash isn't enough, bob has to send the content
+        assert_eq!(store.begin_upload("bob", 3, hash, size, false), Ok(ChunkResult::Progress(0)));
+        assert_eq!(store.write_chunk("bob", hash, 0, b"not the loop at all..."), Err(AssetError::TooLarge));
+        assert_eq!(store.write_chunk("bob", hash, 0, data), Ok(ChunkResult::Complete { project_id: 3, size }));
+        assert_eq!(read_all(&store, &hash), data);
+    }
+
+    #[test]
+    fn upload_resumes_after_a_restart() {
+        let store = store("resume");
+        let data = b"a long recording";
+        let hash = hash(data);
+        let size = data.len() as u64;
+        store.begin_upload("alice", 1, hash, size, false).unwrap();
+        store.write_chunk("alice", hash, 0, &data[..6]).unwrap();
+        // Someone else only gets it once it's been abandoned
+        assert_eq!(store.begin_upload("bob", 1, hash, size, false), Err(AssetError::UploadBusy));
+        assert_eq!(store.upload_project("alice", &hash), Ok(1));
+
+        let restarted = AssetStore::new(store.dir.clone(), Quotas::default()).unwrap();
+        assert_eq!(restarted.begin_upload("alice", 1, hash, size, false), Ok(ChunkResult::Progress(6)));
+        assert_eq!(restarted.write_chunk("alice", hash, 6, &data[6..]), Ok(ChunkResult::Complete { project_id: 1, size }));
+        assert_eq!(read_all(&restarted, &hash), data);
+    }
+}
@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::io::{Read, Write};
+use std::net::{self, SocketAddr};
+use std::time::Instant;
+
+use mio::net::TcpStream;
+use mio::{Events, Poll};
+
+use recording::ClientRecorder;
+
+pub struct ClientSock {
+    /// Registered with the `Poll` of the thread the client is on
+    pub stream: TcpStream,
+    /// The accepted socket, `stream` is a duplicate of it
+    socket: net::TcpStream,
+    pub addr: SocketAddr,
+    data_buf: Vec<u8>,
+    /// Responses the socket didn't take yet. Behind a `RefCell`, because messages are
+    /// sent to other clients while the client map is borrowed immutably.
+    out_buf: RefCell<Vec<u8>>,
+    /// Received commands that weren't handled yet
+    pub commands: VecDeque<proto::Command>,
+    /// Whether the client's previous command waits for the database
+    pub waiting: bool,
+    /// Set if the server records sessions
+    recorder: Option<ClientRecorder>,
+}
+
+impl ClientSock {
+    pub fn new(socket: net::TcpStream, addr: SocketAddr, recorder: Option<ClientRecorder>) -> io::Result<Self> {
+        // Responses are small and should arrive right away, not once enough piled up
+        socket.set_nodelay(true)?;
+        Ok(Self {
+            stream: TcpStream::from_stream(socket.try_clone()?)?,
+            socket,
+            addr,
+            data_buf: Vec::with_capacity(1024),
+            out_buf: RefCell::new(Vec::new()),
+            commands: VecDeque::new(),
+            waiting: false,
+            recorder,
+        })
+    }
+
+    /// Takes the socket off `poll`, so another thread's `Poll` can take it over.
+    /// mio doesn't allow registering a socket with a second `Poll`, so a fresh
+    /// duplicate of the socket replaces the old one.
+    pub fn deregister(&mut self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(&self.stream)?;
+        self.stream = TcpStream::from_stream(self.socket.try_clone()?)?;
+        Ok(())
+    }
+
+    /// Queues `msg` and writes as much of the queue as the socket takes right now.
+    /// The rest is written once the socket is writable again.
+    pub fn send(&self, msg: &proto::Response) {
+        if let Some(ref recorder) = self.recorder {
+            recorder.response(msg);
+        }
+        if let Err(e) = bincode::serialize_into(&mut *self.out_buf.borrow_mut(), msg) {
+            println!("Failed to serialize response: {}", e);
+            return;
+        }
+        if let Err(e) = self.flush() {
+            println!("Failed to write response: {}", e);
+        }
+    }
+
+    /// Writes queued responses. Returns whether the queue is empty now.
+    pub fn flush(&self) -> io::Result<bool> {
+        let mut buf = self.out_buf.borrow_mut();
+        let mut written = 0;
+        let res = loop {
+            if written == buf.len() {
+                break Ok(true);
+            }
+            match (&self.stream).write(&buf[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        buf.drain(..written);
+        res
+    }
+
+    /// Reads everything that's available. Returns `false` once the connection is closed.
+    pub fn read_socket(&mut self) -> bool {
+        match self.stream.read_to_end(&mut self.data_buf) {
+            // Only returns successfully once the peer closed the connection
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(_) => {}
+        }
+        if let Some(ref recorder) = self.recorder {
+            recorder.closed();
+        }
+        false
+    }
+
+    pub fn try_deserialize(&mut self) -> Option<proto::Command> {
+        let (cmd, bytes_read) = {
+            let mut bytes = self.data_buf.as_slice();
+            let cmd = bincode::deserialize_from(&mut bytes);
+            let bytes_read = bytes.as_ptr() as usize - self.data_buf.as_slice().as_ptr() as usize;
+            (cmd, bytes_read)
+        };
+        if bytes_read > 0 {
+            println!("try_deserialize: {} bytes", bytes_read);
+        }
+
+        match cmd {
+            Ok(cmd) => {
+                self.data_buf.drain(0..bytes_read);
+                if let Some(ref recorder) = self.recorder {
+                    recorder.command(&cmd);
+                }
+                Some(cmd)
+            }
+            Err(e) => {
+                match *e {
+                    bincode::ErrorKind::Io(..) => {} // Not enough data received yet
+                    _ => { self.data_buf.drain(0..bytes_read); },
+                };
+                None
+            }
+        }
+    }
+}
+
+pub fn ignore_timeout(err: io::Error) -> Option<io::Error> {
+    if err.kind() == io::ErrorKind::WouldBlock {
+        None
+    } else {
+        Some(err)
+    }
+}
+
+/// Waits until all queued responses are written or `deadline` passed.
+pub fn drain_clients(poll: &Poll, events: &mut Events, clients: &HashMap<usize, ClientSock>, deadline: Instant) {
+    loop {
+        // Clients whose connection broke can't be drained, don't wait for them
+        let pending = clients.values().filter(|c| !c.flush().unwrap_or(true)).count();
+        let now = Instant::now();
+        if pending == 0 || now >= deadline {
+            if pending > 0 {
+                println!("Gave up delivering to {} client(s)", pending);
+            }
+            return;
+        }
+        if let Err(e) = poll.poll(events, Some(deadline - now)) {
+            if let Some(e) = ignore_timeout(e) {
+                println!("poll ERR: {}", e);
+            }
+        }
+    }
+}
+
+pub fn send_to(clients: &HashMap<usize, ClientSock>, client_id: usize, msg: &proto::Response) {
+    if let Some(c) = clients.get(&client_id) {
+        c.send(msg);
+    }
+}
@@ -1,6 +1,22 @@
+use std::path::Path;
+
 use rusqlite as sql;
 use self::sql::OptionalExtension;
+use bincode;
 use proto;
+use proto::asset::{AssetHash, AssetInfo};
+use proto::project::{LoggedOp, Project};
+use project::NewActivity;
+
+pub mod worker;
+
+fn encode_error(e: bincode::Error) -> sql::Error {
+	sql::Error::ToSqlConversionFailure(e)
+}
+
+fn decode_error(column: usize, e: bincode::Error) -> sql::Error {
+	sql::Error::FromSqlConversionFailure(column, sql::types::Type::Blob, e)
+}
 
 pub struct Database {
 	db: sql::Connection,
@@ -8,9 +24,117 @@ pub struct Database {
 
 impl Database {
 	pub fn new() -> sql::Result<Self> {
-		Ok(Self {
-			db: sql::Connection::open_with_flags("chorus_studio.db", sql::OpenFlags::SQLITE_OPEN_READ_WRITE)?,
-		})
+		Self::open(Path::new("chorus_studio.db"))
+	}
+
+	pub fn open(path: &Path) -> sql::Result<Self> {
+		let db = Self {
+			db: sql::Connection::open_with_flags(path, sql::OpenFlags::SQLITE_OPEN_READ_WRITE)?,
+		};
+		db.create_tables()?;
+		Ok(db)
+	}
+
+	/// Writes a consistent copy of the database to `path`, which must not exist yet.
+	pub fn copy_to(&self, path: &Path) -> sql::Result<()> {
+		self.db.execute("VACUUM INTO ?", &[&path.to_string_lossy().into_owned()])?;
+		Ok(())
+	}
+
+	/// Creates the tables added after the initial schema, if they don't exist yet.
+	fn create_tables(&self) -> sql::Result<()> {
+		let had_user_assets = self.db.prepare("SELECT user_asset.refs FROM user_asset LIMIT 0").is_ok();
+		self.db.execute_batch(r#"
+			CREATE TABLE IF NOT EXISTS "project_op" (
+				"project_id"	INTEGER NOT NULL,
+				"seq"	INTEGER NOT NULL,
+				"site"	INTEGER NOT NULL,
+				"local_seq"	INTEGER,
+				"op"	BLOB NOT NULL,
+				FOREIGN KEY("project_id") REFERENCES "project"("id"),
+				PRIMARY KEY("project_id","seq")
+			);
+			CREATE TABLE IF NOT EXISTS "project_snapshot" (
+				"id"	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+				"project_id"	INTEGER NOT NULL,
+				"seq"	INTEGER NOT NULL,
+				"name"	TEXT,
+				"creation_date"	TEXT NOT NULL,
+				"state"	BLOB NOT NULL,
+				FOREIGN KEY("project_id") REFERENCES "project"("id")
+			);
+			CREATE TABLE IF NOT EXISTS "asset" (
+				"hash"	BLOB NOT NULL PRIMARY KEY,
+				"size"	INTEGER NOT NULL
+			);
+			CREATE TABLE IF NOT EXISTS "project_asset" (
+				"project_id"	INTEGER NOT NULL,
+				"hash"	BLOB NOT NULL,
+				"refs"	INTEGER NOT NULL,
+				"owner"	TEXT NOT NULL,
+				FOREIGN KEY("project_id") REFERENCES "project"("id"),
+				FOREIGN KEY("hash") REFERENCES "asset"("hash"),
+				FOREIGN KEY("owner") REFERENCES "user"("user_name"),
+				PRIMARY KEY("project_id","hash")
+			);
+			CREATE TABLE IF NOT EXISTS "user_quota" (
+				"user_name"	TEXT NOT NULL PRIMARY KEY,
+				"limit_bytes"	INTEGER,
+				FOREIGN KEY("user_name") REFERENCES "user"("user_name")
+			);
+			CREATE TABLE IF NOT EXISTS "project_activity" (
+				"id"	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT UNIQUE,
+				"project_id"	INTEGER NOT NULL,
+				"user_name"	TEXT NOT NULL,
+				"date"	TEXT NOT NULL,
+				"kind"	TEXT NOT NULL,
+				"seq"	INTEGER,
+				"summary"	TEXT NOT NULL,
+				FOREIGN KEY("project_id") REFERENCES "project"("id")
+			);
+			CREATE TABLE IF NOT EXISTS "user_profile" (
+				"user_name"	TEXT NOT NULL PRIMARY KEY,
+				"bio"	TEXT,
+				"avatar"	BLOB,
+				"show_real_name"	INTEGER NOT NULL,
+				"show_birth_date"	INTEGER NOT NULL,
+				"show_register_date"	INTEGER NOT NULL,
+				"show_bio"	INTEGER NOT NULL,
+				"show_avatar"	INTEGER NOT NULL,
+				FOREIGN KEY("user_name") REFERENCES "user"("user_name")
+			);
+			CREATE TABLE IF NOT EXISTS "user_asset" (
+				"user_name"	TEXT NOT NULL,
+				"hash"	BLOB NOT NULL,
+				"refs"	INTEGER NOT NULL,
+				FOREIGN KEY("user_name") REFERENCES "user"("user_name"),
+				FOREIGN KEY("hash") REFERENCES "asset"("hash"),
+				PRIMARY KEY("user_name","hash")
+			);
+			CREATE INDEX IF NOT EXISTS "project_activity_project" ON "project_activity" ("project_id", "id");
+			-- An audit log nobody can tamper with through the server
+			CREATE TRIGGER IF NOT EXISTS "project_activity_no_update" BEFORE UPDATE ON "project_activity"
+			BEGIN SELECT RAISE(ABORT, 'project_activity is append-only'); END;
+			CREATE TRIGGER IF NOT EXISTS "project_activity_no_delete" BEFORE DELETE ON "project_activity"
+			BEGIN SELECT RAISE(ABORT, 'project_activity is append-only'); END;
+		"#)?;
+
+		// Databases from before storage quotas don't know who owns an asset
+		if self.db.prepare("SELECT project_asset.owner FROM project_asset LIMIT 0").is_err() {
+			self.db.execute_batch(r#"ALTER TABLE "project_asset" ADD COLUMN "owner" TEXT NOT NULL DEFAULT ''"#)?;
+		}
+		// Databases from before project roles only have plain members
+		if self.db.prepare("SELECT user_project.role FROM user_project LIMIT 0").is_err() {
+			self.db.execute_batch(r#"ALTER TABLE "user_project" ADD COLUMN "role" TEXT NOT NULL DEFAULT 'member'"#)?;
+		}
+		// Avatars picked before users held references to them
+		if !had_user_assets {
+			self.db.execute_batch(r#"
+				INSERT INTO user_asset (user_name, hash, refs)
+				SELECT user_name, avatar, 1 FROM user_profile WHERE avatar IS NOT NULL
+			"#)?;
+		}
+		Ok(())
 	}
 
 	pub fn users_from_user_name_iter<'a>(&self, names: impl Iterator<Item = &'a str>) -> sql::Result<Vec<proto::User>> {
@@ -26,7 +150,7 @@ impl Database {
 		query.push(')');
 
 		let mut stmt = self.db.prepare(&query)?;
-		let iter = stmt.query_map(names.into_iter(), |row| {
+		let iter = stmt.query_map(names, |row| {
 			Ok(proto::User {
 				user_name: row.get(0)?,
 				activity: proto::UserActivity::Active,
@@ -47,4 +171,634 @@ impl Database {
 			})
 		}).optional()
 	}
+
+	pub fn create_user(&self, email: &str, user_name: &str, password_hashed: &[u8]) -> sql::Result<()> {
+		self.db.execute_named(r#"
+			INSERT INTO user (email, password, user_name, register_date)
+			VALUES (:email, :password, :user_name, datetime('now'))
+		"#, &[(":email", &email), (":password", &password_hashed), (":user_name", &user_name)])?;
+		Ok(())
+	}
+
+	/// A user's complete profile, including the private fields.
+	pub fn profile(&self, user_name: &str) -> sql::Result<Option<proto::Profile>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT user.user_name, user.real_name, user.birth_date, CAST(user.register_date AS TEXT),
+				user_profile.bio, user_profile.avatar, user_profile.show_real_name, user_profile.show_birth_date,
+				user_profile.show_register_date, user_profile.show_bio, user_profile.show_avatar
+			FROM user
+			LEFT JOIN user_profile ON user_profile.user_name = user.user_name
+			WHERE user.user_name = :user_name
+		"#)?;
+		stmt.query_row_named(&[(":user_name", &user_name)], |row| {
+			let avatar = match row.get::<_, Option<Vec<u8>>>(5)? {
+				Some(ref blob) if blob.len() == AssetHash::default().len() => {
+					let mut hash = AssetHash::default();
+					hash.copy_from_slice(blob);
+					Some(hash)
+				}
+				Some(_) => return Err(sql::Error::InvalidColumnType(5, "avatar".to_owned(), sql::types::Type::Blob)),
+				None => None,
+			};
+			// Users who never saved their profile have no row in user_profile
+			let visibility = match row.get::<_, Option<bool>>(6)? {
+				Some(real_name) => proto::ProfileVisibility {
+					real_name,
+					birth_date: row.get(7)?,
+					register_date: row.get(8)?,
+					bio: row.get(9)?,
+					avatar: row.get(10)?,
+				},
+				None => Default::default(),
+			};
+			Ok(proto::Profile {
+				user_name: row.get(0)?,
+				real_name: row.get(1)?,
+				birth_date: row.get(2)?,
+				register_date: row.get(3)?,
+				bio: row.get(4)?,
+				avatar,
+				visibility: Some(visibility),
+			})
+		}).optional()
+	}
+
+	/// Saves a profile, moving the user's reference from the old avatar to the new one.
+	/// Returns the old avatar if nothing references it anymore, in which case it's unregistered.
+	pub fn update_profile(&self, user_name: &str, update: &proto::ProfileUpdate) -> sql::Result<Option<AssetHash>> {
+		self.db.execute_batch("BEGIN")?;
+		let res = (|| {
+			let old = self.profile(user_name)?.and_then(|profile| profile.avatar);
+			self.db.execute_named(
+				"UPDATE user SET real_name = :real_name, birth_date = :birth_date WHERE user_name = :user_name",
+				&[(":real_name", &update.real_name), (":birth_date", &update.birth_date), (":user_name", &user_name)],
+			)?;
+			let visibility = &update.visibility;
+			self.db.execute_named(r#"
+				INSERT OR REPLACE INTO user_profile
+					(user_name, bio, avatar, show_real_name, show_birth_date, show_register_date, show_bio, show_avatar)
+				VALUES (:user_name, :bio, :avatar, :real_name, :birth_date, :register_date, :show_bio, :show_avatar)
+			"#, &[
+				(":user_name", &user_name),
+				(":bio", &update.bio),
+				(":avatar", &update.avatar.as_ref().map(|a| &a[..])),
+				(":real_name", &visibility.real_name),
+				(":birth_date", &visibility.birth_date),
+				(":register_date", &visibility.register_date),
+				(":show_bio", &visibility.bio),
+				(":show_avatar", &visibility.avatar),
+			])?;
+			if old == update.avatar {
+				return Ok(None);
+			}
+			if let Some(ref new) = update.avatar {
+				let new = &new[..];
+				self.db.execute_named(
+					"INSERT OR IGNORE INTO user_asset (user_name, hash, refs) VALUES (:user_name, :hash, 0)",
+					&[(":user_name", &user_name), (":hash", &new)],
+				)?;
+				self.db.execute_named(
+					"UPDATE user_asset SET refs = refs + 1 WHERE user_name = :user_name AND hash = :hash",
+					&[(":user_name", &user_name), (":hash", &new)],
+				)?;
+			}
+			let old = match old {
+				Some(old) => old,
+				None => return Ok(None),
+			};
+			self.db.execute_named(
+				"UPDATE user_asset SET refs = refs - 1 WHERE user_name = :user_name AND hash = :hash",
+				&[(":user_name", &user_name), (":hash", &&old[..])],
+			)?;
+			self.db.execute_named("DELETE FROM user_asset WHERE hash = :hash AND refs <= 0", &[(":hash", &&old[..])])?;
+			Ok(if self.unregister_unreferenced_asset(&old)? { Some(old) } else { None })
+		})();
+		match res {
+			Ok(removed) => self.db.execute_batch("COMMIT").map(|()| removed),
+			Err(e) => {
+				let _ = self.db.execute_batch("ROLLBACK");
+				Err(e)
+			}
+		}
+	}
+
+	pub fn project_exists(&self, project_id: u32) -> sql::Result<bool> {
+		let mut stmt = self.db.prepare("SELECT project.id FROM project WHERE project.id = :id")?;
+		stmt.query_row_named(&[(":id", &project_id)], |_| Ok(())).optional().map(|r| r.is_some())
+	}
+
+	/// Appends accepted operations to a project's persistent operation log.
+	/// Operations that are logged already are skipped, so saving again after a failure is safe.
+	pub fn save_ops(&self, project_id: u32, ops: &[LoggedOp]) -> sql::Result<()> {
+		if ops.is_empty() {
+			return Ok(());
+		}
+
+		self.db.execute_batch("BEGIN")?;
+		let res = (|| {
+			let mut stmt = self.db.prepare(r#"
+				INSERT OR IGNORE INTO project_op (project_id, seq, site, local_seq, op)
+				VALUES (:project_id, :seq, :site, :local_seq, :op)
+			"#)?;
+			for op in ops {
+				let blob = bincode::serialize(&op.op).map_err(encode_error)?;
+				stmt.execute_named(&[
+					(":project_id", &project_id),
+					(":seq", &(op.seq as i64)),
+					(":site", &op.site),
+					(":local_seq", &op.local_seq),
+					(":op", &blob),
+				])?;
+			}
+			Ok(())
+		})();
+		match res {
+			Ok(()) => self.db.execute_batch("COMMIT"),
+			Err(e) => {
+				let _ = self.db.execute_batch("ROLLBACK");
+				Err(e)
+			}
+		}
+	}
+
+	/// Appends entries to a project's activity log.
+	pub fn save_activity(&self, project_id: u32, entries: &[NewActivity]) -> sql::Result<()> {
+		if entries.is_empty() {
+			return Ok(());
+		}
+
+		self.db.execute_batch("BEGIN")?;
+		let res = (|| {
+			let mut stmt = self.db.prepare(r#"
+				INSERT INTO project_activity (project_id, user_name, date, kind, seq, summary)
+				VALUES (:project_id, :user_name, datetime(:time, 'unixepoch'), :kind, :seq, :summary)
+			"#)?;
+			for entry in entries {
+				let (kind, seq) = activity_columns(entry.kind);
+				stmt.execute_named(&[
+					(":project_id", &project_id),
+					(":user_name", &entry.user_name),
+					(":time", &(entry.time as i64)),
+					(":kind", &kind),
+					(":seq", &seq),
+					(":summary", &entry.summary),
+				])?;
+			}
+			Ok(())
+		})();
+		match res {
+			Ok(()) => self.db.execute_batch("COMMIT"),
+			Err(e) => {
+				let _ = self.db.execute_batch("ROLLBACK");
+				Err(e)
+			}
+		}
+	}
+
+	/// Up to `limit` entries of a project's activity log older than the entry `before`,
+	/// newest first. Also returns whether there are even older ones.
+	pub fn activity(&self, project_id: u32, before: Option<i64>, limit: usize) -> sql::Result<(Vec<proto::Activity>, bool)> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT project_activity.id, project_activity.user_name, project_activity.date,
+				project_activity.kind, project_activity.seq, project_activity.summary
+			FROM project_activity
+			WHERE project_activity.project_id = :project_id AND project_activity.id < :before
+			ORDER BY project_activity.id DESC
+			LIMIT :limit
+		"#)?;
+		let params: &[(&str, &dyn sql::ToSql)] = &[
+			(":project_id", &project_id),
+			(":before", &before.unwrap_or(i64::MAX)),
+			// One more, to know whether there's another page
+			(":limit", &(limit as i64 + 1)),
+		];
+		let iter = stmt.query_map_named(params, |row| {
+			let seq = row.get::<_, Option<i64>>(4)?.unwrap_or(0) as u64;
+			let kind = match row.get::<_, String>(3)?.as_str() {
+				"edit" => proto::ActivityKind::Edit { seq },
+				"undo" => proto::ActivityKind::Undo { seq },
+				"redo" => proto::ActivityKind::Redo { seq },
+				"joined" => proto::ActivityKind::Joined,
+				"left" => proto::ActivityKind::Left,
+				"member_added" => proto::ActivityKind::MemberAdded,
+				"member_removed" => proto::ActivityKind::MemberRemoved,
+				"role_changed" => proto::ActivityKind::RoleChanged,
+				_ => return Err(sql::Error::InvalidColumnType(3, "kind".to_owned(), sql::types::Type::Text)),
+			};
+			Ok(proto::Activity {
+				id: row.get(0)?,
+				user_name: row.get(1)?,
+				date: row.get(2)?,
+				kind,
+				summary: row.get(5)?,
+			})
+		})?;
+		let mut entries = iter.collect::<sql::Result<Vec<_>>>()?;
+		let more = entries.len() > limit;
+		entries.truncate(limit);
+		Ok((entries, more))
+	}
+
+	/// All persisted operations of a project with a sequence number greater than `since_seq`.
+	pub fn ops_since(&self, project_id: u32, since_seq: u64) -> sql::Result<Vec<LoggedOp>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT project_op.seq, project_op.site, project_op.local_seq, project_op.op FROM project_op
+			WHERE project_op.project_id = :project_id AND project_op.seq > :since_seq
+			ORDER BY project_op.seq
+		"#)?;
+		let iter = stmt.query_map_named(&[(":project_id", &project_id), (":since_seq", &(since_seq as i64))], |row| {
+			let blob: Vec<u8> = row.get(3)?;
+			Ok(LoggedOp {
+				seq: row.get::<_, i64>(0)? as u64,
+				site: row.get(1)?,
+				local_seq: row.get(2)?,
+				op: bincode::deserialize(&blob)
+					.map_err(|e| decode_error(3, e))?,
+			})
+		})?;
+		iter.collect()
+	}
+
+	/// The highest site that ever submitted an operation to a project.
+	pub fn max_site(&self, project_id: u32) -> sql::Result<u32> {
+		let mut stmt = self.db.prepare("SELECT MAX(project_op.site) FROM project_op WHERE project_op.project_id = :project_id")?;
+		stmt.query_row_named(&[(":project_id", &project_id)], |row| row.get::<_, Option<u32>>(0))
+			.map(|site| site.unwrap_or(0))
+	}
+
+	/// Loads the latest state of a project: the newest snapshot with all later operations applied.
+	/// Returns the state together with the sequence number of the last operation.
+	pub fn load_project(&self, project_id: u32) -> sql::Result<(Project, u64)> {
+		let latest = {
+			let mut stmt = self.db.prepare(r#"
+				SELECT project_snapshot.seq, project_snapshot.state FROM project_snapshot
+				WHERE project_snapshot.project_id = :project_id
+				ORDER BY project_snapshot.seq DESC LIMIT 1
+			"#)?;
+			stmt.query_row_named(&[(":project_id", &project_id)], |row| {
+				let blob: Vec<u8> = row.get(1)?;
+				let state = bincode::deserialize(&blob)
+					.map_err(|e| decode_error(1, e))?;
+				Ok((state, row.get::<_, i64>(0)? as u64))
+			}).optional()?
+		};
+		let (mut state, mut seq): (Project, u64) = latest.unwrap_or_default();
+
+		for op in self.ops_since(project_id, seq)? {
+			// Every logged operation applied once already, so it applies again
+			if let Err(e) = state.apply(&op.op) {
+				println!("Logged operation #{} of project {} doesn't apply: {:?}", op.seq, project_id, e);
+			}
+			seq = op.seq;
+		}
+		Ok((state, seq))
+	}
+
+	pub fn save_snapshot(&self, project_id: u32, seq: u64, name: Option<&str>, state: &Project) -> sql::Result<proto::SnapshotInfo> {
+		let blob = bincode::serialize(state).map_err(encode_error)?;
+		self.db.execute_named(r#"
+			INSERT INTO project_snapshot (project_id, seq, name, creation_date, state)
+			VALUES (:project_id, :seq, :name, datetime('now'), :state)
+		"#, &[(":project_id", &project_id), (":seq", &(seq as i64)), (":name", &name), (":state", &blob)])?;
+		let id = self.db.last_insert_rowid();
+		self.snapshots(project_id)?.into_iter()
+			.find(|s| s.id == id)
+			.ok_or(sql::Error::QueryReturnedNoRows)
+	}
+
+	pub fn snapshots(&self, project_id: u32) -> sql::Result<Vec<proto::SnapshotInfo>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT project_snapshot.id, project_snapshot.seq, project_snapshot.name, project_snapshot.creation_date
+			FROM project_snapshot
+			WHERE project_snapshot.project_id = :project_id
+			ORDER BY project_snapshot.seq
+		"#)?;
+		let iter = stmt.query_map_named(&[(":project_id", &project_id)], |row| {
+			Ok(proto::SnapshotInfo {
+				id: row.get(0)?,
+				seq: row.get::<_, i64>(1)? as u64,
+				name: row.get(2)?,
+				creation_date: row.get(3)?,
+			})
+		})?;
+		iter.collect()
+	}
+
+	pub fn snapshot_state(&self, project_id: u32, snapshot_id: i64) -> sql::Result<Option<Project>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT project_snapshot.state FROM project_snapshot
+			WHERE project_snapshot.project_id = :project_id AND project_snapshot.id = :id
+		"#)?;
+		stmt.query_row_named(&[(":project_id", &project_id), (":id", &snapshot_id)], |row| {
+			let blob: Vec<u8> = row.get(0)?;
+			bincode::deserialize(&blob)
+				.map_err(|e| decode_error(0, e))
+		}).optional()
+	}
+
+	/// Adds a reference from a project to an asset, registering the asset if it's new.
+	/// The first user adding an asset to a project is charged for its storage.
+	pub fn add_asset_ref(&self, project_id: u32, hash: &AssetHash, size: u64, owner: &str) -> sql::Result<()> {
+		let hash = &hash[..];
+		self.db.execute_named(
+			"INSERT OR IGNORE INTO asset (hash, size) VALUES (:hash, :size)",
+			&[(":hash", &hash), (":size", &(size as i64))],
+		)?;
+		self.db.execute_named(
+			"INSERT OR IGNORE INTO project_asset (project_id, hash, refs, owner) VALUES (:project_id, :hash, 0, :owner)",
+			&[(":project_id", &project_id), (":hash", &hash), (":owner", &owner)],
+		)?;
+		self.db.execute_named(
+			"UPDATE project_asset SET refs = refs + 1 WHERE project_id = :project_id AND hash = :hash",
+			&[(":project_id", &project_id), (":hash", &hash)],
+		)?;
+		Ok(())
+	}
+
+	/// Drops a reference from a project to an asset.
+	/// Returns whether nothing references the asset anymore, in which case it's unregistered.
+	pub fn release_asset_ref(&self, project_id: u32, hash: &AssetHash) -> sql::Result<bool> {
+		let hash = &hash[..];
+		self.db.execute_named(
+			"UPDATE project_asset SET refs = refs - 1 WHERE project_id = :project_id AND hash = :hash",
+			&[(":project_id", &project_id), (":hash", &hash)],
+		)?;
+		self.db.execute_named("DELETE FROM project_asset WHERE hash = :hash AND refs <= 0", &[(":hash", &hash)])?;
+		self.unregister_unreferenced_asset(hash)
+	}
+
+	/// Unregisters an asset that neither a project nor a user references anymore.
+	/// Returns whether it was.
+	fn unregister_unreferenced_asset(&self, hash: &[u8]) -> sql::Result<bool> {
+		let refs: i64 = self.db.query_row_named(r#"
+			SELECT (SELECT COUNT(*) FROM project_asset WHERE hash = :hash)
+				+ (SELECT COUNT(*) FROM user_asset WHERE hash = :hash)
+		"#, &[(":hash", &hash)], |row| row.get(0))?;
+		if refs == 0 {
+			self.db.execute_named("DELETE FROM asset WHERE hash = :hash", &[(":hash", &hash)])?;
+		}
+		Ok(refs == 0)
+	}
+
+	pub fn asset_exists(&self, hash: &AssetHash) -> sql::Result<bool> {
+		self.db.query_row_named(
+			"SELECT COUNT(*) FROM asset WHERE hash = :hash",
+			&[(":hash", &&hash[..])],
+			|row| row.get::<_, i64>(0),
+		).map(|n| n > 0)
+	}
+
+	pub fn project_has_asset(&self, project_id: u32, hash: &AssetHash) -> sql::Result<bool> {
+		self.db.query_row_named(
+			"SELECT COUNT(*) FROM project_asset WHERE project_id = :project_id AND hash = :hash",
+			&[(":project_id", &project_id), (":hash", &&hash[..])],
+			|row| row.get::<_, i64>(0),
+		).map(|n| n > 0)
+	}
+
+	/// Whether a project the user is a member of references the asset.
+	pub fn user_has_asset(&self, user_name: &str, hash: &AssetHash) -> sql::Result<bool> {
+		self.db.query_row_named(r#"
+			SELECT COUNT(*) FROM project_asset
+			INNER JOIN user_project ON user_project.project_id = project_asset.project_id
+			INNER JOIN user ON user.email = user_project.user_email
+			WHERE user.user_name = :user_name AND project_asset.hash = :hash
+		"#, &[(":user_name", &user_name), (":hash", &&hash[..])], |row| row.get::<_, i64>(0)).map(|n| n > 0)
+	}
+
+	pub fn project_assets(&self, project_id: u32) -> sql::Result<Vec<AssetInfo>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT asset.hash, asset.size FROM asset
+			INNER JOIN project_asset ON project_asset.hash = asset.hash
+			WHERE project_asset.project_id = :project_id
+		"#)?;
+		let iter = stmt.query_map_named(&[(":project_id", &project_id)], |row| {
+			let blob: Vec<u8> = row.get(0)?;
+			let mut hash = AssetHash::default();
+			if blob.len() != hash.len() {
+				return Err(sql::Error::InvalidColumnType(0, "hash".to_owned(), sql::types::Type::Blob));
+			}
+			hash.copy_from_slice(&blob);
+			Ok(AssetInfo {
+				hash,
+				size: row.get::<_, i64>(1)? as u64,
+			})
+		})?;
+		iter.collect()
+	}
+
+	/// Bytes of all assets a user was charged for, counted once per project.
+	pub fn user_storage(&self, user_name: &str) -> sql::Result<u64> {
+		self.db.query_row_named(r#"
+			SELECT COALESCE(SUM(asset.size), 0) FROM asset
+			INNER JOIN project_asset ON project_asset.hash = asset.hash
+			WHERE project_asset.owner = :user_name
+		"#, &[(":user_name", &user_name)], |row| row.get::<_, i64>(0)).map(|n| n as u64)
+	}
+
+	/// Bytes of all assets referenced by a project.
+	pub fn project_storage(&self, project_id: u32) -> sql::Result<u64> {
+		self.db.query_row_named(r#"
+			SELECT COALESCE(SUM(asset.size), 0) FROM asset
+			INNER JOIN project_asset ON project_asset.hash = asset.hash
+			WHERE project_asset.project_id = :project_id
+		"#, &[(":project_id", &project_id)], |row| row.get::<_, i64>(0)).map(|n| n as u64)
+	}
+
+	/// The quota an administrator set for a user, if any. The inner `None` means unlimited.
+	pub fn user_quota(&self, user_name: &str) -> sql::Result<Option<Option<u64>>> {
+		self.db.query_row_named(
+			"SELECT user_quota.limit_bytes FROM user_quota WHERE user_quota.user_name = :user_name",
+			&[(":user_name", &user_name)],
+			|row| row.get::<_, Option<i64>>(0).map(|limit| limit.map(|l| l as u64)),
+		).optional()
+	}
+
+	/// Overrides the default quota of a user. `None` removes the limit.
+	pub fn set_user_quota(&self, user_name: &str, limit: Option<u64>) -> sql::Result<()> {
+		self.db.execute_named(
+			"INSERT OR REPLACE INTO user_quota (user_name, limit_bytes) VALUES (:user_name, :limit)",
+			&[(":user_name", &user_name), (":limit", &limit.map(|l| l as i64))],
+		)?;
+		Ok(())
+	}
+
+	/// Makes the default quota apply to the user again.
+	pub fn reset_user_quota(&self, user_name: &str) -> sql::Result<()> {
+		self.db.execute_named("DELETE FROM user_quota WHERE user_name = :user_name", &[(":user_name", &user_name)])?;
+		Ok(())
+	}
+
+	/// The ids of the projects a user is a member of.
+	pub fn user_projects(&self, user_name: &str) -> sql::Result<Vec<u32>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT user_project.project_id FROM user_project
+			INNER JOIN user ON user.email = user_project.user_email
+			WHERE user.user_name = :user_name
+			ORDER BY user_project.project_id
+		"#)?;
+		let iter = stmt.query_map_named(&[(":user_name", &user_name)], |row| row.get(0))?;
+		iter.collect()
+	}
+
+	/// Whether the user is a member of the project.
+	pub fn is_project_member(&self, project_id: u32, user_name: &str) -> sql::Result<bool> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT user_project.project_id FROM user_project
+			INNER JOIN user ON user.email = user_project.user_email
+			WHERE user.user_name = :user_name AND user_project.project_id = :project_id
+		"#)?;
+		stmt.query_row_named(&[(":user_name", &user_name), (":project_id", &project_id)], |_| Ok(())).optional().map(|r| r.is_some())
+	}
+
+	/// Makes the user a member of the project. Returns `false` if the user or the project
+	/// doesn't exist or the user is a member already.
+	pub fn add_project_member(&self, project_id: u32, user_name: &str, role: proto::ProjectRole) -> sql::Result<bool> {
+		self.membership_change(project_id, user_name, proto::ActivityKind::MemberAdded, &format!("Added as {}", role.name()), || {
+			self.db.execute_named(r#"
+				INSERT OR IGNORE INTO user_project (user_email, project_id, role)
+				SELECT user.email, project.id, :role FROM user, project
+				WHERE user.user_name = :user_name AND project.id = :project_id
+			"#, &[(":user_name", &user_name), (":project_id", &project_id), (":role", &role.name())])
+		})
+	}
+
+	/// Returns `false` if the user isn't a member of the project.
+	pub fn remove_project_member(&self, project_id: u32, user_name: &str) -> sql::Result<bool> {
+		self.membership_change(project_id, user_name, proto::ActivityKind::MemberRemoved, "Removed from the project", || {
+			self.db.execute_named(r#"
+				DELETE FROM user_project
+				WHERE user_project.project_id = :project_id
+					AND user_project.user_email = (SELECT user.email FROM user WHERE user.user_name = :user_name)
+			"#, &[(":user_name", &user_name), (":project_id", &project_id)])
+		})
+	}
+
+	/// Returns `false` if the user isn't a member of the project or has the role already.
+	pub fn set_project_role(&self, project_id: u32, user_name: &str, role: proto::ProjectRole) -> sql::Result<bool> {
+		self.membership_change(project_id, user_name, proto::ActivityKind::RoleChanged, &format!("Made {}", role.name()), || {
+			self.db.execute_named(r#"
+				UPDATE user_project SET role = :role
+				WHERE user_project.project_id = :project_id AND user_project.role != :role
+					AND user_project.user_email = (SELECT user.email FROM user WHERE user.user_name = :user_name)
+			"#, &[(":user_name", &user_name), (":project_id", &project_id), (":role", &role.name())])
+		})
+	}
+
+	/// The role of a member of the project, `None` for users who aren't members.
+	pub fn project_role(&self, project_id: u32, user_name: &str) -> sql::Result<Option<proto::ProjectRole>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT user_project.role FROM user_project
+			INNER JOIN user ON user.email = user_project.user_email
+			WHERE user.user_name = :user_name AND user_project.project_id = :project_id
+		"#)?;
+		let role: Option<String> = stmt.query_row_named(&[(":user_name", &user_name), (":project_id", &project_id)], |row| row.get(0)).optional()?;
+		// Roles are only ever written from [proto::ProjectRole::name]
+		Ok(role.map(|role| proto::ProjectRole::from_name(&role).unwrap_or(proto::ProjectRole::Member)))
+	}
+
+	/// Runs `change` on the user_project table and, if it changed a row, logs it in the
+	/// project's activity log in the same transaction. Returns whether a row changed.
+	fn membership_change(&self, project_id: u32, user_name: &str, kind: proto::ActivityKind, summary: &str, change: impl FnOnce() -> sql::Result<usize>) -> sql::Result<bool> {
+		self.db.execute_batch("BEGIN")?;
+		let res = (|| {
+			if change()? == 0 {
+				return Ok(false);
+			}
+			let (kind, seq) = activity_columns(kind);
+			self.db.execute_named(r#"
+				INSERT INTO project_activity (project_id, user_name, date, kind, seq, summary)
+				VALUES (:project_id, :user_name, datetime('now'), :kind, :seq, :summary)
+			"#, &[(":project_id", &project_id), (":user_name", &user_name), (":kind", &kind), (":seq", &seq), (":summary", &summary)])?;
+			Ok(true)
+		})();
+		match res {
+			Ok(changed) => self.db.execute_batch("COMMIT").map(|()| changed),
+			Err(e) => {
+				let _ = self.db.execute_batch("ROLLBACK");
+				Err(e)
+			}
+		}
+	}
+
+	/// The projects a user is a member of, with their details.
+	pub fn user_project_infos(&self, user_name: &str) -> sql::Result<Vec<proto::ProjectInfo>> {
+		let mut stmt = self.db.prepare(r#"
+			SELECT project.id, project.title, project.description, CAST(project.creation_date AS TEXT) FROM project
+			INNER JOIN user_project ON user_project.project_id = project.id
+			INNER JOIN user ON user.email = user_project.user_email
+			WHERE user.user_name = :user_name
+			ORDER BY project.id
+		"#)?;
+		let iter = stmt.query_map_named(&[(":user_name", &user_name)], |row| {
+			Ok(proto::ProjectInfo {
+				id: row.get(0)?,
+				title: row.get(1)?,
+				description: row.get(2)?,
+				creation_date: row.get(3)?,
+			})
+		})?;
+		iter.collect()
+	}
+}
+
+/// The kind and seq columns of an activity log entry.
+fn activity_columns(kind: proto::ActivityKind) -> (&'static str, Option<i64>) {
+	match kind {
+		proto::ActivityKind::Edit { seq } => ("edit", Some(seq as i64)),
+		proto::ActivityKind::Undo { seq } => ("undo", Some(seq as i64)),
+		proto::ActivityKind::Redo { seq } => ("redo", Some(seq as i64)),
+		proto::ActivityKind::Joined => ("joined", None),
+		proto::ActivityKind::Left => ("left", None),
+		proto::ActivityKind::MemberAdded => ("member_added", None),
+		proto::ActivityKind::MemberRemoved => ("member_removed", None),
+		proto::ActivityKind::RoleChanged => ("role_changed", None),
+	}
+}
+
+#[cfg(test)]
+impl Database {
+	/// A copy of the database the server comes with, for a test to change as it likes.
+	pub fn test_copy(test: &str) -> Self {
+		let path = ::std::env::temp_dir().join(format!("chorus_db_{}_{}.db", test, ::std::process::id()));
+		let _ = ::std::fs::remove_file(&path);
+		::std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/chorus_studio.db"), &path).unwrap();
+		Self::open(&path).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn avatar(avatar: Option<AssetHash>) -> proto::ProfileUpdate {
+		proto::ProfileUpdate {
+			real_name: None,
+			birth_date: None,
+			bio: None,
+			avatar,
+			visibility: Default::default(),
+		}
+	}
+
+	#[test]
+	fn avatars_keep_their_asset() {
+		let db = Database::test_copy("avatar");
+		db.add_asset_ref(1, &[1; 32], 10, "DanHau").unwrap();
+		db.add_asset_ref(1, &[2; 32], 10, "DanHau").unwrap();
+		assert_eq!(db.update_profile("DanHau", &avatar(Some([1; 32]))).unwrap(), None);
+		assert_eq!(db.update_profile("_test_", &avatar(Some([1; 32]))).unwrap(), None);
+
+		// Still the avatar of two users after the project let go of it
+		assert!(!db.release_asset_ref(1, &[1; 32]).unwrap());
+		assert!(db.asset_exists(&[1; 32]).unwrap());
+		assert_eq!(db.update_profile("DanHau", &avatar(Some([1; 32]))).unwrap(), None);
+		assert_eq!(db.update_profile("DanHau", &avatar(Some([2; 32]))).unwrap(), None);
+		assert_eq!(db.update_profile("_test_", &avatar(None)).unwrap(), Some([1; 32]));
+		assert!(!db.asset_exists(&[1; 32]).unwrap());
+
+		// The project's reference is gone, the user's is left
+		assert!(!db.release_asset_ref(1, &[2; 32]).unwrap());
+		assert_eq!(db.profile("DanHau").unwrap().unwrap().avatar, Some([2; 32]));
+	}
 }
@@ -0,0 +1,55 @@
+use std::sync::mpsc;
+use std::thread;
+
+use super::Database;
+
+type Job = Box<dyn FnOnce(&Database) + Send>;
+
+/// Owns the database connection and runs queries on a dedicated thread, so slow
+/// queries don't hold up the event loop. Jobs run one after another, in the order
+/// they were submitted.
+pub struct DbWorker {
+    jobs: mpsc::Sender<Job>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl DbWorker {
+    pub fn spawn(db: Database) -> Self {
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        let thread = thread::spawn(move || {
+            for job in jobs_rx {
+                job(&db);
+            }
+        });
+        Self { jobs, thread }
+    }
+
+    pub fn handle(&self) -> DbHandle {
+        DbHandle {
+            jobs: self.jobs.clone(),
+        }
+    }
+
+    /// Waits until all submitted jobs are done.
+    /// Every [DbHandle] must be dropped before, otherwise this waits forever.
+    pub fn finish(self) {
+        drop(self.jobs);
+        if self.thread.join().is_err() {
+            println!("Database worker panicked");
+        }
+    }
+}
+
+/// Submits jobs to a [DbWorker].
+#[derive(Clone)]
+pub struct DbHandle {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl DbHandle {
+    pub fn run(&self, job: impl FnOnce(&Database) + Send + 'static) {
+        if self.jobs.send(Box::new(job)).is_err() {
+            println!("Database worker is gone, dropping job");
+        }
+    }
+}
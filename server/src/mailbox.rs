@@ -0,0 +1,65 @@
+use std::sync::mpsc;
+
+use mio::{Ready, Registration, SetReadiness};
+
+/// A channel whose receiving end is polled by a mio event loop.
+///
+/// Registering [Mailbox::registration] with the loop's `Poll` wakes it up whenever
+/// a message arrives.
+pub struct Mailbox<T> {
+    rx: mpsc::Receiver<T>,
+    sender: MailboxSender<T>,
+    registration: Registration,
+}
+
+impl<T> Mailbox<T> {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        let (registration, readiness) = Registration::new2();
+        Self {
+            rx,
+            sender: MailboxSender { tx, readiness },
+            registration,
+        }
+    }
+
+    pub fn registration(&self) -> &Registration {
+        &self.registration
+    }
+
+    pub fn sender(&self) -> MailboxSender<T> {
+        self.sender.clone()
+    }
+
+    /// Takes all messages that arrived so far.
+    pub fn take(&self) -> Vec<T> {
+        // Reset first: a message arriving after this sets the readiness again
+        let _ = self.sender.readiness.set_readiness(Ready::empty());
+        self.rx.try_iter().collect()
+    }
+}
+
+pub struct MailboxSender<T> {
+    tx: mpsc::Sender<T>,
+    readiness: SetReadiness,
+}
+
+impl<T> Clone for MailboxSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            readiness: self.readiness.clone(),
+        }
+    }
+}
+
+impl<T> MailboxSender<T> {
+    /// Returns `false` if the mailbox is gone.
+    pub fn send(&self, msg: T) -> bool {
+        if self.tx.send(msg).is_err() {
+            return false;
+        }
+        let _ = self.readiness.set_readiness(Ready::readable());
+        true
+    }
+}
@@ -2,108 +2,218 @@ extern crate bincode;
 extern crate proto;
 extern crate mio;
 extern crate rusqlite;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate sha3;
 
+mod admin;
+mod asset;
+mod client;
 mod db;
+mod mailbox;
+mod project;
+mod quota;
+mod ratelimit;
+mod recording;
+mod replay;
+mod shard;
 
-use std::io;
-use std::io::Read;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::sync::{mpsc, Arc};
+use std::collections::{HashMap, HashSet};
 use std::thread;
-use std::sync::mpsc;
-use std::collections::HashMap;
 
-use mio::net::{TcpListener, TcpStream};
+use mio::net::TcpListener;
+use sha3::{Digest, Sha3_256};
 use mio::{Poll, Token, Ready, PollOpt, Events};
 
+use client::{ClientSock, send_to};
+use mailbox::{Mailbox, MailboxSender};
+use recording::Recorder;
+use shard::{LobbyMsg, ShardMsg};
+
 const LISTENER: Token = Token(0);
+/// Messages from other threads arrived. Client tokens start after [LISTENER].
+const MAILBOX: Token = Token(usize::MAX - 2);
+/// Database jobs finished.
+const DB_DONE: Token = Token(usize::MAX - 1);
+/// How long a shutdown waits for queued responses to reach the clients.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Work left to do on the lobby once a database job is done.
+type DbDone = Box<dyn FnOnce(&mut Lobby) + Send>;
 
-struct ClientSock {
-    stream: TcpStream,
-    data_buf: Vec<u8>,
+/// A client the lobby handed over to a shard.
+struct Remote {
+    shard: usize,
+    addr: SocketAddr,
 }
 
-impl ClientSock {
-    fn new(stream: TcpStream) -> Self {
-        Self {
-            stream,
-            data_buf: Vec::with_capacity(1024),
-        }
+/// The main thread's event loop. Accepts connections and logs clients in, then hands
+/// them to the shard of the project they join. Clients come back once they left
+/// their project. Also keeps track of who's online and runs admin commands.
+struct Lobby {
+    poll: Poll,
+    clients: HashMap<usize, ClientSock>,
+    remote: HashMap<usize, Remote>,
+    /// Every logged in client, including those on shards
+    user_list: HashMap<usize, String>,
+    /// Logged in clients whose user stepped away from the keyboard
+    away: HashSet<usize>,
+    shards: Vec<MailboxSender<ShardMsg>>,
+    quotas: quota::Quotas,
+    assets: Arc<asset::AssetStore>,
+    login_limiter: ratelimit::LoginLimiter,
+    shutdown: Option<admin::Shutdown>,
+    db: db::worker::DbHandle,
+    db_done: MailboxSender<DbDone>,
+    recorder: Option<Recorder>,
+}
+
+/// Everything [serve] runs on, set up from the command line or by a replay.
+struct ServerConfig {
+    listener: TcpListener,
+    database: db::Database,
+    assets: asset::AssetStore,
+    quotas: quota::Quotas,
+    shard_count: usize,
+    recorder: Option<Recorder>,
+}
+
+fn main() {
+    let mut quotas = quota::Quotas::default();
+    if let Some(limit) = flag_value("--user-quota", 1) {
+        quotas.user = quota::Quotas::parse_limit(&limit).expect("--user-quota expects MiB or \"unlimited\"");
     }
+    if let Some(limit) = flag_value("--project-quota", 1) {
+        quotas.project = quota::Quotas::parse_limit(&limit).expect("--project-quota expects MiB or \"unlimited\"");
+    }
+    let shard_count = match flag_value("--workers", 1) {
+        Some(n) => n.parse().ok().filter(|&n| n > 0).expect("--workers expects a number greater than 0"),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
 
-    fn read_socket(&mut self) {
-        let _ = self.stream.read_to_end(&mut self.data_buf);
+    if let Some(path) = flag_value("--replay", 1) {
+        // Runs on a scratch copy of the recorded database, the real one isn't touched
+        let matched = replay::run(Path::new(&path), quotas, shard_count);
+        std::process::exit(if matched { 0 } else { 1 });
     }
 
-    fn try_deserialize(&mut self) -> Option<proto::Command> {
-        let (cmd, bytes_read) = {
-            let mut bytes = self.data_buf.as_slice();
-            let cmd = bincode::deserialize_from(&mut bytes);
-            let bytes_read = bytes.as_ptr() as usize - self.data_buf.as_slice().as_ptr() as usize;
-            (cmd, bytes_read)
-        };
-        println!("try_deserialize: {} bytes", bytes_read);
-        
-        // Catch disconnects
-        let cmd = if cmd.is_err() && bytes_read == 0 {
-            Ok(proto::Command::Disconnect)
-        } else {
-            cmd
-        };
+    let database = db::Database::new().expect("Database");
 
-        match cmd {
-            Ok(cmd) => {
-                self.data_buf.drain(0..bytes_read);
-                Some(cmd)
-            }
-            Err(e) => {
-                match *e {
-                    bincode::ErrorKind::Io(..) => {} // Not enough data received yet
-                    _ => { self.data_buf.drain(0..bytes_read); },
-                };
-                None
+    if let Some(user_name) = flag_value("--set-quota", 1) {
+        // Administration only, the server doesn't start
+        let res = match flag_value("--set-quota", 2).as_deref() {
+            Some("default") => database.reset_user_quota(&user_name),
+            Some(limit) => match quota::Quotas::parse_limit(limit) {
+                Some(limit) => database.set_user_quota(&user_name, limit),
+                None => {
+                    println!("Invalid quota {:?}, expected MiB, \"unlimited\" or \"default\"", limit);
+                    return;
+                }
+            },
+            None => {
+                println!("Usage: --set-quota <user name> <MiB|unlimited|default>");
+                return;
             }
+        };
+        match res {
+            Ok(()) => println!("Quota of {} updated", user_name),
+            Err(e) => println!("Failed to update quota of {}: {}", user_name, e),
         }
+        return;
     }
-}
 
-fn ignore_timeout(err: io::Error) -> Option<io::Error> {
-    if err.kind() == io::ErrorKind::WouldBlock {
-        None
-    } else {
-        Some(err)
+    let addr = flag_value("--listen", 1)
+        .unwrap_or_else(|| "0.0.0.0:4450".to_owned())
+        .parse()
+        .expect("--listen expects an address like 0.0.0.0:4450");
+    let listener = TcpListener::bind(&addr).expect("TCP listen");
+    let asset_dir = flag_value("--asset-dir", 1).unwrap_or_else(|| "assets".to_owned());
+    let assets = asset::AssetStore::new(asset_dir, quotas).expect("Asset store");
+    let recorder = flag_value("--record", 1).map(|path| {
+        println!("Recording sessions to {}", path);
+        Recorder::create(Path::new(&path), &database).expect("Session recording")
+    });
+
+    let (admin_tx, admin_rx) = mpsc::channel();
+    let control_socket = flag_value("--control-socket", 1).unwrap_or_else(|| "chorus_studio.sock".to_owned());
+    if let Err(e) = admin::spawn_control_socket(control_socket.clone().into(), admin_tx.clone()) {
+        println!("Control socket unavailable: {}", e);
     }
-}
+    // Neither thread is joined, they're blocked reading input until the process exits
+    admin::spawn_console(admin_tx);
 
-fn main() {
-    let mut clients: HashMap<usize, ClientSock> = HashMap::new();
-    let mut user_list: HashMap<usize, String> = HashMap::new();
+    println!("Enter \"help\" for a list of admin commands.");
 
-    let addr = "0.0.0.0:4450".parse().unwrap();
-    let listener = TcpListener::bind(&addr).expect("TCP listen");
+    serve(ServerConfig { listener, database, assets, quotas, shard_count, recorder }, admin_rx);
+    let _ = std::fs::remove_file(&control_socket);
+}
+
+/// Runs the server until an admin command shuts it down.
+fn serve(config: ServerConfig, admin_rx: mpsc::Receiver<admin::AdminRequest>) {
+    let ServerConfig { listener, database, assets, quotas, shard_count, recorder } = config;
     let poll = Poll::new().expect("Poll");
     poll.register(&listener, LISTENER, Ready::readable(), PollOpt::edge()).expect("Listener register");
 
     let mut cur_client_id = 1usize;
     let mut events = Events::with_capacity(1024);
 
-    let database = db::Database::new().expect("Database");
+    let assets = Arc::new(assets);
+    let db_worker = db::worker::DbWorker::spawn(database);
+    let db_done = Mailbox::<DbDone>::new();
+    poll.register(db_done.registration(), DB_DONE, Ready::readable(), PollOpt::edge()).expect("Database register");
+    let mailbox = Mailbox::<LobbyMsg>::new();
+    poll.register(mailbox.registration(), MAILBOX, Ready::readable(), PollOpt::edge()).expect("Mailbox register");
 
-    println!("Enter \"quit\" to quit the server.");
+    let mut shards = Vec::with_capacity(shard_count);
+    let mut shard_threads = Vec::with_capacity(shard_count);
+    for index in 0..shard_count {
+        let (sender, thread) = shard::spawn(index, shard_count, assets.clone(), db_worker.handle(), mailbox.sender(), recorder.clone()).expect("Shard");
+        shards.push(sender);
+        shard_threads.push(thread);
+    }
+    println!("Running {} shard(s)", shard_count);
 
-    let (quit_tx, quit_rx) = mpsc::channel();
-    let quit_thread = thread::spawn(move || {
-        let mut dummy = String::new();
-        let _ = io::stdin().read_line(&mut dummy);
-        let _ = quit_tx.send(());
-    });
+    let mut lobby = Lobby {
+        poll,
+        clients: HashMap::new(),
+        remote: HashMap::new(),
+        user_list: HashMap::new(),
+        away: HashSet::new(),
+        shards,
+        quotas,
+        assets,
+        login_limiter: ratelimit::LoginLimiter::default(),
+        shutdown: None,
+        db: db_worker.handle(),
+        db_done: db_done.sender(),
+        recorder,
+    };
 
-    loop {
-        if let Ok(()) = quit_rx.try_recv() {
-            break;
+    let notice = loop {
+        for req in admin_rx.try_iter() {
+            lobby.run_admin(req.cmd, req.reply);
+        }
+        if let Some(ref mut pending) = lobby.shutdown {
+            let now = Instant::now();
+            if pending.is_due(now) {
+                break proto::Response::ServerShuttingDown {
+                    reason: pending.reason.clone(),
+                    reconnect_after: pending.reconnect_after,
+                };
+            }
+            if let Some(secs) = pending.announcement(now) {
+                let msg = proto::Response::SystemMessage(format!("The server shuts down in {} seconds: {}", secs, pending.reason));
+                lobby.broadcast(msg);
+            }
         }
 
-        if let Err(e) = poll.poll(&mut events, Some(Duration::from_secs(1))) {
-            if let Some(e) = ignore_timeout(e) {
+        if let Err(e) = lobby.poll.poll(&mut events, Some(Duration::from_secs(1))) {
+            if let Some(e) = client::ignore_timeout(e) {
                 println!("poll ERR: {}", e);
             }
         }
@@ -111,81 +221,564 @@ fn main() {
         for e in events.iter() {
             match e.token() {
                 LISTENER => {
-                    let (client_stream, client_addr) = listener.accept().expect("Client accept");
-                    println!("New client: {:?}", client_addr);
-                    let client = ClientSock::new(client_stream);
-                    clients.insert(cur_client_id, client);
-                    poll.register(&clients[&cur_client_id].stream, Token(cur_client_id), Ready::readable(), PollOpt::edge()).expect("Client register");
-                    cur_client_id += 1;
+                    // Edge triggered, so take every pending connection
+                    loop {
+                        let (client_stream, client_addr) = match listener.accept_std() {
+                            Ok(accepted) => accepted,
+                            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                println!("Client accept ERR: {}", e);
+                                break;
+                            }
+                        };
+                        println!("New client: {:?}", client_addr);
+                        // Not handed out again even if the client doesn't make it, the recording may know it
+                        let client_id = cur_client_id;
+                        cur_client_id += 1;
+                        let recorder = lobby.recorder.as_ref().map(|r| r.client(client_id));
+                        // Dropping the stream closes the connection
+                        let client = match ClientSock::new(client_stream, client_addr, recorder) {
+                            Ok(client) => client,
+                            Err(e) => {
+                                println!("Client socket ERR: {}", e);
+                                continue;
+                            }
+                        };
+                        if let Err(e) = lobby.poll.register(&client.stream, Token(client_id), Ready::readable() | Ready::writable(), PollOpt::edge()) {
+                            println!("Client register ERR: {}", e);
+                            continue;
+                        }
+                        lobby.clients.insert(client_id, client);
+                    }
                 },
+                MAILBOX => {
+                    for msg in mailbox.take() {
+                        lobby.handle_msg(msg);
+                    }
+                }
+                DB_DONE => {
+                    for done in db_done.take() {
+                        done(&mut lobby);
+                    }
+                }
                 Token(client_id) => {
-                    let cmd = {
-                        let client = &mut clients.get_mut(&client_id).unwrap();
-                        client.read_socket();
-                        client.try_deserialize()
-                    };
-                    if let Some(cmd) = cmd {
-                        if let Some(resp) = build_response(&database, cmd, client_id, &mut user_list, &mut clients) {
-                            let client = &mut clients.get_mut(&client_id).unwrap();
-                            if bincode::serialize_into(&client.stream, &resp).is_err() {
-                                println!("Failed to write response!");
+                    if e.readiness().is_writable() {
+                        if let Some(client) = lobby.clients.get(&client_id) {
+                            if let Err(e) = client.flush() {
+                                println!("Failed to write response: {}", e);
+                            }
+                        }
+                    }
+                    if !e.readiness().is_readable() {
+                        continue;
+                    }
+
+                    match lobby.clients.get_mut(&client_id) {
+                        Some(client) => {
+                            let open = client.read_socket();
+                            while let Some(cmd) = client.try_deserialize() {
+                                client.commands.push_back(cmd);
+                            }
+                            if !open {
+                                client.commands.push_back(proto::Command::Disconnect);
                             }
                         }
+                        None => continue,
                     }
+                    lobby.process_commands(client_id);
                 }
             }
         }
+    };
+
+    // Tell everyone, deliver what's still queued, save everything and hang up
+    println!("Shutting down");
+    for client in lobby.clients.values() {
+        client.send(&notice);
+    }
+    for shard in &lobby.shards {
+        shard.send(ShardMsg::Shutdown(notice.clone()));
+    }
+    client::drain_clients(&lobby.poll, &mut events, &lobby.clients, Instant::now() + SHUTDOWN_DRAIN_TIMEOUT);
+    for thread in shard_threads {
+        if thread.join().is_err() {
+            println!("A shard panicked");
+        }
+    }
+    for client in lobby.clients.values() {
+        let _ = client.stream.shutdown(std::net::Shutdown::Both);
     }
+    drop(lobby);
+    db_worker.finish();
+}
 
-    quit_thread.join().unwrap();
+/// The `n`th argument after the command line flag `name`.
+fn flag_value(name: &str, n: usize) -> Option<String> {
+    std::env::args().skip_while(|a| a != name).nth(n)
 }
 
-fn fetch_users(db: &db::Database, user_list: &HashMap<usize, String>) -> Vec<proto::User> {
-    if let Ok(users) = db.users_from_user_name_iter(user_list.values().map(|s| s.as_ref())) {
-        users
-    } else {
-        Vec::new()
+impl Lobby {
+    /// Runs `job` on the database thread, then `then` on the event loop with its result.
+    /// If the job is done on behalf of a client, its later commands wait until `then`
+    /// ran, so they're handled in order. `then`'s response is sent to that client, but
+    /// `then` doesn't run at all if the client disconnected in the meantime.
+    fn query<R, J, T>(&mut self, client_id: Option<usize>, job: J, then: T)
+    where
+        R: Send + 'static,
+        J: FnOnce(&db::Database) -> R + Send + 'static,
+        T: FnOnce(&mut Lobby, R) -> Option<proto::Response> + Send + 'static,
+    {
+        // Clients on shards wait there
+        if let Some(client) = client_id.and_then(|id| self.clients.get_mut(&id)) {
+            client.waiting = true;
+        }
+        let done = self.db_done.clone();
+        self.db.run(move |db| {
+            let res = job(db);
+            done.send(Box::new(move |lobby: &mut Lobby| match client_id {
+                Some(client_id) => {
+                    if let Some(client) = lobby.clients.get_mut(&client_id) {
+                        // Cleared first, `then` may start another job to wait for
+                        client.waiting = false;
+                    } else if !lobby.remote.contains_key(&client_id) {
+                        return;
+                    }
+                    let resp = then(lobby, res);
+                    lobby.respond(client_id, resp);
+                }
+                None => {
+                    then(lobby, res);
+                }
+            }));
+        });
+    }
+
+    /// Sends the response to a command that was waited for and continues with the
+    /// client's next commands. Clients on shards continue there.
+    fn respond(&mut self, client_id: usize, resp: Option<proto::Response>) {
+        if let Some(remote) = self.remote.get(&client_id) {
+            self.shards[remote.shard].send(ShardMsg::Reply { client_id, resp });
+            return;
+        }
+        if let Some(resp) = resp {
+            send_to(&self.clients, client_id, &resp);
+        }
+        self.process_commands(client_id);
     }
-}
 
-fn build_response(db: &db::Database, cmd: proto::Command, client_id: usize, user_list: &mut HashMap<usize, String>, clients: &mut HashMap<usize, ClientSock>) -> Option<proto::Response> {
-    use proto::Command::*;
-    match cmd {
-        ListUsers => Some(proto::Response::UserList(fetch_users(db, user_list))),
-        Login { email, password, } => {
-            let password_hex = {
-                use std::fmt::Write;
-                let mut buf = String::with_capacity(password.len() * 2);
-                for b in password.iter() {
-                    let _ = write!(&mut buf, "{:X}", b);
-                }
-                buf
+    /// Handles the client's received commands, until one of them waits for the database
+    /// or the client joins a project.
+    fn process_commands(&mut self, client_id: usize) {
+        loop {
+            let cmd = match self.clients.get_mut(&client_id) {
+                Some(client) if !client.waiting => match client.commands.pop_front() {
+                    Some(cmd) => cmd,
+                    None => return,
+                },
+                _ => return,
             };
-            println!("Login with email '{}' and password '{}'", email, password_hex);
-            if let Ok(Some(user)) = db.user_with_credentials(&email, &password) {
-                // We insert the user name instead of the email address, because I want
-                // to avoid moving around and possibly leaking user sensitive data.
-                user_list.insert(client_id, user.user_name);
+            if let proto::Command::JoinProject { project_id } = cmd {
+                if self.user_list.contains_key(&client_id) {
+                    // The project's shard takes it from here, including the commands after this one
+                    self.clients.get_mut(&client_id).unwrap().commands.push_front(cmd);
+                    let shard = shard::shard_of(project_id, self.shards.len());
+                    self.hand_off(client_id, shard);
+                    return;
+                }
+            }
+            if let Some(resp) = self.build_response(cmd, client_id) {
+                send_to(&self.clients, client_id, &resp);
+            }
+        }
+    }
+
+    fn hand_off(&mut self, client_id: usize, shard: usize) {
+        let mut client = self.clients.remove(&client_id).unwrap();
+        if let Err(e) = client.deregister(&self.poll) {
+            println!("Failed to hand client {} to shard {}: {}", client_id, shard, e);
+            self.disconnect(client_id);
+            return;
+        }
+        self.remote.insert(client_id, Remote { shard, addr: client.addr });
+        let user_name = self.user_list[&client_id].clone();
+        self.shards[shard].send(ShardMsg::Adopt { client_id, client, user_name });
+    }
 
-                // Notify other clients about the newly joined guy
-                let msg = proto::Response::UserList(fetch_users(db, user_list));
-                for c in clients.values() {
-                    let _ = bincode::serialize_into(&c.stream, &msg);
+    fn handle_msg(&mut self, msg: LobbyMsg) {
+        match msg {
+            LobbyMsg::Returned { client_id, client } => {
+                self.remote.remove(&client_id);
+                if let Err(e) = self.poll.register(&client.stream, Token(client_id), Ready::readable() | Ready::writable(), PollOpt::edge()) {
+                    println!("Failed to take back client {}: {}", client_id, e);
+                    self.disconnect(client_id);
+                    return;
                 }
+                self.clients.insert(client_id, client);
+                self.process_commands(client_id);
+            }
+            LobbyMsg::Forward { client_id, cmd } => {
+                // Forwarded commands either answer right away or once their query is done
+                if let Some(resp) = self.build_response(cmd, client_id) {
+                    self.respond(client_id, Some(resp));
+                }
+            }
+            LobbyMsg::Presence { client_id, away } => self.set_presence(client_id, away),
+            LobbyMsg::Disconnected { client_id } => self.disconnect(client_id),
+        }
+    }
 
-                Some(proto::Response::LoginOk)
-            } else {
-                Some(proto::Response::LoginInvalid)
+    fn set_presence(&mut self, client_id: usize, away: bool) {
+        // Sent by a shard, the client may be gone by now
+        if !self.user_list.contains_key(&client_id) {
+            return;
+        }
+        let changed = if away { self.away.insert(client_id) } else { self.away.remove(&client_id) };
+        if changed {
+            self.broadcast_user_list();
+        }
+    }
+
+    /// Sends `msg` to every client, no matter which thread it's on.
+    fn broadcast(&self, msg: proto::Response) {
+        for client in self.clients.values() {
+            client.send(&msg);
+        }
+        for shard in &self.shards {
+            shard.send(ShardMsg::Broadcast(msg.clone()));
+        }
+    }
+
+    /// Sends the list of logged in users to every client.
+    fn broadcast_user_list(&mut self) {
+        let names: Vec<String> = self.user_list.values().cloned().collect();
+        self.query(None, move |db| db.users_from_user_name_iter(names.iter().map(|s| s.as_ref())).unwrap_or_default(), |lobby, mut users| {
+            lobby.mark_away(&mut users);
+            lobby.broadcast(proto::Response::UserList(users));
+            None
+        });
+    }
+
+    /// Shows users as away whose clients are all away.
+    fn mark_away(&self, users: &mut [proto::User]) {
+        for user in users {
+            let mut clients = self.user_list.iter().filter(|&(_, name)| *name == user.user_name).peekable();
+            if clients.peek().is_some() && clients.all(|(client_id, _)| self.away.contains(client_id)) {
+                user.activity = proto::UserActivity::Away;
             }
         }
-        Disconnect => {
-            clients.remove(&client_id);
-            user_list.remove(&client_id);
-            let msg = proto::Response::UserList(fetch_users(db, user_list));
-            for c in clients.values() {
-                let _ = bincode::serialize_into(&c.stream, &msg);
+    }
+
+    /// Forgets about a client and tells everyone it's gone.
+    fn disconnect(&mut self, client_id: usize) {
+        self.clients.remove(&client_id);
+        self.remote.remove(&client_id);
+        self.user_list.remove(&client_id);
+        self.away.remove(&client_id);
+        self.broadcast_user_list();
+    }
+
+    fn addr(&self, client_id: usize) -> Option<SocketAddr> {
+        match self.clients.get(&client_id) {
+            Some(client) => Some(client.addr),
+            None => self.remote.get(&client_id).map(|r| r.addr),
+        }
+    }
+
+    fn build_response(&mut self, cmd: proto::Command, client_id: usize) -> Option<proto::Response> {
+        use proto::Command::*;
+        match cmd {
+            ListUsers => {
+                let names: Vec<String> = self.user_list.values().cloned().collect();
+                self.query(Some(client_id), move |db| db.users_from_user_name_iter(names.iter().map(|s| s.as_ref())).unwrap_or_default(), |lobby, mut users| {
+                    lobby.mark_away(&mut users);
+                    Some(proto::Response::UserList(users))
+                });
+                None
+            }
+            Login { email, password, } => {
+                let peer = self.addr(client_id)?.ip();
+                if let Some(wait) = self.login_limiter.retry_after(peer, Instant::now()) {
+                    return Some(proto::Response::LoginThrottled { retry_after_secs: wait.as_secs() + 1 });
+                }
+
+                let password_hex = {
+                    use std::fmt::Write;
+                    let mut buf = String::with_capacity(password.len() * 2);
+                    for b in password.iter() {
+                        let _ = write!(&mut buf, "{:X}", b);
+                    }
+                    buf
+                };
+                println!("Login with email '{}' and password '{}'", email, password_hex);
+                self.query(Some(client_id), move |db| db.user_with_credentials(&email, &password), move |lobby, user| {
+                    if let Ok(Some(user)) = user {
+                        // We insert the user name instead of the email address, because I want
+                        // to avoid moving around and possibly leaking user sensitive data.
+                        lobby.user_list.insert(client_id, user.user_name);
+                        lobby.login_limiter.succeeded(peer);
+
+                        // Notify other clients about the newly joined guy
+                        lobby.broadcast_user_list();
+
+                        Some(proto::Response::LoginOk)
+                    } else {
+                        lobby.login_limiter.failed(peer, Instant::now());
+                        Some(proto::Response::LoginInvalid)
+                    }
+                });
+                None
+            }
+            // Logged in clients are handed to the project's shard before getting here
+            JoinProject { .. } => Some(proto::Response::NotLoggedIn),
+            GetUsage => {
+                let user_name = match self.user_list.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                let quotas = self.quotas;
+                self.query(Some(client_id), move |db| quotas.usage(db, &user_name).map_err(|e| (user_name, e)), |_, usage| match usage {
+                    Ok(usage) => Some(proto::Response::Usage(usage)),
+                    Err((user_name, e)) => {
+                        println!("Failed to compute storage usage of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            GetProfile { user_name } => {
+                let caller = match self.user_list.get(&client_id) {
+                    Some(caller) => caller.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                self.query(Some(client_id), move |db| (db.profile(&user_name), user_name), move |_, (profile, user_name)| match profile {
+                    Ok(Some(profile)) => Some(proto::Response::Profile(if profile.user_name == caller { profile } else { profile.public() })),
+                    Ok(None) => Some(proto::Response::NoSuchUser { user_name }),
+                    Err(e) => {
+                        println!("Failed to load the profile of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            UpdateProfile(update) => {
+                let user_name = match self.user_list.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                if let Err(e) = update.check() {
+                    return Some(proto::Response::ProfileRejected(e));
+                }
+                let assets = self.assets.clone();
+                self.query(Some(client_id), move |db| {
+                    let res = (|| {
+                        // Keeping the avatar is fine, picking one means having access to it
+                        if let Some(ref avatar) = update.avatar {
+                            let current = db.profile(&user_name)?.and_then(|p| p.avatar);
+                            if current.as_ref() != Some(avatar) && !db.user_has_asset(&user_name, avatar)? {
+                                return Ok(Err(proto::ProfileError::NoSuchAsset));
+                            }
+                        }
+                        if let Some(old) = db.update_profile(&user_name, &update)? {
+                            if let Err(e) = assets.remove(&old) {
+                                println!("Failed to delete asset {}: {}", proto::asset::hash_hex(&old), e);
+                            }
+                        }
+                        db.profile(&user_name).map(Ok)
+                    })();
+                    res.map_err(|e| (user_name, e))
+                }, |_, res| match res {
+                    Ok(Ok(Some(profile))) => Some(proto::Response::Profile(profile)),
+                    Ok(Ok(None)) => Some(proto::Response::InternalError),
+                    Ok(Err(e)) => Some(proto::Response::ProfileRejected(e)),
+                    Err((user_name, e)) => {
+                        println!("Failed to update the profile of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            FetchAvatarChunk { user_name, offset } => {
+                let caller = match self.user_list.get(&client_id) {
+                    Some(caller) => caller.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                let assets = self.assets.clone();
+                self.query(Some(client_id), move |db| {
+                    let res = db.profile(&user_name).map(|profile| profile.map(|profile| {
+                        // Gated like the avatar in the profile itself
+                        let profile = if profile.user_name == caller { profile } else { profile.public() };
+                        profile.avatar.map(|hash| (hash, assets.read_chunk(&hash, offset)))
+                    }));
+                    (res, user_name)
+                }, move |_, (res, user_name)| match res {
+                    Ok(Some(Some((hash, Ok((data, size)))))) => Some(proto::Response::AssetChunk { hash, offset, size, data }),
+                    Ok(Some(Some((hash, Err(error))))) => Some(proto::Response::AssetFailed { hash, error }),
+                    Ok(Some(None)) => Some(proto::Response::NoAvatar { user_name }),
+                    Ok(None) => Some(proto::Response::NoSuchUser { user_name }),
+                    Err(e) => {
+                        println!("Failed to load the profile of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            ListProjects => {
+                let user_name = match self.user_list.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                self.query(Some(client_id), move |db| db.user_project_infos(&user_name).map_err(|e| (user_name, e)), |_, projects| match projects {
+                    Ok(projects) => Some(proto::Response::Projects(projects)),
+                    Err((user_name, e)) => {
+                        println!("Failed to list the projects of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            SetPresence { away } => {
+                if !self.user_list.contains_key(&client_id) {
+                    return Some(proto::Response::NotLoggedIn);
+                }
+                self.set_presence(client_id, away);
+                None
+            }
+            Disconnect => {
+                self.disconnect(client_id);
+                None
+            }
+            LeaveProject { .. } | ReleaseLock { .. } => None,
+            SubmitOp { .. } | FetchOps { .. } | AcquireLock { .. } | Undo { .. } | Redo { .. }
+            | CreateSnapshot { .. } | ListSnapshots { .. } | DiffSnapshot { .. } | PreviewSnapshot { .. } | RestoreSnapshot { .. }
+            | BeginUpload { .. } | UploadChunk { .. } | FetchAssetChunk { .. } | ListAssets { .. } | ReleaseAsset { .. }
+            | GetProjectActivity { .. } | SendChat { .. } => {
+                Some(proto::Response::NotInProject)
             }
-            None
         }
     }
+
+    /// Executes an admin command and sends its output to `reply`.
+    fn run_admin(&mut self, cmd: admin::AdminCommand, reply: mpsc::Sender<String>) {
+        use admin::AdminCommand::*;
+        use std::fmt::Write;
+        let output = match cmd {
+            Help => admin::HELP.to_owned(),
+            Clients => {
+                let mut ids: Vec<_> = self.clients.keys().chain(self.remote.keys()).cloned().collect();
+                ids.sort();
+                let mut out = format!("{} client(s) connected", ids.len());
+                for id in ids {
+                    let user = self.user_list.get(&id).map(|u| u.as_str()).unwrap_or("(not logged in)");
+                    let place = match self.remote.get(&id) {
+                        Some(remote) => format!("shard {}", remote.shard),
+                        None => "lobby".to_owned(),
+                    };
+                    let _ = write!(&mut out, "\n  {:<6} {:<24} {:<24} {}", id, self.addr(id).unwrap(), user, place);
+                }
+                out
+            }
+            Kick(client_id) => {
+                if let Some(remote) = self.remote.get(&client_id) {
+                    self.shards[remote.shard].send(ShardMsg::Kick(client_id));
+                    format!("Kicked client {}", client_id)
+                } else if let Some(client) = self.clients.get(&client_id) {
+                    client.send(&proto::Response::SystemMessage("You were disconnected by the server operator.".to_owned()));
+                    let _ = client.stream.shutdown(std::net::Shutdown::Both);
+                    self.disconnect(client_id);
+                    format!("Kicked client {}", client_id)
+                } else {
+                    format!("No client with id {}", client_id)
+                }
+            }
+            Say(text) => {
+                self.broadcast(proto::Response::SystemMessage(text));
+                format!("Sent to {} client(s)", self.clients.len() + self.remote.len())
+            }
+            Limits => {
+                let entries = self.login_limiter.entries(Instant::now());
+                let mut out = format!("{} address(es) with recent failed logins", entries.len());
+                for entry in entries {
+                    let _ = write!(&mut out, "\n  {:<40} {} failed", entry.addr, entry.failures);
+                    if let Some(blocked_for) = entry.blocked_for {
+                        let _ = write!(&mut out, ", blocked for {}s", blocked_for.as_secs() + 1);
+                    }
+                }
+                out
+            }
+            ResetLimits(addr) => format!("Reset {} address(es)", self.login_limiter.reset(addr)),
+            CreateUser { email, user_name, password } => {
+                // Clients send the hash of the password, never the password itself
+                let password_hashed = Sha3_256::digest(password.as_bytes());
+                self.db.run(move |db| {
+                    let _ = reply.send(match db.create_user(&email, &user_name, password_hashed.as_slice()) {
+                        Ok(()) => format!("Created user {}", user_name),
+                        Err(e) => format!("Failed to create user {}: {}", user_name, e),
+                    });
+                });
+                return;
+            }
+            Quota { user_name, limit } => {
+                self.db.run(move |db| {
+                    let res = match limit {
+                        Some(limit) => db.set_user_quota(&user_name, limit),
+                        None => db.reset_user_quota(&user_name),
+                    };
+                    let _ = reply.send(match res {
+                        Ok(()) => format!("Quota of {} updated", user_name),
+                        Err(e) => format!("Failed to update quota of {}: {}", user_name, e),
+                    });
+                });
+                return;
+            }
+            AddMember { user_name, project_id, role } => {
+                self.db.run(move |db| {
+                    let _ = reply.send(match db.add_project_member(project_id, &user_name, role) {
+                        Ok(true) => format!("Added {} to project {} as {}", user_name, project_id, role.name()),
+                        Ok(false) if db.is_project_member(project_id, &user_name).unwrap_or(false) => {
+                            format!("{} is a member of project {} already", user_name, project_id)
+                        }
+                        Ok(false) => format!("No user {} or project {}", user_name, project_id),
+                        Err(e) => format!("Failed to add {} to project {}: {}", user_name, project_id, e),
+                    });
+                });
+                return;
+            }
+            RemoveMember { user_name, project_id } => {
+                self.db.run(move |db| {
+                    let _ = reply.send(match db.remove_project_member(project_id, &user_name) {
+                        Ok(true) => format!("Removed {} from project {}", user_name, project_id),
+                        Ok(false) => format!("{} isn't a member of project {}", user_name, project_id),
+                        Err(e) => format!("Failed to remove {} from project {}: {}", user_name, project_id, e),
+                    });
+                });
+                return;
+            }
+            SetRole { user_name, project_id, role } => {
+                self.db.run(move |db| {
+                    let _ = reply.send(match db.set_project_role(project_id, &user_name, role) {
+                        Ok(true) => format!("Gave {} the role {} in project {}", user_name, role.name(), project_id),
+                        Ok(false) => match db.project_role(project_id, &user_name) {
+                            Ok(Some(_)) => format!("{} has the role {} in project {} already", user_name, role.name(), project_id),
+                            Ok(None) => format!("{} isn't a member of project {}", user_name, project_id),
+                            Err(e) => format!("Failed to look up the role of {}: {}", user_name, e),
+                        },
+                        Err(e) => format!("Failed to change the role of {} in project {}: {}", user_name, project_id, e),
+                    });
+                });
+                return;
+            }
+            Shutdown { delay, reason, reconnect_after } => {
+                self.shutdown = Some(admin::Shutdown::new(delay, reason, reconnect_after, Instant::now()));
+                format!("Shutting down in {} seconds", delay.as_secs())
+            }
+            CancelShutdown => match self.shutdown.take() {
+                Some(_) => {
+                    self.broadcast(proto::Response::SystemMessage("The server shutdown was cancelled.".to_owned()));
+                    "Shutdown cancelled".to_owned()
+                }
+                None => "No shutdown pending".to_owned(),
+            },
+        };
+        let _ = reply.send(output);
+    }
 }
@@ -0,0 +1,762 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use proto;
+use proto::project::{LoggedOp, ObjectId, OpError, Operation, Project};
+
+/// How long a lock is held without being renewed.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum Submitted {
+    /// The request was accepted, send these to every member of the project.
+    Broadcast(Vec<proto::Response>),
+    /// The request was rejected, send this to the submitter only.
+    Reply(proto::Response),
+}
+
+/// After how many operations a snapshot is taken automatically.
+pub const SNAPSHOT_INTERVAL: u64 = 200;
+
+/// How many steps each user can undo.
+pub const UNDO_DEPTH: usize = 100;
+
+/// How many persisted operations stay in memory, so clients catching up after a short
+/// disconnect don't need the database.
+pub const KEPT_OPS: usize = 1000;
+
+/// How long a project stays loaded after the last member left.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+struct Member {
+    site: u32,
+    user_name: String,
+}
+
+struct LockInfo {
+    client_id: usize,
+    holder: String,
+    expires: Instant,
+}
+
+/// The inverse of an operation, recorded for undo or redo.
+struct HistoryEntry {
+    /// Sequence number at which the reverted operation was applied.
+    seq: u64,
+    inverse: Operation,
+}
+
+#[derive(Default)]
+struct History {
+    undo: Vec<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HistoryDirection {
+    Undo,
+    Redo,
+}
+
+/// An entry of the activity log that isn't persisted yet.
+pub struct NewActivity {
+    pub user_name: String,
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub kind: proto::ActivityKind,
+    pub summary: String,
+}
+
+/// In-memory state of a project that at least one client has joined.
+///
+/// The server is the single authority on the order of operations: every accepted
+/// operation gets the next sequence number and is appended to the log before it is
+/// broadcast, so all members apply the exact same sequence.
+pub struct ProjectSession {
+    id: u32,
+    state: Project,
+    seq: u64,
+    /// Operations accepted since the session was created, starting after `log_start`.
+    /// Operations are dropped once they're persisted and there are enough newer ones.
+    log: Vec<LoggedOp>,
+    log_start: u64,
+    /// How many operations of `log` are persisted already
+    saved: usize,
+    /// How many jobs saving operations or activity haven't finished yet
+    saving: usize,
+    /// Since when nobody is in the project
+    idle_since: Option<Instant>,
+    snapshot_seq: u64,
+    next_site: u32,
+    members: HashMap<usize, Member>,
+    /// Site -> user name, for every site ever handed out
+    site_users: HashMap<u32, String>,
+    locks: HashMap<ObjectId, LockInfo>,
+    /// Undo history per user name. Kept when a user leaves, so it's still there when
+    /// they come back while the project is loaded.
+    histories: HashMap<String, History>,
+    /// Activity log entries that aren't persisted yet
+    activity: Vec<NewActivity>,
+}
+
+impl ProjectSession {
+    /// Creates a session for a project at state `state` and sequence number `seq`.
+    /// Sites up to `max_site` must not be handed out again.
+    pub fn new(id: u32, state: Project, seq: u64, max_site: u32) -> Self {
+        Self {
+            id,
+            next_site: state.max_site().max(max_site) + 1,
+            state,
+            seq,
+            log: Vec::new(),
+            log_start: seq,
+            saved: 0,
+            saving: 0,
+            idle_since: Some(Instant::now()),
+            snapshot_seq: seq,
+            members: HashMap::new(),
+            site_users: HashMap::new(),
+            locks: HashMap::new(),
+            histories: HashMap::new(),
+            activity: Vec::new(),
+        }
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = usize> + '_ {
+        self.members.keys().cloned()
+    }
+
+    pub fn is_member(&self, client_id: usize) -> bool {
+        self.members.contains_key(&client_id)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn state(&self) -> &Project {
+        &self.state
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// All logged operations with a sequence number greater than `since_seq`, or `None`
+    /// if some of them happened before the session was created.
+    pub fn ops_since(&self, since_seq: u64) -> Option<Vec<LoggedOp>> {
+        if since_seq < self.log_start {
+            return None;
+        }
+        let start = self.log.iter().position(|o| o.seq > since_seq).unwrap_or(self.log.len());
+        Some(self.log[start..].to_vec())
+    }
+
+    /// Operations that haven't been persisted yet.
+    pub fn unsaved_ops(&self) -> &[LoggedOp] {
+        &self.log[self.saved..]
+    }
+
+    /// Marks the unsaved operations as being saved.
+    pub fn mark_saved(&mut self) {
+        self.saved = self.log.len();
+        self.saving += 1;
+    }
+
+    /// Handles the operations up to `last_seq` having been saved. Drops them from the
+    /// log, except for the most recent [KEPT_OPS].
+    pub fn ops_saved(&mut self, last_seq: u64) {
+        self.saving -= 1;
+        let persisted = self.log.iter().take_while(|o| o.seq <= last_seq).count().min(self.saved);
+        let dropped = persisted.min(self.log.len().saturating_sub(KEPT_OPS));
+        if dropped == 0 {
+            return;
+        }
+        self.log_start = self.log[dropped - 1].seq;
+        self.log.drain(..dropped);
+        self.saved -= dropped;
+
+        // Without the operations since, there's no telling whether older steps would
+        // revert someone else's work
+        let log_start = self.log_start;
+        for history in self.histories.values_mut() {
+            history.undo.retain(|e| e.seq >= log_start);
+            history.redo.retain(|e| e.seq >= log_start);
+        }
+        self.histories.retain(|_, h| !h.undo.is_empty() || !h.redo.is_empty());
+    }
+
+    /// Marks the operations from `first_seq` on as unsaved again, after saving them failed.
+    pub fn save_failed(&mut self, first_seq: u64) {
+        self.saving -= 1;
+        let idx = self.log.iter().position(|o| o.seq >= first_seq).unwrap_or(self.log.len());
+        self.saved = self.saved.min(idx);
+    }
+
+    /// Whether enough operations happened since the last snapshot to take a new one.
+    pub fn needs_snapshot(&self) -> bool {
+        self.seq >= self.snapshot_seq + SNAPSHOT_INTERVAL
+    }
+
+    /// Whether any operations happened since the last snapshot.
+    pub fn changed_since_snapshot(&self) -> bool {
+        self.seq > self.snapshot_seq
+    }
+
+    pub fn snapshot_taken(&mut self, seq: u64) {
+        self.snapshot_seq = self.snapshot_seq.max(seq);
+    }
+
+    /// Takes the activity log entries that weren't persisted yet, to save them.
+    pub fn take_activity(&mut self) -> Vec<NewActivity> {
+        if !self.activity.is_empty() {
+            self.saving += 1;
+        }
+        ::std::mem::take(&mut self.activity)
+    }
+
+    pub fn activity_saved(&mut self) {
+        self.saving -= 1;
+    }
+
+    /// Puts back entries taken with [ProjectSession::take_activity], after saving them failed.
+    pub fn activity_save_failed(&mut self, mut entries: Vec<NewActivity>) {
+        self.saving -= 1;
+        entries.append(&mut self.activity);
+        self.activity = entries;
+    }
+
+    /// Whether nobody has been in the project for [IDLE_TIMEOUT] and everything about it
+    /// is persisted, so the session can be dropped.
+    pub fn is_idle(&self, now: Instant) -> bool {
+        self.idle_since.is_some_and(|since| now >= since + IDLE_TIMEOUT)
+            && self.saving == 0
+            && self.unsaved_ops().is_empty()
+            && self.activity.is_empty()
+            && !self.changed_since_snapshot()
+    }
+
+    fn log_activity(&mut self, user_name: String, kind: proto::ActivityKind, summary: String) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.activity.push(NewActivity { user_name, time, kind, summary });
+    }
+
+    pub fn join(&mut self, client_id: usize, user_name: &str) -> proto::Response {
+        let site = match self.members.get(&client_id) {
+            Some(member) => member.site,
+            None => {
+                let site = self.next_site;
+                self.next_site += 1;
+                self.site_users.insert(site, user_name.to_owned());
+                self.members.insert(client_id, Member {
+                    site,
+                    user_name: user_name.to_owned(),
+                });
+                self.log_activity(user_name.to_owned(), proto::ActivityKind::Joined, "Joined the project".to_owned());
+                site
+            }
+        };
+        self.idle_since = None;
+        proto::Response::ProjectJoined {
+            project_id: self.id,
+            site,
+            seq: self.seq,
+            state: self.state.clone(),
+            locks: self.locks.iter().map(|(&object, l)| proto::Lock {
+                object,
+                holder: l.holder.clone(),
+            }).collect(),
+        }
+    }
+
+    /// Removes a client from the project. Returns the notifications about the locks it held.
+    pub fn leave(&mut self, client_id: usize) -> Vec<proto::Response> {
+        if let Some(member) = self.members.remove(&client_id) {
+            self.log_activity(member.user_name, proto::ActivityKind::Left, "Left the project".to_owned());
+            if self.members.is_empty() {
+                self.idle_since = Some(Instant::now());
+            }
+        }
+        let held = self.locks_where(|_, l| l.client_id == client_id);
+        self.release(held)
+    }
+
+    pub fn acquire_lock(&mut self, client_id: usize, object: ObjectId, now: Instant) -> Submitted {
+        let deny = |reason| Submitted::Reply(proto::Response::LockDenied {
+            project_id: self.id,
+            object,
+            reason,
+        });
+        let user_name = match self.members.get(&client_id) {
+            Some(member) => member.user_name.clone(),
+            None => return Submitted::Reply(proto::Response::NotInProject),
+        };
+        if !self.state.contains(object) {
+            return deny(proto::LockError::NoSuchObject);
+        }
+        if let Some(holder) = self.foreign_lock_holder(client_id, object) {
+            return deny(proto::LockError::HeldBy(holder.to_owned()));
+        }
+
+        let renewed = self.locks.insert(object, LockInfo {
+            client_id,
+            holder: user_name.clone(),
+            expires: now + LOCK_TIMEOUT,
+        }).is_some();
+        if renewed {
+            Submitted::Broadcast(Vec::new())
+        } else {
+            Submitted::Broadcast(vec![proto::Response::LockChanged {
+                project_id: self.id,
+                object,
+                holder: Some(user_name),
+            }])
+        }
+    }
+
+    pub fn release_lock(&mut self, client_id: usize, object: ObjectId) -> Vec<proto::Response> {
+        match self.locks.get(&object) {
+            Some(l) if l.client_id == client_id => self.release(vec![object]),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Releases all locks that haven't been renewed in time.
+    pub fn expire_locks(&mut self, now: Instant) -> Vec<proto::Response> {
+        let expired = self.locks_where(|_, l| l.expires <= now);
+        self.release(expired)
+    }
+
+    fn locks_where(&self, pred: impl Fn(ObjectId, &LockInfo) -> bool) -> Vec<ObjectId> {
+        self.locks.iter()
+            .filter(|&(&object, l)| pred(object, l))
+            .map(|(&object, _)| object)
+            .collect()
+    }
+
+    fn release(&mut self, objects: Vec<ObjectId>) -> Vec<proto::Response> {
+        objects.into_iter().map(|object| {
+            self.locks.remove(&object);
+            proto::Response::LockChanged {
+                project_id: self.id,
+                object,
+                holder: None,
+            }
+        }).collect()
+    }
+
+    /// The user name of whoever other than `client_id` locked `object`, one of its parents
+    /// or one of its children.
+    fn foreign_lock_holder(&self, client_id: usize, object: ObjectId) -> Option<&str> {
+        let lineage = self.state.lineage(object);
+        self.locks.iter()
+            .filter(|&(_, l)| l.client_id != client_id)
+            .find(|&(&locked, _)| lineage.contains(&locked) || self.state.lineage(locked).contains(&object))
+            .map(|(_, l)| l.holder.as_str())
+    }
+
+    /// Applies an operation submitted by a member.
+    pub fn submit(&mut self, client_id: usize, local_seq: u32, base_seq: u64, op: Operation) -> Submitted {
+        let user_name = match self.members.get(&client_id) {
+            Some(member) => member.user_name.clone(),
+            None => return Submitted::Reply(proto::Response::NotInProject),
+        };
+        let res = if base_seq > self.seq {
+            Err(OpError::InvalidBase)
+        } else if let Operation::Restore { .. } = op {
+            // Only ever built from a snapshot, by [ProjectSession::restore]
+            Err(OpError::NotAllowed)
+        } else {
+            self.commit(client_id, Some(local_seq), op, None)
+        };
+        match res {
+            Ok((msgs, inverse)) => {
+                self.record_undo(user_name, inverse);
+                Submitted::Broadcast(msgs)
+            }
+            Err(error) => Submitted::Reply(proto::Response::OpRejected {
+                project_id: self.id,
+                local_seq,
+                error,
+            }),
+        }
+    }
+
+    /// Replaces the project's state on behalf of `client_id`, as a new undoable edit.
+    pub fn restore(&mut self, client_id: usize, state: Project) -> Submitted {
+        let user_name = match self.members.get(&client_id) {
+            Some(member) => member.user_name.clone(),
+            None => return Submitted::Reply(proto::Response::NotInProject),
+        };
+        match self.commit(client_id, None, Operation::Restore { state }, None) {
+            Ok((msgs, inverse)) => {
+                self.record_undo(user_name, inverse);
+                Submitted::Broadcast(msgs)
+            }
+            Err(error) => Submitted::Reply(proto::Response::RestoreRejected {
+                project_id: self.id,
+                error,
+            }),
+        }
+    }
+
+    fn record_undo(&mut self, user_name: String, inverse: Operation) {
+        let history = self.histories.entry(user_name).or_default();
+        history.undo.push(HistoryEntry {
+            seq: self.seq,
+            inverse,
+        });
+        if history.undo.len() > UNDO_DEPTH {
+            history.undo.remove(0);
+        }
+        history.redo.clear();
+    }
+
+    /// Undoes the caller's last step. Also returns the [proto::Response::HistorySkipped]
+    /// notice for the caller if steps had to be dropped.
+    pub fn undo(&mut self, client_id: usize) -> (Submitted, Option<proto::Response>) {
+        self.step_history(client_id, HistoryDirection::Undo)
+    }
+
+    /// Like [ProjectSession::undo], the other way round.
+    pub fn redo(&mut self, client_id: usize) -> (Submitted, Option<proto::Response>) {
+        self.step_history(client_id, HistoryDirection::Redo)
+    }
+
+    /// Applies the next applicable entry of the caller's undo or redo stack and records
+    /// its inverse on the opposite stack.
+    ///
+    /// Entries that would revert other users' later edits, or that don't apply to the
+    /// current state anymore, are dropped and reported to the caller. Since operations
+    /// address objects by id, all other entries stay valid no matter what others did
+    /// in the meantime.
+    fn step_history(&mut self, client_id: usize, dir: HistoryDirection) -> (Submitted, Option<proto::Response>) {
+        let user_name = match self.members.get(&client_id) {
+            Some(member) => member.user_name.clone(),
+            None => return (Submitted::Reply(proto::Response::NotInProject), None),
+        };
+        let mut skipped = Vec::new();
+        let submitted = self.next_history_step(client_id, &user_name, dir, &mut skipped);
+        let notice = if skipped.is_empty() {
+            None
+        } else {
+            Some(proto::Response::HistorySkipped { project_id: self.id, steps: skipped })
+        };
+        (submitted, notice)
+    }
+
+    fn next_history_step(&mut self, client_id: usize, user_name: &str, dir: HistoryDirection, skipped: &mut Vec<proto::SkippedStep>) -> Submitted {
+        loop {
+            let entry = {
+                let history = self.histories.entry(user_name.to_owned()).or_default();
+                let stack = match dir {
+                    HistoryDirection::Undo => &mut history.undo,
+                    HistoryDirection::Redo => &mut history.redo,
+                };
+                match stack.pop() {
+                    Some(entry) => entry,
+                    None => return Submitted::Reply(match dir {
+                        HistoryDirection::Undo => proto::Response::NothingToUndo { project_id: self.id },
+                        HistoryDirection::Redo => proto::Response::NothingToRedo { project_id: self.id },
+                    }),
+                }
+            };
+            let summary = self.state.summarize(&entry.inverse);
+            if let Some(other) = self.clobbered_by(&entry, user_name) {
+                skipped.push(proto::SkippedStep { summary, reason: proto::SkipReason::Clobbered { user_name: other } });
+                continue;
+            }
+
+            match self.commit(client_id, None, entry.inverse.clone(), Some(dir)) {
+                Ok((msgs, inverse)) => {
+                    let history = self.histories.get_mut(user_name).unwrap();
+                    let opposite = match dir {
+                        HistoryDirection::Undo => &mut history.redo,
+                        HistoryDirection::Redo => &mut history.undo,
+                    };
+                    opposite.push(HistoryEntry {
+                        seq: self.seq,
+                        inverse,
+                    });
+                    return Submitted::Broadcast(msgs);
+                }
+                Err(error @ OpError::Locked(_)) => {
+                    // Might work later, keep it
+                    let history = self.histories.get_mut(user_name).unwrap();
+                    match dir {
+                        HistoryDirection::Undo => history.undo.push(entry),
+                        HistoryDirection::Redo => history.redo.push(entry),
+                    }
+                    return Submitted::Reply(proto::Response::UndoRejected {
+                        project_id: self.id,
+                        error,
+                    });
+                }
+                Err(error) => skipped.push(proto::SkippedStep { summary, reason: proto::SkipReason::Conflict(error) }),
+            }
+        }
+    }
+
+    /// The user whose work applying `entry` would revert, if someone else edited the same
+    /// thing after it was recorded.
+    fn clobbered_by(&self, entry: &HistoryEntry, user_name: &str) -> Option<String> {
+        let author = |o: &LoggedOp| self.site_users.get(&o.site).map_or("someone", |u| u.as_str());
+        self.log.iter()
+            .filter(|o| o.seq > entry.seq)
+            .filter(|o| author(o) != user_name)
+            .find(|o| entry.inverse.clobbers(&o.op, &self.state))
+            .map(|o| author(o).to_owned())
+    }
+
+    /// Applies an operation on behalf of `client_id` and appends it to the log.
+    /// `history` tells whether it's an undo or redo step. Returns the messages for
+    /// all members and the operation's inverse.
+    fn commit(&mut self, client_id: usize, local_seq: Option<u32>, op: Operation, history: Option<HistoryDirection>) -> Result<(Vec<proto::Response>, Operation), OpError> {
+        let site = self.members[&client_id].site;
+        let locked = match op {
+            // Replacing everything touches everything
+            Operation::Restore { .. } => self.locks.iter()
+                .find(|&(_, l)| l.client_id != client_id)
+                .map(|(&object, _)| object),
+            _ => op.target().and_then(|target| {
+                self.foreign_lock_holder(client_id, target).map(|_| target)
+            }),
+        };
+        if let Some(target) = locked {
+            return Err(OpError::Locked(target));
+        }
+        // Before applying, removed objects can still be named
+        let summary = self.state.summarize(&op);
+        let inverse = self.state.apply(&op)?;
+
+        // Editing a locked object counts as activity, keep the lock alive
+        if let Some(l) = op.target().and_then(|t| self.locks.get_mut(&t)) {
+            l.expires = Instant::now() + LOCK_TIMEOUT;
+        }
+
+        self.seq += 1;
+        let seq = self.seq;
+        let kind = match history {
+            None => proto::ActivityKind::Edit { seq },
+            Some(HistoryDirection::Undo) => proto::ActivityKind::Undo { seq },
+            Some(HistoryDirection::Redo) => proto::ActivityKind::Redo { seq },
+        };
+        let user_name = self.members[&client_id].user_name.clone();
+        self.log_activity(user_name, kind, summary);
+        self.log.push(LoggedOp {
+            seq: self.seq,
+            site,
+            local_seq,
+            op: op.clone(),
+        });
+        let applied = proto::Response::OpApplied {
+            project_id: self.id,
+            seq: self.seq,
+            site,
+            local_seq,
+            op,
+        };
+
+        // Locks of objects that were just removed are gone as well
+        let removed = self.locks_where(|object, _| !self.state.contains(object));
+        let mut msgs = vec![applied];
+        msgs.extend(self.release(removed));
+        Ok((msgs, inverse))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::project::{Clip, ClipProp, Device, Track, TrackProp};
+
+    const TRACK: ObjectId = ObjectId { site: 1, counter: 0 };
+    const CLIP: ObjectId = ObjectId { site: 1, counter: 1 };
+    const DEVICE: ObjectId = ObjectId { site: 1, counter: 2 };
+
+    /// A session with one track holding a clip and a device, joined by alice (client 1)
+    /// and bob (client 2).
+    fn session() -> ProjectSession {
+        let track = Track {
+            id: TRACK,
+            name: "Drums".to_owned(),
+            volume: 1.0,
+            pan: 0.0,
+            muted: false,
+            clips: vec![Clip { id: CLIP, start: 0, length: 96, notes: Vec::new() }],
+            devices: vec![Device { id: DEVICE, name: "Reverb".to_owned(), params: Default::default() }],
+        };
+        let mut session = ProjectSession::new(1, Project { tracks: vec![track] }, 0, 1);
+        session.join(1, "alice");
+        session.join(2, "bob");
+        session
+    }
+
+    fn is_locked(submitted: Submitted, object: ObjectId) -> bool {
+        match submitted {
+            Submitted::Reply(proto::Response::OpRejected { error: OpError::Locked(o), .. }) => o == object,
+            _ => false,
+        }
+    }
+
+    fn lock_denied_by(submitted: Submitted) -> Option<String> {
+        match submitted {
+            Submitted::Reply(proto::Response::LockDenied { reason: proto::LockError::HeldBy(holder), .. }) => Some(holder),
+            _ => None,
+        }
+    }
+
+    fn released(msgs: &[proto::Response]) -> Vec<ObjectId> {
+        msgs.iter().filter_map(|m| match *m {
+            proto::Response::LockChanged { object, holder: None, .. } => Some(object),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn locked_object_is_only_edited_by_its_holder() {
+        let mut session = session();
+        let now = Instant::now();
+        assert!(matches!(session.acquire_lock(1, TRACK, now), Submitted::Broadcast(_)));
+
+        let mute = Operation::SetTrack { id: TRACK, prop: TrackProp::Muted(true) };
+        assert!(is_locked(session.submit(2, 1, 0, mute.clone()), TRACK));
+        // The parent's lock covers its children too
+        assert!(is_locked(session.submit(2, 2, 0, Operation::RemoveClip { id: CLIP }), CLIP));
+        assert!(matches!(session.submit(1, 1, 0, mute), Submitted::Broadcast(_)));
+        assert_eq!(session.seq(), 1);
+    }
+
+    #[test]
+    fn locked_child_protects_its_parent() {
+        let mut session = session();
+        let now = Instant::now();
+        assert!(matches!(session.acquire_lock(1, CLIP, now), Submitted::Broadcast(_)));
+
+        assert!(is_locked(session.submit(2, 1, 0, Operation::RemoveTrack { id: TRACK }), TRACK));
+        assert_eq!(lock_denied_by(session.acquire_lock(2, TRACK, now)), Some("alice".to_owned()));
+        // Siblings are free to take
+        assert!(matches!(session.acquire_lock(2, DEVICE, now), Submitted::Broadcast(_)));
+        assert!(is_locked(session.submit(1, 1, 0, Operation::RemoveTrack { id: TRACK }), TRACK));
+        assert!(session.state().contains(TRACK));
+    }
+
+    #[test]
+    fn removing_an_object_releases_its_locks() {
+        let mut session = session();
+        assert!(matches!(session.acquire_lock(1, CLIP, Instant::now()), Submitted::Broadcast(_)));
+        match session.submit(1, 1, 0, Operation::RemoveTrack { id: TRACK }) {
+            Submitted::Broadcast(msgs) => assert_eq!(released(&msgs), vec![CLIP]),
+            Submitted::Reply(resp) => panic!("Rejected: {:?}", resp),
+        }
+        assert!(session.locks.is_empty());
+    }
+
+    #[test]
+    fn locks_expire_unless_renewed() {
+        let mut session = session();
+        let now = Instant::now();
+        assert!(matches!(session.acquire_lock(1, TRACK, now), Submitted::Broadcast(_)));
+        let half = now + LOCK_TIMEOUT / 2;
+        // Renewing announces nothing new
+        assert!(matches!(session.acquire_lock(1, TRACK, half), Submitted::Broadcast(ref msgs) if msgs.is_empty()));
+
+        assert!(released(&session.expire_locks(now + LOCK_TIMEOUT)).is_empty());
+        assert_eq!(released(&session.expire_locks(half + LOCK_TIMEOUT)), vec![TRACK]);
+        assert!(matches!(session.acquire_lock(2, TRACK, half + LOCK_TIMEOUT), Submitted::Broadcast(_)));
+    }
+
+    #[test]
+    fn leaving_releases_the_locks() {
+        let mut session = session();
+        let now = Instant::now();
+        assert!(matches!(session.acquire_lock(1, CLIP, now), Submitted::Broadcast(_)));
+        assert!(matches!(session.acquire_lock(2, DEVICE, now), Submitted::Broadcast(_)));
+
+        assert_eq!(released(&session.leave(1)), vec![CLIP]);
+        assert!(matches!(session.submit(2, 1, 0, Operation::RemoveClip { id: CLIP }), Submitted::Broadcast(_)));
+        // Someone else's lock is no reason to release
+        assert!(session.release_lock(1, DEVICE).is_empty());
+        assert!(session.locks.contains_key(&DEVICE));
+    }
+
+    fn volume(session: &ProjectSession) -> f32 {
+        session.state().tracks[0].volume
+    }
+
+    fn set_volume(volume: f32) -> Operation {
+        Operation::SetTrack { id: TRACK, prop: TrackProp::Volume(volume) }
+    }
+
+    #[test]
+    fn undo_and_redo_own_edits() {
+        let mut session = session();
+        assert!(matches!(session.submit(1, 1, 0, set_volume(0.5)), Submitted::Broadcast(_)));
+        assert!(matches!(session.submit(1, 2, 1, set_volume(0.8)), Submitted::Broadcast(_)));
+
+        assert!(matches!(session.undo(1), (Submitted::Broadcast(_), None)));
+        assert_eq!(volume(&session), 0.5);
+        assert!(matches!(session.undo(1), (Submitted::Broadcast(_), None)));
+        assert_eq!(volume(&session), 1.0);
+        assert!(matches!(session.undo(1), (Submitted::Reply(proto::Response::NothingToUndo { .. }), None)));
+        // Someone else's history is their own
+        assert!(matches!(session.undo(2), (Submitted::Reply(proto::Response::NothingToUndo { .. }), None)));
+
+        assert!(matches!(session.redo(1), (Submitted::Broadcast(_), None)));
+        assert_eq!(volume(&session), 0.5);
+        // A new edit ends what can be redone
+        assert!(matches!(session.submit(1, 3, 5, set_volume(0.2)), Submitted::Broadcast(_)));
+        assert!(matches!(session.redo(1), (Submitted::Reply(proto::Response::NothingToRedo { .. }), None)));
+    }
+
+    #[test]
+    fn undo_skips_what_others_edited_since() {
+        let mut session = session();
+        assert!(matches!(session.submit(1, 1, 0, Operation::SetTrack { id: TRACK, prop: TrackProp::Muted(true) }), Submitted::Broadcast(_)));
+        assert!(matches!(session.submit(1, 2, 1, set_volume(0.5)), Submitted::Broadcast(_)));
+        assert!(matches!(session.submit(2, 1, 2, set_volume(0.8)), Submitted::Broadcast(_)));
+
+        // Setting the volume back would revert bob's change, the mute is undone instead
+        let (submitted, notice) = session.undo(1);
+        assert!(matches!(submitted, Submitted::Broadcast(_)));
+        match notice {
+            Some(proto::Response::HistorySkipped { steps, .. }) => {
+                assert_eq!(steps.len(), 1);
+                assert_eq!(steps[0].reason, proto::SkipReason::Clobbered { user_name: "bob".to_owned() });
+            }
+            _ => panic!("Expected the volume step to be skipped"),
+        }
+        assert_eq!(volume(&session), 0.8);
+        assert!(!session.state().tracks[0].muted);
+    }
+
+    #[test]
+    fn undo_of_removed_objects_is_skipped() {
+        let mut session = session();
+        assert!(matches!(session.submit(1, 1, 0, Operation::SetClip { id: CLIP, prop: ClipProp::Length(48) }), Submitted::Broadcast(_)));
+        assert!(matches!(session.submit(2, 1, 1, Operation::RemoveClip { id: CLIP }), Submitted::Broadcast(_)));
+
+        match session.undo(1) {
+            (Submitted::Reply(proto::Response::NothingToUndo { .. }), Some(proto::Response::HistorySkipped { steps, .. })) => {
+                assert_eq!(steps.len(), 1);
+            }
+            _ => panic!("Expected the step to be skipped"),
+        }
+    }
+
+    #[test]
+    fn undo_waits_for_a_lock() {
+        let mut session = session();
+        let now = Instant::now();
+        assert!(matches!(session.submit(1, 1, 0, set_volume(0.5)), Submitted::Broadcast(_)));
+        assert!(matches!(session.acquire_lock(2, TRACK, now), Submitted::Broadcast(_)));
+
+        match session.undo(1) {
+            (Submitted::Reply(proto::Response::UndoRejected { error: OpError::Locked(object), .. }), None) => assert_eq!(object, TRACK),
+            _ => panic!("Expected the undo to be refused"),
+        }
+        // Kept for when the lock is gone
+        session.release_lock(2, TRACK);
+        assert!(matches!(session.undo(1), (Submitted::Broadcast(_), None)));
+        assert_eq!(volume(&session), 1.0);
+    }
+}
@@ -0,0 +1,137 @@
+use rusqlite as sql;
+
+use db::Database;
+use proto::asset::{AssetError, AssetHash, ProjectUsage, QuotaScope, StorageUsage};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Storage limits for assets. `None` means unlimited.
+///
+/// The user limit applies to the bytes a user uploaded over all their projects and
+/// can be overridden per user by an administrator. The project limit applies to the
+/// bytes referenced by a single project, no matter who uploaded them.
+#[derive(Debug, Clone, Copy)]
+pub struct Quotas {
+    pub user: Option<u64>,
+    pub project: Option<u64>,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self {
+            user: Some(1024 * MIB),
+            project: None,
+        }
+    }
+}
+
+impl Quotas {
+    /// Parses a limit given in MiB, or "unlimited".
+    pub fn parse_limit(arg: &str) -> Option<Option<u64>> {
+        if arg == "unlimited" {
+            return Some(None);
+        }
+        // Too large to count in bytes isn't a limit, it's a typo
+        arg.parse::<u64>().ok().and_then(|mib| mib.checked_mul(MIB)).map(Some)
+    }
+
+    pub fn user_limit(&self, db: &Database, user_name: &str) -> sql::Result<Option<u64>> {
+        Ok(db.user_quota(user_name)?.unwrap_or(self.user))
+    }
+
+    /// Checks whether `user_name` may add an asset to a project.
+    /// Returns the error to refuse the upload with, if it would exceed a quota.
+    pub fn check(&self, db: &Database, user_name: &str, project_id: u32, hash: &AssetHash, size: u64) -> sql::Result<Option<AssetError>> {
+        if db.project_has_asset(project_id, hash)? {
+            // Already stored and paid for
+            return Ok(None);
+        }
+
+        if let Some(limit) = self.user_limit(db, user_name)? {
+            let used = db.user_storage(user_name)?;
+            if exceeds(used, size, limit) {
+                return Ok(Some(AssetError::QuotaExceeded { scope: QuotaScope::User, used, limit }));
+            }
+        }
+        if let Some(limit) = self.project {
+            let used = db.project_storage(project_id)?;
+            if exceeds(used, size, limit) {
+                return Ok(Some(AssetError::QuotaExceeded { scope: QuotaScope::Project, used, limit }));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn usage(&self, db: &Database, user_name: &str) -> sql::Result<StorageUsage> {
+        let projects = db.user_projects(user_name)?
+            .into_iter()
+            .map(|project_id| {
+                Ok(ProjectUsage {
+                    project_id,
+                    used: db.project_storage(project_id)?,
+                    limit: self.project,
+                })
+            })
+            .collect::<sql::Result<_>>()?;
+        Ok(StorageUsage {
+            used: db.user_storage(user_name)?,
+            limit: self.user_limit(db, user_name)?,
+            projects,
+        })
+    }
+}
+
+/// Whether `size` more bytes don't fit. The size comes from the client, so it may be
+/// anything up to `u64::MAX`.
+fn exceeds(used: u64, size: u64, limit: u64) -> bool {
+    used.checked_add(size).is_none_or(|total| total > limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = "DanHau";
+    const PROJECT: u32 = 1;
+
+    #[test]
+    fn limits_in_mib() {
+        assert_eq!(Quotas::parse_limit("unlimited"), Some(None));
+        assert_eq!(Quotas::parse_limit("10"), Some(Some(10 * MIB)));
+        assert_eq!(Quotas::parse_limit("-1"), None);
+        assert_eq!(Quotas::parse_limit("ten"), None);
+        assert_eq!(Quotas::parse_limit(&u64::MAX.to_string()), None);
+    }
+
+    #[test]
+    fn exceeding() {
+        assert!(!exceeds(60, 40, 100));
+        assert!(exceeds(60, 41, 100));
+        assert!(exceeds(1, u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn user_and_project_quotas() {
+        let db = Database::test_copy("quota");
+        let quotas = Quotas { user: Some(100), project: Some(150) };
+        db.add_asset_ref(PROJECT, &[1; 32], 60, USER).unwrap();
+
+        assert_eq!(quotas.check(&db, USER, PROJECT, &[2; 32], 40).unwrap(), None);
+        assert_eq!(
+            quotas.check(&db, USER, PROJECT, &[2; 32], 41).unwrap(),
+            Some(AssetError::QuotaExceeded { scope: QuotaScope::User, used: 60, limit: 100 })
+        );
+        // Already in the project, nothing more to store
+        assert_eq!(quotas.check(&db, USER, PROJECT, &[1; 32], 60).unwrap(), None);
+
+        // An administrator's limit for the user counts instead, the project's still applies
+        db.set_user_quota(USER, None).unwrap();
+        assert_eq!(
+            quotas.check(&db, USER, PROJECT, &[2; 32], 91).unwrap(),
+            Some(AssetError::QuotaExceeded { scope: QuotaScope::Project, used: 60, limit: 150 })
+        );
+        let usage = quotas.usage(&db, USER).unwrap();
+        assert_eq!((usage.used, usage.limit), (60, None));
+        assert_eq!(usage.projects, vec![ProjectUsage { project_id: PROJECT, used: 60, limit: Some(150) }]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Failed logins from one address within [FAILED_LOGIN_WINDOW] after which further
+/// attempts are refused.
+const MAX_FAILED_LOGINS: usize = 5;
+const FAILED_LOGIN_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+pub struct LimitEntry {
+    pub addr: IpAddr,
+    pub failures: usize,
+    /// How long logins stay refused, if they are
+    pub blocked_for: Option<Duration>,
+}
+
+/// Throttles password guessing by refusing logins from addresses with too many
+/// recent failed attempts.
+#[derive(Default)]
+pub struct LoginLimiter {
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl LoginLimiter {
+    fn prune(&mut self, now: Instant) {
+        for attempts in self.failures.values_mut() {
+            while attempts.front().is_some_and(|&t| now.duration_since(t) >= FAILED_LOGIN_WINDOW) {
+                attempts.pop_front();
+            }
+        }
+        self.failures.retain(|_, attempts| !attempts.is_empty());
+    }
+
+    /// How long until `addr` may try to log in again, if it's blocked right now.
+    pub fn retry_after(&mut self, addr: IpAddr, now: Instant) -> Option<Duration> {
+        self.prune(now);
+        let attempts = self.failures.get(&addr)?;
+        if attempts.len() < MAX_FAILED_LOGINS {
+            return None;
+        }
+        // Unblocked once enough failures left the window
+        let oldest = attempts[attempts.len() - MAX_FAILED_LOGINS];
+        Some(FAILED_LOGIN_WINDOW - now.duration_since(oldest))
+    }
+
+    pub fn failed(&mut self, addr: IpAddr, now: Instant) {
+        self.failures.entry(addr).or_default().push_back(now);
+    }
+
+    pub fn succeeded(&mut self, addr: IpAddr) {
+        self.failures.remove(&addr);
+    }
+
+    pub fn entries(&mut self, now: Instant) -> Vec<LimitEntry> {
+        self.prune(now);
+        let mut addrs: Vec<_> = self.failures.keys().cloned().collect();
+        addrs.sort();
+        addrs.into_iter()
+            .map(|addr| LimitEntry {
+                addr,
+                failures: self.failures[&addr].len(),
+                blocked_for: self.retry_after(addr, now),
+            })
+            .collect()
+    }
+
+    /// Forgets the failed logins of `addr`, or of every address. Returns the number of addresses reset.
+    pub fn reset(&mut self, addr: Option<IpAddr>) -> usize {
+        match addr {
+            Some(addr) => self.failures.remove(&addr).map_or(0, |_| 1),
+            None => {
+                let n = self.failures.len();
+                self.failures.clear();
+                n
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[test]
+    fn blocks_after_too_many_failures() {
+        let mut limiter = LoginLimiter::default();
+        let start = Instant::now();
+        for i in 0..MAX_FAILED_LOGINS as u64 {
+            assert_eq!(limiter.retry_after(addr(1), start), None);
+            limiter.failed(addr(1), start + Duration::from_secs(i * 10));
+        }
+        let now = start + Duration::from_secs(60);
+        assert_eq!(limiter.retry_after(addr(1), now), Some(FAILED_LOGIN_WINDOW - Duration::from_secs(60)));
+        assert_eq!(limiter.retry_after(addr(2), now), None);
+
+        // Once the first failure left the window, one more try is allowed
+        let later = start + FAILED_LOGIN_WINDOW;
+        assert_eq!(limiter.retry_after(addr(1), later), None);
+        limiter.failed(addr(1), later);
+        assert_eq!(limiter.retry_after(addr(1), later), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn success_forgets_failures() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        for _ in 0..MAX_FAILED_LOGINS - 1 {
+            limiter.failed(addr(1), now);
+        }
+        limiter.succeeded(addr(1));
+        limiter.failed(addr(1), now);
+        assert_eq!(limiter.retry_after(addr(1), now), None);
+    }
+
+    #[test]
+    fn entries_and_reset() {
+        let mut limiter = LoginLimiter::default();
+        let now = Instant::now();
+        for _ in 0..MAX_FAILED_LOGINS {
+            limiter.failed(addr(2), now);
+        }
+        limiter.failed(addr(1), now);
+        let entries = limiter.entries(now);
+        let listed: Vec<_> = entries.iter().map(|e| (e.addr, e.failures, e.blocked_for)).collect();
+        assert_eq!(listed, vec![(addr(1), 1, None), (addr(2), MAX_FAILED_LOGINS, Some(FAILED_LOGIN_WINDOW))]);
+        // Failures that left the window aren't listed
+        assert!(limiter.entries(now + FAILED_LOGIN_WINDOW).is_empty());
+
+        limiter.failed(addr(1), now);
+        limiter.failed(addr(2), now);
+        assert_eq!(limiter.reset(Some(addr(3))), 0);
+        assert_eq!(limiter.reset(Some(addr(1))), 1);
+        assert_eq!(limiter.reset(None), 1);
+        assert!(limiter.entries(now).is_empty());
+    }
+}
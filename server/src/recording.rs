@@ -0,0 +1,140 @@
+//! Session recordings, for reproducing bugs that depend on what several clients did when.
+//!
+//! A recording starts with a copy of the database as it was when the server started,
+//! followed by every command the server decoded and every response it sent, in the
+//! order they happened. When the server shuts down, the final state of each loaded
+//! project is appended. `--replay` plays a recording back, see the `replay` module.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use proto::project::Project;
+
+use db;
+
+/// Start of every recording, changes whenever the format does.
+const MAGIC: &[u8; 8] = b"CHSREC01";
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    /// The database file at the start of the recording
+    db: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Event {
+    /// The server accepted a connection.
+    Connected { client_id: usize },
+    Command { client_id: usize, cmd: proto::Command },
+    Response { client_id: usize, resp: proto::Response },
+    /// The client closed the connection.
+    Closed { client_id: usize },
+    /// A project's state when the server shut down.
+    ProjectState { project_id: u32, seq: u64, state: Project },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Microseconds since the start of the recording
+    pub micros: u64,
+    pub event: Event,
+}
+
+/// Appends events to a recording. Shared by all threads of the server, so the
+/// events end up in the order they happened.
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Starts a recording at `path` with a copy of `database`.
+    pub fn create(path: &Path, database: &db::Database) -> io::Result<Self> {
+        let copy = path.with_extension("db.tmp");
+        let _ = fs::remove_file(&copy);
+        database.copy_to(&copy).map_err(io::Error::other)?;
+        let db = fs::read(&copy);
+        let _ = fs::remove_file(&copy);
+
+        let mut out = File::create(path)?;
+        out.write_all(MAGIC)?;
+        bincode::serialize_into(&mut out, &Header { db: db? }).map_err(io::Error::other)?;
+        Ok(Self {
+            out: Arc::new(Mutex::new(out)),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&self, event: Event) {
+        let entry = Entry {
+            micros: self.start.elapsed().as_micros() as u64,
+            event,
+        };
+        let buf = match bincode::serialize(&entry) {
+            Ok(buf) => buf,
+            Err(e) => {
+                println!("Failed to record event: {}", e);
+                return;
+            }
+        };
+        // Unbuffered, so a crashing server still leaves a usable recording behind
+        if let Err(e) = self.out.lock().unwrap().write_all(&buf) {
+            println!("Failed to record event: {}", e);
+        }
+    }
+
+    /// The recorder for the events of one client.
+    pub fn client(&self, client_id: usize) -> ClientRecorder {
+        self.record(Event::Connected { client_id });
+        ClientRecorder { recorder: self.clone(), client_id }
+    }
+}
+
+/// Records what a [client::ClientSock] receives and sends.
+pub struct ClientRecorder {
+    recorder: Recorder,
+    client_id: usize,
+}
+
+impl ClientRecorder {
+    pub fn command(&self, cmd: &proto::Command) {
+        self.recorder.record(Event::Command { client_id: self.client_id, cmd: cmd.clone() });
+    }
+
+    pub fn response(&self, resp: &proto::Response) {
+        self.recorder.record(Event::Response { client_id: self.client_id, resp: resp.clone() });
+    }
+
+    pub fn closed(&self) {
+        self.recorder.record(Event::Closed { client_id: self.client_id });
+    }
+}
+
+/// A recording read back from disk.
+pub struct Recording {
+    /// The database file at the start of the recording
+    pub db: Vec<u8>,
+    pub entries: Vec<Entry>,
+}
+
+impl Recording {
+    /// Reads a recording. A recording cut short by a crash ends at its last complete event.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a session recording of this server version"));
+        }
+        let header: Header = bincode::deserialize_from(&mut file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut entries = Vec::new();
+        while let Ok(entry) = bincode::deserialize_from(&mut file) {
+            entries.push(entry);
+        }
+        Ok(Self { db: header.db, entries })
+    }
+}
@@ -0,0 +1,312 @@
+//! Plays a session recording back against a server running in this process, on a
+//! scratch copy of the recorded database, and checks that the clients get the same
+//! responses and the projects end up in the same state.
+//!
+//! Clients connect, send their commands and hang up in the recorded order and at the
+//! recorded times. Before each of these, the replay waits until every client got as
+//! many responses as it had by then in the recording, so the server sees the same
+//! interleaving of clients. Admin commands aren't recorded, so system messages and
+//! shutdown notices aren't compared. Neither are asset files, uploads of assets
+//! the server already had when recording may go differently.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::net::TcpListener;
+use proto::Response;
+use proto::project::Project;
+
+use admin::{AdminCommand, AdminRequest};
+use recording::{Event, Recorder, Recording};
+use {asset, db, quota, serve, ServerConfig};
+
+/// How long to wait for the responses a client got at some point of the recording.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Replays the recording at `path`. Returns whether the replay matched it.
+pub fn run(path: &Path, quotas: quota::Quotas, shard_count: usize) -> bool {
+    let recording = match Recording::read(path) {
+        Ok(recording) => recording,
+        Err(e) => {
+            println!("Failed to read {}: {}", path.display(), e);
+            return false;
+        }
+    };
+    let dir = std::env::temp_dir().join(format!("chorus_replay_{}", std::process::id()));
+    let res = replay(&recording, &dir, quotas, shard_count);
+    let _ = fs::remove_dir_all(&dir);
+    match res {
+        Ok(matched) => matched,
+        Err(e) => {
+            println!("Replay failed: {}", e);
+            false
+        }
+    }
+}
+
+fn replay(recording: &Recording, dir: &Path, quotas: quota::Quotas, shard_count: usize) -> io::Result<bool> {
+    fs::create_dir_all(dir)?;
+    let db_path = dir.join("chorus_studio.db");
+    fs::write(&db_path, &recording.db)?;
+    let database = db::Database::open(&db_path).map_err(io::Error::other)?;
+    // The replay is recorded too, that's where the final project states come from
+    let replay_path = dir.join("replay.rec");
+    let recorder = Recorder::create(&replay_path, &database)?;
+    let assets = asset::AssetStore::new(dir.join("assets"), quotas)?;
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap())?;
+    let addr = listener.local_addr()?;
+
+    let (admin_tx, admin_rx) = mpsc::channel();
+    let server = thread::spawn(move || {
+        let config = ServerConfig { listener, database, assets, quotas, shard_count, recorder: Some(recorder) };
+        serve(config, admin_rx)
+    });
+
+    let mut player = Player::new(addr);
+    let mut recorded: HashMap<usize, Vec<Response>> = HashMap::new();
+    let mut recorded_states = HashMap::new();
+    let start = Instant::now();
+    let mut commands = 0;
+    for entry in &recording.entries {
+        let client_id = match entry.event {
+            Event::Connected { client_id } | Event::Command { client_id, .. } | Event::Closed { client_id } => client_id,
+            Event::Response { client_id, ref resp } => {
+                if compared(resp) {
+                    recorded.entry(client_id).or_default().push(resp.clone());
+                }
+                continue;
+            }
+            Event::ProjectState { project_id, seq, ref state } => {
+                recorded_states.insert(project_id, (seq, state.clone()));
+                continue;
+            }
+        };
+
+        player.wait_for(&recorded);
+        let at = start + Duration::from_micros(entry.micros);
+        thread::sleep(at.saturating_duration_since(Instant::now()));
+        match entry.event {
+            Event::Connected { .. } => player.connect(client_id)?,
+            Event::Command { ref cmd, .. } => {
+                player.send(client_id, cmd);
+                commands += 1;
+            }
+            _ => player.close(client_id),
+        }
+    }
+    player.wait_for(&recorded);
+
+    let (reply, _) = mpsc::channel();
+    let cmd = AdminCommand::Shutdown { delay: Duration::from_secs(0), reason: "Replay finished".to_owned(), reconnect_after: None };
+    let _ = admin_tx.send(AdminRequest { cmd, reply });
+    if server.join().is_err() {
+        return Err(io::Error::other("The server panicked"));
+    }
+    let received = player.finish();
+
+    println!("Replayed {} command(s) of {} client(s)", commands, player_count(recording));
+    let mut matched = compare_responses(&recorded, &received);
+
+    let mut replayed_states = HashMap::new();
+    for entry in Recording::read(&replay_path)?.entries {
+        if let Event::ProjectState { project_id, seq, state } = entry.event {
+            replayed_states.insert(project_id, (seq, state));
+        }
+    }
+    if recording.entries.iter().any(|e| matches!(e.event, Event::ProjectState { .. })) {
+        matched &= compare_states(&recorded_states, &replayed_states);
+    } else {
+        println!("The recording ends without project states, the server didn't shut down cleanly. Only responses were compared.");
+    }
+
+    if matched {
+        println!("The replay matches the recording");
+    }
+    Ok(matched)
+}
+
+/// Drives the replayed clients and collects what they receive.
+struct Player {
+    addr: SocketAddr,
+    /// Recorded client id -> connection
+    clients: HashMap<usize, TcpStream>,
+    received: HashMap<usize, Vec<Response>>,
+    tx: mpsc::Sender<(usize, Response)>,
+    rx: mpsc::Receiver<(usize, Response)>,
+    /// Set once a client missed a response, waiting for more of them wouldn't help
+    diverged: bool,
+}
+
+impl Player {
+    fn new(addr: SocketAddr) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { addr, clients: HashMap::new(), received: HashMap::new(), tx, rx, diverged: false }
+    }
+
+    fn connect(&mut self, client_id: usize) -> io::Result<()> {
+        let stream = TcpStream::connect(self.addr)?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            while let Ok(resp) = bincode::deserialize_from(&mut reader) {
+                if tx.send((client_id, resp)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.clients.insert(client_id, stream);
+        Ok(())
+    }
+
+    fn send(&mut self, client_id: usize, cmd: &proto::Command) {
+        if let Some(stream) = self.clients.get_mut(&client_id) {
+            // A failed write shows up as missing responses
+            if let Ok(buf) = bincode::serialize(cmd) {
+                let _ = stream.write_all(&buf);
+            }
+        }
+    }
+
+    fn close(&mut self, client_id: usize) {
+        if let Some(stream) = self.clients.get(&client_id) {
+            let _ = stream.shutdown(Shutdown::Write);
+        }
+    }
+
+    /// Waits until every client received at least as many responses as `recorded`,
+    /// or gives up after [RESPONSE_TIMEOUT].
+    fn wait_for(&mut self, recorded: &HashMap<usize, Vec<Response>>) {
+        if self.diverged {
+            while let Ok((client_id, resp)) = self.rx.try_recv() {
+                self.receive(client_id, resp);
+            }
+            return;
+        }
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        loop {
+            let behind = recorded.iter().find(|&(id, resps)| self.received.get(id).map_or(0, |r| r.len()) < resps.len());
+            let client_id = match behind {
+                Some((&client_id, _)) => client_id,
+                None => return,
+            };
+            let now = Instant::now();
+            let res = if now < deadline { self.rx.recv_timeout(deadline - now).ok() } else { None };
+            match res {
+                Some((client_id, resp)) => self.receive(client_id, resp),
+                None => {
+                    println!("Client {} is missing responses, replaying the rest without waiting for any", client_id);
+                    self.diverged = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn receive(&mut self, client_id: usize, resp: Response) {
+        if compared(&resp) {
+            self.received.entry(client_id).or_default().push(resp);
+        }
+    }
+
+    /// Everything the clients received. Call after the server shut down.
+    fn finish(self) -> HashMap<usize, Vec<Response>> {
+        let Player { clients, mut received, tx, rx, .. } = self;
+        drop((clients, tx));
+        // The readers end with their connections, which the server closed
+        for (client_id, resp) in rx {
+            if compared(&resp) {
+                received.entry(client_id).or_default().push(resp);
+            }
+        }
+        received
+    }
+}
+
+fn player_count(recording: &Recording) -> usize {
+    recording.entries.iter().filter(|e| matches!(e.event, Event::Connected { .. })).count()
+}
+
+/// Whether a response is expected to be the same in the replay. User lists are sent
+/// whenever anybody logs in or out, they race with everything else.
+fn compared(resp: &Response) -> bool {
+    !matches!(*resp, Response::SystemMessage(_) | Response::ServerShuttingDown { .. } | Response::UserList(_))
+}
+
+/// `resp` without what legitimately differs between runs, serialized for comparing.
+fn normalized(resp: &Response) -> Vec<u8> {
+    let mut resp = resp.clone();
+    match resp {
+        // Listed in no particular order
+        Response::ProjectJoined { ref mut locks, .. } => locks.sort_by_key(|l| l.object),
+        Response::SnapshotCreated { ref mut snapshot, .. } => snapshot.creation_date.clear(),
+        Response::ProjectActivity { ref mut entries, .. } => {
+            for entry in entries {
+                entry.date.clear();
+            }
+        }
+        Response::Snapshots { ref mut snapshots, .. } => {
+            for snapshot in snapshots {
+                snapshot.creation_date.clear();
+            }
+        }
+        _ => {}
+    }
+    bincode::serialize(&resp).unwrap_or_default()
+}
+
+/// Prints where the replayed responses differ from the recorded ones. Returns whether they match.
+fn compare_responses(recorded: &HashMap<usize, Vec<Response>>, received: &HashMap<usize, Vec<Response>>) -> bool {
+    let mut ids: Vec<_> = recorded.keys().chain(received.keys()).cloned().collect();
+    ids.sort();
+    ids.dedup();
+    let none = Vec::new();
+    let mut matched = true;
+    for id in ids {
+        let (recorded, received) = (recorded.get(&id).unwrap_or(&none), received.get(&id).unwrap_or(&none));
+        if let Some(idx) = (0..recorded.len().min(received.len())).find(|&i| normalized(&recorded[i]) != normalized(&received[i])) {
+            println!("Client {}: response {} differs", id, idx + 1);
+            println!("  recorded: {:?}", recorded[idx]);
+            println!("  replayed: {:?}", received[idx]);
+            matched = false;
+        } else if recorded.len() != received.len() {
+            println!("Client {}: {} response(s) recorded, {} replayed", id, recorded.len(), received.len());
+            let extra = if recorded.len() > received.len() { &recorded[received.len()] } else { &received[recorded.len()] };
+            println!("  first unmatched: {:?}", extra);
+            matched = false;
+        }
+    }
+    matched
+}
+
+/// Prints which projects ended up differently. Returns whether all match.
+fn compare_states(recorded: &HashMap<u32, (u64, Project)>, replayed: &HashMap<u32, (u64, Project)>) -> bool {
+    let mut ids: Vec<_> = recorded.keys().cloned().collect();
+    ids.sort();
+    let mut matched = true;
+    for id in ids {
+        let (seq, ref state) = recorded[&id];
+        match replayed.get(&id) {
+            None => {
+                println!("Project {}: not loaded in the replay", id);
+                matched = false;
+            }
+            Some(&(replayed_seq, _)) if replayed_seq != seq => {
+                println!("Project {}: recorded at seq {}, replayed at seq {}", id, seq, replayed_seq);
+                matched = false;
+            }
+            Some((_, replayed_state)) if replayed_state != state => {
+                println!("Project {}: state differs at seq {}", id, seq);
+                matched = false;
+            }
+            Some(_) => println!("Project {}: state matches at seq {}", id, seq),
+        }
+    }
+    matched
+}
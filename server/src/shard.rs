@@ -0,0 +1,821 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use rusqlite as sql;
+
+use asset;
+use client::{self, ClientSock, send_to};
+use db;
+use mailbox::{Mailbox, MailboxSender};
+use project;
+use recording::{self, Recorder};
+use {DB_DONE, MAILBOX, SHUTDOWN_DRAIN_TIMEOUT};
+
+/// Messages from the lobby to a shard.
+pub enum ShardMsg {
+    /// Takes over a logged in client, which continues with its queued commands.
+    Adopt { client_id: usize, client: ClientSock, user_name: String },
+    /// The answer to a command the shard forwarded.
+    Reply { client_id: usize, resp: Option<proto::Response> },
+    /// Sends a message to every client of the shard.
+    Broadcast(proto::Response),
+    Kick(usize),
+    /// Sends the notice to every client, saves all projects and stops the shard.
+    Shutdown(proto::Response),
+}
+
+/// Messages from a shard to the lobby.
+pub enum LobbyMsg {
+    /// The client isn't in any project of the shard anymore.
+    Returned { client_id: usize, client: ClientSock },
+    /// A command that's handled by the lobby, answered with [ShardMsg::Reply].
+    Forward { client_id: usize, cmd: proto::Command },
+    /// The client's user stepped away or came back. Not answered, unlike [LobbyMsg::Forward].
+    Presence { client_id: usize, away: bool },
+    Disconnected { client_id: usize },
+}
+
+/// The shard a project lives on. Always the same one, so a project's state is only
+/// ever touched by one thread.
+pub fn shard_of(project_id: u32, shards: usize) -> usize {
+    project_id as usize % shards
+}
+
+/// Work left to do on the shard once a database job is done.
+type DbDone = Box<dyn FnOnce(&mut Shard) + Send>;
+
+/// An event loop on its own thread, owning a share of the projects and the sockets of
+/// the clients working on them. Edits in one busy project don't hold up projects on
+/// other shards.
+pub struct Shard {
+    index: usize,
+    shards: usize,
+    poll: Poll,
+    clients: HashMap<usize, ClientSock>,
+    user_names: HashMap<usize, String>,
+    projects: HashMap<u32, project::ProjectSession>,
+    /// Projects being loaded from the database -> clients waiting to join them
+    loading: HashMap<u32, Vec<usize>>,
+    assets: Arc<asset::AssetStore>,
+    db: db::worker::DbHandle,
+    db_done: MailboxSender<DbDone>,
+    lobby: MailboxSender<LobbyMsg>,
+    recorder: Option<Recorder>,
+}
+
+/// Starts shard number `index` of `shards`. Returns where to send it messages.
+pub fn spawn(index: usize, shards: usize, assets: Arc<asset::AssetStore>, db: db::worker::DbHandle, lobby: MailboxSender<LobbyMsg>, recorder: Option<Recorder>) -> io::Result<(MailboxSender<ShardMsg>, thread::JoinHandle<()>)> {
+    let poll = Poll::new()?;
+    let mailbox = Mailbox::new();
+    let db_done = Mailbox::new();
+    poll.register(mailbox.registration(), MAILBOX, Ready::readable(), PollOpt::edge())?;
+    poll.register(db_done.registration(), DB_DONE, Ready::readable(), PollOpt::edge())?;
+    let sender = mailbox.sender();
+    let shard = Shard {
+        index,
+        shards,
+        poll,
+        clients: HashMap::new(),
+        user_names: HashMap::new(),
+        projects: HashMap::new(),
+        loading: HashMap::new(),
+        assets,
+        db,
+        db_done: db_done.sender(),
+        lobby,
+        recorder,
+    };
+    let thread = thread::Builder::new()
+        .name(format!("shard {}", index))
+        .spawn(move || shard.run(mailbox, db_done))?;
+    Ok((sender, thread))
+}
+
+/// The session of a project the client is a member of.
+fn member_session(projects: &mut HashMap<u32, project::ProjectSession>, project_id: u32, client_id: usize) -> Option<&mut project::ProjectSession> {
+    projects.get_mut(&project_id).filter(|s| s.is_member(client_id))
+}
+
+/// Sends `msgs` to every member of a project.
+fn broadcast(clients: &HashMap<usize, ClientSock>, session: &project::ProjectSession, msgs: &[proto::Response]) {
+    for msg in msgs {
+        for member in session.members() {
+            send_to(clients, member, msg);
+        }
+    }
+}
+
+/// The outcome of registering a completely uploaded asset.
+enum Registered {
+    Done,
+    /// Over quota. The file is deleted again unless another project uses it.
+    Refused(proto::asset::AssetError),
+}
+
+impl Shard {
+    fn run(mut self, mailbox: Mailbox<ShardMsg>, db_done: Mailbox<DbDone>) {
+        let mut events = Events::with_capacity(1024);
+        let notice = 'run: loop {
+            if let Err(e) = self.poll.poll(&mut events, Some(Duration::from_secs(1))) {
+                if let Some(e) = client::ignore_timeout(e) {
+                    println!("poll ERR: {}", e);
+                }
+            }
+
+            for e in events.iter() {
+                match e.token() {
+                    MAILBOX => {
+                        for msg in mailbox.take() {
+                            if let Some(notice) = self.handle_msg(msg) {
+                                break 'run notice;
+                            }
+                        }
+                    }
+                    DB_DONE => {
+                        for done in db_done.take() {
+                            done(&mut self);
+                        }
+                    }
+                    Token(client_id) => self.client_event(client_id, e.readiness()),
+                }
+            }
+
+            let now = Instant::now();
+            for session in self.projects.values_mut() {
+                let msgs = session.expire_locks(now);
+                broadcast(&self.clients, session, &msgs);
+            }
+            self.persist_projects(false);
+            self.projects.retain(|_, session| !session.is_idle(now));
+        };
+
+        for client in self.clients.values() {
+            client.send(&notice);
+        }
+        client::drain_clients(&self.poll, &mut events, &self.clients, Instant::now() + SHUTDOWN_DRAIN_TIMEOUT);
+        self.persist_projects(true);
+        if let Some(ref recorder) = self.recorder {
+            for session in self.projects.values() {
+                recorder.record(recording::Event::ProjectState { project_id: session.id(), seq: session.seq(), state: session.state().clone() });
+            }
+        }
+        for client in self.clients.values() {
+            let _ = client.stream.shutdown(::std::net::Shutdown::Both);
+        }
+    }
+
+    /// Handles a message from the lobby. Returns the notice to shut down with, if it's time.
+    fn handle_msg(&mut self, msg: ShardMsg) -> Option<proto::Response> {
+        match msg {
+            ShardMsg::Adopt { client_id, client, user_name } => self.adopt(client_id, client, user_name),
+            ShardMsg::Reply { client_id, resp } => {
+                if self.clients.contains_key(&client_id) {
+                    self.resume(client_id, resp);
+                }
+            }
+            ShardMsg::Broadcast(msg) => {
+                for client in self.clients.values() {
+                    client.send(&msg);
+                }
+            }
+            ShardMsg::Kick(client_id) => {
+                if let Some(client) = self.clients.get(&client_id) {
+                    client.send(&proto::Response::SystemMessage("You were disconnected by the server operator.".to_owned()));
+                    let _ = client.stream.shutdown(::std::net::Shutdown::Both);
+                    self.disconnect(client_id);
+                }
+            }
+            ShardMsg::Shutdown(notice) => return Some(notice),
+        }
+        None
+    }
+
+    fn client_event(&mut self, client_id: usize, readiness: Ready) {
+        if readiness.is_writable() {
+            if let Some(client) = self.clients.get(&client_id) {
+                if let Err(e) = client.flush() {
+                    println!("Failed to write response: {}", e);
+                }
+            }
+        }
+        if !readiness.is_readable() {
+            return;
+        }
+
+        match self.clients.get_mut(&client_id) {
+            Some(client) => {
+                let open = client.read_socket();
+                while let Some(cmd) = client.try_deserialize() {
+                    client.commands.push_back(cmd);
+                }
+                if !open {
+                    client.commands.push_back(proto::Command::Disconnect);
+                }
+            }
+            None => return,
+        }
+        self.process_commands(client_id);
+    }
+}
+
+impl Shard {
+    /// Runs `job` on the database thread, then `then` on the event loop with its result.
+    /// If the job is done on behalf of a client, its later commands wait until `then`
+    /// ran, so they're handled in order. `then`'s response is sent to that client, but
+    /// `then` doesn't run at all if the client disconnected in the meantime.
+    fn query<R, J, T>(&mut self, client_id: Option<usize>, job: J, then: T)
+    where
+        R: Send + 'static,
+        J: FnOnce(&db::Database) -> R + Send + 'static,
+        T: FnOnce(&mut Shard, R) -> Option<proto::Response> + Send + 'static,
+    {
+        if let Some(client) = client_id.and_then(|id| self.clients.get_mut(&id)) {
+            client.waiting = true;
+        }
+        let done = self.db_done.clone();
+        self.db.run(move |db| {
+            let res = job(db);
+            done.send(Box::new(move |shard: &mut Shard| match client_id {
+                Some(client_id) => {
+                    if let Some(client) = shard.clients.get_mut(&client_id) {
+                        // Cleared first, `then` may start another job to wait for
+                        client.waiting = false;
+                        let resp = then(shard, res);
+                        shard.respond(client_id, resp);
+                    }
+                }
+                None => {
+                    then(shard, res);
+                }
+            }));
+        });
+    }
+
+    /// Sends the response to a command that was waited for and continues with the
+    /// client's next commands.
+    fn respond(&mut self, client_id: usize, resp: Option<proto::Response>) {
+        if let Some(resp) = resp {
+            send_to(&self.clients, client_id, &resp);
+        }
+        self.process_commands(client_id);
+    }
+
+    /// Stops waiting, then [Shard::respond]s.
+    fn resume(&mut self, client_id: usize, resp: Option<proto::Response>) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.waiting = false;
+        }
+        self.respond(client_id, resp);
+    }
+
+    /// Handles the client's received commands, until one of them waits for the database
+    /// or the lobby. Clients that aren't in any project of this shard anymore go back
+    /// to the lobby, together with the commands they sent since.
+    fn process_commands(&mut self, client_id: usize) {
+        loop {
+            let cmd = match self.clients.get_mut(&client_id) {
+                Some(client) if client.waiting => return,
+                Some(client) => match client.commands.pop_front() {
+                    Some(cmd) => cmd,
+                    None => break,
+                },
+                None => return,
+            };
+            if let proto::Command::JoinProject { project_id } = cmd {
+                if shard_of(project_id, self.shards) != self.index {
+                    // Someone else's project, the lobby hands the client over
+                    self.leave_all(client_id);
+                    self.clients.get_mut(&client_id).unwrap().commands.push_front(cmd);
+                    break;
+                }
+            }
+            if let Some(resp) = self.build_response(cmd, client_id) {
+                send_to(&self.clients, client_id, &resp);
+            }
+        }
+        if !self.projects.values().any(|s| s.is_member(client_id)) {
+            self.release(client_id);
+        }
+    }
+
+    /// Hands a client back to the lobby.
+    fn release(&mut self, client_id: usize) {
+        if let Some(mut client) = self.clients.remove(&client_id) {
+            self.user_names.remove(&client_id);
+            match client.deregister(&self.poll) {
+                Ok(()) => self.lobby.send(LobbyMsg::Returned { client_id, client }),
+                Err(e) => {
+                    println!("Shard {} failed to release client {}: {}", self.index, client_id, e);
+                    self.lobby.send(LobbyMsg::Disconnected { client_id })
+                }
+            };
+        }
+    }
+
+    fn adopt(&mut self, client_id: usize, client: ClientSock, user_name: String) {
+        if let Err(e) = self.poll.register(&client.stream, Token(client_id), Ready::readable() | Ready::writable(), PollOpt::edge()) {
+            println!("Shard {} failed to take over client {}: {}", self.index, client_id, e);
+            self.lobby.send(LobbyMsg::Disconnected { client_id });
+            return;
+        }
+        self.clients.insert(client_id, client);
+        self.user_names.insert(client_id, user_name);
+        self.process_commands(client_id);
+    }
+
+    /// Lets the lobby handle a command that isn't about this shard's projects.
+    fn forward(&mut self, client_id: usize, cmd: proto::Command) {
+        self.clients.get_mut(&client_id).unwrap().waiting = true;
+        self.lobby.send(LobbyMsg::Forward { client_id, cmd });
+    }
+
+    fn leave_all(&mut self, client_id: usize) {
+        for session in self.projects.values_mut() {
+            let msgs = session.leave(client_id);
+            broadcast(&self.clients, session, &msgs);
+        }
+    }
+
+    /// Writes new operations and activity to the database, and takes snapshots if it's time for them.
+    /// Sessions that are idle once everything is saved are dropped by the event loop.
+    /// `closing` takes a snapshot of any unsnapshotted changes, so projects load quickly next time.
+    fn persist_projects(&mut self, closing: bool) {
+        for session in self.projects.values_mut() {
+            let project_id = session.id();
+            let ops = session.unsaved_ops().to_vec();
+            if let (Some(first_seq), Some(last_seq)) = (ops.first().map(|op| op.seq), ops.last().map(|op| op.seq)) {
+                session.mark_saved();
+                let done = self.db_done.clone();
+                self.db.run(move |db| {
+                    let res = db.save_ops(project_id, &ops);
+                    if let Err(ref e) = res {
+                        println!("Failed to save operations of project {}: {}", project_id, e);
+                    }
+                    done.send(Box::new(move |shard: &mut Shard| {
+                        if let Some(session) = shard.projects.get_mut(&project_id) {
+                            match res {
+                                Ok(()) => session.ops_saved(last_seq),
+                                // Try again next time
+                                Err(_) => session.save_failed(first_seq),
+                            }
+                        }
+                    }));
+                });
+            }
+
+            let activity = session.take_activity();
+            if !activity.is_empty() {
+                let done = self.db_done.clone();
+                self.db.run(move |db| {
+                    let res = db.save_activity(project_id, &activity);
+                    if let Err(ref e) = res {
+                        println!("Failed to save activity of project {}: {}", project_id, e);
+                    }
+                    done.send(Box::new(move |shard: &mut Shard| {
+                        if let Some(session) = shard.projects.get_mut(&project_id) {
+                            match res {
+                                Ok(()) => session.activity_saved(),
+                                Err(_) => session.activity_save_failed(activity),
+                            }
+                        }
+                    }));
+                });
+            }
+
+            // Projects nobody is in are snapshotted right away, so they load quickly once dropped
+            let empty = session.members().next().is_none();
+            if session.needs_snapshot() || ((closing || empty) && session.changed_since_snapshot()) {
+                let (seq, state) = (session.seq(), session.state().clone());
+                session.snapshot_taken(seq);
+                self.db.run(move |db| {
+                    if let Err(e) = db.save_snapshot(project_id, seq, None, &state) {
+                        println!("Failed to snapshot project {}: {}", project_id, e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Forgets about a client and tells everyone it's gone.
+    fn disconnect(&mut self, client_id: usize) {
+        self.clients.remove(&client_id);
+        self.user_names.remove(&client_id);
+        self.leave_all(client_id);
+        self.lobby.send(LobbyMsg::Disconnected { client_id });
+    }
+
+    /// Lets a member of the project join it, loading the project first if needed.
+    fn join(&mut self, client_id: usize, project_id: u32, user_name: &str) -> Option<proto::Response> {
+        if let Some(session) = self.projects.get_mut(&project_id) {
+            return Some(session.join(client_id, user_name));
+        }
+
+        // Whoever asks first loads the project, everyone else waits for that
+        self.clients.get_mut(&client_id).unwrap().waiting = true;
+        match self.loading.entry(project_id) {
+            Entry::Occupied(e) => e.into_mut().push(client_id),
+            Entry::Vacant(e) => {
+                e.insert(vec![client_id]);
+                self.query(None, move |db| -> sql::Result<_> {
+                    if !db.project_exists(project_id)? {
+                        return Ok(None);
+                    }
+                    let (state, seq) = db.load_project(project_id)?;
+                    Ok(Some(project::ProjectSession::new(project_id, state, seq, db.max_site(project_id)?)))
+                }, move |shard, loaded| {
+                    shard.project_loaded(project_id, loaded);
+                    None
+                });
+            }
+        }
+        None
+    }
+
+    /// Joins the clients waiting for a project that finished loading.
+    fn project_loaded(&mut self, project_id: u32, loaded: sql::Result<Option<project::ProjectSession>>) {
+        let missing = match loaded {
+            Ok(Some(session)) => {
+                self.projects.insert(project_id, session);
+                false
+            }
+            Ok(None) => true,
+            Err(e) => {
+                println!("Failed to load project {}: {}", project_id, e);
+                false
+            }
+        };
+        for client_id in self.loading.remove(&project_id).unwrap_or_default() {
+            let resp = match (self.projects.get_mut(&project_id), self.user_names.get(&client_id)) {
+                (Some(session), Some(user_name)) => session.join(client_id, user_name),
+                (_, None) => proto::Response::NotLoggedIn,
+                (None, _) if missing => proto::Response::NoSuchProject,
+                (None, _) => proto::Response::InternalError,
+            };
+            self.resume(client_id, Some(resp));
+        }
+    }
+
+    /// Registers a completely uploaded asset with the project, if the quotas allow it.
+    fn register_upload(&mut self, client_id: usize, user_name: String, hash: proto::asset::AssetHash, project_id: u32, size: u64) {
+        let assets = self.assets.clone();
+        self.query(Some(client_id), move |db| -> sql::Result<Registered> {
+            // Checked again, other uploads may have finished since this one started
+            match assets.quotas().check(db, &user_name, project_id, &hash, size)? {
+                Some(error) => {
+                    if !db.asset_exists(&hash)? {
+                        let _ = assets.remove(&hash);
+                    }
+                    Ok(Registered::Refused(error))
+                }
+                None => db.add_asset_ref(project_id, &hash, size, &user_name).map(|()| Registered::Done),
+            }
+        }, move |_, res| match res {
+            Ok(Registered::Done) => Some(proto::Response::UploadComplete { project_id, hash }),
+            Ok(Registered::Refused(error)) => Some(proto::Response::AssetFailed { hash, error }),
+            Err(e) => {
+                println!("Failed to register asset {}: {}", proto::asset::hash_hex(&hash), e);
+                Some(proto::Response::AssetFailed { hash, error: proto::asset::AssetError::Storage })
+            }
+        });
+    }
+
+    /// Turns the outcome of an upload chunk into the response for the uploader.
+    fn upload_response(&mut self, client_id: usize, user_name: String, hash: proto::asset::AssetHash, res: Result<asset::ChunkResult, proto::asset::AssetError>) -> Option<proto::Response> {
+        match res {
+            Ok(asset::ChunkResult::Progress(received)) => Some(proto::Response::UploadProgress { hash, received }),
+            Ok(asset::ChunkResult::Complete { project_id, size }) => {
+                self.register_upload(client_id, user_name, hash, project_id, size);
+                None
+            }
+            Err(error) => Some(proto::Response::AssetFailed { hash, error }),
+        }
+    }
+
+    fn build_response(&mut self, cmd: proto::Command, client_id: usize) -> Option<proto::Response> {
+        use proto::Command::*;
+        match cmd {
+            Login { .. } | ListUsers | GetUsage | GetProfile { .. } | UpdateProfile(..) | FetchAvatarChunk { .. } | ListProjects => {
+                self.forward(client_id, cmd);
+                None
+            }
+            JoinProject { project_id } => {
+                let user_name = match self.user_names.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                // Checked every time, membership may have changed since the project was loaded
+                let member_name = user_name.clone();
+                self.query(Some(client_id), move |db| -> sql::Result<_> {
+                    if !db.project_exists(project_id)? {
+                        return Ok(None);
+                    }
+                    db.is_project_member(project_id, &member_name).map(Some)
+                }, move |shard, member| match member {
+                    Ok(Some(true)) => shard.join(client_id, project_id, &user_name),
+                    Ok(Some(false)) => Some(proto::Response::NotInProject),
+                    Ok(None) => Some(proto::Response::NoSuchProject),
+                    Err(e) => {
+                        println!("Failed to check whether {} is a member of project {}: {}", user_name, project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            LeaveProject { project_id } => {
+                if let Some(session) = self.projects.get_mut(&project_id) {
+                    let msgs = session.leave(client_id);
+                    broadcast(&self.clients, session, &msgs);
+                }
+                None
+            }
+            SubmitOp { project_id, local_seq, base_seq, op } => {
+                let session = match self.projects.get_mut(&project_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                match session.submit(client_id, local_seq, base_seq, op) {
+                    project::Submitted::Broadcast(msgs) => {
+                        broadcast(&self.clients, session, &msgs);
+                        None
+                    }
+                    project::Submitted::Reply(msg) => Some(msg),
+                }
+            }
+            AcquireLock { project_id, object } => {
+                let session = match self.projects.get_mut(&project_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                match session.acquire_lock(client_id, object, Instant::now()) {
+                    project::Submitted::Broadcast(msgs) => {
+                        broadcast(&self.clients, session, &msgs);
+                        None
+                    }
+                    project::Submitted::Reply(msg) => Some(msg),
+                }
+            }
+            Undo { project_id } | Redo { project_id } => {
+                let session = match self.projects.get_mut(&project_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                let (res, notice) = if let Undo { .. } = cmd {
+                    session.undo(client_id)
+                } else {
+                    session.redo(client_id)
+                };
+                if let Some(notice) = notice {
+                    send_to(&self.clients, client_id, &notice);
+                }
+                match res {
+                    project::Submitted::Broadcast(msgs) => {
+                        broadcast(&self.clients, session, &msgs);
+                        None
+                    }
+                    project::Submitted::Reply(msg) => Some(msg),
+                }
+            }
+            ReleaseLock { project_id, object } => {
+                if let Some(session) = self.projects.get_mut(&project_id) {
+                    let msgs = session.release_lock(client_id, object);
+                    broadcast(&self.clients, session, &msgs);
+                }
+                None
+            }
+            FetchOps { project_id, since_seq } => {
+                let session = match member_session(&mut self.projects, project_id, client_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                if let Some(ops) = session.ops_since(since_seq) {
+                    return Some(proto::Response::Ops { project_id, ops });
+                }
+                // Older operations aren't in memory, but they are persisted. The latest
+                // ones may not be yet, those come from memory.
+                self.query(Some(client_id), move |db| db.ops_since(project_id, since_seq), move |shard, ops| match ops {
+                    Ok(mut ops) => {
+                        let last_seq = ops.last().map_or(since_seq, |op| op.seq);
+                        if let Some(newer) = shard.projects.get(&project_id).and_then(|s| s.ops_since(last_seq)) {
+                            ops.extend(newer);
+                        }
+                        Some(proto::Response::Ops { project_id, ops })
+                    }
+                    Err(e) => {
+                        println!("Failed to fetch operations of project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            CreateSnapshot { project_id, name } => {
+                let session = match member_session(&mut self.projects, project_id, client_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                let (seq, state) = (session.seq(), session.state().clone());
+                self.query(Some(client_id), move |db| db.save_snapshot(project_id, seq, Some(&name), &state), move |shard, snapshot| match snapshot {
+                    Ok(snapshot) => {
+                        if let Some(session) = shard.projects.get_mut(&project_id) {
+                            session.snapshot_taken(snapshot.seq);
+                        }
+                        Some(proto::Response::SnapshotCreated { project_id, snapshot })
+                    }
+                    Err(e) => {
+                        println!("Failed to snapshot project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            ListSnapshots { project_id } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                self.query(Some(client_id), move |db| db.snapshots(project_id), move |_, snapshots| match snapshots {
+                    Ok(snapshots) => Some(proto::Response::Snapshots { project_id, snapshots }),
+                    Err(e) => {
+                        println!("Failed to list snapshots of project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            DiffSnapshot { project_id, snapshot_id } | PreviewSnapshot { project_id, snapshot_id } | RestoreSnapshot { project_id, snapshot_id } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                self.query(Some(client_id), move |db| db.snapshot_state(project_id, snapshot_id), move |shard, state| {
+                    let state = match state {
+                        Ok(Some(state)) => state,
+                        Ok(None) => return Some(proto::Response::NoSuchSnapshot { project_id, snapshot_id }),
+                        Err(e) => {
+                            println!("Failed to load snapshot {} of project {}: {}", snapshot_id, project_id, e);
+                            return Some(proto::Response::InternalError);
+                        }
+                    };
+                    // The client may have left while the snapshot was loading
+                    let session = match member_session(&mut shard.projects, project_id, client_id) {
+                        Some(session) => session,
+                        None => return Some(proto::Response::NotInProject),
+                    };
+                    match cmd {
+                        DiffSnapshot { .. } => Some(proto::Response::SnapshotDiff {
+                            project_id,
+                            snapshot_id,
+                            summary: state.diff_summary(session.state()),
+                        }),
+                        PreviewSnapshot { .. } => Some(proto::Response::SnapshotPreview { project_id, snapshot_id, state }),
+                        _ => match session.restore(client_id, state) {
+                            project::Submitted::Broadcast(msgs) => {
+                                broadcast(&shard.clients, session, &msgs);
+                                None
+                            }
+                            project::Submitted::Reply(msg) => Some(msg),
+                        },
+                    }
+                });
+                None
+            }
+            BeginUpload { project_id, hash, size } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                let user_name = match self.user_names.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                let assets = self.assets.clone();
+                let uploader = user_name.clone();
+                // Partial files are opened and hashed on the database thread, not on the event loop
+                self.query(Some(client_id), move |db| -> sql::Result<_> {
+                    if let Some(error) = assets.quotas().check(db, &uploader, project_id, &hash, size)? {
+                        return Ok(Err(error));
+                    }
+                    let known = db.user_has_asset(&uploader, &hash)?;
+                    Ok(assets.begin_upload(&uploader, project_id, hash, size, known))
+                }, move |shard, res| match res {
+                    Ok(Ok(asset::ChunkResult::Progress(offset))) => Some(proto::Response::UploadReady { hash, offset }),
+                    Ok(res) => shard.upload_response(client_id, user_name, hash, res),
+                    Err(e) => {
+                        println!("Failed to check quota of {}: {}", user_name, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            UploadChunk { hash, offset, data } => {
+                let user_name = match self.user_names.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                match self.assets.upload_project(&user_name, &hash) {
+                    // Only members may add to a project, and they may have left since
+                    Ok(project_id) if member_session(&mut self.projects, project_id, client_id).is_none() => {
+                        return Some(proto::Response::NotInProject);
+                    }
+                    Ok(_) => {}
+                    Err(error) => return Some(proto::Response::AssetFailed { hash, error }),
+                }
+                let assets = self.assets.clone();
+                let uploader = user_name.clone();
+                self.query(Some(client_id), move |_| assets.write_chunk(&uploader, hash, offset, &data), move |shard, res| {
+                    shard.upload_response(client_id, user_name, hash, res)
+                });
+                None
+            }
+            FetchAssetChunk { project_id, hash, offset } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                let assets = self.assets.clone();
+                self.query(Some(client_id), move |db| -> sql::Result<_> {
+                    if !db.project_has_asset(project_id, &hash)? {
+                        return Ok(Err(proto::asset::AssetError::NoSuchAsset));
+                    }
+                    Ok(assets.read_chunk(&hash, offset))
+                }, move |_, chunk| match chunk {
+                    Ok(Ok((data, size))) => Some(proto::Response::AssetChunk { hash, offset, size, data }),
+                    Ok(Err(error)) => Some(proto::Response::AssetFailed { hash, error }),
+                    Err(e) => {
+                        println!("Failed to check assets of project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            GetProjectActivity { project_id, before } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                // Includes what happened in the project just now
+                self.persist_projects(false);
+                self.query(Some(client_id), move |db| db.activity(project_id, before, proto::ACTIVITY_PAGE_SIZE), move |_, page| match page {
+                    Ok((entries, more)) => Some(proto::Response::ProjectActivity { project_id, entries, more }),
+                    Err(e) => {
+                        println!("Failed to fetch activity of project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            ListAssets { project_id } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                self.query(Some(client_id), move |db| db.project_assets(project_id), move |_, assets| match assets {
+                    Ok(assets) => Some(proto::Response::Assets { project_id, assets }),
+                    Err(e) => {
+                        println!("Failed to list assets of project {}: {}", project_id, e);
+                        Some(proto::Response::InternalError)
+                    }
+                });
+                None
+            }
+            ReleaseAsset { project_id, hash } => {
+                if member_session(&mut self.projects, project_id, client_id).is_none() {
+                    return Some(proto::Response::NotInProject);
+                }
+                let assets = self.assets.clone();
+                self.query(Some(client_id), move |db| {
+                    match db.release_asset_ref(project_id, &hash) {
+                        Ok(true) => {
+                            if let Err(e) = assets.remove(&hash) {
+                                println!("Failed to delete asset {}: {}", proto::asset::hash_hex(&hash), e);
+                            }
+                        }
+                        Ok(false) => {}
+                        Err(e) => println!("Failed to release asset {}: {}", proto::asset::hash_hex(&hash), e),
+                    }
+                }, |_, ()| None);
+                None
+            }
+            SetPresence { away } => {
+                // The lobby keeps track of who's online
+                self.lobby.send(LobbyMsg::Presence { client_id, away });
+                None
+            }
+            SendChat { project_id, text } => {
+                let user_name = match self.user_names.get(&client_id) {
+                    Some(user_name) => user_name.clone(),
+                    None => return Some(proto::Response::NotLoggedIn),
+                };
+                let session = match member_session(&mut self.projects, project_id, client_id) {
+                    Some(session) => session,
+                    None => return Some(proto::Response::NotInProject),
+                };
+                let text = text.chars().take(proto::MAX_CHAT_LEN).collect();
+                broadcast(&self.clients, session, &[proto::Response::ChatMessage { project_id, user_name, text }]);
+                None
+            }
+            Disconnect => {
+                self.disconnect(client_id);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,737 @@
+//! The client's state and how it changes, apart from the window it's shown in.
+//!
+//! Everything that happens, a response from the server, something the user did in a
+//! view or time passing, is a [Msg] handed to [App::update]. Views only read the state
+//! and turn input into [Action]s, and the main loop carries out the [Effect]s an update
+//! asks for. Nothing here touches the window, so state transitions can be driven and
+//! checked without one.
+
+use client::assets::SyncProgress;
+use client::net::NetState;
+use client::settings::Settings;
+use proto;
+use proto::project::{ObjectId, Operation, Track};
+use proto::sync::ClientProject;
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long the login form stays highlighted after a failed attempt
+const LOGIN_ERROR_TIME: Duration = Duration::from_secs(3);
+
+/// How often the lock on the selected object is renewed, well before it expires
+const LOCK_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a notice stays on screen
+const NOTICE_TIME: Duration = Duration::from_secs(5);
+
+/// How long a message from the server stays on screen, unless the next one replaces it.
+/// Longer than the longest pause between shutdown announcements, so a countdown stays up.
+const SERVER_NOTICE_TIME: Duration = Duration::from_secs(600);
+
+pub enum Msg {
+    /// The connection to the server changed its state.
+    State(NetState),
+    Response(proto::Response),
+    /// The asset cache is being brought up to date with the joined project.
+    AssetProgress(SyncProgress),
+    /// All assets of the joined project are available locally.
+    AssetsReady { project_id: u32 },
+    /// The user did something in the view on screen.
+    Action(Action),
+    /// Sent before anything else on every turn of the main loop, with the current time.
+    Tick(Instant),
+}
+
+/// What the user asked for in a view.
+pub enum Action {
+    Login { email: String, password: Vec<u8> },
+    /// Connect to another server, given as "host:port".
+    SelectServer(String),
+    OpenProfile { user_name: String },
+    CloseProfile,
+    OpenProject { project_id: u32 },
+    LeaveProject,
+    /// The user selected an object of the joined project to work on, which locks it.
+    Select(Option<ObjectId>),
+    /// An edit of the joined project
+    Edit(Operation),
+    /// Appends a new track to the joined project.
+    AddTrack,
+    /// Files dropped on the window, to upload as assets of the joined project
+    Upload(Vec<PathBuf>),
+    /// A command the view put together by itself
+    Send(proto::Command),
+}
+
+/// What the main loop has to do after an update.
+pub enum Effect {
+    Send(proto::Command),
+    /// Connect to another server, given as "host:port".
+    SetServer(String),
+    Upload { project_id: u32, path: PathBuf },
+}
+
+/// Which view is on screen.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Screen {
+    /// Waiting for the server, showing what's being waited for
+    Loading,
+    Login,
+    /// The user list
+    Main,
+    Profile,
+    Project,
+}
+
+/// The pages of a project's activity the client fetched so far, newest first.
+#[derive(Default)]
+pub struct ActivityFeed {
+    pub entries: Vec<proto::Activity>,
+    /// Whether there are older entries on the server
+    pub more: bool,
+}
+
+impl ActivityFeed {
+    /// Adds a page the server sent. Older pages are appended, the first page replaces the feed.
+    pub fn receive(&mut self, entries: Vec<proto::Activity>, more: bool) {
+        let older = match (self.entries.last(), entries.first()) {
+            (Some(last), Some(first)) => first.id < last.id,
+            _ => false,
+        };
+        if !older {
+            self.entries.clear();
+        }
+        self.entries.extend(entries);
+        self.more = more;
+    }
+}
+
+/// A message for the user, shown on top of every view until it expires.
+pub struct Notice {
+    pub text: String,
+    /// Sent by the server operator, rather than about something the user did
+    pub from_server: bool,
+    until: Instant,
+}
+
+pub struct App {
+    pub settings: Settings,
+    screen: Screen,
+    net_state: NetState,
+    load_task: String,
+    users: Vec<proto::User>,
+    /// The projects the user is a member of
+    projects: Vec<proto::ProjectInfo>,
+    project: Option<ClientProject>,
+    /// The object of the joined project the user works on, locked for them
+    selected: Option<ObjectId>,
+    /// When the lock on `selected` was last acquired or renewed
+    lock_renewed_at: Instant,
+    activity: ActivityFeed,
+    profile: Option<proto::Profile>,
+    failed_logins: usize,
+    /// When the last login failed, while the form is still highlighted
+    login_failed_at: Option<Instant>,
+    /// Oldest first
+    notices: Vec<Notice>,
+    /// The time of the last tick
+    now: Instant,
+}
+
+impl App {
+    /// Starts out on the login screen, so another server can be chosen while connecting.
+    pub fn new(settings: Settings) -> Self {
+        App {
+            settings,
+            screen: Screen::Login,
+            net_state: NetState::Connecting,
+            load_task: "Connecting to server...".to_owned(),
+            users: Vec::new(),
+            projects: Vec::new(),
+            project: None,
+            selected: None,
+            lock_renewed_at: Instant::now(),
+            activity: Default::default(),
+            profile: None,
+            failed_logins: 0,
+            login_failed_at: None,
+            notices: Vec::new(),
+            now: Instant::now(),
+        }
+    }
+
+    pub fn screen(&self) -> Screen {
+        self.screen
+    }
+
+    pub fn net_state(&self) -> &NetState {
+        &self.net_state
+    }
+
+    /// What the loading screen says is going on.
+    pub fn load_task(&self) -> &str {
+        &self.load_task
+    }
+
+    pub fn users(&self) -> &[proto::User] {
+        &self.users
+    }
+
+    pub fn projects(&self) -> &[proto::ProjectInfo] {
+        &self.projects
+    }
+
+    /// The joined project.
+    pub fn project(&self) -> Option<&ClientProject> {
+        self.project.as_ref()
+    }
+
+    /// The selected object of the joined project.
+    pub fn selected(&self) -> Option<ObjectId> {
+        self.selected
+    }
+
+    pub fn activity(&self) -> &ActivityFeed {
+        &self.activity
+    }
+
+    /// The profile last fetched.
+    pub fn profile(&self) -> Option<&proto::Profile> {
+        self.profile.as_ref()
+    }
+
+    /// How many logins failed on this login screen.
+    pub fn failed_logins(&self) -> usize {
+        self.failed_logins
+    }
+
+    /// Whether a login failed just now.
+    pub fn login_just_failed(&self) -> bool {
+        self.login_failed_at.is_some()
+    }
+
+    /// The notices to show, oldest first.
+    pub fn notices(&self) -> &[Notice] {
+        &self.notices
+    }
+
+    /// When the next [Msg::Tick] changes something, if it will.
+    pub fn next_timer(&self) -> Option<Instant> {
+        let login_error = self.login_failed_at.map(|at| at + LOGIN_ERROR_TIME);
+        let lock_renewal = self.selected.map(|_| self.lock_renewed_at + LOCK_RENEW_INTERVAL);
+        let notice_expiry = self.notices.iter().map(|n| n.until).min();
+        login_error.into_iter().chain(lock_renewal).chain(notice_expiry).min()
+    }
+
+    /// Shows `text` on top of the view for a while.
+    fn notify(&mut self, text: String) {
+        self.notices.push(Notice { text, from_server: false, until: self.now + NOTICE_TIME });
+    }
+
+    /// Shows a message from the server, replacing the one before.
+    fn notify_from_server(&mut self, text: String) {
+        self.notices.retain(|n| !n.from_server);
+        self.notices.push(Notice { text, from_server: true, until: self.now + SERVER_NOTICE_TIME });
+    }
+
+    fn joined(&self, project_id: u32) -> bool {
+        self.project.as_ref().is_some_and(|p| p.project_id() == project_id)
+    }
+
+    fn show(&mut self, screen: Screen) {
+        if screen == Screen::Login && self.screen != Screen::Login {
+            self.failed_logins = 0;
+            self.login_failed_at = None;
+        }
+        self.screen = screen;
+    }
+
+    pub fn update(&mut self, msg: Msg) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        match msg {
+            Msg::State(state) => self.state_changed(state),
+            Msg::Response(res) => self.response(res, &mut effects),
+            Msg::AssetProgress(progress) => {
+                let percent = (progress.done_bytes * 100).checked_div(progress.total_bytes).unwrap_or(100);
+                self.load_task = format!("Downloading assets: {} left ({}%)", progress.remaining, percent);
+            }
+            Msg::AssetsReady { project_id } => {
+                if self.joined(project_id) {
+                    self.activity = Default::default();
+                    effects.push(Effect::Send(proto::Command::GetProjectActivity {
+                        project_id,
+                        before: None,
+                    }));
+                    self.show(Screen::Project);
+                }
+            }
+            Msg::Action(action) => match action {
+                Action::Login { email, password } => {
+                    self.settings.last_email = Some(email.clone());
+                    effects.push(Effect::Send(proto::Command::Login { email, password }));
+                }
+                Action::SelectServer(server) => {
+                    self.settings.select_server(&server);
+                    effects.push(Effect::SetServer(server));
+                }
+                Action::OpenProfile { user_name } => {
+                    effects.push(Effect::Send(proto::Command::GetProfile { user_name }));
+                }
+                Action::CloseProfile => {
+                    if self.screen == Screen::Profile {
+                        self.show(Screen::Main);
+                    }
+                }
+                Action::OpenProject { project_id } => {
+                    effects.push(Effect::Send(proto::Command::JoinProject { project_id }));
+                    self.load_task = "Joining project...".to_owned();
+                    self.show(Screen::Loading);
+                }
+                Action::LeaveProject => {
+                    if let Some(project) = self.project.take() {
+                        let project_id = project.project_id();
+                        if let Some(object) = self.selected.take() {
+                            effects.push(Effect::Send(proto::Command::ReleaseLock { project_id, object }));
+                        }
+                        effects.push(Effect::Send(proto::Command::LeaveProject { project_id }));
+                        self.show(Screen::Main);
+                    }
+                }
+                Action::Select(object) => self.select(object, &mut effects),
+                Action::Edit(op) => self.edit(op, &mut effects),
+                Action::AddTrack => {
+                    if let Some(ref mut project) = self.project {
+                        let track = Track {
+                            id: project.new_id(),
+                            name: format!("Track {}", project.state().tracks.len() + 1),
+                            volume: 1.0,
+                            pan: 0.0,
+                            muted: false,
+                            clips: Vec::new(),
+                            devices: Vec::new(),
+                        };
+                        let after = project.state().tracks.last().map(|t| t.id);
+                        self.edit(Operation::InsertTrack { after, track }, &mut effects);
+                    }
+                }
+                Action::Upload(paths) => {
+                    if let Some(ref project) = self.project {
+                        let project_id = project.project_id();
+                        effects.extend(paths.into_iter().map(|path| Effect::Upload { project_id, path }));
+                    }
+                }
+                Action::Send(cmd) => effects.push(Effect::Send(cmd)),
+            },
+            Msg::Tick(now) => {
+                self.now = now;
+                if self.login_failed_at.is_some_and(|at| at + LOGIN_ERROR_TIME <= now) {
+                    self.login_failed_at = None;
+                }
+                self.notices.retain(|n| n.until > now);
+                if let (Some(object), Some(project)) = (self.selected, self.project.as_ref()) {
+                    if self.lock_renewed_at + LOCK_RENEW_INTERVAL <= now {
+                        // Acquiring a held lock renews it
+                        let project_id = project.project_id();
+                        effects.push(Effect::Send(proto::Command::AcquireLock { project_id, object }));
+                        self.lock_renewed_at = now;
+                    }
+                }
+            }
+        }
+        effects
+    }
+
+    /// Locks the object the user selected, releasing the one selected before.
+    fn select(&mut self, object: Option<ObjectId>, effects: &mut Vec<Effect>) {
+        let project_id = match self.project {
+            Some(ref project) if object != self.selected => project.project_id(),
+            _ => return,
+        };
+        if let Some(old) = self.selected {
+            effects.push(Effect::Send(proto::Command::ReleaseLock { project_id, object: old }));
+        }
+        if let Some(object) = object {
+            effects.push(Effect::Send(proto::Command::AcquireLock { project_id, object }));
+        }
+        self.selected = object;
+        self.lock_renewed_at = self.now;
+    }
+
+    fn edit(&mut self, op: Operation, effects: &mut Vec<Effect>) {
+        if let Some(ref mut project) = self.project {
+            // Views only edit what they show, which always applies locally
+            if let Ok(Some(cmd)) = project.submit(op) {
+                effects.push(Effect::Send(cmd));
+            }
+        }
+    }
+
+    fn state_changed(&mut self, state: NetState) {
+        match state {
+            // After a failed login, keep what the user typed
+            NetState::Connected if self.screen != Screen::Login => {
+                // Logged out, whatever was joined is gone
+                self.project = None;
+                self.selected = None;
+                self.show(Screen::Login);
+            }
+            NetState::Authenticating => {
+                self.load_task = "Logging in...".to_owned();
+            }
+            NetState::Disconnected(ref reason) => {
+                self.load_task = format!("{}, reconnecting...", reason);
+                // Work on the project goes on offline until it's rejoined
+                if let Some(ref mut project) = self.project {
+                    project.disconnected();
+                } else if self.screen != Screen::Login {
+                    // Not logged in yet, the user may want to pick another server
+                    self.show(Screen::Loading);
+                }
+            }
+            _ => {}
+        }
+        self.net_state = state;
+    }
+
+    fn response(&mut self, res: proto::Response, effects: &mut Vec<Effect>) {
+        match res {
+            proto::Response::UserList(users) => {
+                self.users = users;
+            }
+            proto::Response::LoginOk if self.project.is_some() => {
+                // Logged in again after the connection dropped, the project is rejoined next
+            }
+            proto::Response::LoginOk => {
+                effects.push(Effect::Send(proto::Command::ListProjects));
+                self.show(Screen::Main);
+            }
+            proto::Response::LoginInvalid => {
+                if self.screen == Screen::Login {
+                    self.failed_logins += 1;
+                    self.login_failed_at = Some(self.now);
+                }
+            }
+            proto::Response::LoginThrottled { retry_after_secs } => {
+                self.notify(format!("Too many failed logins, try again in {} seconds", retry_after_secs));
+                if self.screen == Screen::Login {
+                    self.failed_logins += 1;
+                    self.login_failed_at = Some(self.now);
+                }
+            }
+            proto::Response::SystemMessage(text) => self.notify_from_server(text),
+            proto::Response::ServerShuttingDown { reason, reconnect_after } => {
+                let text = match reconnect_after {
+                    Some(secs) => format!("Server restarting: {}. Reconnecting in {}s...", reason, secs),
+                    None => format!("Server shut down: {}. Reconnecting...", reason),
+                };
+                if self.project.is_some() {
+                    // Work on the project goes on offline, so it stays on screen
+                    self.notify_from_server(text);
+                } else {
+                    self.load_task = text;
+                    self.show(Screen::Loading);
+                }
+            }
+            proto::Response::ProjectJoined { project_id, site, seq, state, locks } if self.joined(project_id) => {
+                // Rejoined after the connection dropped, catch up on what was missed
+                if let Some(ref mut project) = self.project {
+                    effects.extend(project.rejoin(site, seq, state, locks).into_iter().map(Effect::Send));
+                    // Locks don't outlive the connection
+                    if let Some(object) = self.selected {
+                        effects.push(Effect::Send(proto::Command::AcquireLock { project_id, object }));
+                        self.lock_renewed_at = self.now;
+                    }
+                }
+            }
+            proto::Response::ProjectJoined { project_id, site, seq, state, locks } => {
+                self.project = Some(ClientProject::new(project_id, site, seq, state, locks));
+                self.load_task = "Synchronizing assets...".to_owned();
+                self.show(Screen::Loading);
+            }
+            proto::Response::LockChanged { project_id, object, holder } => {
+                if let Some(ref mut project) = self.project {
+                    if project.project_id() == project_id {
+                        project.set_lock(object, holder);
+                    }
+                }
+            }
+            proto::Response::LockDenied { object, reason, .. } => {
+                self.notify(match reason {
+                    proto::LockError::HeldBy(user_name) => format!("{} is editing this right now", user_name),
+                    proto::LockError::NoSuchObject => "Can't edit this, it's gone".to_owned(),
+                });
+                if self.selected == Some(object) {
+                    self.selected = None;
+                }
+            }
+            proto::Response::OpApplied { project_id, seq, site, local_seq, op } => {
+                if let Some(ref mut project) = self.project {
+                    if project.project_id() == project_id {
+                        effects.extend(project.server_op(seq, site, local_seq, &op).map(Effect::Send));
+                    }
+                }
+            }
+            proto::Response::Ops { project_id, ops } => {
+                if let Some(ref mut project) = self.project {
+                    if project.project_id() == project_id {
+                        effects.extend(project.server_ops(&ops).into_iter().map(Effect::Send));
+                    }
+                }
+            }
+            proto::Response::OpRejected { project_id, local_seq, error } => {
+                let rejected = match self.project {
+                    Some(ref mut project) if project.project_id() == project_id => project.reject(local_seq, error),
+                    _ => None,
+                };
+                if let Some(rejected) = rejected {
+                    self.notify(format!("Edit rejected: {} ({:?})", rejected.summary, error));
+                }
+            }
+            proto::Response::NothingToUndo { .. } | proto::Response::NothingToRedo { .. } => {}
+            proto::Response::UndoRejected { error, .. } => {
+                self.notify(format!("Can't undo right now ({:?})", error));
+            }
+            proto::Response::HistorySkipped { steps, .. } => {
+                for step in steps {
+                    self.notify(match step.reason {
+                        proto::SkipReason::Clobbered { user_name } => {
+                            format!("Skipped \"{}\", {} changed it since", step.summary, user_name)
+                        }
+                        proto::SkipReason::Conflict(error) => format!("Skipped \"{}\" ({:?})", step.summary, error),
+                    });
+                }
+            }
+            proto::Response::SnapshotCreated { snapshot, .. } => {
+                println!("Snapshot created: {:?}", snapshot);
+            }
+            proto::Response::Snapshots { snapshots, .. } => {
+                for snapshot in snapshots {
+                    println!("{:?}", snapshot);
+                }
+            }
+            proto::Response::SnapshotDiff { snapshot_id, summary, .. } => {
+                println!("Changes since snapshot {}: {:?}", snapshot_id, summary);
+            }
+            proto::Response::SnapshotPreview { .. } => {}
+            proto::Response::RestoreRejected { error, .. } => {
+                println!("Can't restore right now: {:?}", error);
+            }
+            // Asset downloads are handled by the network thread
+            proto::Response::UploadReady { .. }
+            | proto::Response::UploadProgress { .. }
+            | proto::Response::UploadComplete { .. }
+            | proto::Response::AssetChunk { .. }
+            | proto::Response::Assets { .. }
+            | proto::Response::AssetFailed { .. } => {}
+            proto::Response::ProjectActivity { project_id, entries, more } => {
+                if self.joined(project_id) {
+                    self.activity.receive(entries, more);
+                }
+            }
+            proto::Response::Profile(profile) => {
+                self.profile = Some(profile);
+                if self.screen == Screen::Main {
+                    self.show(Screen::Profile);
+                }
+            }
+            proto::Response::NoSuchUser { user_name } => {
+                println!("There's no user {}", user_name);
+            }
+            proto::Response::NoAvatar { user_name } => {
+                println!("{} has no avatar to show", user_name);
+            }
+            proto::Response::ProfileRejected(error) => {
+                println!("Profile not saved: {:?}", error);
+            }
+            proto::Response::Projects(projects) => {
+                self.projects = projects;
+            }
+            proto::Response::ChatMessage { project_id, user_name, text } => {
+                if self.joined(project_id) {
+                    self.notify(format!("{}: {}", user_name, text));
+                }
+            }
+            proto::Response::Usage(usage) => {
+                println!("Storage used: {} of {:?} bytes", usage.used, usage.limit);
+            }
+            proto::Response::NoSuchProject
+            | proto::Response::NotLoggedIn
+            | proto::Response::NotInProject
+            | proto::Response::NoSuchSnapshot { .. }
+            | proto::Response::InternalError => {
+                println!("Server refused request: {:?}", res);
+                // Catching up after a rejoin failed, the project is joined from scratch
+                if let Some(rejoin) = self.project.as_mut().and_then(|p| p.fetch_failed()) {
+                    effects.push(Effect::Send(rejoin));
+                }
+                if self.screen == Screen::Loading && self.project.is_none() && self.net_state == NetState::Online {
+                    // Joining a project failed
+                    self.show(Screen::Main);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::project::Project;
+
+    fn login(app: &mut App) -> Vec<Effect> {
+        app.update(Msg::State(NetState::Connected));
+        app.update(Msg::Action(Action::Login {
+            email: "a@example.com".to_owned(),
+            password: b"secret".to_vec(),
+        }))
+    }
+
+    fn logged_in() -> App {
+        let mut app = App::new(Settings::default());
+        login(&mut app);
+        app.update(Msg::State(NetState::Online));
+        app.update(Msg::Response(proto::Response::LoginOk));
+        app
+    }
+
+    fn joined(project_id: u32) -> proto::Response {
+        proto::Response::ProjectJoined { project_id, site: 1, seq: 0, state: Project::default(), locks: Vec::new() }
+    }
+
+    fn sent(effects: &[Effect]) -> Vec<&proto::Command> {
+        effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::Send(cmd) => Some(cmd),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn login_success() {
+        let mut app = App::new(Settings::default());
+        assert_eq!(app.screen(), Screen::Login);
+        let effects = login(&mut app);
+        assert!(matches!(sent(&effects)[..], [proto::Command::Login { ref email, .. }] if email == "a@example.com"));
+        assert_eq!(app.settings.last_email.as_deref(), Some("a@example.com"));
+
+        app.update(Msg::State(NetState::Authenticating));
+        assert_eq!(app.load_task(), "Logging in...");
+        app.update(Msg::State(NetState::Online));
+        let effects = app.update(Msg::Response(proto::Response::LoginOk));
+        assert_eq!(app.screen(), Screen::Main);
+        assert!(matches!(sent(&effects)[..], [proto::Command::ListProjects]));
+        assert_eq!(app.net_state(), &NetState::Online);
+    }
+
+    #[test]
+    fn login_failure() {
+        let mut app = App::new(Settings::default());
+        login(&mut app);
+        app.update(Msg::Response(proto::Response::LoginInvalid));
+        app.update(Msg::State(NetState::Connected));
+        assert_eq!(app.screen(), Screen::Login);
+        assert_eq!(app.failed_logins(), 1);
+        assert!(app.login_just_failed());
+
+        // The highlight goes away, the count stays until the login screen is left
+        let later = app.now + LOGIN_ERROR_TIME;
+        assert_eq!(app.next_timer(), Some(later));
+        app.update(Msg::Tick(later));
+        assert!(!app.login_just_failed());
+        assert_eq!(app.failed_logins(), 1);
+
+        app.update(Msg::Response(proto::Response::LoginThrottled { retry_after_secs: 30 }));
+        assert_eq!(app.failed_logins(), 2);
+        assert_eq!(app.notices().len(), 1);
+        assert!(app.notices()[0].text.contains("30 seconds"));
+    }
+
+    #[test]
+    fn losing_the_connection_before_joining() {
+        let mut app = logged_in();
+        app.update(Msg::State(NetState::Disconnected("Connection lost".to_owned())));
+        assert_eq!(app.screen(), Screen::Loading);
+        assert_eq!(app.load_task(), "Connection lost, reconnecting...");
+
+        // Reconnected, but the login didn't survive
+        app.update(Msg::State(NetState::Connected));
+        assert_eq!(app.screen(), Screen::Login);
+        assert_eq!(app.failed_logins(), 0);
+    }
+
+    #[test]
+    fn losing_the_connection_on_the_login_screen() {
+        let mut app = App::new(Settings::default());
+        app.update(Msg::State(NetState::Disconnected("Connection refused".to_owned())));
+        // Another server may be picked meanwhile
+        assert_eq!(app.screen(), Screen::Login);
+        app.update(Msg::Action(Action::SelectServer("other:4450".to_owned())));
+        assert_eq!(app.settings.current_server(), "other:4450");
+    }
+
+    #[test]
+    fn joining_and_leaving_a_project() {
+        let mut app = logged_in();
+        let effects = app.update(Msg::Action(Action::OpenProject { project_id: 7 }));
+        assert!(matches!(sent(&effects)[..], [proto::Command::JoinProject { project_id: 7 }]));
+        assert_eq!(app.screen(), Screen::Loading);
+
+        app.update(Msg::Response(joined(7)));
+        assert_eq!(app.screen(), Screen::Loading);
+        assert_eq!(app.load_task(), "Synchronizing assets...");
+        // Assets of a project that isn't joined change nothing
+        app.update(Msg::AssetsReady { project_id: 8 });
+        assert_eq!(app.screen(), Screen::Loading);
+        let effects = app.update(Msg::AssetsReady { project_id: 7 });
+        assert_eq!(app.screen(), Screen::Project);
+        assert!(matches!(sent(&effects)[..], [proto::Command::GetProjectActivity { project_id: 7, before: None }]));
+
+        let effects = app.update(Msg::Action(Action::LeaveProject));
+        assert!(matches!(sent(&effects)[..], [proto::Command::LeaveProject { project_id: 7 }]));
+        assert_eq!(app.screen(), Screen::Main);
+        assert!(app.project().is_none());
+    }
+
+    #[test]
+    fn joining_a_project_fails() {
+        let mut app = logged_in();
+        app.update(Msg::Action(Action::OpenProject { project_id: 7 }));
+        app.update(Msg::Response(proto::Response::NoSuchProject));
+        assert_eq!(app.screen(), Screen::Main);
+    }
+
+    #[test]
+    fn losing_the_connection_in_a_project() {
+        let mut app = logged_in();
+        app.update(Msg::Action(Action::OpenProject { project_id: 7 }));
+        app.update(Msg::Response(joined(7)));
+        app.update(Msg::AssetsReady { project_id: 7 });
+
+        // Work goes on offline
+        app.update(Msg::State(NetState::Disconnected("Connection lost".to_owned())));
+        assert_eq!(app.screen(), Screen::Project);
+        assert!(sent(&app.update(Msg::Action(Action::AddTrack))).is_empty());
+        assert!(!app.project().unwrap().is_online());
+
+        // The track made offline is submitted after rejoining
+        app.update(Msg::State(NetState::Online));
+        let effects = app.update(Msg::Response(joined(7)));
+        assert!(matches!(sent(&effects)[..], [proto::Command::SubmitOp { project_id: 7, .. }]));
+        assert_eq!(app.screen(), Screen::Project);
+        assert!(app.project().unwrap().is_online());
+    }
+
+    #[test]
+    fn server_shutdown() {
+        let mut app = logged_in();
+        app.update(Msg::Response(proto::Response::ServerShuttingDown {
+            reason: "Update".to_owned(),
+            reconnect_after: Some(10),
+        }));
+        assert_eq!(app.screen(), Screen::Loading);
+        assert_eq!(app.load_task(), "Server restarting: Update. Reconnecting in 10s...");
+    }
+}
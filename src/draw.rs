@@ -0,0 +1,296 @@
+//! What views draw with.
+//!
+//! Views draw on a [Canvas] instead of a nanovg frame. On screen that's nanovg (see
+//! [::render::RenderContext::frame]). In tests, a [Recorder] only writes down what was
+//! drawn, so views can be checked for texts, colors and positions without a GL context.
+
+use std::path::Path;
+#[cfg(test)]
+use std::path::PathBuf;
+
+#[repr(usize)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Fonts {
+    Inter = 0,
+    Vga8,
+    Moderno,
+    NumFonts,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::from_rgba(r, g, b, 255)
+    }
+
+    pub fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VAlign {
+    Top,
+    Middle,
+    Bottom,
+    Baseline,
+}
+
+/// Where text is drawn relative to its position. Left and baseline unless changed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Alignment {
+    pub horizontal: HAlign,
+    pub vertical: VAlign,
+}
+
+impl Alignment {
+    pub fn new() -> Self {
+        Alignment {
+            horizontal: HAlign::Left,
+            vertical: VAlign::Baseline,
+        }
+    }
+
+    pub fn left(self) -> Self {
+        Alignment { horizontal: HAlign::Left, ..self }
+    }
+
+    pub fn center(self) -> Self {
+        Alignment { horizontal: HAlign::Center, ..self }
+    }
+
+    pub fn right(self) -> Self {
+        Alignment { horizontal: HAlign::Right, ..self }
+    }
+
+    pub fn top(self) -> Self {
+        Alignment { vertical: VAlign::Top, ..self }
+    }
+
+    pub fn middle(self) -> Self {
+        Alignment { vertical: VAlign::Middle, ..self }
+    }
+
+    pub fn bottom(self) -> Self {
+        Alignment { vertical: VAlign::Bottom, ..self }
+    }
+
+    pub fn baseline(self) -> Self {
+        Alignment { vertical: VAlign::Baseline, ..self }
+    }
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TextStyle {
+    pub size: f32,
+    pub color: Color,
+    pub align: Alignment,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            size: 16.0,
+            color: Color::from_rgb(255, 255, 255),
+            align: Alignment::new(),
+        }
+    }
+}
+
+/// The box text covers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TextBounds {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+/// An axis-aligned rectangle, e.g. where something was drawn, to check whether a click
+/// hit it.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Rect {
+    pub pos: (f32, f32),
+    pub size: (f32, f32),
+}
+
+impl Rect {
+    pub fn new(pos: (f32, f32), size: (f32, f32)) -> Self {
+        Rect { pos, size }
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        (self.pos.0 + self.size.0 / 2.0, self.pos.1 + self.size.1 / 2.0)
+    }
+
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        point.0 >= self.pos.0
+            && point.0 < self.pos.0 + self.size.0
+            && point.1 >= self.pos.1
+            && point.1 < self.pos.1 + self.size.1
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shape {
+    Rect { pos: (f32, f32), size: (f32, f32) },
+    RoundedRect { pos: (f32, f32), size: (f32, f32), radius: f32 },
+    Circle { center: (f32, f32), radius: f32 },
+}
+
+pub trait Canvas {
+    /// The size of what's drawn on, in window coordinates.
+    fn size(&self) -> (f32, f32);
+    /// Fills all `shapes` with `color`.
+    fn fill(&mut self, shapes: &[Shape], color: Color);
+    fn text(&mut self, font: Fonts, pos: (f32, f32), text: &str, style: TextStyle);
+    /// How far the text advances and the box it covers when drawn at `pos`.
+    fn text_bounds(&self, font: Fonts, pos: (f32, f32), text: &str, style: TextStyle) -> (f32, TextBounds);
+    /// Draws the image file at `path`, scaled to `size`.
+    fn image(&mut self, path: &Path, pos: (f32, f32), size: (f32, f32));
+
+    /// Draws text within `rect`, where the style's alignment says. Baseline aligned
+    /// text sits on the bottom edge.
+    fn text_in(&mut self, font: Fonts, rect: Rect, text: &str, style: TextStyle) {
+        let x = match style.align.horizontal {
+            HAlign::Left => rect.pos.0,
+            HAlign::Center => rect.center().0,
+            HAlign::Right => rect.pos.0 + rect.size.0,
+        };
+        let y = match style.align.vertical {
+            VAlign::Top => rect.pos.1,
+            VAlign::Middle => rect.center().1,
+            VAlign::Bottom | VAlign::Baseline => rect.pos.1 + rect.size.1,
+        };
+        self.text(font, (x, y), text, style);
+    }
+}
+
+/// Something drawn on a [Recorder].
+#[cfg(test)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum DrawCommand {
+    Fill { shapes: Vec<Shape>, color: Color },
+    Text { font: Fonts, pos: (f32, f32), text: String, style: TextStyle },
+    Image { path: PathBuf, pos: (f32, f32), size: (f32, f32) },
+}
+
+/// A canvas that writes down what's drawn on it instead of drawing.
+///
+/// There are no fonts to measure text with, so every character is taken to be half as
+/// wide as the text is high. That's enough to check where things end up relative to
+/// each other, not how the client really looks.
+#[cfg(test)]
+pub struct Recorder {
+    size: (f32, f32),
+    commands: Vec<DrawCommand>,
+}
+
+#[cfg(test)]
+impl Recorder {
+    pub fn new(size: (f32, f32)) -> Self {
+        Recorder {
+            size,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Everything drawn so far, in order.
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    /// Every text drawn so far, in order.
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.commands.iter().filter_map(|cmd| match *cmd {
+            DrawCommand::Text { ref text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The first text drawn that contains `needle`.
+    pub fn find_text(&self, needle: &str) -> Option<&DrawCommand> {
+        self.commands.iter().find(|cmd| match **cmd {
+            DrawCommand::Text { ref text, .. } => text.contains(needle),
+            _ => false,
+        })
+    }
+
+    /// Starts over, e.g. for the next frame.
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+}
+
+#[cfg(test)]
+impl Canvas for Recorder {
+    fn size(&self) -> (f32, f32) {
+        self.size
+    }
+
+    fn fill(&mut self, shapes: &[Shape], color: Color) {
+        self.commands.push(DrawCommand::Fill {
+            shapes: shapes.to_vec(),
+            color,
+        });
+    }
+
+    fn text(&mut self, font: Fonts, pos: (f32, f32), text: &str, style: TextStyle) {
+        self.commands.push(DrawCommand::Text {
+            font,
+            pos,
+            text: text.to_owned(),
+            style,
+        });
+    }
+
+    fn text_bounds(&self, _font: Fonts, pos: (f32, f32), text: &str, style: TextStyle) -> (f32, TextBounds) {
+        let (width, height) = (text.chars().count() as f32 * style.size / 2.0, style.size);
+        let min_x = match style.align.horizontal {
+            HAlign::Left => pos.0,
+            HAlign::Center => pos.0 - width / 2.0,
+            HAlign::Right => pos.0 - width,
+        };
+        let min_y = match style.align.vertical {
+            VAlign::Top => pos.1,
+            VAlign::Middle => pos.1 - height / 2.0,
+            VAlign::Bottom => pos.1 - height,
+            VAlign::Baseline => pos.1 - height * 0.8,
+        };
+        let bounds = TextBounds {
+            min_x,
+            min_y,
+            max_x: min_x + width,
+            max_y: min_y + height,
+        };
+        (width, bounds)
+    }
+
+    fn image(&mut self, path: &Path, pos: (f32, f32), size: (f32, f32)) {
+        self.commands.push(DrawCommand::Image {
+            path: path.to_owned(),
+            pos,
+            size,
+        });
+    }
+}
@@ -1,6 +1,17 @@
 use glfw_ffi::*;
 
 use std::ops::{Deref, Index, Range};
+use std::path::PathBuf;
+
+/// Input from the window, queued by the GLFW callbacks until the main loop hands it
+/// to the view on screen.
+pub enum InputEvent {
+    Char(char),
+    Key(KeyAction),
+    Mouse(MouseAction),
+    /// Files were dropped on the window.
+    Drop(Vec<PathBuf>),
+}
 
 /// Keyboard key
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -32,16 +43,93 @@ pub enum KeyCode {
     Delete = GLFW_KEY_DELETE,
     Return = GLFW_KEY_ENTER,
     Tab = GLFW_KEY_TAB,
+    Space = GLFW_KEY_SPACE,
+    Escape = GLFW_KEY_ESCAPE,
     Left = GLFW_KEY_LEFT,
     Right = GLFW_KEY_RIGHT,
+    Up = GLFW_KEY_UP,
+    Down = GLFW_KEY_DOWN,
     Home = GLFW_KEY_HOME,
     End = GLFW_KEY_END,
+    PageDown = GLFW_KEY_PAGE_DOWN,
+    F5 = GLFW_KEY_F5,
+    M = GLFW_KEY_M,
+    N = GLFW_KEY_N,
+    Z = GLFW_KEY_Z,
+    Num1 = GLFW_KEY_1,
+    Num2 = GLFW_KEY_2,
+    Num3 = GLFW_KEY_3,
+    Num4 = GLFW_KEY_4,
+    Num5 = GLFW_KEY_5,
 }
 
 #[repr(u32)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum KeyMod {
     Shift = GLFW_MOD_SHIFT,
+    Control = GLFW_MOD_CONTROL,
+}
+
+/// Mouse input. Positions are in window coordinates, like everything drawn.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MouseAction {
+    /// The cursor moved to `pos`.
+    Move { pos: (f32, f32), mods: u32 },
+    Button {
+        button: u32,
+        action: u32,
+        pos: (f32, f32),
+        mods: u32,
+    },
+    /// The wheel or touchpad scrolled by `offset`, positive is up and right.
+    Scroll {
+        offset: (f32, f32),
+        pos: (f32, f32),
+        mods: u32,
+    },
+    /// The cursor entered the window, or left it if `false`.
+    Enter(bool),
+}
+
+impl MouseAction {
+    /// Where the cursor is, `None` when it entered or left the window.
+    pub fn pos(&self) -> Option<(f32, f32)> {
+        match *self {
+            MouseAction::Move { pos, .. } | MouseAction::Button { pos, .. } | MouseAction::Scroll { pos, .. } => Some(pos),
+            MouseAction::Enter(_) => None,
+        }
+    }
+
+    pub fn was_pressed(&self, button: MouseButton) -> bool {
+        match *self {
+            MouseAction::Button { button: b, action, .. } => action == GLFW_PRESS && b == button as u32,
+            _ => false,
+        }
+    }
+
+    pub fn was_released(&self, button: MouseButton) -> bool {
+        match *self {
+            MouseAction::Button { button: b, action, .. } => action == GLFW_RELEASE && b == button as u32,
+            _ => false,
+        }
+    }
+
+    pub fn with_modifier(&self, keymod: KeyMod) -> bool {
+        match *self {
+            MouseAction::Move { mods, .. } | MouseAction::Button { mods, .. } | MouseAction::Scroll { mods, .. } => {
+                mods & keymod as u32 != 0
+            }
+            MouseAction::Enter(_) => false,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MouseButton {
+    Left = GLFW_MOUSE_BUTTON_LEFT,
+    Right = GLFW_MOUSE_BUTTON_RIGHT,
+    Middle = GLFW_MOUSE_BUTTON_MIDDLE,
 }
 
 /// A string type similar to [::std::string::String], but which
@@ -1,35 +1,41 @@
-extern crate bincode;
+extern crate client;
 extern crate glfw_ffi;
 extern crate nanovg;
 extern crate proto;
 extern crate sha3;
 
+mod app;
+mod draw;
 mod gl;
 mod input;
 mod render;
 mod ui;
 
+use app::App;
+use client::{assets, net, settings};
 use glfw_ffi::*;
+use input::InputEvent;
+use ui::View;
 
 use std::cell::RefCell;
-use std::net;
-use std::os::raw::{c_int, c_uint};
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::io;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::path::PathBuf;
 use std::ptr;
 use std::sync;
 use std::thread;
 use std::time;
 use std::mem::MaybeUninit;
 
-const SERVER_IP: &str = "127.0.0.1:4450";
-
 enum MainThreadMsg {
     Shutdown,
     Command(proto::Command),
-}
-
-enum NetThreadMsg {
-    Connected,
-    Response(proto::Response),
+    /// Connect to another server, given as "host:port".
+    SetServer(String),
+    /// Upload an asset once the more urgent traffic is out
+    Upload(assets::AssetUpload),
 }
 
 struct ScopeGuard<F: FnMut()> {
@@ -43,21 +49,45 @@ impl<F: FnMut()> std::ops::Drop for ScopeGuard<F> {
 }
 
 struct MainWindowCtx<'a> {
-    char_input_handler: Box<dyn Fn(char) + 'a>,
-    key_input_handler: Box<dyn Fn(c_int, c_int, c_int, c_int) + 'a>,
+    input_handler: Box<dyn Fn(InputEvent) + 'a>,
+}
+
+/// Hands `event` to the window's input handler.
+unsafe fn send_input(window: *mut GLFWwindow, event: InputEvent) {
+    let ptr = glfwGetWindowUserPointer(window) as *mut MainWindowCtx;
+    if !ptr.is_null() {
+        ((*ptr).input_handler)(event);
+    }
+}
+
+/// The modifier keys held down right now, as in key events.
+unsafe fn current_mods(window: *mut GLFWwindow) -> u32 {
+    let pressed = |key: u32| glfwGetKey(window, key as c_int) == GLFW_PRESS as c_int;
+    let mut mods = 0;
+    if pressed(GLFW_KEY_LEFT_SHIFT) || pressed(GLFW_KEY_RIGHT_SHIFT) {
+        mods |= GLFW_MOD_SHIFT;
+    }
+    if pressed(GLFW_KEY_LEFT_CONTROL) || pressed(GLFW_KEY_RIGHT_CONTROL) {
+        mods |= GLFW_MOD_CONTROL;
+    }
+    if pressed(GLFW_KEY_LEFT_ALT) || pressed(GLFW_KEY_RIGHT_ALT) {
+        mods |= GLFW_MOD_ALT;
+    }
+    if pressed(GLFW_KEY_LEFT_SUPER) || pressed(GLFW_KEY_RIGHT_SUPER) {
+        mods |= GLFW_MOD_SUPER;
+    }
+    mods
+}
+
+unsafe fn cursor_pos(window: *mut GLFWwindow) -> (f32, f32) {
+    let (mut x, mut y) = (0f64, 0f64);
+    glfwGetCursorPos(window, &mut x as *mut _, &mut y as *mut _);
+    (x as f32, y as f32)
 }
 
 unsafe extern "C" fn char_callback(window: *mut GLFWwindow, codepoint: c_uint) {
-    let ctx = {
-        let ptr = glfwGetWindowUserPointer(window) as *mut MainWindowCtx;
-        if ptr.is_null() {
-            return;
-        } else {
-            &mut *ptr
-        }
-    };
     if let Some(c) = std::char::from_u32(codepoint) {
-        (ctx.char_input_handler)(c);
+        send_input(window, InputEvent::Char(c));
     }
 }
 
@@ -68,19 +98,51 @@ unsafe extern "C" fn key_callback(
     action: c_int,
     mods: c_int,
 ) {
-    let ctx = {
-        let ptr = glfwGetWindowUserPointer(window) as *mut MainWindowCtx;
-        if ptr.is_null() {
-            return;
-        } else {
-            &mut *ptr
-        }
-    };
-    (ctx.key_input_handler)(key, scancode, action, mods);
+    send_input(window, InputEvent::Key(input::KeyAction {
+        key: key as u32,
+        scancode: scancode as u32,
+        action: action as u32,
+        mods: mods as u32,
+    }));
+}
+
+unsafe extern "C" fn cursor_pos_callback(window: *mut GLFWwindow, x: f64, y: f64) {
+    send_input(window, InputEvent::Mouse(input::MouseAction::Move {
+        pos: (x as f32, y as f32),
+        mods: current_mods(window),
+    }));
 }
 
-fn load_fonts<'a>(nvg: &'a nanovg::Context) -> Result<[nanovg::Font<'a>; render::Fonts::NumFonts as usize], nanovg::CreateFontError> {
-    use render::Fonts;
+unsafe extern "C" fn mouse_button_callback(window: *mut GLFWwindow, button: c_int, action: c_int, mods: c_int) {
+    send_input(window, InputEvent::Mouse(input::MouseAction::Button {
+        button: button as u32,
+        action: action as u32,
+        pos: cursor_pos(window),
+        mods: mods as u32,
+    }));
+}
+
+unsafe extern "C" fn scroll_callback(window: *mut GLFWwindow, x_offset: f64, y_offset: f64) {
+    send_input(window, InputEvent::Mouse(input::MouseAction::Scroll {
+        offset: (x_offset as f32, y_offset as f32),
+        pos: cursor_pos(window),
+        mods: current_mods(window),
+    }));
+}
+
+unsafe extern "C" fn cursor_enter_callback(window: *mut GLFWwindow, entered: c_int) {
+    send_input(window, InputEvent::Mouse(input::MouseAction::Enter(entered != 0)));
+}
+
+unsafe extern "C" fn drop_callback(window: *mut GLFWwindow, count: c_int, paths: *mut *const c_char) {
+    let paths = (0..count as usize)
+        .map(|i| PathBuf::from(CStr::from_ptr(*paths.add(i)).to_string_lossy().into_owned()))
+        .collect();
+    send_input(window, InputEvent::Drop(paths));
+}
+
+fn load_fonts<'a>(nvg: &'a nanovg::Context) -> Result<[nanovg::Font<'a>; draw::Fonts::NumFonts as usize], nanovg::CreateFontError> {
+    use draw::Fonts;
     
     let inter = nanovg::Font::from_file(&nvg, "Inter UI", "assets/Inter-UI-Regular.ttf")?;
     let vga8 = nanovg::Font::from_file(&nvg, "PxPlus IBM VGA8", "assets/PxPlus_IBM_VGA8.ttf")?;
@@ -111,7 +173,46 @@ fn load_fonts<'a>(nvg: &'a nanovg::Context) -> Result<[nanovg::Font<'a>; render:
     }
 }
 
+/// The value following the command line flag `name`.
+fn flag_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|a| a != name).nth(1)
+}
+
+/// Updates the app and hands what it asks for to the network thread.
+fn update(app: &mut App, msg: app::Msg, main_tx: &sync::mpsc::Sender<MainThreadMsg>) {
+    for effect in app.update(msg) {
+        let _ = main_tx.send(match effect {
+            app::Effect::Send(cmd) => MainThreadMsg::Command(cmd),
+            app::Effect::SetServer(server) => MainThreadMsg::SetServer(server),
+            app::Effect::Upload { project_id, path } => {
+                // Hashing a large file takes a while, the network thread can't wait for it
+                let main_tx = main_tx.clone();
+                thread::spawn(move || match assets::AssetUpload::open(project_id, &path) {
+                    Ok(upload) => {
+                        let _ = main_tx.send(MainThreadMsg::Upload(upload));
+                    }
+                    Err(e) => println!("Can't upload {}: {}", path.display(), e),
+                });
+                continue;
+            }
+        });
+    }
+}
+
 fn main() {
+    // --config picks the settings file, --server overrides the server to connect to for
+    // this run, without changing the one saved
+    let settings_path = flag_value("--config").map(std::path::PathBuf::from).or_else(settings::Settings::default_path);
+    let mut settings = match settings_path {
+        Some(ref path) => settings::Settings::load(path).unwrap_or_else(|e| {
+            println!("Failed to read {}: {}", path.display(), e);
+            Default::default()
+        }),
+        None => Default::default(),
+    };
+    settings.server_override = flag_value("--server");
+    let mut app = App::new(settings);
+
     unsafe {
         if glfwInit() == 0 {
             panic!("glfwInit");
@@ -128,9 +229,11 @@ fn main() {
         glfwWindowHint(GLFW_SAMPLES as _, 4);
         glfwWindowHint(GLFW_DOUBLEBUFFER as _, 1);
 
+        let geometry = app.settings.window;
+        let (width, height) = geometry.map_or((1280, 720), |g| (g.width, g.height));
         let window = glfwCreateWindow(
-            1280,
-            720,
+            width,
+            height,
             b"Chorus Studio\0".as_ptr() as _,
             ptr::null_mut(),
             ptr::null_mut(),
@@ -140,82 +243,182 @@ fn main() {
             println!("glfwCreateWindow");
             return;
         }
+        if let Some(geometry) = geometry {
+            glfwSetWindowPos(window, geometry.x, geometry.y);
+        }
 
         // Networking
         let (main_tx, main_rx) = sync::mpsc::channel();
         let (server_tx, server_rx) = sync::mpsc::channel();
+        let server = app.settings.current_server().to_owned();
         let network_thread = thread::spawn(move || -> Result<(), ()> {
-            use std::str::FromStr;
-            let server_addr = net::SocketAddr::from_str(SERVER_IP).map_err(|_| ())?;
-            
-            // Endlessly connect to server:
-            let mut stream = loop {
-                if let Ok(MainThreadMsg::Shutdown) = main_rx.try_recv() {
-                    return Ok(());
-                }
 
-                let timeout = time::Duration::from_secs(4);
-                if let Ok(s) = net::TcpStream::connect_timeout(&server_addr, timeout) {
-                    break s;
+            let mut asset_sync = match assets::AssetCache::open_default() {
+                Ok(cache) => Some(assets::AssetSync::new(cache)),
+                Err(e) => {
+                    println!("Asset cache unavailable: {}", e);
+                    None
                 }
             };
 
-            // We've got a connection, notify the main thread.
-            server_tx.send(NetThreadMsg::Connected).map_err(|_| ())?;
-            glfwPostEmptyEvent(); // Wake up main loop
+            // Uploads go one after the other
+            let mut uploads: VecDeque<assets::AssetUpload> = VecDeque::new();
+
+            let state_tx = server_tx.clone();
+            let mut client = net::NetClient::new(
+                server,
+                Box::new(move |state| {
+                    let _ = state_tx.send(app::Msg::State(state.clone()));
+                    glfwPostEmptyEvent(); // Wake up main loop
+                }),
+            );
 
-            stream
-                .set_read_timeout(Some(time::Duration::from_millis(500)))
-                .map_err(|_| ())?;
-            
             loop {
-                if let Ok(msg) = main_rx.try_recv() {
+                if let Some(wait) = client.retry_in() {
+                    // Anything in flight is gone with the connection
+                    if let Some(ref mut sync) = asset_sync {
+                        sync.stop();
+                    }
+                    for upload in uploads.drain(..) {
+                        println!("Upload of asset {} interrupted, drop the file again to resume", proto::asset::hash_hex(&upload.hash()));
+                    }
+                    // Commands issued in the meantime are queued by the client
+                    match main_rx.recv_timeout(wait) {
+                        Ok(MainThreadMsg::Shutdown) | Err(sync::mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+                        Ok(MainThreadMsg::Command(cmd)) => client.send(cmd),
+                        Ok(MainThreadMsg::SetServer(server)) => client.set_server(server),
+                        Ok(MainThreadMsg::Upload(upload)) => {
+                            println!("Not connected, drop the file again to upload asset {}", proto::asset::hash_hex(&upload.hash()));
+                        }
+                        Err(sync::mpsc::RecvTimeoutError::Timeout) => client.connect(),
+                    }
+                    continue;
+                }
+
+                // Realtime messages go out first, asset downloads only fill the gaps.
+                let mut sent = false;
+                for msg in main_rx.try_iter() {
                     match msg {
                         MainThreadMsg::Shutdown => return Ok(()),
                         MainThreadMsg::Command(cmd) => {
-                            bincode::serialize_into(&mut stream, &cmd).map_err(|_| ())?;
+                            client.send(cmd);
+                            sent = true;
+                        }
+                        MainThreadMsg::SetServer(server) => client.set_server(server),
+                        MainThreadMsg::Upload(upload) => uploads.push_back(upload),
+                    }
+                }
+                if !sent {
+                    if let Some(upload) = uploads.front_mut() {
+                        match upload.next_request() {
+                            Ok(Some(cmd)) => {
+                                client.send_now(&cmd);
+                                sent = true;
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                println!("Failed to read asset {}: {}", proto::asset::hash_hex(&upload.hash()), e);
+                                uploads.pop_front();
+                            }
+                        }
+                    }
+                }
+                if !sent {
+                    if let Some(ref mut sync) = asset_sync {
+                        match sync.next_request() {
+                            Ok(Some(cmd)) => {
+                                client.send_now(&cmd);
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                println!("Asset cache error: {}", e);
+                                sync.stop();
+                            }
                         }
                     }
                 }
 
-                let resp = bincode::deserialize_from::<_, proto::Response>(&stream);
-                if let Ok(resp) = resp {
-                    server_tx
-                        .send(NetThreadMsg::Response(resp))
-                        .map_err(|_| ())?;
-                    glfwPostEmptyEvent(); // Wake up main loop
+                let resp = match client.receive() {
+                    Some(resp) => resp,
+                    None => continue,
+                };
+                let was_syncing = asset_sync.as_ref().is_some_and(|s| !s.is_done());
+                if uploads.front_mut().is_some_and(|upload| upload.on_response(&resp)) {
+                    if let proto::Response::AssetFailed { hash, ref error } = resp {
+                        println!("Couldn't upload asset {}: {:?}", proto::asset::hash_hex(&hash), error);
+                    }
+                    uploads.pop_front();
+                }
+                match resp {
+                    proto::Response::ProjectJoined { project_id, .. } => {
+                        match asset_sync {
+                            Some(ref mut sync) => {
+                                sync.stop();
+                                client.send_now(&proto::Command::ListAssets { project_id });
+                            }
+                            None => {
+                                server_tx.send(app::Msg::Response(resp)).map_err(|_| ())?;
+                                server_tx.send(app::Msg::AssetsReady { project_id }).map_err(|_| ())?;
+                                glfwPostEmptyEvent(); // Wake up main loop
+                                continue;
+                            }
+                        }
+                        server_tx.send(app::Msg::Response(resp)).map_err(|_| ())?;
+                    }
+                    proto::Response::Assets { project_id, assets } => {
+                        if let Some(ref mut sync) = asset_sync {
+                            sync.start(project_id, assets);
+                            if sync.is_done() {
+                                server_tx.send(app::Msg::AssetsReady { project_id }).map_err(|_| ())?;
+                            }
+                        }
+                    }
+                    proto::Response::AssetChunk { hash, offset, data, .. } => {
+                        if let Some(ref mut sync) = asset_sync {
+                            if let Err(e) = sync.on_chunk(hash, offset, &data) {
+                                println!("Asset cache error: {}", e);
+                            }
+                        }
+                    }
+                    proto::Response::AssetFailed { hash, error } => {
+                        if let Some(ref mut sync) = asset_sync {
+                            sync.on_failed(hash, error);
+                        }
+                    }
+                    resp => {
+                        server_tx.send(app::Msg::Response(resp)).map_err(|_| ())?;
+                    }
                 }
+                if was_syncing {
+                    if let Some(ref sync) = asset_sync {
+                        if let Some(progress) = sync.progress() {
+                            server_tx.send(app::Msg::AssetProgress(progress)).map_err(|_| ())?;
+                            if sync.is_done() {
+                                server_tx
+                                    .send(app::Msg::AssetsReady { project_id: progress.project_id })
+                                    .map_err(|_| ())?;
+                            }
+                        }
+                    }
+                }
+                glfwPostEmptyEvent(); // Wake up main loop
             }
         });
 
-        // Data the views depend on
-        let load_task = RefCell::new("Connecting to server...".to_owned());
-        let cur_users = RefCell::new(Vec::new());
-
-        let cur_view: RefCell<ui::DynamicView> =
-            RefCell::new(ui::DynamicView::MainLoading(ui::views::MainLoadingView {
-                cur_load_task: &load_task,
-            }));
-
+        // Input is queued by the callbacks and handled on the next turn of the main loop
+        let input_events = RefCell::new(Vec::new());
         let mut main_window_ctx = MainWindowCtx {
-            char_input_handler: Box::new(|c| {
-                let mut cur_view = cur_view.borrow_mut();
-                cur_view.view().on_char_input(c);
-            }),
-            key_input_handler: Box::new(|key, scancode, action, mods| {
-                let mut cur_view = cur_view.borrow_mut();
-                cur_view.view().on_key_input(input::KeyAction {
-                    key: key as u32,
-                    scancode: scancode as u32,
-                    action: action as u32,
-                    mods: mods as u32,
-                });
-            }),
+            input_handler: Box::new(|event| input_events.borrow_mut().push(event)),
         };
 
         glfwSetWindowUserPointer(window, &mut main_window_ctx as *mut MainWindowCtx as *mut _);
         glfwSetCharCallback(window, Some(char_callback));
         glfwSetKeyCallback(window, Some(key_callback));
+        glfwSetCursorPosCallback(window, Some(cursor_pos_callback));
+        glfwSetMouseButtonCallback(window, Some(mouse_button_callback));
+        glfwSetScrollCallback(window, Some(scroll_callback));
+        glfwSetCursorEnterCallback(window, Some(cursor_enter_callback));
+        glfwSetDropCallback(window, Some(drop_callback));
 
         glfwMakeContextCurrent(window);
         gl::load_with(|s| {
@@ -237,57 +440,72 @@ fn main() {
         {
             let render_ctx = render::RenderContext::new(window, &nvg, fonts);
 
+            let mut cur_screen = app.screen();
+            let mut cur_view = ui::DynamicView::new(cur_screen, &app);
+            // Shown on top of every view
+            let mut net_status = ui::views::NetStatusView;
+            let mut notices = ui::views::NoticeView;
+
             while glfwWindowShouldClose(window) == 0 {
-                for msg in server_rx.try_iter() {
-                    match msg {
-                        NetThreadMsg::Connected => {
-                            cur_view.replace(ui::DynamicView::Login(ui::views::LoginView::new(
-                                Box::new(|email, password| {
-                                    let _ = main_tx.send(MainThreadMsg::Command(
-                                        proto::Command::Login {
-                                            email: email.to_owned(),
-                                            password: password.to_owned(),
-                                        },
-                                    ));
-                                }),
-                            )));
-                        }
-                        NetThreadMsg::Response(res) => match res {
-                            proto::Response::UserList(users) => {
-                                cur_users.replace(users);
-                            }
-                            proto::Response::LoginOk => {
-                                cur_view.replace(ui::DynamicView::Main(ui::views::MainView {
-                                    user_list: &cur_users,
-                                }));
-                            }
-                            proto::Response::LoginInvalid => {
-                                let mut cur_view = cur_view.borrow_mut();
-                                if let ui::DynamicView::Login(ref mut login) = *cur_view {
-                                    login.invalid_login();
-                                }
-                            }
-                        },
+                update(&mut app, app::Msg::Tick(time::Instant::now()), &main_tx);
+
+                let events: Vec<InputEvent> = input_events.borrow_mut().drain(..).collect();
+                for event in events {
+                    let mut actions = Vec::new();
+                    match event {
+                        InputEvent::Char(c) => cur_view.view().on_char_input(&app, c, &mut actions),
+                        InputEvent::Key(key) => cur_view.view().on_key_input(&app, key, &mut actions),
+                        InputEvent::Mouse(mouse) => cur_view.view().on_mouse_input(&app, mouse, &mut actions),
+                        InputEvent::Drop(paths) => actions.push(app::Action::Upload(paths)),
+                    }
+                    for action in actions {
+                        update(&mut app, app::Msg::Action(action), &main_tx);
                     }
                 }
+                for msg in server_rx.try_iter() {
+                    update(&mut app, msg, &main_tx);
+                }
+
+                if app.screen() != cur_screen {
+                    cur_screen = app.screen();
+                    cur_view = ui::DynamicView::new(cur_screen, &app);
+                }
 
                 let (mut w, mut h): (c_int, c_int) = (0, 0);
                 glfwGetFramebufferSize(window, &mut w as *mut _, &mut h as *mut _);
                 gl::Viewport(0, 0, w, h);
-                gl::ClearColor(0.2, 0.4, 0.8, 1.0);
+                let (r, g, b) = app.settings.theme.background();
+                gl::ClearColor(r, g, b, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
 
-                {
-                    let mut cur_view = cur_view.borrow_mut();
-                    cur_view.view().present(&render_ctx);
-                }
+                render_ctx.frame(|canvas| {
+                    cur_view.view().present(&app, canvas);
+                    net_status.present(&app, canvas);
+                    notices.present(&app, canvas);
+                });
                 glfwSwapBuffers(window);
-                glfwWaitEvents();
+                match app.next_timer() {
+                    Some(at) => {
+                        let wait = at.saturating_duration_since(time::Instant::now());
+                        glfwWaitEventsTimeout(wait.as_secs_f64());
+                    }
+                    None => glfwWaitEvents(),
+                }
             }
         }
 
+        let mut geometry = settings::WindowGeometry { x: 0, y: 0, width: 0, height: 0 };
+        glfwGetWindowPos(window, &mut geometry.x as *mut _, &mut geometry.y as *mut _);
+        glfwGetWindowSize(window, &mut geometry.width as *mut _, &mut geometry.height as *mut _);
         glfwHideWindow(window);
 
+        app.settings.window = Some(geometry);
+        if let Some(ref path) = settings_path {
+            if let Err(e) = app.settings.save(path) {
+                println!("Failed to save settings to {}: {}", path.display(), e);
+            }
+        }
+
         if let Ok(..) = main_tx.send(MainThreadMsg::Shutdown) {
             let _ = network_thread.join();
         }
@@ -1,22 +1,19 @@
+use draw::{Canvas, Color, Fonts, HAlign, Shape, TextBounds, TextStyle, VAlign};
 use glfw_ffi::*;
 use nanovg;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
 use std::ptr;
 
-#[repr(usize)]
-#[derive(PartialEq, Eq)]
-pub enum Fonts {
-    Inter = 0,
-    Vga8,
-    Moderno,
-    NumFonts,
-}
-
 pub struct RenderContext<'a> {
     window: *mut GLFWwindow,
     nvg: &'a nanovg::Context,
     fonts: [nanovg::Font<'a>; Fonts::NumFonts as usize],
+    /// Images by file, loaded when first drawn. `None` if loading failed.
+    images: RefCell<HashMap<PathBuf, Option<nanovg::Image<'a>>>>,
 }
 
 impl<'a> RenderContext<'a> {
@@ -25,7 +22,12 @@ impl<'a> RenderContext<'a> {
         nvg: &'a nanovg::Context,
         fonts: [nanovg::Font<'a>; Fonts::NumFonts as usize],
     ) -> Self {
-        Self { window, nvg, fonts }
+        Self {
+            window,
+            nvg,
+            fonts,
+            images: RefCell::new(HashMap::new()),
+        }
     }
 
     pub fn size(&self) -> (f32, f32) {
@@ -46,8 +48,12 @@ impl<'a> RenderContext<'a> {
         }
     }
 
-    pub fn frame<F: FnOnce(nanovg::Frame)>(&self, f: F) {
-        self.nvg.frame(self.size(), self.pixel_ratio(), f);
+    /// Draws a frame through nanovg.
+    pub fn frame<F: FnOnce(&mut dyn Canvas)>(&self, f: F) {
+        let size = self.size();
+        self.nvg.frame(size, self.pixel_ratio(), |frame| {
+            f(&mut FrameCanvas { ctx: self, frame, size });
+        });
     }
 
     pub fn font(&self, id: Fonts) -> nanovg::Font<'a> {
@@ -58,3 +64,103 @@ impl<'a> RenderContext<'a> {
         self.fonts[id as usize]
     }
 }
+
+/// A nanovg frame as a [Canvas].
+struct FrameCanvas<'r, 'a: 'r, 'f> {
+    ctx: &'r RenderContext<'a>,
+    frame: nanovg::Frame<'f>,
+    size: (f32, f32),
+}
+
+fn nvg_color(color: Color) -> nanovg::Color {
+    nanovg::Color::from_rgba(color.r, color.g, color.b, color.a)
+}
+
+fn nvg_text_options(style: TextStyle) -> nanovg::TextOptions {
+    let align = nanovg::Alignment::new();
+    let align = match style.align.horizontal {
+        HAlign::Left => align.left(),
+        HAlign::Center => align.center(),
+        HAlign::Right => align.right(),
+    };
+    let align = match style.align.vertical {
+        VAlign::Top => align.top(),
+        VAlign::Middle => align.middle(),
+        VAlign::Bottom => align.bottom(),
+        VAlign::Baseline => align.baseline(),
+    };
+    nanovg::TextOptions {
+        size: style.size,
+        color: nvg_color(style.color),
+        align,
+        ..Default::default()
+    }
+}
+
+impl<'r, 'a, 'f> Canvas for FrameCanvas<'r, 'a, 'f> {
+    fn size(&self) -> (f32, f32) {
+        self.size
+    }
+
+    fn fill(&mut self, shapes: &[Shape], color: Color) {
+        self.frame.path(
+            |p| {
+                for shape in shapes {
+                    match *shape {
+                        Shape::Rect { pos, size } => p.rect(pos, size),
+                        Shape::RoundedRect { pos, size, radius } => p.rounded_rect(pos, size, radius),
+                        Shape::Circle { center, radius } => p.circle(center, radius),
+                    }
+                }
+                p.fill(nvg_color(color), Default::default());
+            },
+            Default::default(),
+        );
+    }
+
+    fn text(&mut self, font: Fonts, pos: (f32, f32), text: &str, style: TextStyle) {
+        self.frame.text(self.ctx.font(font), pos, text, nvg_text_options(style));
+    }
+
+    fn text_bounds(&self, font: Fonts, pos: (f32, f32), text: &str, style: TextStyle) -> (f32, TextBounds) {
+        let (advance, bounds) = self.frame.text_bounds(self.ctx.font(font), pos, text, nvg_text_options(style));
+        let bounds = TextBounds {
+            min_x: bounds.min_x,
+            min_y: bounds.min_y,
+            max_x: bounds.max_x,
+            max_y: bounds.max_y,
+        };
+        (advance, bounds)
+    }
+
+    fn image(&mut self, path: &Path, pos: (f32, f32), size: (f32, f32)) {
+        let mut images = self.ctx.images.borrow_mut();
+        let image = images.entry(path.to_owned()).or_insert_with(|| {
+            match nanovg::Image::new(self.ctx.nvg).build_from_file(path) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    println!("Failed to load image {}: {:?}", path.display(), e);
+                    None
+                }
+            }
+        });
+        if let Some(ref image) = *image {
+            self.frame.path(
+                |p| {
+                    p.rect(pos, size);
+                    p.fill(
+                        nanovg::ImagePattern {
+                            image,
+                            origin: pos,
+                            size,
+                            angle: 0.0,
+                            alpha: 1.0,
+                        },
+                        Default::default(),
+                    );
+                },
+                Default::default(),
+            );
+        }
+    }
+}
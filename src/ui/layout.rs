@@ -0,0 +1,544 @@
+//! Where things go on screen.
+//!
+//! Instead of computing coordinates, views describe their screen as a tree of
+//! [Layout]s every frame: rows and columns that share out their space by
+//! [Size], grids, stacks of layers, and leaves named by a key of the view's choosing.
+//! [Layout::arrange] fits the tree into the window and tells the view the rectangle of
+//! every leaf, so everything reflows when the window is resized.
+//!
+//! ```ignore
+//! let placement = Layout::row(vec![
+//!     Layout::leaf(Panel::Tracks).width(Size::flex(1.0).min(200.0).max(350.0)),
+//!     Layout::leaf(Panel::Timeline).width(Size::flex(3.0)),
+//! ])
+//! .spacing(10.0)
+//! .arrange(Rect::new((0.0, 0.0), canvas.size()));
+//! let timeline = placement.rect(&Panel::Timeline);
+//! ```
+
+use draw::Rect;
+
+use std::f32;
+
+/// How big a layout wants to be along one axis.
+///
+/// It starts out at `basis`, and if its row, column or grid has space left, it gets
+/// a share of that in proportion to `grow`. It never ends up smaller than `min` or
+/// larger than `max`, though it may not fit if its parent is too small for the mins.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Size {
+    pub basis: f32,
+    pub grow: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Size {
+    /// Exactly `size`, unless limited with [Size::min] or [Size::max].
+    pub fn fixed(size: f32) -> Self {
+        Size {
+            basis: size,
+            grow: 0.0,
+            min: 0.0,
+            max: f32::INFINITY,
+        }
+    }
+
+    /// A `grow` share of the space left.
+    pub fn flex(grow: f32) -> Self {
+        Size {
+            basis: 0.0,
+            grow,
+            min: 0.0,
+            max: f32::INFINITY,
+        }
+    }
+
+    pub fn min(self, min: f32) -> Self {
+        Size { min, ..self }
+    }
+
+    pub fn max(self, max: f32) -> Self {
+        Size { max, ..self }
+    }
+
+    fn clamp(&self, size: f32) -> f32 {
+        size.max(self.min).min(self.max)
+    }
+
+    /// The size within `available` space when there's nothing to share it with: all
+    /// of it if growing, the basis otherwise.
+    fn fit(&self, available: f32) -> f32 {
+        if self.grow > 0.0 {
+            self.clamp(available)
+        } else {
+            self.clamp(self.basis)
+        }
+    }
+}
+
+/// Shares `available` space out between `sizes`, see [Size].
+fn distribute(sizes: &[Size], available: f32) -> Vec<f32> {
+    let mut result: Vec<f32> = sizes.iter().map(|s| s.clamp(s.basis)).collect();
+    let mut frozen: Vec<bool> = sizes.iter().map(|s| s.grow <= 0.0).collect();
+    // Whoever hits their min or max keeps it, the rest is shared again among the others
+    loop {
+        let grow: f32 = sizes.iter().zip(&frozen).filter(|&(_, &f)| !f).map(|(s, _)| s.grow).sum();
+        if grow <= 0.0 {
+            return result;
+        }
+        let used: f32 = (0..sizes.len())
+            .map(|i| if frozen[i] { result[i] } else { sizes[i].basis })
+            .sum();
+        let free = (available - used).max(0.0);
+        let mut clamped = false;
+        for ((size, length), frozen) in sizes.iter().zip(result.iter_mut()).zip(frozen.iter_mut()) {
+            if *frozen {
+                continue;
+            }
+            let wanted = size.basis + free * size.grow / grow;
+            *length = size.clamp(wanted);
+            if *length != wanted {
+                *frozen = true;
+                clamped = true;
+            }
+        }
+        if !clamped {
+            return result;
+        }
+    }
+}
+
+/// Space kept free inside a layout's rectangle.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Padding {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Padding {
+    pub fn all(padding: f32) -> Self {
+        Padding {
+            left: padding,
+            top: padding,
+            right: padding,
+            bottom: padding,
+        }
+    }
+
+    pub fn symmetric(horizontal: f32, vertical: f32) -> Self {
+        Padding {
+            left: horizontal,
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+        }
+    }
+
+    fn shrink(&self, rect: Rect) -> Rect {
+        Rect::new(
+            (rect.pos.0 + self.left, rect.pos.1 + self.top),
+            (
+                (rect.size.0 - self.left - self.right).max(0.0),
+                (rect.size.1 - self.top - self.bottom).max(0.0),
+            ),
+        )
+    }
+}
+
+/// Where children go in space they don't fill.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+impl Align {
+    fn offset(self, free: f32) -> f32 {
+        match self {
+            Align::Start => 0.0,
+            Align::Center => free.max(0.0) / 2.0,
+            Align::End => free.max(0.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+struct Cell<K> {
+    column: usize,
+    row: usize,
+    columns: usize,
+    rows: usize,
+    layout: Layout<K>,
+}
+
+enum Kind<K> {
+    /// Takes up space, nothing more
+    Space,
+    Leaf(K),
+    /// Children one after the other along the axis
+    Flex(Axis, Vec<Layout<K>>),
+    /// Children on top of each other
+    Stack(Vec<Layout<K>>),
+    Grid {
+        columns: Vec<Size>,
+        rows: Vec<Size>,
+        cells: Vec<Cell<K>>,
+    },
+}
+
+/// A part of the screen, see the [module](self) docs.
+///
+/// Unless told otherwise, a layout grows to fill the space its parent has for it.
+pub struct Layout<K> {
+    kind: Kind<K>,
+    width: Size,
+    height: Size,
+    padding: Padding,
+    spacing: f32,
+    align: (Align, Align),
+}
+
+impl<K: Copy + PartialEq> Layout<K> {
+    fn new(kind: Kind<K>) -> Self {
+        Layout {
+            kind,
+            width: Size::flex(1.0),
+            height: Size::flex(1.0),
+            padding: Default::default(),
+            spacing: 0.0,
+            align: (Align::Start, Align::Start),
+        }
+    }
+
+    /// Something the view draws, found by `key` in the [Placement].
+    pub fn leaf(key: K) -> Self {
+        Self::new(Kind::Leaf(key))
+    }
+
+    /// Empty space, e.g. to push what follows it to the end of a row.
+    pub fn space() -> Self {
+        Self::new(Kind::Space)
+    }
+
+    /// Children from left to right, sharing the width by their [Layout::width]s.
+    pub fn row(children: Vec<Layout<K>>) -> Self {
+        Self::new(Kind::Flex(Axis::Horizontal, children))
+    }
+
+    /// Children from top to bottom, sharing the height by their [Layout::height]s.
+    pub fn column(children: Vec<Layout<K>>) -> Self {
+        Self::new(Kind::Flex(Axis::Vertical, children))
+    }
+
+    /// Children on top of each other, the first at the bottom.
+    pub fn stack(children: Vec<Layout<K>>) -> Self {
+        Self::new(Kind::Stack(children))
+    }
+
+    /// A grid whose columns and rows share the space by their sizes. Put children in
+    /// with [Layout::cell].
+    pub fn grid(columns: Vec<Size>, rows: Vec<Size>) -> Self {
+        Self::new(Kind::Grid {
+            columns,
+            rows,
+            cells: Vec::new(),
+        })
+    }
+
+    /// Puts `layout` into a grid's cell. Does nothing if this isn't a grid.
+    pub fn cell(self, column: usize, row: usize, layout: Layout<K>) -> Self {
+        self.span(column, row, 1, 1, layout)
+    }
+
+    /// Puts `layout` into a grid, across `columns` columns and `rows` rows from the cell
+    /// at `column`, `row`. Does nothing if this isn't a grid.
+    pub fn span(mut self, column: usize, row: usize, columns: usize, rows: usize, layout: Layout<K>) -> Self {
+        if let Kind::Grid { ref mut cells, .. } = self.kind {
+            cells.push(Cell {
+                column,
+                row,
+                columns,
+                rows,
+                layout,
+            });
+        }
+        self
+    }
+
+    pub fn width(self, width: Size) -> Self {
+        Layout { width, ..self }
+    }
+
+    pub fn height(self, height: Size) -> Self {
+        Layout { height, ..self }
+    }
+
+    pub fn padding(self, padding: Padding) -> Self {
+        Layout { padding, ..self }
+    }
+
+    /// The gap between the children of a row or column, or the cells of a grid.
+    pub fn spacing(self, spacing: f32) -> Self {
+        Layout { spacing, ..self }
+    }
+
+    /// Where the children go when they don't fill this layout, horizontally and
+    /// vertically.
+    pub fn align(self, horizontal: Align, vertical: Align) -> Self {
+        Layout {
+            align: (horizontal, vertical),
+            ..self
+        }
+    }
+
+    /// Fits the layout into `rect`, e.g. the whole window.
+    pub fn arrange(&self, rect: Rect) -> Placement<K> {
+        let mut placement = Placement { rects: Vec::new() };
+        self.place(rect, &mut placement);
+        placement
+    }
+
+    /// Where `child` goes within `rect` when it has it all to itself.
+    fn fit(&self, child: &Layout<K>, rect: Rect) -> Rect {
+        let size = (child.width.fit(rect.size.0), child.height.fit(rect.size.1));
+        let pos = (
+            rect.pos.0 + self.align.0.offset(rect.size.0 - size.0),
+            rect.pos.1 + self.align.1.offset(rect.size.1 - size.1),
+        );
+        Rect::new(pos, size)
+    }
+
+    fn place(&self, rect: Rect, placement: &mut Placement<K>) {
+        let inner = self.padding.shrink(rect);
+        match self.kind {
+            Kind::Space => {}
+            Kind::Leaf(key) => placement.rects.push((key, inner)),
+            Kind::Stack(ref children) => {
+                for child in children {
+                    child.place(self.fit(child, inner), placement);
+                }
+            }
+            Kind::Flex(axis, ref children) => self.place_flex(axis, children, inner, placement),
+            Kind::Grid {
+                ref columns,
+                ref rows,
+                ref cells,
+            } => {
+                let (xs, widths) = self.tracks(columns, inner.pos.0, inner.size.0, self.align.0);
+                let (ys, heights) = self.tracks(rows, inner.pos.1, inner.size.1, self.align.1);
+                for cell in cells {
+                    if cell.column >= xs.len() || cell.row >= ys.len() {
+                        continue;
+                    }
+                    let last_column = (cell.column + cell.columns.max(1)).min(xs.len()) - 1;
+                    let last_row = (cell.row + cell.rows.max(1)).min(ys.len()) - 1;
+                    let area = Rect::new(
+                        (xs[cell.column], ys[cell.row]),
+                        (
+                            xs[last_column] + widths[last_column] - xs[cell.column],
+                            ys[last_row] + heights[last_row] - ys[cell.row],
+                        ),
+                    );
+                    cell.layout.place(self.fit(&cell.layout, area), placement);
+                }
+            }
+        }
+    }
+
+    fn place_flex(&self, axis: Axis, children: &[Layout<K>], inner: Rect, placement: &mut Placement<K>) {
+        let (main_size, cross_size) = match axis {
+            Axis::Horizontal => (inner.size.0, inner.size.1),
+            Axis::Vertical => (inner.size.1, inner.size.0),
+        };
+        let (main_align, cross_align) = match axis {
+            Axis::Horizontal => (self.align.0, self.align.1),
+            Axis::Vertical => (self.align.1, self.align.0),
+        };
+        let sizes: Vec<Size> = children
+            .iter()
+            .map(|c| if axis == Axis::Horizontal { c.width } else { c.height })
+            .collect();
+        let gaps = self.spacing * children.len().saturating_sub(1) as f32;
+        let lengths = distribute(&sizes, main_size - gaps);
+
+        let mut main = main_align.offset(main_size - gaps - lengths.iter().sum::<f32>());
+        for (child, &length) in children.iter().zip(&lengths) {
+            let cross_length = if axis == Axis::Horizontal {
+                child.height.fit(cross_size)
+            } else {
+                child.width.fit(cross_size)
+            };
+            let cross = cross_align.offset(cross_size - cross_length);
+            let rect = match axis {
+                Axis::Horizontal => Rect::new((inner.pos.0 + main, inner.pos.1 + cross), (length, cross_length)),
+                Axis::Vertical => Rect::new((inner.pos.0 + cross, inner.pos.1 + main), (cross_length, length)),
+            };
+            child.place(rect, placement);
+            main += length + self.spacing;
+        }
+    }
+
+    /// Where the columns or rows of a grid start and how long they are.
+    fn tracks(&self, sizes: &[Size], start: f32, available: f32, align: Align) -> (Vec<f32>, Vec<f32>) {
+        let gaps = self.spacing * sizes.len().saturating_sub(1) as f32;
+        let lengths = distribute(sizes, available - gaps);
+        let mut pos = start + align.offset(available - gaps - lengths.iter().sum::<f32>());
+        let mut starts = Vec::with_capacity(lengths.len());
+        for length in lengths.iter() {
+            starts.push(pos);
+            pos += length + self.spacing;
+        }
+        (starts, lengths)
+    }
+}
+
+/// Where the leaves of a [Layout] ended up.
+pub struct Placement<K> {
+    rects: Vec<(K, Rect)>,
+}
+
+impl<K: PartialEq> Placement<K> {
+    /// The rectangle of the leaf with `key`, if there is one.
+    pub fn get(&self, key: &K) -> Option<Rect> {
+        self.rects.iter().find(|(k, _)| k == key).map(|&(_, rect)| rect)
+    }
+
+    /// The rectangle of the leaf with `key`.
+    ///
+    /// # Panics
+    ///
+    /// If there's no leaf with `key` in the layout.
+    pub fn rect(&self, key: &K) -> Rect {
+        self.get(key).expect("No layout leaf with that key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect::new((x, y), (width, height))
+    }
+
+    #[test]
+    fn fixed_sizes() {
+        let sizes = [Size::fixed(30.0), Size::fixed(50.0), Size::fixed(30.0).min(40.0), Size::fixed(80.0).max(60.0)];
+        assert_eq!(distribute(&sizes, 500.0), vec![30.0, 50.0, 40.0, 60.0]);
+        // Not squeezed either, it just doesn't fit
+        assert_eq!(distribute(&sizes, 100.0), vec![30.0, 50.0, 40.0, 60.0]);
+    }
+
+    #[test]
+    fn weighted_sizes() {
+        let sizes = [Size::fixed(40.0), Size::flex(1.0), Size::flex(3.0)];
+        assert_eq!(distribute(&sizes, 200.0), vec![40.0, 40.0, 120.0]);
+        assert_eq!(distribute(&sizes, 20.0), vec![40.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn min_and_max_sizes() {
+        // The limited ones keep their limit, the rest goes to the others
+        let sizes = [Size::flex(1.0).max(20.0), Size::flex(1.0), Size::flex(2.0).min(150.0)];
+        assert_eq!(distribute(&sizes, 200.0), vec![20.0, 30.0, 150.0]);
+        let sizes = [Size::flex(1.0).min(100.0), Size::flex(1.0).min(100.0)];
+        assert_eq!(distribute(&sizes, 150.0), vec![100.0, 100.0]);
+    }
+
+    #[test]
+    fn row_with_padding_and_spacing() {
+        let placement = Layout::row(vec![
+            Layout::leaf('a').width(Size::fixed(50.0)),
+            Layout::space().width(Size::fixed(20.0)),
+            Layout::leaf('b'),
+        ])
+        .spacing(10.0)
+        .padding(Padding::symmetric(5.0, 10.0))
+        .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'a'), rect(5.0, 10.0, 50.0, 80.0));
+        assert_eq!(placement.rect(&'b'), rect(95.0, 10.0, 100.0, 80.0));
+        assert_eq!(placement.get(&'c'), None);
+    }
+
+    #[test]
+    fn aligned_column() {
+        let placement = Layout::column(vec![
+            Layout::leaf('a').width(Size::fixed(30.0)).height(Size::fixed(20.0)),
+            Layout::leaf('b').height(Size::fixed(10.0)),
+        ])
+        .align(Align::End, Align::End)
+        .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'a'), rect(170.0, 70.0, 30.0, 20.0));
+        assert_eq!(placement.rect(&'b'), rect(0.0, 90.0, 200.0, 10.0));
+    }
+
+    #[test]
+    fn centered_row() {
+        let placement = Layout::row(vec![
+            Layout::leaf('a').width(Size::fixed(40.0)).height(Size::fixed(20.0)),
+            Layout::leaf('b').width(Size::fixed(60.0)),
+        ])
+        .spacing(20.0)
+        .padding(Padding::all(10.0))
+        .align(Align::Center, Align::Center)
+        .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'a'), rect(40.0, 40.0, 40.0, 20.0));
+        assert_eq!(placement.rect(&'b'), rect(100.0, 10.0, 60.0, 80.0));
+    }
+
+    #[test]
+    fn stack() {
+        let placement = Layout::stack(vec![
+            Layout::leaf('a'),
+            Layout::leaf('b').padding(Padding::all(10.0)),
+            Layout::leaf('c').width(Size::fixed(50.0)).height(Size::fixed(20.0)),
+        ])
+        .align(Align::Center, Align::End)
+        .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        // All get the same space, unless they don't fill it
+        assert_eq!(placement.rect(&'a'), rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'b'), rect(10.0, 10.0, 180.0, 80.0));
+        assert_eq!(placement.rect(&'c'), rect(75.0, 80.0, 50.0, 20.0));
+    }
+
+    #[test]
+    fn grid() {
+        let placement = Layout::grid(
+            vec![Size::fixed(50.0), Size::flex(1.0)],
+            vec![Size::fixed(20.0), Size::flex(1.0)],
+        )
+        .spacing(10.0)
+        .cell(0, 0, Layout::leaf('a'))
+        .cell(1, 0, Layout::leaf('b').width(Size::fixed(30.0)))
+        .span(0, 1, 2, 1, Layout::leaf('c'))
+        // Outside the grid
+        .cell(2, 0, Layout::leaf('d'))
+        .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'a'), rect(0.0, 0.0, 50.0, 20.0));
+        assert_eq!(placement.rect(&'b'), rect(60.0, 0.0, 30.0, 20.0));
+        assert_eq!(placement.rect(&'c'), rect(0.0, 30.0, 200.0, 70.0));
+        assert_eq!(placement.get(&'d'), None);
+    }
+
+    #[test]
+    fn grid_alignment() {
+        // The columns don't fill the width, so they go to the end of it
+        let placement = Layout::grid(vec![Size::fixed(40.0), Size::fixed(40.0)], vec![Size::fixed(20.0)])
+            .spacing(10.0)
+            .align(Align::End, Align::Start)
+            .cell(0, 0, Layout::leaf('a'))
+            .cell(1, 0, Layout::leaf('b'))
+            .arrange(rect(0.0, 0.0, 200.0, 100.0));
+        assert_eq!(placement.rect(&'a'), rect(110.0, 0.0, 40.0, 20.0));
+        assert_eq!(placement.rect(&'b'), rect(160.0, 0.0, 40.0, 20.0));
+    }
+}
@@ -1,26 +1,47 @@
+pub mod layout;
 pub mod views;
+pub mod widgets;
 
-use input::KeyAction;
-use render::RenderContext;
+use app::{Action, App, Screen};
+use draw::Canvas;
+use input::{KeyAction, MouseAction};
 
+/// Views draw the state of the [App] and turn input into [Action]s. What they keep
+/// themselves is only what's on screen, like the text typed into a form.
 pub trait View {
-    fn present(&mut self, ctx: &RenderContext);
-    fn on_char_input(&mut self, _c: char) {}
-    fn on_key_input(&mut self, _k: KeyAction) {}
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas);
+    fn on_char_input(&mut self, _app: &App, _c: char, _actions: &mut Vec<Action>) {}
+    fn on_key_input(&mut self, _app: &App, _k: KeyAction, _actions: &mut Vec<Action>) {}
+    fn on_mouse_input(&mut self, _app: &App, _m: MouseAction, _actions: &mut Vec<Action>) {}
 }
 
-pub enum DynamicView<'a> {
-    MainLoading(views::MainLoadingView<'a>),
-    Main(views::MainView<'a>),
-    Login(views::LoginView<'a>),
+pub enum DynamicView {
+    MainLoading(views::MainLoadingView),
+    Main(views::MainView),
+    Login(views::LoginView),
+    Project(views::ProjectView),
+    Profile(views::ProfileView),
 }
 
-impl<'a> DynamicView<'a> {
+impl DynamicView {
+    /// A fresh view for `screen`.
+    pub fn new(screen: Screen, app: &App) -> Self {
+        match screen {
+            Screen::Loading => DynamicView::MainLoading(views::MainLoadingView),
+            Screen::Login => DynamicView::Login(views::LoginView::new(app)),
+            Screen::Main => DynamicView::Main(views::MainView::default()),
+            Screen::Profile => DynamicView::Profile(views::ProfileView),
+            Screen::Project => DynamicView::Project(views::ProjectView::default()),
+        }
+    }
+
     pub fn view(&mut self) -> &mut dyn View {
         match self {
             DynamicView::MainLoading(v) => v,
             DynamicView::Main(v) => v,
             DynamicView::Login(v) => v,
+            DynamicView::Project(v) => v,
+            DynamicView::Profile(v) => v,
         }
     }
 }
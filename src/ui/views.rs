@@ -1,406 +1,1172 @@
-use nanovg::{Alignment, Color, TextOptions};
 use proto;
+use proto::project::{ObjectId, Operation, TrackProp};
 use sha3::{Digest, Sha3_256};
 
-use input::{InputString, KeyAction, KeyCode, KeyMod};
-use render::{Fonts, RenderContext};
+use app::{Action, ActivityFeed, App};
+use client::net::NetState;
+use draw::{Alignment, Canvas, Color, Fonts, Rect, Shape, TextStyle};
+use input::{KeyAction, KeyCode, KeyMod, MouseAction};
+use ui::layout::{Align, Layout, Padding, Size};
+use ui::widgets::{self, Button, Checkbox, Event, Knob, Label, List, PasswordInput, Slider, TextInput, Widget};
 
-use std::cell::RefCell;
-use std::time::{Duration, Instant};
+/// The whole window, to lay a view out in.
+fn window(canvas: &dyn Canvas) -> Rect {
+    Rect::new((0.0, 0.0), canvas.size())
+}
+
+/// The heading at the top of a screen.
+fn title(canvas: &mut dyn Canvas, rect: Rect, text: &str) {
+    canvas.text_in(
+        Fonts::Moderno,
+        rect,
+        text,
+        TextStyle {
+            align: Alignment::new().left().middle(),
+            size: 40.0,
+            color: Color::from_rgb(255, 255, 255),
+        },
+    );
+}
+
+pub struct MainLoadingView;
 
-pub struct MainLoadingView<'a> {
-    pub cur_load_task: &'a RefCell<String>,
+#[derive(Copy, Clone, PartialEq)]
+enum LoadingPart {
+    Title,
+    Task,
+    Spinner,
+}
+
+impl super::View for MainLoadingView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let placement = Layout::column(vec![
+            Layout::space(),
+            Layout::leaf(LoadingPart::Title).height(Size::fixed(60.0)),
+            Layout::leaf(LoadingPart::Task).height(Size::fixed(40.0)),
+            Layout::space(),
+            Layout::leaf(LoadingPart::Spinner).height(Size::fixed(60.0)),
+            Layout::space(),
+        ])
+        .spacing(20.0)
+        .arrange(window(canvas));
+
+        canvas.fill(
+            &[Shape::Circle {
+                center: placement.rect(&LoadingPart::Spinner).center(),
+                radius: 30.0,
+            }],
+            Color::from_rgb(200, 100, 0),
+        );
+        canvas.text_in(
+            Fonts::Moderno,
+            placement.rect(&LoadingPart::Title),
+            "Chorus Studio",
+            TextStyle {
+                align: Alignment::new().center().middle(),
+                size: 60.0,
+                color: Color::from_rgb(255, 255, 255),
+            },
+        );
+        canvas.text_in(
+            Fonts::Vga8,
+            placement.rect(&LoadingPart::Task),
+            app.load_task(),
+            TextStyle {
+                align: Alignment::new().center().middle(),
+                size: 28.0,
+                color: Color::from_rgb(200, 200, 200),
+            },
+        );
+    }
+}
+
+/// The state of the connection to the server, drawn in the top right corner on top
+/// of the current view.
+pub struct NetStatusView;
+
+#[derive(Copy, Clone, PartialEq)]
+enum StatusPart {
+    Light,
+    Text,
+}
+
+impl super::View for NetStatusView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let (text, color) = match *app.net_state() {
+            NetState::Connecting => ("Connecting".to_owned(), Color::from_rgb(255, 200, 80)),
+            NetState::Connected => ("Connected".to_owned(), Color::from_rgb(255, 200, 80)),
+            NetState::Authenticating => ("Logging in".to_owned(), Color::from_rgb(255, 200, 80)),
+            NetState::Online => ("Online".to_owned(), Color::from_rgb(80, 220, 80)),
+            NetState::Disconnected(_) => ("Offline".to_owned(), Color::from_rgb(230, 60, 60)),
+            NetState::Reconnecting(attempt) => (format!("Reconnecting ({})", attempt), Color::from_rgb(255, 200, 80)),
+        };
+        let style = TextStyle {
+            align: Alignment::new().left().middle(),
+            size: 16.0,
+            color: Color::from_rgb(255, 255, 255),
+        };
+        let (width, _) = canvas.text_bounds(Fonts::Inter, (0.0, 0.0), &text, style);
+        let placement = Layout::column(vec![
+            Layout::row(vec![
+                Layout::leaf(StatusPart::Light).width(Size::fixed(20.0)),
+                Layout::leaf(StatusPart::Text).width(Size::fixed(width)),
+            ])
+            .align(Align::End, Align::Start)
+            .height(Size::fixed(20.0)),
+            Layout::space(),
+        ])
+        .padding(Padding::symmetric(10.0, 5.0))
+        .arrange(window(canvas));
+
+        canvas.fill(
+            &[Shape::Circle {
+                center: placement.rect(&StatusPart::Light).center(),
+                radius: 5.0,
+            }],
+            color,
+        );
+        canvas.text_in(Fonts::Inter, placement.rect(&StatusPart::Text), &text, style);
+    }
 }
 
-impl<'a> super::View for MainLoadingView<'a> {
-    fn present(&mut self, ctx: &RenderContext) {
-        let (w, h) = ctx.size();
-        ctx.frame(|f| {
-            f.path(
-                |p| {
-                    let radius = 30.0;
-                    p.circle((w / 2.0, h / 2.0), radius);
-                    p.fill(Color::from_rgb(200, 100, 0), Default::default());
+/// Messages from the server and about what the user did, drawn along the bottom edge
+/// on top of the current view.
+pub struct NoticeView;
+
+#[derive(Copy, Clone, PartialEq)]
+enum NoticePart {
+    Background(usize),
+    Text(usize),
+}
+
+impl super::View for NoticeView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let notices = app.notices();
+        if notices.is_empty() {
+            return;
+        }
+        let mut lines: Vec<_> = (0..notices.len())
+            .map(|i| {
+                Layout::stack(vec![
+                    Layout::leaf(NoticePart::Background(i)),
+                    Layout::leaf(NoticePart::Text(i)).padding(Padding::symmetric(10.0, 0.0)),
+                ])
+                .width(Size::flex(1.0).max(900.0))
+                .height(Size::fixed(30.0))
+            })
+            .collect();
+        lines.insert(0, Layout::space());
+        let placement = Layout::column(lines)
+            .spacing(5.0)
+            .padding(Padding::all(10.0))
+            .align(Align::Center, Align::Start)
+            .arrange(window(canvas));
+
+        for (i, notice) in notices.iter().enumerate() {
+            let rect = placement.rect(&NoticePart::Background(i));
+            let background = if notice.from_server {
+                Color::from_rgb(200, 100, 0)
+            } else {
+                Color::from_rgb(60, 60, 70)
+            };
+            canvas.fill(&[Shape::RoundedRect { pos: rect.pos, size: rect.size, radius: 4.0 }], background);
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&NoticePart::Text(i)),
+                &notice.text,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color: Color::from_rgb(255, 255, 255),
                 },
-                Default::default(),
             );
-            // Chorus Studio
-            {
-                let size = 60.0;
-                let text = "Chorus Studio";
-                let font = ctx.font(Fonts::Moderno);
-                let (_, bounds) = f.text_bounds(
-                    font,
-                    (0.0, 0.0),
-                    text,
-                    TextOptions {
-                        size: size,
-                        ..Default::default()
-                    },
-                );
-                let (width, height) = (bounds.max_x, bounds.max_y);
-                f.text(
-                    font,
-                    ((w - width) / 2.0, (h - height) / 8.0),
-                    text,
-                    TextOptions {
-                        size: size,
-                        color: Color::from_rgb(255, 255, 255),
-                        ..Default::default()
-                    },
-                );
-            }
-            // loading
-            {
-                let size = 28.0;
-                let font = ctx.font(Fonts::Vga8);
-                let (_, bounds) = f.text_bounds(
-                    font,
-                    (0.0, 0.0),
-                    &*self.cur_load_task.borrow(),
-                    TextOptions {
-                        size: size,
-                        ..Default::default()
-                    },
-                );
-                let (width, height) = (bounds.max_x, bounds.max_y);
-                f.text(
-                    font,
-                    ((w - width) / 2.0, (h - height) / 4.0),
-                    &*self.cur_load_task.borrow(),
-                    TextOptions {
-                        size: size,
-                        color: Color::from_rgb(200, 200, 200),
-                        ..Default::default()
+        }
+    }
+}
+
+/// The users who are online next to the user's projects. Left and Right switch between
+/// the lists, Return opens the highlighted user's profile or joins the highlighted project.
+#[derive(Default)]
+pub struct MainView {
+    /// Index of the highlighted user
+    selected: usize,
+    /// Index of the highlighted project
+    selected_project: usize,
+    /// Whether the project list has the keyboard
+    in_projects: bool,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum MainPart {
+    Title,
+    Name(usize),
+    Activity(usize),
+    ProjectsHeading,
+    Project(usize),
+}
+
+impl MainView {
+    fn highlight(highlighted: bool) -> Color {
+        if highlighted {
+            Color::from_rgb(255, 200, 80)
+        } else {
+            Color::from_rgb(255, 255, 255)
+        }
+    }
+}
+
+impl super::View for MainView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let users = app.users();
+        let projects = app.projects();
+        let mut list = Layout::grid(
+            vec![Size::flex(1.0).min(150.0).max(300.0), Size::flex(2.0)],
+            vec![Size::fixed(24.0); users.len()],
+        )
+        .spacing(10.0);
+        for i in 0..users.len() {
+            list = list
+                .cell(0, i, Layout::leaf(MainPart::Name(i)))
+                .cell(1, i, Layout::leaf(MainPart::Activity(i)));
+        }
+        let mut project_list = vec![Layout::leaf(MainPart::ProjectsHeading).height(Size::fixed(30.0))];
+        project_list.extend((0..projects.len()).map(|i| Layout::leaf(MainPart::Project(i)).height(Size::fixed(24.0))));
+        project_list.push(Layout::space());
+        let placement = Layout::column(vec![
+            Layout::leaf(MainPart::Title).height(Size::fixed(45.0)),
+            Layout::row(vec![
+                Layout::column(vec![list, Layout::space()]).width(Size::flex(2.0)),
+                Layout::column(project_list).spacing(4.0).width(Size::flex(1.0).min(200.0).max(400.0)),
+            ])
+            .spacing(20.0),
+        ])
+        .padding(Padding::symmetric(10.0, 5.0))
+        .arrange(window(canvas));
+
+        title(canvas, placement.rect(&MainPart::Title), "Chorus Studio");
+        for (i, user) in users.iter().enumerate() {
+            canvas.text_in(
+                Fonts::Vga8,
+                placement.rect(&MainPart::Name(i)),
+                &user.user_name,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 24.0,
+                    color: Self::highlight(!self.in_projects && i == self.selected),
+                },
+            );
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&MainPart::Activity(i)),
+                match user.activity {
+                    proto::UserActivity::Offline => "offline",
+                    proto::UserActivity::Away => "away",
+                    proto::UserActivity::Active => "online",
+                    proto::UserActivity::InProject(ref prj) => prj,
+                },
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color: if let proto::UserActivity::InProject(_) = user.activity {
+                        Color::from_rgb(200, 155, 200)
+                    } else {
+                        Color::from_rgb(155, 155, 155)
                     },
-                );
-            }
-        });
+                },
+            );
+        }
+
+        canvas.text_in(
+            Fonts::Vga8,
+            placement.rect(&MainPart::ProjectsHeading),
+            if projects.is_empty() { "No projects" } else { "Projects" },
+            TextStyle {
+                align: Alignment::new().left().middle(),
+                size: 24.0,
+                color: Color::from_rgb(155, 155, 155),
+            },
+        );
+        for (i, project) in projects.iter().enumerate() {
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&MainPart::Project(i)),
+                &project.title,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 20.0,
+                    color: Self::highlight(self.in_projects && i == self.selected_project),
+                },
+            );
+        }
+    }
+
+    fn on_key_input(&mut self, app: &App, key: KeyAction, actions: &mut Vec<Action>) {
+        if key.was_pressed_once(KeyCode::Left) {
+            self.in_projects = false;
+        } else if key.was_pressed_once(KeyCode::Right) {
+            self.in_projects = true;
+        }
+
+        let (len, selected) = if self.in_projects {
+            (app.projects().len(), &mut self.selected_project)
+        } else {
+            (app.users().len(), &mut self.selected)
+        };
+        if len == 0 {
+            return;
+        }
+        *selected = (*selected).min(len - 1);
+
+        if key.was_pressed(KeyCode::Up) {
+            *selected = selected.saturating_sub(1);
+        } else if key.was_pressed(KeyCode::Down) {
+            *selected = (*selected + 1).min(len - 1);
+        } else if key.was_pressed_once(KeyCode::Return) {
+            actions.push(if self.in_projects {
+                Action::OpenProject {
+                    project_id: app.projects()[*selected].id,
+                }
+            } else {
+                Action::OpenProfile {
+                    user_name: app.users()[*selected].user_name.clone(),
+                }
+            });
+        }
     }
 }
 
-pub struct MainView<'a> {
-    pub user_list: &'a RefCell<Vec<proto::User>>,
+/// Shows a user's profile. The own profile also shows, and lets the user toggle with
+/// the number keys, which fields other users can see.
+pub struct ProfileView;
+
+#[derive(Copy, Clone, PartialEq)]
+enum ProfilePart {
+    Title,
+    Label(usize),
+    Value(usize),
 }
 
-impl<'a> super::View for MainView<'a> {
-    fn present(&mut self, ctx: &RenderContext) {
-        ctx.frame(|f| {
-            let mut cur_y = 50.0;
-            let size = 24.0;
-
-            f.text(
-                ctx.font(Fonts::Moderno),
-                (5.0, 5.0),
-                "Chorus Studio",
-                TextOptions {
-                    size: 40.0,
+impl super::View for ProfileView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let profile = match app.profile() {
+            Some(profile) => profile,
+            None => return,
+        };
+
+        let avatar = profile.avatar.map(|hash| proto::asset::hash_hex(&hash)[..16].to_owned());
+        let visibility = profile.visibility;
+        let fields = [
+            ("Real name", &profile.real_name, visibility.map(|v| v.real_name)),
+            ("Born", &profile.birth_date, visibility.map(|v| v.birth_date)),
+            ("Member since", &profile.register_date, visibility.map(|v| v.register_date)),
+            ("About", &profile.bio, visibility.map(|v| v.bio)),
+            ("Avatar", &avatar, visibility.map(|v| v.avatar)),
+        ];
+
+        let mut grid = Layout::grid(
+            vec![Size::flex(1.0).min(200.0).max(260.0), Size::flex(3.0)],
+            vec![Size::fixed(24.0); fields.len()],
+        )
+        .spacing(10.0);
+        for i in 0..fields.len() {
+            grid = grid
+                .cell(0, i, Layout::leaf(ProfilePart::Label(i)))
+                .cell(1, i, Layout::leaf(ProfilePart::Value(i)));
+        }
+        let placement = Layout::column(vec![Layout::leaf(ProfilePart::Title).height(Size::fixed(55.0)), grid])
+            .padding(Padding::symmetric(10.0, 5.0))
+            .arrange(window(canvas));
+
+        title(canvas, placement.rect(&ProfilePart::Title), &profile.user_name);
+        for (i, &(label, value, public)) in fields.iter().enumerate() {
+            let label = match public {
+                Some(true) => format!("{}. {} (public)", i + 1, label),
+                Some(false) => format!("{}. {} (private)", i + 1, label),
+                None => label.to_owned(),
+            };
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&ProfilePart::Label(i)),
+                &label,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color: Color::from_rgb(155, 155, 155),
+                },
+            );
+            canvas.text_in(
+                Fonts::Vga8,
+                placement.rect(&ProfilePart::Value(i)),
+                value.as_ref().map_or("-", |v| v.as_str()),
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 24.0,
                     color: Color::from_rgb(255, 255, 255),
-                    ..Default::default()
                 },
             );
+        }
+    }
 
-            for user in self.user_list.borrow().iter() {
-                f.text(
-                    ctx.font(Fonts::Vga8),
-                    (10.0, cur_y),
-                    &user.user_name,
-                    TextOptions {
-                        size: size,
-                        color: Color::from_rgb(255, 255, 255),
-                        ..Default::default()
-                    },
-                );
-                f.text(
-                    ctx.font(Fonts::Inter),
-                    (200.0, cur_y + 7.0),
-                    match user.activity {
-                        proto::UserActivity::Offline => "offline",
-                        proto::UserActivity::Away => "away",
-                        proto::UserActivity::Active => "online",
-                        proto::UserActivity::InProject(ref prj) => prj,
-                    },
-                    TextOptions {
-                        size: 16.0,
-                        color: if let proto::UserActivity::InProject(_) = user.activity {
-                            Color::from_rgb(200, 155, 200)
-                        } else {
-                            Color::from_rgb(155, 155, 155)
-                        },
-                        ..Default::default()
-                    },
-                );
-                cur_y += size;
-            }
-        });
+    fn on_key_input(&mut self, app: &App, key: KeyAction, actions: &mut Vec<Action>) {
+        if key.was_pressed_once(KeyCode::Escape) {
+            actions.push(Action::CloseProfile);
+            return;
+        }
+
+        let (profile, mut visibility) = match app.profile() {
+            Some(profile) => match profile.visibility {
+                Some(visibility) => (profile, visibility),
+                // Not the own profile
+                None => return,
+            },
+            None => return,
+        };
+        {
+            let flag = if key.was_pressed_once(KeyCode::Num1) {
+                &mut visibility.real_name
+            } else if key.was_pressed_once(KeyCode::Num2) {
+                &mut visibility.birth_date
+            } else if key.was_pressed_once(KeyCode::Num3) {
+                &mut visibility.register_date
+            } else if key.was_pressed_once(KeyCode::Num4) {
+                &mut visibility.bio
+            } else if key.was_pressed_once(KeyCode::Num5) {
+                &mut visibility.avatar
+            } else {
+                return;
+            };
+            *flag = !*flag;
+        }
+        actions.push(Action::Send(proto::Command::UpdateProfile(proto::ProfileUpdate {
+            real_name: profile.real_name.clone(),
+            birth_date: profile.birth_date.clone(),
+            bio: profile.bio.clone(),
+            avatar: profile.avatar,
+            visibility,
+        })));
     }
 }
 
-pub struct LoginView<'a> {
-    username_input: InputString,
-    password_input: InputString,
-    username_cursor: usize,
-    password_cursor: usize,
-    active_input: LoginViewActiveInput,
-    on_submit: Box<dyn FnMut(&str, &[u8]) + 'a>,
-    invalid_timer_start: Option<Instant>,
-    invalid_attempts: usize,
+/// The login form: email, password, the button and the server to log in to.
+pub struct LoginView {
+    title: Label,
+    error: Label,
+    email: TextInput,
+    password: PasswordInput,
+    login: Button,
+    server_label: Label,
+    servers: List,
 }
 
-#[derive(PartialEq, Eq, Copy, Clone)]
-enum LoginViewActiveInput {
-    Username,
+#[derive(Copy, Clone, PartialEq)]
+enum LoginPart {
+    Title,
+    Email,
     Password,
+    Login,
+    Error,
+    ServerLabel,
+    Servers,
 }
 
-impl<'a> LoginView<'a> {
-    pub fn new(on_submit: Box<dyn FnMut(&str, &[u8]) + 'a>) -> Self {
-        LoginView {
-            username_input: InputString::new(),
-            password_input: InputString::new(),
-            username_cursor: 0,
-            password_cursor: 0,
-            active_input: LoginViewActiveInput::Username,
-            on_submit,
-            invalid_timer_start: None,
-            invalid_attempts: 0,
-        }
+impl LoginView {
+    // Tab order of the interactive widgets, see [LoginView::widgets]
+    const EMAIL: usize = 0;
+    const PASSWORD: usize = 1;
+    const LOGIN: usize = 2;
+    const SERVERS: usize = 3;
+
+    /// The last email used is filled in, the servers in the settings can be chosen from.
+    pub fn new(app: &App) -> Self {
+        let settings = &app.settings;
+        let email = TextInput::new("Email").with_text(settings.last_email.as_ref().map_or("", |e| e.as_str()));
+        let mut view = LoginView {
+            title: Label::new(
+                "Login",
+                Fonts::Moderno,
+                TextStyle {
+                    align: Alignment::new().center().middle(),
+                    size: 60.0,
+                    color: Color::from_rgb(255, 255, 255),
+                },
+            ),
+            error: Label::new(
+                "Your username and / or password is wrong. Please double check.",
+                Fonts::Inter,
+                TextStyle {
+                    align: Alignment::new().center().middle(),
+                    size: 14.0,
+                    color: Color::from_rgb(255, 150, 150),
+                },
+            ),
+            email,
+            password: PasswordInput::new("Password"),
+            login: Button::new("Log in"),
+            server_label: Label::new(
+                "Server",
+                Fonts::Inter,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color: Color::from_rgb(200, 200, 200),
+                },
+            ),
+            servers: List::new(settings.server_choices(), settings.server_index()),
+        };
+        let first = if view.email.is_empty() { Self::EMAIL } else { Self::PASSWORD };
+        widgets::focus(&mut view.widgets(), first);
+        view
     }
 
-    pub fn invalid_login(&mut self) {
-        self.invalid_timer_start = Some(Instant::now());
-        self.invalid_attempts += 1;
+    fn widgets(&mut self) -> [&mut dyn Widget; 4] {
+        [&mut self.email, &mut self.password, &mut self.login, &mut self.servers]
     }
 
-    fn active_input_data(&mut self) -> (&mut InputString, &mut usize) {
-        match self.active_input {
-            LoginViewActiveInput::Username => (&mut self.username_input, &mut self.username_cursor),
-            LoginViewActiveInput::Password => (&mut self.password_input, &mut self.password_cursor),
+    fn handle(&mut self, event: Option<(usize, Event)>, actions: &mut Vec<Action>) {
+        match event {
+            Some((Self::EMAIL, Event::Activated)) => widgets::focus(&mut self.widgets(), Self::PASSWORD),
+            Some((Self::PASSWORD, Event::Activated)) | Some((Self::LOGIN, Event::Activated)) => {
+                actions.push(Action::Login {
+                    email: self.email.text().to_owned(),
+                    password: Sha3_256::digest(self.password.text().as_bytes()).as_slice().to_vec(),
+                });
+            }
+            Some((Self::SERVERS, Event::Changed)) => {
+                if let Some(server) = self.servers.selected_item() {
+                    actions.push(Action::SelectServer(server.to_owned()));
+                }
+            }
+            _ => {}
         }
     }
+}
+
+impl super::View for LoginView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let input = Size::fixed(40.0);
+        let list_rows = self.servers.items().len().min(4) as f32;
+        let form = vec![
+            Layout::leaf(LoginPart::Email).height(input),
+            Layout::leaf(LoginPart::Password).height(input),
+            Layout::leaf(LoginPart::Login).height(input),
+            Layout::leaf(LoginPart::Error).height(Size::fixed(20.0)),
+            Layout::leaf(LoginPart::ServerLabel).height(Size::fixed(20.0)),
+            Layout::leaf(LoginPart::Servers).height(Size::fixed(list_rows * self.servers.row_height)),
+        ];
+        let spacing = 20.0;
+        let form_height = 3.0 * 40.0 + 2.0 * 20.0 + list_rows * self.servers.row_height + 5.0 * spacing;
+        let placement = Layout::column(vec![
+            Layout::space(),
+            Layout::leaf(LoginPart::Title).height(Size::fixed(60.0)),
+            Layout::space(),
+            Layout::row(vec![
+                Layout::space(),
+                Layout::column(form).spacing(spacing).width(Size::flex(1.0).min(280.0).max(600.0)),
+                Layout::space(),
+            ])
+            .height(Size::fixed(form_height)),
+            Layout::space(),
+        ])
+        .spacing(20.0)
+        .arrange(window(canvas));
 
-    fn active_input_str(&self) -> (&str, usize) {
-        match self.active_input {
-            LoginViewActiveInput::Username => (&self.username_input.as_str(), self.username_cursor),
-            LoginViewActiveInput::Password => (self.password_stars(), self.password_cursor),
+        self.title.present(canvas, placement.rect(&LoginPart::Title));
+        self.email.set_error(app.login_just_failed());
+        self.password.set_error(app.login_just_failed());
+        self.email.present(canvas, placement.rect(&LoginPart::Email));
+        self.password.present(canvas, placement.rect(&LoginPart::Password));
+        self.login.present(canvas, placement.rect(&LoginPart::Login));
+        if app.failed_logins() > 0 {
+            self.error.present(canvas, placement.rect(&LoginPart::Error));
         }
+        if !self.servers.items().is_empty() {
+            self.server_label.present(canvas, placement.rect(&LoginPart::ServerLabel));
+            self.servers.present(canvas, placement.rect(&LoginPart::Servers));
+        }
+    }
+
+    fn on_char_input(&mut self, _app: &App, c: char, actions: &mut Vec<Action>) {
+        let event = widgets::on_char_input(&mut self.widgets(), c);
+        self.handle(event, actions);
     }
 
-    fn password_stars(&self) -> &str {
-        const STARS: &str =
-            "*************************************************************************************";
-        &STARS[0..self.password_input.len().min(STARS.len())]
+    fn on_key_input(&mut self, _app: &App, key: KeyAction, actions: &mut Vec<Action>) {
+        // The servers can be switched from anywhere in the form, as there's only ever a few
+        let event = if key.was_pressed(KeyCode::Up) || key.was_pressed(KeyCode::Down) {
+            self.servers.on_key_input(key).map(|e| (Self::SERVERS, e))
+        } else {
+            widgets::on_key_input(&mut self.widgets(), key)
+        };
+        self.handle(event, actions);
+    }
+
+    fn on_mouse_input(&mut self, _app: &App, mouse: MouseAction, actions: &mut Vec<Action>) {
+        let event = widgets::on_mouse_input(&mut self.widgets(), mouse);
+        self.handle(event, actions);
     }
 }
 
-impl<'a> super::View for LoginView<'a> {
-    fn present(&mut self, ctx: &RenderContext) {
-        if let Some(inst) = self.invalid_timer_start.clone() {
-            if inst.elapsed() > Duration::from_secs(3) {
-                self.invalid_timer_start = None;
-            }
+/// A project's tracks with their devices, next to what happened in it lately. Up and
+/// Down select a track to work on, the keys listed under the tracks or the controls
+/// below them edit it.
+pub struct ProjectView {
+    muted: Checkbox,
+    volume: Slider,
+    pan: Knob,
+}
+
+/// Volume change per key press
+const VOLUME_STEP: f32 = 0.05;
+/// Pan change per key press or wheel step on the knob
+const PAN_STEP: f32 = 0.1;
+
+#[derive(Copy, Clone, PartialEq)]
+enum ProjectPart {
+    Title,
+    Track(usize),
+    Clips(usize),
+    TrackLock(usize),
+    Device(usize, usize),
+    DeviceLock(usize, usize),
+    Note(usize),
+    Muted,
+    VolumeLabel,
+    Volume,
+    PanLabel,
+    Pan,
+    Keys,
+    Activity,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ActivityPart {
+    Heading,
+    Entries,
+    More,
+    Author(usize),
+    Summary(usize),
+}
+
+impl Default for ProjectView {
+    fn default() -> Self {
+        ProjectView {
+            muted: Checkbox::new("Muted", false),
+            volume: Slider::new(0.0, 1.0, VOLUME_STEP, 1.0),
+            pan: Knob::new(-1.0, 1.0, PAN_STEP, 0.0),
         }
+    }
+}
 
-        let (w, h) = ctx.size();
-        ctx.frame(|f| {
-            // "Login"
-            f.text(
-                ctx.font(Fonts::Moderno),
-                (w / 2.0, h / 4.0),
-                "Login",
-                TextOptions {
-                    align: Alignment::new().center().middle(),
-                    size: 60.0,
-                    color: Color::from_rgb(255, 255, 255),
-                    ..Default::default()
+impl ProjectView {
+    // Order of the track controls, see [ProjectView::widgets]
+    const MUTED: usize = 0;
+    const VOLUME: usize = 1;
+    const PAN: usize = 2;
+
+    fn widgets(&mut self) -> [&mut dyn Widget; 3] {
+        [&mut self.muted, &mut self.volume, &mut self.pan]
+    }
+
+    /// Turns what the controls did into an edit of `track`.
+    fn handle(&self, track: ObjectId, event: Option<(usize, Event)>, actions: &mut Vec<Action>) {
+        let prop = match event {
+            Some((Self::MUTED, Event::Changed)) => TrackProp::Muted(self.muted.checked),
+            Some((Self::VOLUME, Event::Changed)) => TrackProp::Volume(self.volume.value()),
+            Some((Self::PAN, Event::Changed)) => TrackProp::Pan(self.pan.value()),
+            _ => return,
+        };
+        actions.push(Action::Edit(Operation::SetTrack { id: track, prop }));
+    }
+
+    fn lock_label(canvas: &mut dyn Canvas, rect: Rect, holder: &str) {
+        canvas.text_in(
+            Fonts::Inter,
+            rect,
+            &format!("locked by {}", holder),
+            TextStyle {
+                align: Alignment::new().left().middle(),
+                size: 16.0,
+                color: Color::from_rgb(255, 200, 80),
+            },
+        );
+    }
+
+    /// The newest entries that fit into `rect`, and a hint if older ones can be loaded.
+    fn activity_panel(canvas: &mut dyn Canvas, feed: &ActivityFeed, rect: Rect) {
+        let placement = Layout::column(vec![
+            Layout::leaf(ActivityPart::Heading).height(Size::fixed(30.0)),
+            Layout::leaf(ActivityPart::Entries),
+            Layout::leaf(ActivityPart::More).height(Size::fixed(if feed.more { 20.0 } else { 0.0 })),
+        ])
+        .arrange(rect);
+        canvas.text_in(
+            Fonts::Vga8,
+            placement.rect(&ActivityPart::Heading),
+            "Activity",
+            TextStyle {
+                align: Alignment::new().left().middle(),
+                size: 24.0,
+                color: Color::from_rgb(255, 255, 255),
+            },
+        );
+
+        let list = placement.rect(&ActivityPart::Entries);
+        let entries = Layout::column(
+            (0..feed.entries.len())
+                .map(|i| {
+                    Layout::column(vec![
+                        Layout::leaf(ActivityPart::Author(i)).height(Size::fixed(16.0)),
+                        Layout::leaf(ActivityPart::Summary(i))
+                            .height(Size::fixed(20.0))
+                            .padding(Padding {
+                                left: 10.0,
+                                ..Default::default()
+                            }),
+                    ])
+                    .height(Size::fixed(36.0))
+                })
+                .collect(),
+        )
+        .spacing(4.0)
+        .arrange(list);
+        for (i, entry) in feed.entries.iter().enumerate() {
+            let summary = entries.rect(&ActivityPart::Summary(i));
+            if summary.pos.1 + summary.size.1 > list.pos.1 + list.size.1 {
+                break;
+            }
+            let what = match entry.kind {
+                proto::ActivityKind::Undo { .. } => format!("undid: {}", entry.summary),
+                proto::ActivityKind::Redo { .. } => format!("redid: {}", entry.summary),
+                _ => entry.summary.clone(),
+            };
+            canvas.text_in(
+                Fonts::Inter,
+                entries.rect(&ActivityPart::Author(i)),
+                &format!("{}  {}", entry.date, entry.user_name),
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 14.0,
+                    color: Color::from_rgb(155, 155, 155),
                 },
             );
-
-            // Button shapes
-            let input_width = w / 3.0;
-            let input_height = 40f32;
-            let input_vert_dist = 20f32;
-            f.path(
-                |p| {
-                    p.rounded_rect(
-                        (w / 3.0, h / 2.0 - input_height - input_vert_dist / 2.0),
-                        (input_width, input_height),
-                        5.0,
-                    );
-                    p.rounded_rect(
-                        (w / 3.0, h / 2.0 + input_vert_dist / 2.0),
-                        (input_width, input_height),
-                        5.0,
-                    );
-                    p.fill(Color::from_rgb(128, 30, 80), Default::default());
+            canvas.text_in(
+                Fonts::Inter,
+                summary,
+                &what,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color: Color::from_rgb(200, 200, 200),
                 },
-                Default::default(),
             );
+        }
+        if feed.more {
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&ActivityPart::More),
+                "Page Down for older entries",
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 14.0,
+                    color: Color::from_rgb(155, 155, 155),
+                },
+            );
+        }
+    }
+}
 
-            // Invalid credentials text
-
-            if self.invalid_attempts > 0 {
-                f.text(
-                    ctx.font(Fonts::Inter),
-                    (w / 2.0, h / 2.0 + 100.0),
-                    "Your username and / or password is wrong. Please double check.",
-                    TextOptions {
-                        align: Alignment::new().center().middle(),
-                        size: 14.0,
-                        color: Color::from_rgb(255, 150, 150),
-                        ..Default::default()
-                    },
-                )
-            }
+impl super::View for ProjectView {
+    fn present(&mut self, app: &App, canvas: &mut dyn Canvas) {
+        let project = match app.project() {
+            Some(project) => project,
+            None => return,
+        };
+        let tracks = &project.state().tracks;
+        let selected = app.selected().and_then(|id| project.state().locate_track(id)).map(|t| &tracks[t]);
+        if let Some(track) = selected {
+            self.muted.checked = track.muted;
+            self.volume.set_value(track.volume);
+            self.pan.set_value(track.pan);
+        }
 
-            // Button contents
-
-            let (placeholder_color, content_color) = {
-                if self.invalid_timer_start.is_some() {
-                    (
-                        Color::from_rgb(255, 150, 150),
-                        Color::from_rgb(255, 150, 150),
-                    )
-                } else {
-                    (
-                        Color::from_rgb(128, 128, 128),
-                        Color::from_rgb(255, 255, 255),
-                    )
-                }
+        // One grid row per track and one per device under it, so the columns line up
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        for (t, track) in tracks.iter().enumerate() {
+            let row = rows.len();
+            rows.push(Size::fixed(24.0));
+            cells.push((0, row, ProjectPart::Track(t)));
+            cells.push((1, row, ProjectPart::Clips(t)));
+            cells.push((2, row, ProjectPart::TrackLock(t)));
+            for d in 0..track.devices.len() {
+                let row = rows.len();
+                rows.push(Size::fixed(18.0));
+                cells.push((0, row, ProjectPart::Device(t, d)));
+                cells.push((2, row, ProjectPart::DeviceLock(t, d)));
+            }
+        }
+        let track_list_height = rows.iter().map(|r| r.basis).sum();
+        let mut track_list = Layout::grid(
+            vec![
+                Size::flex(2.0).min(150.0).max(280.0),
+                Size::fixed(90.0),
+                Size::flex(1.0).min(150.0),
+            ],
+            rows,
+        )
+        .spacing(10.0)
+        .height(Size::fixed(track_list_height));
+        for (column, row, part) in cells {
+            let cell = if let ProjectPart::Device(..) = part {
+                Layout::leaf(part).padding(Padding {
+                    left: 20.0,
+                    ..Default::default()
+                })
+            } else {
+                Layout::leaf(part)
             };
+            track_list = track_list.cell(column, row, cell);
+        }
 
-            // Username content
-            f.text(
-                ctx.font(Fonts::Inter),
-                (
-                    w / 3.0 + 4.0,
-                    h / 2.0 - input_height / 2.0 - input_vert_dist / 2.0,
-                ),
-                if self.username_input.is_empty() {
-                    "Email"
-                } else {
-                    &self.username_input
-                },
-                TextOptions {
+        // What the user should know about their edits
+        let mut notes = Vec::new();
+        if !project.is_online() {
+            notes.push((
+                format!("Offline, {} edits waiting to be sent", project.pending().count()),
+                Color::from_rgb(255, 200, 80),
+            ));
+        }
+        for discarded in project.discarded() {
+            notes.push((
+                format!("Edit discarded: {} ({:?})", discarded.summary, discarded.error),
+                Color::from_rgb(255, 100, 100),
+            ));
+        }
+
+        let mut tracks_panel = vec![track_list, Layout::space().height(Size::fixed(10.0))];
+        if selected.is_some() {
+            tracks_panel.push(
+                Layout::row(vec![
+                    Layout::leaf(ProjectPart::Muted).width(Size::fixed(110.0)).height(Size::fixed(24.0)),
+                    Layout::leaf(ProjectPart::VolumeLabel).width(Size::fixed(60.0)),
+                    Layout::leaf(ProjectPart::Volume).width(Size::flex(1.0).max(250.0)),
+                    Layout::leaf(ProjectPart::PanLabel).width(Size::fixed(35.0)),
+                    Layout::leaf(ProjectPart::Pan).width(Size::fixed(36.0)),
+                    Layout::space(),
+                ])
+                .spacing(10.0)
+                .align(Align::Start, Align::Center)
+                .height(Size::fixed(36.0)),
+            );
+            tracks_panel.push(Layout::space().height(Size::fixed(10.0)));
+        }
+        tracks_panel.extend((0..notes.len()).map(|i| Layout::leaf(ProjectPart::Note(i)).height(Size::fixed(20.0))));
+        tracks_panel.push(Layout::space());
+        tracks_panel.push(Layout::leaf(ProjectPart::Keys).height(Size::fixed(20.0)));
+        let placement = Layout::column(vec![
+            Layout::leaf(ProjectPart::Title).height(Size::fixed(45.0)),
+            Layout::row(vec![
+                Layout::column(tracks_panel).width(Size::flex(2.0).min(300.0)),
+                Layout::leaf(ProjectPart::Activity).width(Size::flex(1.0).min(250.0).max(400.0)),
+            ])
+            .spacing(20.0),
+        ])
+        .padding(Padding::symmetric(10.0, 5.0))
+        .arrange(window(canvas));
+
+        title(canvas, placement.rect(&ProjectPart::Title), "Chorus Studio");
+        for (t, track) in tracks.iter().enumerate() {
+            canvas.text_in(
+                Fonts::Vga8,
+                placement.rect(&ProjectPart::Track(t)),
+                &track.name,
+                TextStyle {
                     align: Alignment::new().left().middle(),
-                    size: input_height - 4.0,
-                    color: if self.username_input.is_empty() {
-                        placeholder_color
-                    } else {
-                        content_color
+                    size: 24.0,
+                    color: match (app.selected() == Some(track.id), track.muted) {
+                        (true, _) => Color::from_rgb(255, 200, 80),
+                        (false, true) => Color::from_rgb(155, 155, 155),
+                        (false, false) => Color::from_rgb(255, 255, 255),
                     },
-                    ..Default::default()
                 },
             );
-
-            // Password content
-            f.text(
-                ctx.font(Fonts::Inter),
-                (
-                    w / 3.0 + 4.0,
-                    h / 2.0 + input_height / 2.0 + input_vert_dist / 2.0,
-                ),
-                if self.password_input.is_empty() {
-                    "Password"
-                } else {
-                    self.password_stars()
-                },
-                TextOptions {
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&ProjectPart::Clips(t)),
+                &format!("{} clips, {:.0}%", track.clips.len(), track.volume * 100.0),
+                TextStyle {
                     align: Alignment::new().left().middle(),
-                    size: input_height - 4.0,
-                    color: if self.password_input.is_empty() {
-                        placeholder_color
-                    } else {
-                        content_color
-                    },
-                    ..Default::default()
+                    size: 16.0,
+                    color: Color::from_rgb(155, 155, 155),
                 },
             );
+            if let Some(holder) = project.lock_holder(track.id) {
+                Self::lock_label(canvas, placement.rect(&ProjectPart::TrackLock(t)), holder);
+            }
 
-            // Input cursor
-            {
-                let origin = match self.active_input {
-                    LoginViewActiveInput::Username => {
-                        (w / 3.0, h / 2.0 - input_height - input_vert_dist / 2.0)
-                    }
-                    LoginViewActiveInput::Password => (w / 3.0, h / 2.0 + input_vert_dist / 2.0),
-                };
-
-                let (string, cursor) = self.active_input_str();
-                let (adv, _bounds) = f.text_bounds(
-                    ctx.font(Fonts::Inter),
-                    origin,
-                    &string[0..cursor],
-                    TextOptions {
+            for (d, device) in track.devices.iter().enumerate() {
+                canvas.text_in(
+                    Fonts::Inter,
+                    placement.rect(&ProjectPart::Device(t, d)),
+                    &device.name,
+                    TextStyle {
                         align: Alignment::new().left().middle(),
-                        size: input_height - 4.0,
-                        ..Default::default()
-                    },
-                );
-                f.path(
-                    |p| {
-                        p.rect((origin.0 + adv, origin.1 + 2.0), (2.0, input_height - 4.0));
-                        p.fill(Color::from_rgb(255, 0, 0), Default::default());
+                        size: 18.0,
+                        color: Color::from_rgb(200, 200, 200),
                     },
-                    Default::default(),
                 );
+                if let Some(holder) = project.lock_holder(device.id) {
+                    Self::lock_label(canvas, placement.rect(&ProjectPart::DeviceLock(t, d)), holder);
+                }
             }
-        });
-    }
+        }
 
-    fn on_char_input(&mut self, c: char) {
-        if !c.is_control() {
-            let (string, cursor) = self.active_input_data();
-            string.insert(*cursor, c);
-            *cursor += 1;
+        if selected.is_some() {
+            let label = TextStyle {
+                align: Alignment::new().left().middle(),
+                size: 16.0,
+                color: Color::from_rgb(200, 200, 200),
+            };
+            canvas.text_in(Fonts::Inter, placement.rect(&ProjectPart::VolumeLabel), "Volume", label);
+            canvas.text_in(Fonts::Inter, placement.rect(&ProjectPart::PanLabel), "Pan", label);
+            self.muted.present(canvas, placement.rect(&ProjectPart::Muted));
+            self.volume.present(canvas, placement.rect(&ProjectPart::Volume));
+            self.pan.present(canvas, placement.rect(&ProjectPart::Pan));
         }
+
+        for (i, &(ref note, color)) in notes.iter().enumerate() {
+            canvas.text_in(
+                Fonts::Inter,
+                placement.rect(&ProjectPart::Note(i)),
+                note,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: 16.0,
+                    color,
+                },
+            );
+        }
+
+        canvas.text_in(
+            Fonts::Inter,
+            placement.rect(&ProjectPart::Keys),
+            "Up/Down: select   N: new track   M: mute   Left/Right: volume   Delete: remove   Esc: leave",
+            TextStyle {
+                align: Alignment::new().left().middle(),
+                size: 14.0,
+                color: Color::from_rgb(155, 155, 155),
+            },
+        );
+
+        Self::activity_panel(canvas, app.activity(), placement.rect(&ProjectPart::Activity));
     }
 
-    fn on_key_input(&mut self, key: KeyAction) {
-        if key.was_pressed(KeyCode::Backspace) {
-            let (string, cursor) = self.active_input_data();
-            if *cursor > 0 {
-                string.remove(*cursor - 1);
-                *cursor -= 1;
-            }
-        } else if key.was_pressed(KeyCode::Delete) {
-            let (string, cursor) = self.active_input_data();
-            if *cursor < string.len() {
-                string.remove(*cursor);
-            }
-        } else if key.was_pressed_once(KeyCode::Return) {
-            if self.active_input == LoginViewActiveInput::Username {
-                self.active_input = LoginViewActiveInput::Password;
-            } else if self.active_input == LoginViewActiveInput::Password {
-                (self.on_submit)(
-                    &self.username_input.as_str(),
-                    Sha3_256::digest(self.password_input.as_str().as_bytes()).as_slice(),
-                );
-            }
-        } else if key.was_pressed_once(KeyCode::Tab) && key.with_modifier(KeyMod::Shift) {
-            if self.active_input == LoginViewActiveInput::Password {
-                self.active_input = LoginViewActiveInput::Username;
-            }
-        } else if key.was_pressed_once(KeyCode::Tab) {
-            if self.active_input == LoginViewActiveInput::Username {
-                self.active_input = LoginViewActiveInput::Password;
+    fn on_key_input(&mut self, app: &App, key: KeyAction, actions: &mut Vec<Action>) {
+        let project = match app.project() {
+            Some(project) => project,
+            None => return,
+        };
+        let project_id = project.project_id();
+        let tracks = &project.state().tracks;
+        let selected = app.selected().and_then(|id| project.state().locate_track(id));
+
+        if key.was_pressed(KeyCode::Up) || key.was_pressed(KeyCode::Down) {
+            let next = match selected {
+                _ if tracks.is_empty() => None,
+                None => Some(0),
+                Some(t) if key.was_pressed(KeyCode::Up) => Some(t.saturating_sub(1)),
+                Some(t) => Some((t + 1).min(tracks.len() - 1)),
+            };
+            actions.push(Action::Select(next.map(|t| tracks[t].id)));
+        } else if key.was_pressed_once(KeyCode::N) {
+            actions.push(Action::AddTrack);
+        } else if key.was_pressed_once(KeyCode::Escape) {
+            actions.push(Action::LeaveProject);
+        } else if let Some(track) = selected.map(|t| &tracks[t]) {
+            let id = track.id;
+            if key.was_pressed_once(KeyCode::M) {
+                actions.push(Action::Edit(Operation::SetTrack { id, prop: TrackProp::Muted(!track.muted) }));
+            } else if key.was_pressed(KeyCode::Left) || key.was_pressed(KeyCode::Right) {
+                let step = if key.was_pressed(KeyCode::Left) { -VOLUME_STEP } else { VOLUME_STEP };
+                let volume = (track.volume + step).clamp(0.0, 1.0);
+                actions.push(Action::Edit(Operation::SetTrack { id, prop: TrackProp::Volume(volume) }));
+            } else if key.was_pressed_once(KeyCode::Delete) {
+                actions.push(Action::Select(None));
+                actions.push(Action::Edit(Operation::RemoveTrack { id }));
             }
-        } else if key.was_pressed(KeyCode::Left) {
-            let (_string, cursor) = self.active_input_data();
-            if *cursor > 0 {
-                *cursor -= 1;
+        }
+
+        if key.was_pressed(KeyCode::Z) && key.with_modifier(KeyMod::Control) {
+            if key.with_modifier(KeyMod::Shift) {
+                actions.push(Action::Send(proto::Command::Redo { project_id }));
+            } else {
+                actions.push(Action::Send(proto::Command::Undo { project_id }));
             }
-        } else if key.was_pressed(KeyCode::Right) {
-            let (string, cursor) = self.active_input_data();
-            if *cursor < string.len() {
-                *cursor += 1;
+        } else if key.was_pressed_once(KeyCode::F5) {
+            actions.push(Action::Send(proto::Command::GetProjectActivity { project_id, before: None }));
+        } else if key.was_pressed_once(KeyCode::PageDown) {
+            let feed = app.activity();
+            if feed.more {
+                let before = feed.entries.last().map(|e| e.id);
+                actions.push(Action::Send(proto::Command::GetProjectActivity { project_id, before }));
             }
-        } else if key.was_pressed(KeyCode::Home) {
-            let (_, cursor) = self.active_input_data();
-            *cursor = 0;
-        } else if key.was_pressed(KeyCode::End) {
-            let (string, cursor) = self.active_input_data();
-            *cursor = string.len();
+        }
+    }
+
+    fn on_mouse_input(&mut self, app: &App, mouse: MouseAction, actions: &mut Vec<Action>) {
+        // The controls are only on screen while a track is selected
+        let track = match (app.project(), app.selected()) {
+            (Some(project), Some(id)) if project.state().locate_track(id).is_some() => id,
+            _ => return,
+        };
+        let event = widgets::on_mouse_input(&mut self.widgets(), mouse);
+        self.handle(track, event, actions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app::Msg;
+    use client::settings::Settings;
+    use draw::{DrawCommand, Recorder};
+    use ui::View;
+
+    const WINDOW: (f32, f32) = (1200.0, 720.0);
+
+    fn draw(view: &mut dyn View, app: &App) -> Recorder {
+        let mut canvas = Recorder::new(WINDOW);
+        view.present(app, &mut canvas);
+        canvas
+    }
+
+    fn text_color(canvas: &Recorder, needle: &str) -> Color {
+        match canvas.find_text(needle) {
+            Some(DrawCommand::Text { style, .. }) => style.color,
+            _ => panic!("No text {:?} among {:?}", needle, canvas.texts().collect::<Vec<_>>()),
+        }
+    }
+
+    #[test]
+    fn login_shows_invalid_credentials() {
+        let mut app = App::new(Settings {
+            last_email: Some("a@example.com".to_owned()),
+            ..Default::default()
+        });
+        let mut view = LoginView::new(&app);
+
+        let mut canvas = draw(&mut view, &app);
+        assert!(canvas.find_text("password is wrong").is_none());
+        assert_eq!(text_color(&canvas, "a@example.com"), Color::from_rgb(255, 255, 255));
+
+        app.update(Msg::Response(proto::Response::LoginInvalid));
+        canvas.clear();
+        view.present(&app, &mut canvas);
+        assert!(canvas.find_text("password is wrong").is_some());
+        // The fields light up too, until the error times out
+        assert_eq!(text_color(&canvas, "a@example.com"), Color::from_rgb(255, 150, 150));
+        assert_eq!(text_color(&canvas, "Password"), Color::from_rgb(255, 150, 150));
+    }
+
+    #[test]
+    fn login_lists_servers() {
+        let mut settings = Settings::parse("server = a:4450\nserver = b:4450\n");
+        settings.server_override = Some("c:4450".to_owned());
+        let app = App::new(settings);
+        let canvas = draw(&mut LoginView::new(&app), &app);
+        let texts: Vec<_> = canvas.texts().collect();
+        for server in &["a:4450", "b:4450", "c:4450"] {
+            assert!(texts.contains(server), "{} missing from {:?}", server, texts);
+        }
+    }
+
+    #[test]
+    fn loading_shows_the_task() {
+        let mut app = App::new(Settings::default());
+        app.update(Msg::Response(proto::Response::LoginOk));
+        app.update(Msg::State(NetState::Disconnected("Connection lost".to_owned())));
+        let canvas = draw(&mut MainLoadingView, &app);
+        assert!(canvas.find_text("Connection lost, reconnecting...").is_some());
+    }
+
+    #[test]
+    fn notices_from_the_server_stand_out() {
+        let mut app = App::new(Settings::default());
+        assert!(draw(&mut NoticeView, &app).commands().is_empty());
+
+        app.update(Msg::Response(proto::Response::LoginThrottled { retry_after_secs: 30 }));
+        app.update(Msg::Response(proto::Response::SystemMessage("Back in 5 minutes".to_owned())));
+        let canvas = draw(&mut NoticeView, &app);
+        let backgrounds: Vec<_> = canvas
+            .commands()
+            .iter()
+            .filter_map(|cmd| match cmd {
+                DrawCommand::Fill { color, .. } => Some(*color),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(backgrounds, vec![Color::from_rgb(60, 60, 70), Color::from_rgb(200, 100, 0)]);
+        assert_eq!(canvas.texts().collect::<Vec<_>>(), vec![
+            "Too many failed logins, try again in 30 seconds",
+            "Back in 5 minutes",
+        ]);
+        // Not wider than 900, in the middle of the window
+        match canvas.commands()[0] {
+            DrawCommand::Fill { ref shapes, .. } => match shapes[..] {
+                [Shape::RoundedRect { pos, size, .. }] => assert_eq!((pos.0, size.0), (150.0, 900.0)),
+                _ => panic!("Expected the background, got {:?}", shapes),
+            },
+            ref other => panic!("Expected the background, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn track_controls_edit_the_selected_track() {
+        use input::MouseButton;
+        use proto::project::{ObjectId, Project, Track};
+
+        let id = ObjectId { site: 1, counter: 0 };
+        let track = Track {
+            id,
+            name: "Drums".to_owned(),
+            volume: 0.5,
+            pan: 0.0,
+            muted: false,
+            clips: Vec::new(),
+            devices: Vec::new(),
+        };
+        let mut app = App::new(Settings::default());
+        app.update(Msg::Response(proto::Response::LoginOk));
+        app.update(Msg::Action(Action::OpenProject { project_id: 7 }));
+        app.update(Msg::Response(proto::Response::ProjectJoined {
+            project_id: 7,
+            site: 2,
+            seq: 0,
+            state: Project { tracks: vec![track] },
+            locks: Vec::new(),
+        }));
+        let mut view = ProjectView::default();
+        assert!(draw(&mut view, &app).find_text("Muted").is_none());
+
+        app.update(Msg::Action(Action::Select(Some(id))));
+        let canvas = draw(&mut view, &app);
+        let pos = match canvas.find_text("Muted") {
+            Some(&DrawCommand::Text { pos, .. }) => pos,
+            _ => panic!("No controls among {:?}", canvas.texts().collect::<Vec<_>>()),
+        };
+        // The box is left of its label
+        let click = |action| MouseAction::Button {
+            button: MouseButton::Left as u32,
+            action,
+            pos: (pos.0 - 20.0, pos.1),
+            mods: 0,
+        };
+        let mut actions = Vec::new();
+        view.on_mouse_input(&app, click(::glfw_ffi::GLFW_PRESS), &mut actions);
+        view.on_mouse_input(&app, click(::glfw_ffi::GLFW_RELEASE), &mut actions);
+        match actions[..] {
+            [Action::Edit(Operation::SetTrack { id: edited, prop: TrackProp::Muted(true) })] => assert_eq!(edited, id),
+            _ => panic!("Expected the track to be muted"),
         }
     }
 }
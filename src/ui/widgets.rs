@@ -0,0 +1,1190 @@
+//! The pieces views are built from.
+//!
+//! A widget draws itself into the rectangle its view gives it and reacts to input.
+//! Views keep their widgets as fields and hand input to the interactive ones, in Tab
+//! order, with [on_char_input], [on_key_input] and [on_mouse_input]. These keep track
+//! of which widget is focused, hovered and pressed, and tell the view which widget did
+//! what.
+
+use draw::{Alignment, Canvas, Color, Fonts, Rect, Shape, TextStyle};
+use input::{InputString, KeyAction, KeyCode, KeyMod, MouseAction, MouseButton};
+
+use std::f32::consts::PI;
+use std::ops::{Deref, DerefMut};
+
+const FIELD_COLOR: Color = Color { r: 128, g: 30, b: 80, a: 255 };
+const HOVER_COLOR: Color = Color { r: 150, g: 45, b: 100, a: 255 };
+const PRESSED_COLOR: Color = Color { r: 100, g: 20, b: 60, a: 255 };
+const FOCUS_COLOR: Color = Color { r: 255, g: 200, b: 80, a: 255 };
+const TEXT_COLOR: Color = Color { r: 255, g: 255, b: 255, a: 255 };
+const PLACEHOLDER_COLOR: Color = Color { r: 128, g: 128, b: 128, a: 255 };
+const HANDLE_HOVER_COLOR: Color = Color { r: 200, g: 200, b: 200, a: 255 };
+const ERROR_COLOR: Color = Color { r: 255, g: 150, b: 150, a: 255 };
+const CARET_COLOR: Color = Color { r: 255, g: 0, b: 0, a: 255 };
+
+/// What a widget tells its view about input it handled.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The value changed: text was typed, a box ticked, a slider moved.
+    Changed,
+    /// The widget was triggered: a button clicked, return pressed in a text box.
+    Activated,
+}
+
+/// How the user is interacting with a widget.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Interaction {
+    /// Where the widget was drawn last
+    pub rect: Rect,
+    pub hovered: bool,
+    /// The left mouse button went down on the widget and is still down
+    pub pressed: bool,
+    pub focused: bool,
+}
+
+impl Interaction {
+    /// The color of a field or button in this state.
+    fn fill_color(&self) -> Color {
+        if self.pressed {
+            PRESSED_COLOR
+        } else if self.hovered {
+            HOVER_COLOR
+        } else {
+            FIELD_COLOR
+        }
+    }
+}
+
+pub trait Widget {
+    fn interaction(&self) -> &Interaction;
+    fn interaction_mut(&mut self) -> &mut Interaction;
+
+    /// Whether the widget takes the keyboard focus, when clicked or with Tab.
+    fn focusable(&self) -> bool {
+        true
+    }
+
+    /// Draws the widget into its rectangle.
+    fn draw(&mut self, canvas: &mut dyn Canvas);
+
+    fn on_char_input(&mut self, _c: char) -> Option<Event> {
+        None
+    }
+
+    fn on_key_input(&mut self, _key: KeyAction) -> Option<Event> {
+        None
+    }
+
+    /// Mouse input over the widget, or any while it's pressed, e.g. to drag a slider.
+    fn on_mouse_input(&mut self, _mouse: MouseAction) -> Option<Event> {
+        None
+    }
+
+    /// Draws the widget into `rect`, which also is where it takes mouse input from.
+    fn present(&mut self, canvas: &mut dyn Canvas, rect: Rect) {
+        self.interaction_mut().rect = rect;
+        self.draw(canvas);
+    }
+}
+
+/// The index of the focused widget.
+pub fn focused(widgets: &[&mut dyn Widget]) -> Option<usize> {
+    widgets.iter().position(|w| w.interaction().focused)
+}
+
+/// Moves the focus to `widgets[index]`.
+pub fn focus(widgets: &mut [&mut dyn Widget], index: usize) {
+    for (i, widget) in widgets.iter_mut().enumerate() {
+        widget.interaction_mut().focused = i == index;
+    }
+}
+
+/// Moves the focus to the next focusable widget, or the previous one if `backwards`.
+fn move_focus(widgets: &mut [&mut dyn Widget], backwards: bool) {
+    let n = widgets.len();
+    let start = match focused(widgets) {
+        Some(i) => i,
+        None if backwards => 0,
+        None => n - 1,
+    };
+    let next = (1..=n)
+        .map(|step| if backwards { (start + n - step) % n } else { (start + step) % n })
+        .find(|&i| widgets[i].focusable());
+    if let Some(next) = next {
+        focus(widgets, next);
+    }
+}
+
+/// Hands a typed character to the focused widget.
+pub fn on_char_input(widgets: &mut [&mut dyn Widget], c: char) -> Option<(usize, Event)> {
+    let i = focused(widgets)?;
+    widgets[i].on_char_input(c).map(|e| (i, e))
+}
+
+/// Tab and Shift+Tab move the focus, other keys go to the focused widget.
+pub fn on_key_input(widgets: &mut [&mut dyn Widget], key: KeyAction) -> Option<(usize, Event)> {
+    if widgets.is_empty() {
+        return None;
+    }
+    if key.was_pressed(KeyCode::Tab) {
+        move_focus(widgets, key.with_modifier(KeyMod::Shift));
+        return None;
+    }
+    let i = focused(widgets)?;
+    widgets[i].on_key_input(key).map(|e| (i, e))
+}
+
+/// Updates which widget is hovered and pressed, focuses what's clicked and hands the
+/// input to the widget under the cursor, or to the pressed one while dragging.
+pub fn on_mouse_input(widgets: &mut [&mut dyn Widget], mouse: MouseAction) -> Option<(usize, Event)> {
+    if let MouseAction::Enter(false) = mouse {
+        for widget in widgets.iter_mut() {
+            widget.interaction_mut().hovered = false;
+        }
+    }
+    let pos = mouse.pos()?;
+    for widget in widgets.iter_mut() {
+        let hovered = widget.interaction().rect.contains(pos);
+        widget.interaction_mut().hovered = hovered;
+    }
+    let pressed = widgets.iter().position(|w| w.interaction().pressed);
+    let hit = widgets.iter().position(|w| w.interaction().rect.contains(pos));
+
+    if mouse.was_pressed(MouseButton::Left) {
+        let i = hit?;
+        if widgets[i].focusable() {
+            focus(widgets, i);
+        }
+        widgets[i].interaction_mut().pressed = true;
+        widgets[i].on_mouse_input(mouse).map(|e| (i, e))
+    } else if mouse.was_released(MouseButton::Left) {
+        // The widget still knows it was pressed, so it can tell a click from a drag off it
+        let i = pressed?;
+        let event = widgets[i].on_mouse_input(mouse);
+        widgets[i].interaction_mut().pressed = false;
+        event.map(|e| (i, e))
+    } else {
+        let i = pressed.or(hit)?;
+        widgets[i].on_mouse_input(mouse).map(|e| (i, e))
+    }
+}
+
+/// Fills `rect` in the color for `interaction`, with a frame if it's focused.
+fn draw_field(canvas: &mut dyn Canvas, rect: Rect, interaction: &Interaction) {
+    if interaction.focused {
+        canvas.fill(
+            &[Shape::RoundedRect {
+                pos: (rect.pos.0 - 2.0, rect.pos.1 - 2.0),
+                size: (rect.size.0 + 4.0, rect.size.1 + 4.0),
+                radius: 7.0,
+            }],
+            FOCUS_COLOR,
+        );
+    }
+    canvas.fill(
+        &[Shape::RoundedRect {
+            pos: rect.pos,
+            size: rect.size,
+            radius: 5.0,
+        }],
+        interaction.fill_color(),
+    );
+}
+
+/// Whether a release of the left mouse button completes a click on the widget.
+fn clicked(mouse: MouseAction, interaction: &Interaction) -> bool {
+    mouse.was_released(MouseButton::Left) && interaction.pressed && interaction.hovered
+}
+
+/// Static text, aligned within its rectangle the way its style says.
+pub struct Label {
+    pub text: String,
+    pub font: Fonts,
+    pub style: TextStyle,
+    interaction: Interaction,
+}
+
+impl Label {
+    pub fn new(text: &str, font: Fonts, style: TextStyle) -> Self {
+        Label {
+            text: text.to_owned(),
+            font,
+            style,
+            interaction: Default::default(),
+        }
+    }
+}
+
+impl Widget for Label {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn focusable(&self) -> bool {
+        false
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        canvas.text_in(self.font, self.interaction.rect, &self.text, self.style);
+    }
+}
+
+/// The x coordinate of the caret for every cursor position in `text`, drawn at `origin`.
+fn caret_stops(canvas: &dyn Canvas, origin: (f32, f32), text: &str, style: TextStyle) -> Vec<f32> {
+    let mut stops = vec![origin.0];
+    let mut prefix = String::new();
+    for c in text.chars() {
+        prefix.push(c);
+        let (advance, _) = canvas.text_bounds(Fonts::Inter, origin, &prefix, style);
+        stops.push(origin.0 + advance);
+    }
+    stops
+}
+
+/// The cursor position whose caret is closest to `x`.
+fn nearest_stop(stops: &[f32], x: f32) -> usize {
+    let distance = |i: usize| (stops[i] - x).abs();
+    (0..stops.len()).fold(0, |best, i| if distance(i) < distance(best) { i } else { best })
+}
+
+/// A single line of text to edit. Return activates it.
+pub struct TextInput {
+    input: InputString,
+    cursor: usize,
+    placeholder: String,
+    /// Shown instead of every character, for passwords
+    mask: Option<char>,
+    /// Shows the text in red, e.g. after it was rejected
+    error: bool,
+    /// Where the caret goes for every cursor position, as of the last frame
+    caret_stops: Vec<f32>,
+    interaction: Interaction,
+}
+
+impl TextInput {
+    /// `placeholder` is shown while the input is empty.
+    pub fn new(placeholder: &str) -> Self {
+        TextInput {
+            input: InputString::new(),
+            cursor: 0,
+            placeholder: placeholder.to_owned(),
+            mask: None,
+            error: false,
+            caret_stops: Vec::new(),
+            interaction: Default::default(),
+        }
+    }
+
+    /// Starts out with `text`, the cursor at its end.
+    pub fn with_text(mut self, text: &str) -> Self {
+        for c in text.chars() {
+            self.input.insert(self.input.len(), c);
+        }
+        self.cursor = self.input.len();
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        self.input.as_str()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn set_error(&mut self, error: bool) {
+        self.error = error;
+    }
+
+    /// The text as it's shown.
+    fn shown(&self) -> String {
+        match self.mask {
+            Some(mask) => self.input.chars().map(|_| mask).collect(),
+            None => self.input.as_str().to_owned(),
+        }
+    }
+}
+
+impl Widget for TextInput {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        draw_field(canvas, rect, &self.interaction);
+
+        let style = TextStyle {
+            align: Alignment::new().left().middle(),
+            size: rect.size.1 - 4.0,
+            color: if self.error { ERROR_COLOR } else { TEXT_COLOR },
+        };
+        let origin = (rect.pos.0 + 4.0, rect.center().1);
+        let shown = self.shown();
+        if self.input.is_empty() {
+            let color = if self.error { ERROR_COLOR } else { PLACEHOLDER_COLOR };
+            canvas.text(Fonts::Inter, origin, &self.placeholder, TextStyle { color, ..style });
+        } else {
+            canvas.text(Fonts::Inter, origin, &shown, style);
+        }
+        self.caret_stops = caret_stops(canvas, origin, &shown, style);
+
+        if self.interaction.focused {
+            let x = self.caret_stops.get(self.cursor).cloned().unwrap_or(origin.0);
+            canvas.fill(
+                &[Shape::Rect {
+                    pos: (x, rect.pos.1 + 2.0),
+                    size: (2.0, rect.size.1 - 4.0),
+                }],
+                CARET_COLOR,
+            );
+        }
+    }
+
+    fn on_char_input(&mut self, c: char) -> Option<Event> {
+        if c.is_control() {
+            return None;
+        }
+        self.input.insert(self.cursor, c);
+        self.cursor += 1;
+        Some(Event::Changed)
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        if key.was_pressed(KeyCode::Backspace) {
+            if self.cursor > 0 {
+                self.input.remove(self.cursor - 1);
+                self.cursor -= 1;
+                return Some(Event::Changed);
+            }
+        } else if key.was_pressed(KeyCode::Delete) {
+            if self.cursor < self.input.len() {
+                self.input.remove(self.cursor);
+                return Some(Event::Changed);
+            }
+        } else if key.was_pressed_once(KeyCode::Return) {
+            return Some(Event::Activated);
+        } else if key.was_pressed(KeyCode::Left) {
+            self.cursor = self.cursor.saturating_sub(1);
+        } else if key.was_pressed(KeyCode::Right) {
+            self.cursor = (self.cursor + 1).min(self.input.len());
+        } else if key.was_pressed(KeyCode::Home) {
+            self.cursor = 0;
+        } else if key.was_pressed(KeyCode::End) {
+            self.cursor = self.input.len();
+        }
+        None
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        if let (true, Some(pos)) = (mouse.was_pressed(MouseButton::Left), mouse.pos()) {
+            self.cursor = nearest_stop(&self.caret_stops, pos.0).min(self.input.len());
+        }
+        None
+    }
+}
+
+/// A [TextInput] that shows stars instead of what's typed.
+pub struct PasswordInput(TextInput);
+
+impl PasswordInput {
+    pub fn new(placeholder: &str) -> Self {
+        let mut input = TextInput::new(placeholder);
+        input.mask = Some('*');
+        PasswordInput(input)
+    }
+}
+
+impl Deref for PasswordInput {
+    type Target = TextInput;
+    fn deref(&self) -> &TextInput {
+        &self.0
+    }
+}
+
+impl DerefMut for PasswordInput {
+    fn deref_mut(&mut self) -> &mut TextInput {
+        &mut self.0
+    }
+}
+
+impl Widget for PasswordInput {
+    fn interaction(&self) -> &Interaction {
+        self.0.interaction()
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        self.0.interaction_mut()
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        self.0.draw(canvas)
+    }
+
+    fn on_char_input(&mut self, c: char) -> Option<Event> {
+        self.0.on_char_input(c)
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        self.0.on_key_input(key)
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        self.0.on_mouse_input(mouse)
+    }
+}
+
+/// Activated by a click, or return or space while focused.
+pub struct Button {
+    pub label: String,
+    interaction: Interaction,
+}
+
+impl Button {
+    pub fn new(label: &str) -> Self {
+        Button {
+            label: label.to_owned(),
+            interaction: Default::default(),
+        }
+    }
+}
+
+impl Widget for Button {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        draw_field(canvas, rect, &self.interaction);
+        canvas.text(
+            Fonts::Inter,
+            rect.center(),
+            &self.label,
+            TextStyle {
+                align: Alignment::new().center().middle(),
+                size: rect.size.1 * 0.6,
+                color: TEXT_COLOR,
+            },
+        );
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        if key.was_pressed_once(KeyCode::Return) || key.was_pressed_once(KeyCode::Space) {
+            Some(Event::Activated)
+        } else {
+            None
+        }
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        if clicked(mouse, &self.interaction) {
+            Some(Event::Activated)
+        } else {
+            None
+        }
+    }
+}
+
+/// A box to tick, with its label to the right. Toggled by a click, or return or space
+/// while focused.
+pub struct Checkbox {
+    pub label: String,
+    pub checked: bool,
+    interaction: Interaction,
+}
+
+impl Checkbox {
+    pub fn new(label: &str, checked: bool) -> Self {
+        Checkbox {
+            label: label.to_owned(),
+            checked,
+            interaction: Default::default(),
+        }
+    }
+
+    fn toggle(&mut self) -> Option<Event> {
+        self.checked = !self.checked;
+        Some(Event::Changed)
+    }
+}
+
+impl Widget for Checkbox {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        let side = rect.size.1;
+        draw_field(canvas, Rect::new(rect.pos, (side, side)), &self.interaction);
+        if self.checked {
+            canvas.fill(
+                &[Shape::RoundedRect {
+                    pos: (rect.pos.0 + side / 4.0, rect.pos.1 + side / 4.0),
+                    size: (side / 2.0, side / 2.0),
+                    radius: 2.0,
+                }],
+                FOCUS_COLOR,
+            );
+        }
+        canvas.text(
+            Fonts::Inter,
+            (rect.pos.0 + side + 8.0, rect.center().1),
+            &self.label,
+            TextStyle {
+                align: Alignment::new().left().middle(),
+                size: side * 0.8,
+                color: TEXT_COLOR,
+            },
+        );
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        if key.was_pressed_once(KeyCode::Return) || key.was_pressed_once(KeyCode::Space) {
+            self.toggle()
+        } else {
+            None
+        }
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        if clicked(mouse, &self.interaction) {
+            self.toggle()
+        } else {
+            None
+        }
+    }
+}
+
+/// Items to pick one of, with the up and down keys, a click or the wheel. Return
+/// activates the selected item.
+pub struct List {
+    items: Vec<String>,
+    selected: usize,
+    pub row_height: f32,
+    /// The first row shown, so the selected one is always visible
+    top: usize,
+    interaction: Interaction,
+}
+
+impl List {
+    pub fn new(items: Vec<String>, selected: usize) -> Self {
+        List {
+            selected: selected.min(items.len().saturating_sub(1)),
+            items,
+            row_height: 24.0,
+            top: 0,
+            interaction: Default::default(),
+        }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn selected_item(&self) -> Option<&str> {
+        self.items.get(self.selected).map(|s| s.as_str())
+    }
+
+    fn select(&mut self, index: usize) -> Option<Event> {
+        if index < self.items.len() && index != self.selected {
+            self.selected = index;
+            Some(Event::Changed)
+        } else {
+            None
+        }
+    }
+}
+
+impl Widget for List {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        draw_field(canvas, rect, &Interaction { pressed: false, ..self.interaction });
+
+        let visible = ((rect.size.1 / self.row_height) as usize).max(1);
+        self.top = self.top.min(self.selected).max((self.selected + 1).saturating_sub(visible));
+        for (row, (i, item)) in self.items.iter().enumerate().skip(self.top).take(visible).enumerate() {
+            let y = rect.pos.1 + row as f32 * self.row_height;
+            if i == self.selected {
+                canvas.fill(
+                    &[Shape::Rect {
+                        pos: (rect.pos.0, y),
+                        size: (rect.size.0, self.row_height),
+                    }],
+                    PRESSED_COLOR,
+                );
+            }
+            canvas.text(
+                Fonts::Inter,
+                (rect.pos.0 + 6.0, y + self.row_height / 2.0),
+                item,
+                TextStyle {
+                    align: Alignment::new().left().middle(),
+                    size: self.row_height * 0.7,
+                    color: if i == self.selected { FOCUS_COLOR } else { TEXT_COLOR },
+                },
+            );
+        }
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        if key.was_pressed(KeyCode::Up) {
+            self.select(self.selected.saturating_sub(1))
+        } else if key.was_pressed(KeyCode::Down) {
+            self.select(self.selected + 1)
+        } else if key.was_pressed_once(KeyCode::Return) && !self.items.is_empty() {
+            Some(Event::Activated)
+        } else {
+            None
+        }
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        match mouse {
+            MouseAction::Button { pos, .. } if mouse.was_pressed(MouseButton::Left) => {
+                let row = ((pos.1 - self.interaction.rect.pos.1) / self.row_height) as usize;
+                self.select(self.top + row)
+            }
+            MouseAction::Scroll { offset, .. } if offset.1 > 0.0 => self.select(self.selected.saturating_sub(1)),
+            MouseAction::Scroll { offset, .. } if offset.1 < 0.0 => self.select(self.selected + 1),
+            _ => None,
+        }
+    }
+}
+
+/// A value within a range, where `step` is how much a key press or the wheel changes it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Range {
+    min: f32,
+    max: f32,
+    step: f32,
+}
+
+impl Range {
+    fn clamp(&self, value: f32) -> f32 {
+        value.max(self.min).min(self.max)
+    }
+
+    /// Where `value` is in the range, from 0 to 1.
+    fn fraction(&self, value: f32) -> f32 {
+        if self.max > self.min {
+            (value - self.min) / (self.max - self.min)
+        } else {
+            0.0
+        }
+    }
+
+    /// How many steps up, or down if negative, keys or the wheel asked for.
+    fn steps(key: Option<KeyAction>, mouse: Option<MouseAction>) -> f32 {
+        if let Some(key) = key {
+            if key.was_pressed(KeyCode::Up) || key.was_pressed(KeyCode::Right) {
+                return 1.0;
+            } else if key.was_pressed(KeyCode::Down) || key.was_pressed(KeyCode::Left) {
+                return -1.0;
+            }
+        }
+        match mouse {
+            Some(MouseAction::Scroll { offset, .. }) => offset.1.signum(),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Sets `value` to `new` within `range`, `Changed` if that changed it.
+fn change(value: &mut f32, new: f32, range: Range) -> Option<Event> {
+    let new = range.clamp(new);
+    if new != *value {
+        *value = new;
+        Some(Event::Changed)
+    } else {
+        None
+    }
+}
+
+/// Moves `value` by `steps` steps within `range`.
+fn step(value: &mut f32, steps: f32, range: Range) -> Option<Event> {
+    let new = *value + steps * range.step;
+    change(value, new, range)
+}
+
+/// A value picked by dragging a handle along a track, with the arrow keys or the wheel.
+pub struct Slider {
+    value: f32,
+    range: Range,
+    interaction: Interaction,
+}
+
+impl Slider {
+    pub fn new(min: f32, max: f32, step: f32, value: f32) -> Self {
+        let range = Range { min, max, step };
+        Slider {
+            value: range.clamp(value),
+            range,
+            interaction: Default::default(),
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.value = self.range.clamp(value);
+    }
+
+    /// The handle's radius and the part of the rectangle it moves along.
+    fn track(&self) -> (f32, f32, f32) {
+        let rect = self.interaction.rect;
+        let radius = rect.size.1 / 2.0 - 2.0;
+        (radius, rect.pos.0 + radius, rect.size.0 - 2.0 * radius)
+    }
+}
+
+impl Widget for Slider {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        let (radius, start, length) = self.track();
+        let y = rect.center().1;
+        let x = start + length * self.range.fraction(self.value);
+        canvas.fill(
+            &[Shape::Rect {
+                pos: (start, y - 2.0),
+                size: (length, 4.0),
+            }],
+            FIELD_COLOR,
+        );
+        canvas.fill(
+            &[Shape::Rect {
+                pos: (start, y - 2.0),
+                size: (x - start, 4.0),
+            }],
+            FOCUS_COLOR,
+        );
+        if self.interaction.focused {
+            canvas.fill(&[Shape::Circle { center: (x, y), radius: radius + 2.0 }], FOCUS_COLOR);
+        }
+        let handle = if self.interaction.pressed {
+            TEXT_COLOR
+        } else if self.interaction.hovered {
+            HANDLE_HOVER_COLOR
+        } else {
+            PLACEHOLDER_COLOR
+        };
+        canvas.fill(&[Shape::Circle { center: (x, y), radius }], handle);
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        let steps = Range::steps(Some(key), None);
+        step(&mut self.value, steps, self.range)
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        match mouse {
+            MouseAction::Button { pos, .. } | MouseAction::Move { pos, .. } if self.interaction.pressed => {
+                let (_, start, length) = self.track();
+                let fraction = if length > 0.0 { (pos.0 - start) / length } else { 0.0 };
+                let value = self.range.min + fraction * (self.range.max - self.range.min);
+                change(&mut self.value, value, self.range)
+            }
+            MouseAction::Scroll { .. } => {
+                let steps = Range::steps(None, Some(mouse));
+                step(&mut self.value, steps, self.range)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How far the cursor has to be dragged to turn a knob all the way
+const KNOB_DRAG_DISTANCE: f32 = 200.0;
+
+/// A value picked by dragging up or down on a dial, with the arrow keys or the wheel.
+pub struct Knob {
+    value: f32,
+    range: Range,
+    /// Where a drag started and the value at that point
+    drag_start: Option<(f32, f32)>,
+    interaction: Interaction,
+}
+
+impl Knob {
+    pub fn new(min: f32, max: f32, step: f32, value: f32) -> Self {
+        let range = Range { min, max, step };
+        Knob {
+            value: range.clamp(value),
+            range,
+            drag_start: None,
+            interaction: Default::default(),
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: f32) {
+        self.value = self.range.clamp(value);
+    }
+}
+
+impl Widget for Knob {
+    fn interaction(&self) -> &Interaction {
+        &self.interaction
+    }
+
+    fn interaction_mut(&mut self) -> &mut Interaction {
+        &mut self.interaction
+    }
+
+    fn draw(&mut self, canvas: &mut dyn Canvas) {
+        let rect = self.interaction.rect;
+        let center = rect.center();
+        let radius = rect.size.0.min(rect.size.1) / 2.0 - 2.0;
+        if self.interaction.focused {
+            canvas.fill(&[Shape::Circle { center, radius: radius + 2.0 }], FOCUS_COLOR);
+        }
+        canvas.fill(&[Shape::Circle { center, radius }], self.interaction.fill_color());
+        // The dial turns from 135° left of the top to 135° right of it
+        let angle = (-0.75 + 1.5 * self.range.fraction(self.value)) * PI;
+        let mark = (center.0 + angle.sin() * radius * 0.7, center.1 - angle.cos() * radius * 0.7);
+        canvas.fill(&[Shape::Circle { center: mark, radius: radius * 0.15 }], TEXT_COLOR);
+    }
+
+    fn on_key_input(&mut self, key: KeyAction) -> Option<Event> {
+        let steps = Range::steps(Some(key), None);
+        step(&mut self.value, steps, self.range)
+    }
+
+    fn on_mouse_input(&mut self, mouse: MouseAction) -> Option<Event> {
+        match mouse {
+            MouseAction::Button { pos, .. } if mouse.was_pressed(MouseButton::Left) => {
+                self.drag_start = Some((pos.1, self.value));
+                None
+            }
+            MouseAction::Button { .. } if mouse.was_released(MouseButton::Left) => {
+                self.drag_start = None;
+                None
+            }
+            MouseAction::Move { pos, .. } => match self.drag_start {
+                Some((y, value)) if self.interaction.pressed => {
+                    let delta = (y - pos.1) / KNOB_DRAG_DISTANCE * (self.range.max - self.range.min);
+                    change(&mut self.value, value + delta, self.range)
+                }
+                _ => None,
+            },
+            MouseAction::Scroll { .. } => {
+                let steps = Range::steps(None, Some(mouse));
+                step(&mut self.value, steps, self.range)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use draw::{DrawCommand, Recorder};
+    use glfw_ffi::*;
+
+    fn key(code: KeyCode) -> KeyAction {
+        KeyAction { key: code as u32, scancode: 0, action: GLFW_PRESS, mods: 0 }
+    }
+
+    fn held(code: KeyCode) -> KeyAction {
+        KeyAction { action: GLFW_REPEAT, ..key(code) }
+    }
+
+    fn shifted(code: KeyCode) -> KeyAction {
+        KeyAction { mods: KeyMod::Shift as u32, ..key(code) }
+    }
+
+    fn mouse(action: u32, pos: (f32, f32)) -> MouseAction {
+        MouseAction::Button { button: MouseButton::Left as u32, action, pos, mods: 0 }
+    }
+
+    /// Presents `widget` in `rect`, which is where it takes mouse input from afterwards.
+    fn place(widget: &mut dyn Widget, rect: Rect) -> Recorder {
+        let mut canvas = Recorder::new((800.0, 600.0));
+        widget.present(&mut canvas, rect);
+        canvas
+    }
+
+    #[test]
+    fn text_input_edits_at_the_cursor() {
+        let mut input = TextInput::new("Email").with_text("héllo");
+        assert_eq!(input.cursor, 5);
+        assert_eq!(input.on_key_input(key(KeyCode::Home)), None);
+        assert_eq!(input.on_key_input(key(KeyCode::Backspace)), None);
+        assert_eq!(input.on_key_input(key(KeyCode::Delete)), Some(Event::Changed));
+        assert_eq!(input.text(), "éllo");
+
+        input.on_key_input(key(KeyCode::End));
+        assert_eq!(input.on_key_input(key(KeyCode::Delete)), None);
+        assert_eq!(input.on_key_input(held(KeyCode::Backspace)), Some(Event::Changed));
+        assert_eq!(input.text(), "éll");
+
+        input.on_key_input(key(KeyCode::Left));
+        assert_eq!(input.on_char_input('x'), Some(Event::Changed));
+        assert_eq!(input.on_char_input('\u{8}'), None);
+        assert_eq!(input.text(), "élxl");
+        assert_eq!(input.cursor, 3);
+        input.on_key_input(key(KeyCode::Right));
+        input.on_key_input(key(KeyCode::Right));
+        assert_eq!(input.cursor, 4);
+
+        assert_eq!(input.on_key_input(key(KeyCode::Return)), Some(Event::Activated));
+        assert_eq!(input.on_key_input(held(KeyCode::Return)), None);
+    }
+
+    #[test]
+    fn text_input_click_moves_the_cursor() {
+        let mut input = TextInput::new("Email").with_text("abcd");
+        // 24 high, so every character is 10 wide, starting 4 into the field
+        place(&mut input, Rect::new((0.0, 0.0), (200.0, 24.0)));
+        input.on_mouse_input(mouse(GLFW_PRESS, (26.0, 12.0)));
+        assert_eq!(input.cursor, 2);
+        input.on_mouse_input(mouse(GLFW_PRESS, (190.0, 12.0)));
+        assert_eq!(input.cursor, 4);
+    }
+
+    #[test]
+    fn text_input_shows_the_placeholder_while_empty() {
+        let mut input = TextInput::new("Email");
+        let canvas = place(&mut input, Rect::new((0.0, 0.0), (200.0, 24.0)));
+        assert_eq!(canvas.texts().collect::<Vec<_>>(), vec!["Email"]);
+
+        let mut password = PasswordInput::new("Password");
+        for c in "hunter2".chars() {
+            password.on_char_input(c);
+        }
+        let canvas = place(&mut password, Rect::new((0.0, 0.0), (200.0, 24.0)));
+        assert_eq!(canvas.texts().collect::<Vec<_>>(), vec!["*******"]);
+        assert_eq!(password.text(), "hunter2");
+    }
+
+    #[test]
+    fn button_activation() {
+        let mut button = Button::new("Log in");
+        assert_eq!(button.on_key_input(key(KeyCode::Return)), Some(Event::Activated));
+        assert_eq!(button.on_key_input(key(KeyCode::Space)), Some(Event::Activated));
+        assert_eq!(button.on_key_input(held(KeyCode::Space)), None);
+        assert_eq!(button.on_key_input(key(KeyCode::Tab)), None);
+
+        place(&mut button, Rect::new((10.0, 10.0), (100.0, 40.0)));
+        let mut widgets: [&mut dyn Widget; 1] = [&mut button];
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_PRESS, (50.0, 30.0))), None);
+        assert!(widgets[0].interaction().pressed);
+        assert!(widgets[0].interaction().focused);
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_RELEASE, (60.0, 30.0))), Some((0, Event::Activated)));
+        assert!(!widgets[0].interaction().pressed);
+
+        // Dragged off before letting go
+        on_mouse_input(&mut widgets, mouse(GLFW_PRESS, (50.0, 30.0)));
+        on_mouse_input(&mut widgets, MouseAction::Move { pos: (200.0, 30.0), mods: 0 });
+        assert!(!widgets[0].interaction().hovered);
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_RELEASE, (200.0, 30.0))), None);
+    }
+
+    #[test]
+    fn tab_skips_what_cant_be_focused() {
+        let mut email = TextInput::new("Email");
+        let mut label = Label::new("Server", Fonts::Inter, TextStyle::default());
+        let mut login = Button::new("Log in");
+        let mut widgets: [&mut dyn Widget; 3] = [&mut email, &mut label, &mut login];
+        assert_eq!(focused(&widgets), None);
+        on_key_input(&mut widgets, key(KeyCode::Tab));
+        assert_eq!(focused(&widgets), Some(0));
+        on_key_input(&mut widgets, key(KeyCode::Tab));
+        assert_eq!(focused(&widgets), Some(2));
+        on_key_input(&mut widgets, key(KeyCode::Tab));
+        assert_eq!(focused(&widgets), Some(0));
+        on_key_input(&mut widgets, shifted(KeyCode::Tab));
+        assert_eq!(focused(&widgets), Some(2));
+
+        // Everything else goes to the focused widget
+        assert_eq!(on_key_input(&mut widgets, key(KeyCode::Return)), Some((2, Event::Activated)));
+        assert_eq!(on_char_input(&mut widgets, 'a'), None);
+        focus(&mut widgets, 0);
+        assert_eq!(on_char_input(&mut widgets, 'a'), Some((0, Event::Changed)));
+    }
+
+    #[test]
+    fn list_selection() {
+        let items = vec!["a:4450".to_owned(), "b:4450".to_owned(), "c:4450".to_owned()];
+        let mut list = List::new(items, 5);
+        assert_eq!(list.selected_item(), Some("c:4450"));
+        assert_eq!(list.on_key_input(key(KeyCode::Down)), None);
+        assert_eq!(list.on_key_input(key(KeyCode::Up)), Some(Event::Changed));
+        assert_eq!(list.selected_item(), Some("b:4450"));
+        assert_eq!(list.on_key_input(key(KeyCode::Return)), Some(Event::Activated));
+
+        // Two rows fit, the selected one is kept in view
+        let canvas = place(&mut list, Rect::new((0.0, 100.0), (200.0, 48.0)));
+        assert_eq!(canvas.texts().collect::<Vec<_>>(), vec!["a:4450", "b:4450"]);
+        assert_eq!(list.on_mouse_input(mouse(GLFW_PRESS, (10.0, 110.0))), Some(Event::Changed));
+        assert_eq!(list.selected_item(), Some("a:4450"));
+        let scroll_down = MouseAction::Scroll { offset: (0.0, -1.0), pos: (10.0, 110.0), mods: 0 };
+        assert_eq!(list.on_mouse_input(scroll_down), Some(Event::Changed));
+        assert_eq!(list.selected_item(), Some("b:4450"));
+
+        assert_eq!(List::new(Vec::new(), 0).selected_item(), None);
+    }
+
+    #[test]
+    fn checkbox_toggles() {
+        let mut checkbox = Checkbox::new("Muted", false);
+        assert_eq!(checkbox.on_key_input(key(KeyCode::Space)), Some(Event::Changed));
+        assert!(checkbox.checked);
+        assert_eq!(checkbox.on_key_input(held(KeyCode::Space)), None);
+        assert_eq!(checkbox.on_key_input(key(KeyCode::Return)), Some(Event::Changed));
+        assert!(!checkbox.checked);
+
+        place(&mut checkbox, Rect::new((0.0, 0.0), (100.0, 20.0)));
+        let mut widgets: [&mut dyn Widget; 1] = [&mut checkbox];
+        on_mouse_input(&mut widgets, mouse(GLFW_PRESS, (10.0, 10.0)));
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_RELEASE, (10.0, 10.0))), Some((0, Event::Changed)));
+        assert!(checkbox.checked);
+    }
+
+    #[test]
+    fn checkbox_shows_its_state() {
+        let mut checkbox = Checkbox::new("Muted", false);
+        let rect = Rect::new((0.0, 0.0), (100.0, 20.0));
+        let fills = |canvas: Recorder| -> Vec<Color> {
+            canvas.commands().iter().filter_map(|c| match *c {
+                DrawCommand::Fill { color, .. } => Some(color),
+                _ => None,
+            }).collect()
+        };
+        assert_eq!(fills(place(&mut checkbox, rect)), vec![FIELD_COLOR]);
+        checkbox.interaction.hovered = true;
+        assert_eq!(fills(place(&mut checkbox, rect)), vec![HOVER_COLOR]);
+        checkbox.interaction.pressed = true;
+        checkbox.interaction.focused = true;
+        checkbox.checked = true;
+        // The focus frame, the box and the tick
+        assert_eq!(fills(place(&mut checkbox, rect)), vec![FOCUS_COLOR, PRESSED_COLOR, FOCUS_COLOR]);
+    }
+
+    #[test]
+    fn slider_follows_keys_wheel_and_drag() {
+        let mut slider = Slider::new(0.0, 1.0, 0.25, 2.0);
+        assert_eq!(slider.value(), 1.0);
+        assert_eq!(slider.on_key_input(key(KeyCode::Right)), None);
+        assert_eq!(slider.on_key_input(key(KeyCode::Left)), Some(Event::Changed));
+        assert_eq!(slider.on_key_input(held(KeyCode::Down)), Some(Event::Changed));
+        assert_eq!(slider.value(), 0.5);
+        let scroll_up = MouseAction::Scroll { offset: (0.0, 1.0), pos: (0.0, 0.0), mods: 0 };
+        assert_eq!(slider.on_mouse_input(scroll_up), Some(Event::Changed));
+        assert_eq!(slider.value(), 0.75);
+
+        // 20 high, so the handle's radius is 8 and it moves from 8 to 208
+        place(&mut slider, Rect::new((0.0, 0.0), (216.0, 20.0)));
+        let mut widgets: [&mut dyn Widget; 1] = [&mut slider];
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_PRESS, (58.0, 10.0))), Some((0, Event::Changed)));
+        assert_eq!(
+            on_mouse_input(&mut widgets, MouseAction::Move { pos: (108.0, 40.0), mods: 0 }),
+            Some((0, Event::Changed))
+        );
+        // Dragged past the end
+        on_mouse_input(&mut widgets, MouseAction::Move { pos: (400.0, 10.0), mods: 0 });
+        on_mouse_input(&mut widgets, mouse(GLFW_RELEASE, (400.0, 10.0)));
+        assert_eq!(slider.value(), 1.0);
+        // Not pressed anymore, moving does nothing
+        assert_eq!(slider.on_mouse_input(MouseAction::Move { pos: (8.0, 10.0), mods: 0 }), None);
+    }
+
+    #[test]
+    fn slider_handle_shows_its_state() {
+        let mut slider = Slider::new(0.0, 1.0, 0.1, 0.5);
+        let rect = Rect::new((0.0, 0.0), (216.0, 20.0));
+        let handle = |canvas: Recorder| match canvas.commands().last() {
+            Some(&DrawCommand::Fill { color, .. }) => color,
+            other => panic!("Expected the handle, got {:?}", other),
+        };
+        assert_eq!(handle(place(&mut slider, rect)), PLACEHOLDER_COLOR);
+        slider.interaction.hovered = true;
+        assert_eq!(handle(place(&mut slider, rect)), HANDLE_HOVER_COLOR);
+        slider.interaction.pressed = true;
+        assert_eq!(handle(place(&mut slider, rect)), TEXT_COLOR);
+        slider.interaction.focused = true;
+        // Track, filled part, focus ring and handle
+        assert_eq!(place(&mut slider, rect).commands().len(), 4);
+    }
+
+    #[test]
+    fn knob_turns_with_a_vertical_drag() {
+        let mut knob = Knob::new(-1.0, 1.0, 0.1, 0.0);
+        place(&mut knob, Rect::new((0.0, 0.0), (40.0, 40.0)));
+        let mut widgets: [&mut dyn Widget; 1] = [&mut knob];
+        assert_eq!(on_mouse_input(&mut widgets, mouse(GLFW_PRESS, (20.0, 20.0))), None);
+        assert!(widgets[0].interaction().focused);
+        // Half of KNOB_DRAG_DISTANCE up turns it by half its range, wherever the cursor is sideways
+        assert_eq!(
+            on_mouse_input(&mut widgets, MouseAction::Move { pos: (300.0, -80.0), mods: 0 }),
+            Some((0, Event::Changed))
+        );
+        on_mouse_input(&mut widgets, mouse(GLFW_RELEASE, (300.0, -80.0)));
+        assert_eq!(knob.value(), 1.0);
+        assert_eq!(knob.on_mouse_input(MouseAction::Move { pos: (20.0, 300.0), mods: 0 }), None);
+
+        assert_eq!(knob.on_key_input(key(KeyCode::Down)), Some(Event::Changed));
+        let scroll_down = MouseAction::Scroll { offset: (0.0, -1.0), pos: (20.0, 20.0), mods: 0 };
+        assert_eq!(knob.on_mouse_input(scroll_down), Some(Event::Changed));
+        assert!((knob.value() - 0.8).abs() < 1e-6);
+        knob.set_value(-5.0);
+        assert_eq!(knob.value(), -1.0);
+    }
+
+    #[test]
+    fn knob_shows_its_state() {
+        let mut knob = Knob::new(0.0, 1.0, 0.1, 0.0);
+        let rect = Rect::new((0.0, 0.0), (40.0, 40.0));
+        let dial = |canvas: Recorder| -> Vec<Color> {
+            canvas.commands().iter().filter_map(|c| match *c {
+                DrawCommand::Fill { color, .. } => Some(color),
+                _ => None,
+            }).collect()
+        };
+        assert_eq!(dial(place(&mut knob, rect)), vec![FIELD_COLOR, TEXT_COLOR]);
+        knob.interaction.hovered = true;
+        assert_eq!(dial(place(&mut knob, rect)), vec![HOVER_COLOR, TEXT_COLOR]);
+        knob.interaction.pressed = true;
+        knob.interaction.focused = true;
+        assert_eq!(dial(place(&mut knob, rect)), vec![FOCUS_COLOR, PRESSED_COLOR, TEXT_COLOR]);
+    }
+}